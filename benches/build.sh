@@ -0,0 +1,59 @@
+# Builds the benchmark images run by `cargo bench --bench suite` into benches/bin.
+# The toolchain needs a newlib multilib for rv64ia/lp64 as the emulator has no
+# M, C or floating point extensions, e.g. riscv-gnu-toolchain configured with
+# --with-multilib-generator="rv64ia_zicsr_zifencei-lp64--".
+# Each suite is built when the path to its sources is set:
+#   COREMARK_PATH     https://github.com/eembc/coremark
+#   RISCV_TESTS_PATH  https://github.com/riscv-software-src/riscv-tests (Dhrystone)
+#   EMBENCH_PATH      https://github.com/embench/embench-iot
+export TOOLCHAIN_BIN_PATH=${TOOLCHAIN_BIN_PATH:-/opt/riscv}
+export TOOLCHAIN_PREFIX=${TOOLCHAIN_BIN_PATH}/bin/riscv64-unknown-elf-
+export SOURCE_PATH="$(
+    cd -- "$(dirname "$0")" >/dev/null 2>&1
+    pwd -P
+)"
+export PORT_PATH=${SOURCE_PATH}/port
+export OUTPUT_PATH=${SOURCE_PATH}/bin
+export COREMARK_ITERATIONS=${COREMARK_ITERATIONS:-100}
+
+set -e
+mkdir -p ${OUTPUT_PATH} ${OUTPUT_PATH}/elf
+
+CFLAGS="-march=rv64ia_zicsr_zifencei -mabi=lp64 -mcmodel=medany -O2"
+LDFLAGS="-nostartfiles -T ${PORT_PATH}/link.ld -Wl,--no-relax --specs=nosys.specs"
+STARTUP="${PORT_PATH}/start.S ${PORT_PATH}/port.c"
+
+# build <name> <cc arguments>...
+build() {
+    local name=$1
+    shift
+    ${TOOLCHAIN_PREFIX}gcc ${CFLAGS} ${LDFLAGS} ${STARTUP} "$@" -lm -lgcc -o ${OUTPUT_PATH}/elf/${name}.elf
+    ${TOOLCHAIN_PREFIX}objcopy -O binary ${OUTPUT_PATH}/elf/${name}.elf ${OUTPUT_PATH}/${name}.bin
+    echo "Built ${name}"
+}
+
+if [ -n "${COREMARK_PATH}" ]; then
+    build coremark -I ${PORT_PATH} -I ${COREMARK_PATH} \
+        -DITERATIONS=${COREMARK_ITERATIONS} -DPERFORMANCE_RUN=1 \
+        -DFLAGS_STR="\"${CFLAGS}\"" \
+        ${COREMARK_PATH}/core_list_join.c ${COREMARK_PATH}/core_main.c \
+        ${COREMARK_PATH}/core_matrix.c ${COREMARK_PATH}/core_state.c \
+        ${COREMARK_PATH}/core_util.c ${PORT_PATH}/core_portme.c
+fi
+
+if [ -n "${RISCV_TESTS_PATH}" ]; then
+    DHRYSTONE_PATH=${RISCV_TESTS_PATH}/benchmarks/dhrystone
+    build dhrystone -I ${PORT_PATH} -I ${RISCV_TESTS_PATH}/env -I ${DHRYSTONE_PATH} \
+        -std=gnu99 -fno-common -Wno-implicit \
+        ${DHRYSTONE_PATH}/dhrystone.c ${DHRYSTONE_PATH}/dhrystone_main.c
+fi
+
+if [ -n "${EMBENCH_PATH}" ]; then
+    for BENCHMARK_PATH in ${EMBENCH_PATH}/src/*/; do
+        BENCHMARK=$(basename ${BENCHMARK_PATH})
+        build embench-${BENCHMARK} -I ${PORT_PATH} -I ${EMBENCH_PATH}/support \
+            -DHAVE_BOARDSUPPORT_H -DWARMUP_HEAT=1 \
+            ${BENCHMARK_PATH}*.c ${EMBENCH_PATH}/support/main.c \
+            ${EMBENCH_PATH}/support/beebsc.c ${PORT_PATH}/boardsupport.c
+    done
+fi
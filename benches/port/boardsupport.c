@@ -0,0 +1,15 @@
+/* Embench-IoT board support for the emulator, the benchmark runner times the
+ * whole image so the triggers do nothing. */
+#include <support.h>
+
+void initialise_board(void)
+{
+}
+
+void __attribute__((noinline)) start_trigger(void)
+{
+}
+
+void __attribute__((noinline)) stop_trigger(void)
+{
+}
@@ -0,0 +1,2 @@
+/* Embench-IoT board support for the emulator. */
+#define CPU_MHZ 1
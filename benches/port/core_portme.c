@@ -0,0 +1,81 @@
+/* CoreMark port for the emulator, timed with the time CSR. */
+#include <string.h>
+
+#include "coremark.h"
+
+void _exit(int status);
+
+#if VALIDATION_RUN
+volatile ee_s32 seed1_volatile = 0x3415;
+volatile ee_s32 seed2_volatile = 0x3415;
+volatile ee_s32 seed3_volatile = 0x66;
+#endif
+#if PERFORMANCE_RUN
+volatile ee_s32 seed1_volatile = 0x0;
+volatile ee_s32 seed2_volatile = 0x0;
+volatile ee_s32 seed3_volatile = 0x66;
+#endif
+#if PROFILE_RUN
+volatile ee_s32 seed1_volatile = 0x8;
+volatile ee_s32 seed2_volatile = 0x8;
+volatile ee_s32 seed3_volatile = 0x8;
+#endif
+volatile ee_s32 seed4_volatile = ITERATIONS;
+volatile ee_s32 seed5_volatile = 0;
+
+ee_u32 default_num_contexts = 1;
+
+/* Matches the timebase of the emulator */
+#define TIMER_RES_DIVIDER 1
+#define EE_TICKS_PER_SEC 10000000
+
+static CORE_TICKS start_time_val, stop_time_val;
+static int reported_errors;
+
+static CORE_TICKS read_time(void)
+{
+    CORE_TICKS time;
+    asm volatile("rdtime %0" : "=r"(time));
+    return time;
+}
+
+void start_time(void)
+{
+    start_time_val = read_time();
+}
+
+void stop_time(void)
+{
+    stop_time_val = read_time();
+}
+
+CORE_TICKS get_time(void)
+{
+    return stop_time_val - start_time_val;
+}
+
+secs_ret time_in_secs(CORE_TICKS ticks)
+{
+    return ticks / EE_TICKS_PER_SEC;
+}
+
+int ee_printf(const char *format, ...)
+{
+    if (strstr(format, "ERROR") || strstr(format, "Errors detected"))
+        reported_errors = 1;
+    return 0;
+}
+
+void portable_init(core_portable *p, int *argc, char *argv[])
+{
+    (void)argc;
+    (void)argv;
+    p->portable_id = 1;
+}
+
+void portable_fini(core_portable *p)
+{
+    p->portable_id = 0;
+    if (reported_errors)
+        _exit(1);
+}
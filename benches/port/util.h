@@ -0,0 +1,15 @@
+/* Stands in for the util.h of riscv-tests when building Dhrystone. */
+#ifndef PORT_UTIL_H
+#define PORT_UTIL_H
+
+#include "encoding.h"
+
+/* Only the whole run is measured, by the benchmark runner. */
+static inline void setStats(int enable)
+{
+    (void)enable;
+}
+
+void debug_printf(const char *format, ...);
+
+#endif
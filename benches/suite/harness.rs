@@ -0,0 +1,123 @@
+//! Measurement of a benchmark image and comparison against a saved baseline,
+//! shared by the runner and its tests
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use riscvemulator::{
+    cpu::{config::CpuConfig, side_effects::OperationSideEffect, Cpu},
+    machine::Machine,
+};
+
+/// Instructions and host time of a benchmark, the fastest of the runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+impl Measurement {
+    pub fn mips(&self) -> f64 {
+        self.instructions as f64 / self.time.as_secs_f64() / 1e6
+    }
+}
+
+/// Runs a benchmark image on a single hart until it powers the machine off
+/// through the test finisher, the exit status of main is the one of the run.
+pub fn run(code: &[u8], config: &CpuConfig) -> Result<Measurement, String> {
+    let machine = Arc::new(Machine::new(config, code, 1).map_err(|err| err.to_string())?);
+    let mut cpu = Cpu::new(Arc::clone(&machine), 0, config.clone());
+    let start = Instant::now();
+    while !machine.is_stopped() {
+        match cpu.step() {
+            Ok(OperationSideEffect::WaitForInterrupt) => cpu.wait_for_interrupt(),
+            Ok(OperationSideEffect::WaitForStore { bounded }) => cpu.wait_for_store(bounded),
+            Ok(_) => (),
+            Err(err) => return Err(format!("{:x}: {err}", cpu.get_program_counter())),
+        }
+    }
+    let time = start.elapsed();
+    match machine.exit_status() {
+        Some(0) => Ok(Measurement {
+            instructions: cpu.executed_instructions(),
+            time,
+        }),
+        Some(status) => Err(format!("exited with status {status}")),
+        None => Err("stopped without exit status".to_string()),
+    }
+}
+
+/// One line per benchmark: name, instructions and time in nanoseconds
+pub fn parse_baseline(text: &str) -> BTreeMap<String, Measurement> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.to_string();
+            let instructions = fields.next()?.parse().ok()?;
+            let nanos = fields.next()?.parse().ok()?;
+            Some((
+                name,
+                Measurement {
+                    instructions,
+                    time: Duration::from_nanos(nanos),
+                },
+            ))
+        })
+        .collect()
+}
+
+pub fn format_baseline(measurements: &BTreeMap<String, Measurement>) -> String {
+    measurements
+        .iter()
+        .map(|(benchmark, measurement)| {
+            format!(
+                "{benchmark} {} {}\n",
+                measurement.instructions,
+                measurement.time.as_nanos()
+            )
+        })
+        .collect()
+}
+
+/// Measurement of a benchmark relative to its baseline
+#[derive(Debug, PartialEq)]
+pub enum Comparison {
+    /// The image or the emulated semantics changed, the times can't be compared
+    InstructionsChanged,
+    /// Change of the time in percent
+    Change(f64),
+    /// Slowdown in percent over the threshold
+    Regression(f64),
+}
+
+impl Comparison {
+    /// `threshold` is the slowdown in percent reported as a regression
+    pub fn new(saved: &Measurement, measurement: &Measurement, threshold: f64) -> Self {
+        if saved.instructions != measurement.instructions {
+            return Comparison::InstructionsChanged;
+        }
+        let change = (measurement.time.as_secs_f64() / saved.time.as_secs_f64() - 1.0) * 100.0;
+        match change > threshold {
+            true => Comparison::Regression(change),
+            false => Comparison::Change(change),
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        !matches!(self, Comparison::Change(_))
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::InstructionsChanged => write!(f, "instructions changed"),
+            Comparison::Change(change) => write!(f, "{change:+.1}%"),
+            Comparison::Regression(change) => write!(f, "{change:+.1}% regression"),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Runs the benchmark images of benches/bin, built by benches/build.sh, and
+//! reports the time and MIPS of each one. Timings can be saved as a named
+//! baseline and later runs compared against it.
+
+mod harness;
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use harness::{format_baseline, parse_baseline, run, Comparison, Measurement};
+use riscvemulator::cpu::{basic_blocks::ExecutionEngine, config::CpuConfig};
+
+const USAGE: &str = "Usage: cargo bench --bench suite -- [--engine <interpreter|blocks|jit>] \
+    [--runs <n>] [--dir <path>] [--save-baseline <name>] [--baseline <name>] \
+    [--threshold <percent>] [<benchmark>]...";
+
+struct Options {
+    config: CpuConfig,
+    runs: u32,
+    dir: PathBuf,
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+    /// Slowdown in percent over the baseline reported as a regression
+    threshold: f64,
+    /// Benchmarks to run, all of them when empty
+    names: Vec<String>,
+}
+
+fn parse_args() -> Options {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut options = Options {
+        config: CpuConfig::default(),
+        runs: 3,
+        dir: root.join("benches/bin"),
+        save_baseline: None,
+        baseline: None,
+        threshold: 5.0,
+        names: Vec::new(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            //Added by cargo bench
+            "--bench" => (),
+            "--engine" => {
+                options.config.engine = args
+                    .next()
+                    .and_then(|engine| ExecutionEngine::from_name(&engine))
+                    .expect(USAGE);
+            }
+            "--runs" => {
+                options.runs = args
+                    .next()
+                    .and_then(|runs| runs.parse().ok())
+                    .filter(|runs| *runs > 0)
+                    .expect(USAGE)
+            }
+            "--dir" => options.dir = args.next().expect(USAGE).into(),
+            "--save-baseline" => options.save_baseline = Some(args.next().expect(USAGE)),
+            "--baseline" => options.baseline = Some(args.next().expect(USAGE)),
+            "--threshold" => {
+                options.threshold = args
+                    .next()
+                    .and_then(|threshold| threshold.parse().ok())
+                    .expect(USAGE)
+            }
+            _ if !arg.starts_with("--") => options.names.push(arg),
+            _ => panic!("{USAGE}"),
+        }
+    }
+    options
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target/benchmarks")
+        .join(format!("{name}.baseline"))
+}
+
+fn load_baseline(name: &str) -> BTreeMap<String, Measurement> {
+    let path = baseline_path(name);
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Cannot read baseline {}: {err}", path.display()));
+    parse_baseline(&text)
+}
+
+fn save_baseline(name: &str, measurements: &BTreeMap<String, Measurement>) {
+    let path = baseline_path(name);
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(&path, format_baseline(measurements)))
+        .unwrap_or_else(|err| panic!("Cannot write baseline {}: {err}", path.display()));
+    println!("Saved baseline {}", path.display());
+}
+
+fn main() {
+    let options = parse_args();
+    let mut images: Vec<PathBuf> = fs::read_dir(&options.dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "bin"))
+                .collect()
+        })
+        .unwrap_or_default();
+    images.sort();
+    images.retain(|path| {
+        let name = path.file_stem().unwrap().to_string_lossy();
+        options.names.is_empty() || options.names.iter().any(|wanted| *wanted == name)
+    });
+    if images.is_empty() {
+        eprintln!(
+            "No benchmark image in {}, build them with benches/build.sh",
+            options.dir.display()
+        );
+        process::exit(1);
+    }
+    let baseline = options.baseline.as_deref().map(load_baseline);
+
+    let mut measurements = BTreeMap::new();
+    let mut failed = false;
+    println!(
+        "{:<24} {:>14} {:>12} {:>10} {:>10}",
+        "benchmark", "instructions", "time", "MIPS", "baseline"
+    );
+    for path in images {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let code = fs::read(&path).unwrap();
+        //The fastest run is the least disturbed by the host
+        let result = (0..options.runs)
+            .map(|_| run(&code, &options.config))
+            .try_fold(None, |fastest: Option<Measurement>, result| {
+                result.map(|measurement| match fastest {
+                    Some(fastest) if fastest.time <= measurement.time => Some(fastest),
+                    _ => Some(measurement),
+                })
+            });
+        let measurement = match result {
+            Ok(measurement) => measurement.unwrap(),
+            Err(err) => {
+                println!("{name:<24} {err}");
+                failed = true;
+                continue;
+            }
+        };
+        let comparison = match baseline.as_ref().and_then(|baseline| baseline.get(&name)) {
+            None => String::new(),
+            Some(saved) => {
+                let comparison = Comparison::new(saved, &measurement, options.threshold);
+                failed |= comparison.failed();
+                comparison.to_string()
+            }
+        };
+        println!(
+            "{name:<24} {:>14} {:>12.2?} {:>10.2} {:>10}",
+            measurement.instructions,
+            measurement.time,
+            measurement.mips(),
+            comparison
+        );
+        measurements.insert(name, measurement);
+    }
+
+    if let Some(name) = options.save_baseline.as_deref() {
+        save_baseline(name, &measurements);
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,143 @@
+//! Advanced core local interruptor, the machine level software interrupt
+//! device (MSWI) lets harts send each other IPIs and the machine level timer
+//! device (MTIMER) raises their machine timer interrupts
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::memory::MemoryOpSize;
+
+/// Machine level software interrupt device, the MSIP register of each hart
+/// drives its mip.MSIP bit
+pub struct Mswi {
+    msip: Vec<bool>,
+}
+
+impl Mswi {
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            msip: vec![false; hart_count],
+        }
+    }
+
+    /// MSIP registers of missing harts read as zero
+    pub fn load(&self, offset: u64) -> u32 {
+        self.msip
+            .get((offset / 4) as usize)
+            .map_or(0, |msip| *msip as u32)
+    }
+
+    /// Only bit 0 of the MSIP registers is writable
+    pub fn store(&mut self, offset: u64, value: u32) {
+        if let Some(msip) = self.msip.get_mut((offset / 4) as usize) {
+            *msip = value & 1 != 0;
+        }
+    }
+
+    #[inline(always)]
+    pub fn interrupt_pending(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+}
+
+/// Offset of the mtime register in the MTIMER region, the mtimecmp register of
+/// each hart is at 8 times its hart id
+pub const MTIME_OFFSET: u64 = 0x7ff8;
+
+/// Machine level timer device, the mtimecmp register of each hart drives its
+/// mip.MTIP bit. The harts compare their own time against it without taking
+/// the device lock, mtime is the time of the machine.
+pub struct Mtimer {
+    mtimecmp: Vec<AtomicU64>,
+}
+
+impl Mtimer {
+    /// mtimecmp resets to the largest value so no interrupt is pending
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            mtimecmp: (0..hart_count).map(|_| AtomicU64::new(u64::MAX)).collect(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn mtimecmp(&self, hart: usize) -> u64 {
+        self.mtimecmp[hart].load(Ordering::Acquire)
+    }
+
+    /// 32-bit accesses read half of a register, the mtimecmp registers of
+    /// missing harts read as zero
+    pub fn load(&self, offset: u64, size: MemoryOpSize, mtime: u64) -> u64 {
+        let register = match offset & !0x7 {
+            MTIME_OFFSET => mtime,
+            register => self
+                .mtimecmp
+                .get((register / 8) as usize)
+                .map_or(0, |mtimecmp| mtimecmp.load(Ordering::Acquire)),
+        };
+        size.zero_extend(register >> ((offset & 0x7) * 8))
+    }
+
+    /// Writes the mtimecmp register of a hart and returns the hart, 32-bit
+    /// accesses write half of it
+    pub fn store_mtimecmp(&self, offset: u64, size: MemoryOpSize, value: u64) -> Option<usize> {
+        let hart = (offset / 8) as usize;
+        let mtimecmp = self.mtimecmp.get(hart)?;
+        mtimecmp.store(
+            write_register(mtimecmp.load(Ordering::Acquire), offset, size, value),
+            Ordering::Release,
+        );
+        Some(hart)
+    }
+}
+
+/// Value of a 64-bit register after a write of `size` bytes at `offset`
+pub fn write_register(register: u64, offset: u64, size: MemoryOpSize, value: u64) -> u64 {
+    let shift = (offset & 0x7) * 8;
+    let mask = size.zero_extend(u64::MAX) << shift;
+    (register & !mask) | ((value << shift) & mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msip_registers_hold_one_bit_per_hart() {
+        let mut mswi = Mswi::new(2);
+        mswi.store(4, 0xffff_ffff);
+        assert_eq!(mswi.load(4), 1);
+        assert!(mswi.interrupt_pending(1));
+        assert!(!mswi.interrupt_pending(0));
+        //Harts that don't exist
+        mswi.store(8, 1);
+        assert_eq!(mswi.load(8), 0);
+    }
+
+    #[test]
+    fn mtimer_registers_are_accessed_by_halves() {
+        let mtimer = Mtimer::new(2);
+        assert_eq!(mtimer.mtimecmp(1), u64::MAX);
+        assert_eq!(mtimer.store_mtimecmp(8, MemoryOpSize::B32, 0x1234), Some(1));
+        assert_eq!(mtimer.store_mtimecmp(12, MemoryOpSize::B32, 0), Some(1));
+        assert_eq!(mtimer.mtimecmp(1), 0x1234);
+        assert_eq!(mtimer.mtimecmp(0), u64::MAX);
+        assert_eq!(mtimer.store_mtimecmp(16, MemoryOpSize::B64, 0), None);
+        let mtime = 0x1_0000_0002;
+        assert_eq!(mtimer.load(MTIME_OFFSET, MemoryOpSize::B64, mtime), mtime);
+        assert_eq!(mtimer.load(MTIME_OFFSET + 4, MemoryOpSize::B32, mtime), 1);
+        assert_eq!(mtimer.load(12, MemoryOpSize::B32, mtime), 0);
+        assert_eq!(mtimer.load(16, MemoryOpSize::B64, mtime), 0);
+    }
+
+    #[test]
+    fn partial_writes_keep_the_other_bytes() {
+        let register = 0x8877_6655_4433_2211;
+        assert_eq!(
+            write_register(register, 4, MemoryOpSize::B32, 0xaabb_ccdd),
+            0xaabb_ccdd_4433_2211
+        );
+        assert_eq!(
+            write_register(register, 1, MemoryOpSize::B8, 0x1ff),
+            0x8877_6655_4433_ff11
+        );
+    }
+}
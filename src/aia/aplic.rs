@@ -0,0 +1,830 @@
+use crate::consts::{IMSIC_M_BASE_ADDR, IMSIC_S_BASE_ADDR, PAGE_SIZE};
+
+use super::{InterruptDomain, Msi};
+
+/// Number of wired interrupt sources, source 0 is reserved
+pub const APLIC_NUM_SOURCES: usize = 63;
+
+/// Offsets of the memory mapped registers of an interrupt domain
+struct AplicRegisters;
+impl AplicRegisters {
+    const DOMAINCFG: u64 = 0x0000;
+    const SOURCECFG1: u64 = 0x0004;
+    const SOURCECFG1023: u64 = 0x0ffc;
+    const MMSIADDRCFG: u64 = 0x1bc0;
+    const MMSIADDRCFGH: u64 = 0x1bc4;
+    const SMSIADDRCFG: u64 = 0x1bc8;
+    const SMSIADDRCFGH: u64 = 0x1bcc;
+    const SETIP0: u64 = 0x1c00;
+    const SETIP31: u64 = 0x1c7c;
+    const SETIPNUM: u64 = 0x1cdc;
+    const IN_CLRIP0: u64 = 0x1d00;
+    const IN_CLRIP31: u64 = 0x1d7c;
+    const CLRIPNUM: u64 = 0x1ddc;
+    const SETIE0: u64 = 0x1e00;
+    const SETIE31: u64 = 0x1e7c;
+    const SETIENUM: u64 = 0x1edc;
+    const CLRIE0: u64 = 0x1f00;
+    const CLRIE31: u64 = 0x1f7c;
+    const CLRIENUM: u64 = 0x1fdc;
+    const SETIPNUM_LE: u64 = 0x2000;
+    const SETIPNUM_BE: u64 = 0x2004;
+    const GENMSI: u64 = 0x3000;
+    const TARGET1: u64 = 0x3004;
+    const TARGET1023: u64 = 0x3ffc;
+    /// Interrupt delivery controls of each hart, only used in direct delivery mode
+    const IDC0: u64 = 0x4000;
+    const IDC_SIZE: u64 = 32;
+}
+
+/// Offsets of the registers of an interrupt delivery control structure
+struct IdcRegisters;
+impl IdcRegisters {
+    const IDELIVERY: u64 = 0x00;
+    const IFORCE: u64 = 0x04;
+    const ITHRESHOLD: u64 = 0x08;
+    const TOPI: u64 = 0x18;
+    const CLAIMI: u64 = 0x1c;
+}
+
+struct DomainCfgFields;
+impl DomainCfgFields {
+    /// Interrupt enable
+    const IE: u32 = 1 << 8;
+    /// Delivery mode, set for MSI delivery
+    const DM: u32 = 1 << 2;
+    /// The upper byte reads as 0x80 so domaincfg can't be confused with a big-endian register
+    const READ_ONE: u32 = 0x80 << 24;
+    const WRITE_MASK: u32 = Self::IE | Self::DM;
+}
+
+struct SourceCfgFields;
+impl SourceCfgFields {
+    /// Delegate, the source belongs to the child domain
+    const D: u32 = 1 << 10;
+    /// Source mode, the child index takes its place while delegated
+    const SM: u32 = 0b111;
+
+    const INACTIVE: u32 = 0;
+    const DETACHED: u32 = 1;
+    const EDGE1: u32 = 4;
+    const EDGE0: u32 = 5;
+    const LEVEL1: u32 = 6;
+    const LEVEL0: u32 = 7;
+}
+
+/// Fields of the upper half of mmsiaddrcfg, smsiaddrcfgh only has LHXS and the PPN
+struct MsiAddrCfgFields;
+impl MsiAddrCfgFields {
+    /// Locks the MSI address configuration registers
+    const L: u32 = 1 << 31;
+    const HHXS_SHIFT: u32 = 24;
+    const LHXS_SHIFT: u32 = 20;
+    const HHXW_SHIFT: u32 = 16;
+    const LHXW_SHIFT: u32 = 12;
+    const PPN_HIGH: u32 = 0xfff;
+
+    const MMSIADDRCFGH_MASK: u32 = Self::L
+        | (0x1f << Self::HHXS_SHIFT)
+        | (0b111 << Self::LHXS_SHIFT)
+        | (0b111 << Self::HHXW_SHIFT)
+        | (0xf << Self::LHXW_SHIFT)
+        | Self::PPN_HIGH;
+    const SMSIADDRCFGH_MASK: u32 = (0b111 << Self::LHXS_SHIFT) | Self::PPN_HIGH;
+}
+
+/// Fields of the target and genmsi registers
+struct TargetFields;
+impl TargetFields {
+    const HART_INDEX_SHIFT: u32 = 18;
+    /// External interrupt identity sent in MSI delivery mode
+    const EIID: u32 = 0x7ff;
+    /// Interrupt priority used in direct delivery mode, lower values have higher priority
+    const IPRIO: u32 = 0xff;
+}
+
+/// Interrupt delivery control of a hart
+#[derive(Clone, Copy, Default)]
+struct Idc {
+    idelivery: u32,
+    iforce: u32,
+    ithreshold: u32,
+}
+
+/// Interrupt domain, the pending, enabled and input bitmaps hold one bit per source
+struct Domain {
+    domaincfg: u32,
+    sourcecfg: [u32; APLIC_NUM_SOURCES + 1],
+    target: [u32; APLIC_NUM_SOURCES + 1],
+    pending: u64,
+    enabled: u64,
+    genmsi: u32,
+    idcs: Vec<Idc>,
+}
+
+impl Domain {
+    fn new(hart_count: usize) -> Self {
+        Self {
+            domaincfg: 0,
+            sourcecfg: [0; APLIC_NUM_SOURCES + 1],
+            target: [0; APLIC_NUM_SOURCES + 1],
+            pending: 0,
+            enabled: 0,
+            genmsi: 0,
+            idcs: vec![Idc::default(); hart_count],
+        }
+    }
+
+    #[inline(always)]
+    fn msi_delivery(&self) -> bool {
+        self.domaincfg & DomainCfgFields::DM != 0
+    }
+
+    /// Mode of a source in this domain, delegated sources are inactive
+    #[inline(always)]
+    fn source_mode(&self, source: usize) -> u32 {
+        match self.sourcecfg[source] & SourceCfgFields::D {
+            0 => self.sourcecfg[source] & SourceCfgFields::SM,
+            _ => SourceCfgFields::INACTIVE,
+        }
+    }
+
+    /// Bitmap of the sources that are active in this domain
+    fn active_sources(&self) -> u64 {
+        (1..=APLIC_NUM_SOURCES)
+            .filter(|source| self.source_mode(*source) != SourceCfgFields::INACTIVE)
+            .fold(0, |active, source| active | 1 << source)
+    }
+
+    /// Rectified input of a source, the value of its raw input inverted for
+    /// the active low modes and zero for detached or inactive sources
+    fn rectified_input(&self, source: usize, inputs: u64) -> bool {
+        let input = (inputs >> source) & 1 == 1;
+        match self.source_mode(source) {
+            SourceCfgFields::EDGE1 | SourceCfgFields::LEVEL1 => input,
+            SourceCfgFields::EDGE0 | SourceCfgFields::LEVEL0 => !input,
+            _ => false,
+        }
+    }
+
+    /// Pending bit set by software, a level triggered source can only be set
+    /// pending in MSI delivery mode while its rectified input is high
+    fn set_pending(&mut self, source: usize, inputs: u64) {
+        if source == 0 || source > APLIC_NUM_SOURCES {
+            return;
+        }
+        let allowed = match self.source_mode(source) {
+            SourceCfgFields::DETACHED | SourceCfgFields::EDGE1 | SourceCfgFields::EDGE0 => true,
+            SourceCfgFields::LEVEL1 | SourceCfgFields::LEVEL0 => {
+                self.msi_delivery() && self.rectified_input(source, inputs)
+            }
+            _ => false,
+        };
+        if allowed {
+            self.pending |= 1 << source;
+        }
+    }
+
+    /// Pending bit cleared by software, the pending bit of a level triggered
+    /// source follows its input in direct delivery mode
+    fn clear_pending(&mut self, source: usize) {
+        if source == 0 || source > APLIC_NUM_SOURCES {
+            return;
+        }
+        let allowed = match self.source_mode(source) {
+            SourceCfgFields::LEVEL1 | SourceCfgFields::LEVEL0 => self.msi_delivery(),
+            _ => true,
+        };
+        if allowed {
+            self.pending &= !(1 << source);
+        }
+    }
+
+    fn set_enabled(&mut self, source: usize, enabled: bool) {
+        if source == 0 || source > APLIC_NUM_SOURCES {
+            return;
+        }
+        match enabled && self.source_mode(source) != SourceCfgFields::INACTIVE {
+            true => self.enabled |= 1 << source,
+            false => self.enabled &= !(1 << source),
+        }
+    }
+
+    /// Updates the pending bit after the raw input of a source changed
+    fn input_changed(&mut self, source: usize, old_inputs: u64, inputs: u64) {
+        let was_high = self.rectified_input(source, old_inputs);
+        let high = self.rectified_input(source, inputs);
+        match self.source_mode(source) {
+            SourceCfgFields::EDGE1 | SourceCfgFields::EDGE0 if high && !was_high => {
+                self.pending |= 1 << source
+            }
+            SourceCfgFields::LEVEL1 | SourceCfgFields::LEVEL0 if !high => {
+                self.pending &= !(1 << source)
+            }
+            SourceCfgFields::LEVEL1 | SourceCfgFields::LEVEL0
+                if !self.msi_delivery() || !was_high =>
+            {
+                self.pending |= 1 << source
+            }
+            _ => (),
+        }
+    }
+
+    /// Sets the mode of a non delegated source, a source that becomes inactive
+    /// loses its pending, enabled and target state
+    fn write_source_mode(&mut self, source: usize, mode: u32, inputs: u64) {
+        //The reserved modes are legalized to inactive
+        let mode = match mode {
+            SourceCfgFields::DETACHED
+            | SourceCfgFields::EDGE1
+            | SourceCfgFields::EDGE0
+            | SourceCfgFields::LEVEL1
+            | SourceCfgFields::LEVEL0 => mode,
+            _ => SourceCfgFields::INACTIVE,
+        };
+        self.sourcecfg[source] = mode;
+        if mode == SourceCfgFields::INACTIVE {
+            self.pending &= !(1 << source);
+            self.enabled &= !(1 << source);
+            self.target[source] = 0;
+        } else if matches!(mode, SourceCfgFields::LEVEL1 | SourceCfgFields::LEVEL0)
+            && !self.msi_delivery()
+        {
+            match self.rectified_input(source, inputs) {
+                true => self.pending |= 1 << source,
+                false => self.pending &= !(1 << source),
+            }
+        }
+    }
+
+    fn write_target(&mut self, source: usize, value: u32, hart_count: usize) {
+        if self.source_mode(source) == SourceCfgFields::INACTIVE {
+            return;
+        }
+        let hart = (value >> TargetFields::HART_INDEX_SHIFT) as usize;
+        let hart = match hart < hart_count {
+            true => hart as u32,
+            false => 0,
+        };
+        self.target[source] = match self.msi_delivery() {
+            true => (hart << TargetFields::HART_INDEX_SHIFT) | (value & TargetFields::EIID),
+            //A priority of zero is not valid and reads as one
+            false => {
+                (hart << TargetFields::HART_INDEX_SHIFT) | (value & TargetFields::IPRIO).max(1)
+            }
+        };
+    }
+
+    /// Highest priority pending and enabled source targeting a hart in direct
+    /// delivery mode, encoded as the topi register
+    fn topi(&self, hart: usize) -> u32 {
+        let idc = &self.idcs[hart];
+        let mut candidates = self.pending & self.enabled & self.active_sources();
+        let mut top: Option<(u32, u32)> = None;
+        while candidates != 0 {
+            let source = candidates.trailing_zeros();
+            candidates &= candidates - 1;
+            let target = self.target[source as usize];
+            let priority = target & TargetFields::IPRIO;
+            if (target >> TargetFields::HART_INDEX_SHIFT) as usize != hart
+                || (idc.ithreshold != 0 && priority >= idc.ithreshold)
+            {
+                continue;
+            }
+            //Ties are won by the lower source number, which is visited first
+            if top.is_none_or(|(_, top_priority)| priority < top_priority) {
+                top = Some((source, priority));
+            }
+        }
+        top.map_or(0, |(source, priority)| (source << 16) | priority)
+    }
+
+    /// The IDC of a hart asserts the external interrupt in direct delivery mode
+    fn interrupt_pending(&self, hart: usize) -> bool {
+        let idc = &self.idcs[hart];
+        self.domaincfg & DomainCfgFields::IE != 0
+            && !self.msi_delivery()
+            && idc.idelivery == 1
+            && (idc.iforce == 1 || self.topi(hart) != 0)
+    }
+
+    /// Reading claimi acknowledges the top interrupt, clearing its pending bit
+    /// or the forced interrupt if there is none
+    fn claim(&mut self, hart: usize, inputs: u64) -> u32 {
+        let topi = self.topi(hart);
+        match topi >> 16 {
+            0 => self.idcs[hart].iforce = 0,
+            source => {
+                let source = source as usize;
+                match self.source_mode(source) {
+                    SourceCfgFields::LEVEL1 | SourceCfgFields::LEVEL0 => {
+                        //The pending bit follows the input of level triggered sources
+                        if !self.rectified_input(source, inputs) {
+                            self.pending &= !(1 << source);
+                        }
+                    }
+                    _ => self.pending &= !(1 << source),
+                }
+            }
+        }
+        topi
+    }
+
+    fn load_idc(&mut self, hart: usize, offset: u64, inputs: u64) -> u32 {
+        let idc = self.idcs[hart];
+        match offset {
+            IdcRegisters::IDELIVERY => idc.idelivery,
+            IdcRegisters::IFORCE => idc.iforce,
+            IdcRegisters::ITHRESHOLD => idc.ithreshold,
+            IdcRegisters::TOPI => self.topi(hart),
+            IdcRegisters::CLAIMI => self.claim(hart, inputs),
+            _ => 0,
+        }
+    }
+
+    fn store_idc(&mut self, hart: usize, offset: u64, value: u32) {
+        let idc = &mut self.idcs[hart];
+        match offset {
+            IdcRegisters::IDELIVERY => idc.idelivery = value & 1,
+            IdcRegisters::IFORCE => idc.iforce = value & 1,
+            IdcRegisters::ITHRESHOLD => idc.ithreshold = value & TargetFields::IPRIO,
+            _ => (),
+        }
+    }
+
+    /// Reads one of the registers holding a bit for each of 32 sources
+    fn bits_register(bits: u64, index: u64) -> u32 {
+        match index {
+            0 | 1 => (bits >> (index * 32)) as u32,
+            _ => 0,
+        }
+    }
+
+    /// Sources selected by a write to one of the registers holding a bit for each of 32 sources
+    fn sources_of(index: u64, value: u32) -> impl Iterator<Item = usize> {
+        (0..32)
+            .filter(move |bit| (value >> bit) & 1 == 1)
+            .map(move |bit| (index * 32 + bit) as usize)
+    }
+}
+
+/// Advanced platform level interrupt controller with a machine level root
+/// domain and a supervisor level child domain. Sources are delegated to the
+/// child through the D bit of the root sourcecfg registers. In MSI delivery
+/// mode pending interrupts are forwarded to the IMSIC interrupt files.
+pub struct Aplic {
+    root: Domain,
+    child: Domain,
+    /// Raw level of the wired inputs
+    inputs: u64,
+    mmsiaddrcfg: u32,
+    mmsiaddrcfgh: u32,
+    smsiaddrcfg: u32,
+    smsiaddrcfgh: u32,
+    hart_count: usize,
+}
+
+impl Aplic {
+    pub fn new(hart_count: usize) -> Self {
+        //Guest index bits are not used, the hart index selects the interrupt file page
+        let lhxw = hart_count.next_power_of_two().trailing_zeros();
+        Self {
+            root: Domain::new(hart_count),
+            child: Domain::new(hart_count),
+            inputs: 0,
+            mmsiaddrcfg: (IMSIC_M_BASE_ADDR / PAGE_SIZE) as u32,
+            mmsiaddrcfgh: lhxw << MsiAddrCfgFields::LHXW_SHIFT,
+            smsiaddrcfg: (IMSIC_S_BASE_ADDR / PAGE_SIZE) as u32,
+            smsiaddrcfgh: 0,
+            hart_count,
+        }
+    }
+
+    fn domain(&self, domain: InterruptDomain) -> &Domain {
+        match domain {
+            InterruptDomain::Machine => &self.root,
+            InterruptDomain::Supervisor => &self.child,
+        }
+    }
+
+    fn domain_mut(&mut self, domain: InterruptDomain) -> &mut Domain {
+        match domain {
+            InterruptDomain::Machine => &mut self.root,
+            InterruptDomain::Supervisor => &mut self.child,
+        }
+    }
+
+    /// Domain that owns a source, sources not delegated to the child stay in the root
+    fn owner_of(&self, source: usize) -> InterruptDomain {
+        match self.root.sourcecfg[source] & SourceCfgFields::D {
+            0 => InterruptDomain::Machine,
+            _ => InterruptDomain::Supervisor,
+        }
+    }
+
+    /// Changes the level of a wired interrupt input and returns the MSIs that
+    /// have to be sent as a result
+    pub fn set_input(&mut self, source: usize, level: bool) -> Vec<Msi> {
+        if source == 0 || source > APLIC_NUM_SOURCES {
+            return Vec::new();
+        }
+        let old_inputs = self.inputs;
+        match level {
+            true => self.inputs |= 1 << source,
+            false => self.inputs &= !(1 << source),
+        }
+        let owner = self.owner_of(source);
+        let inputs = self.inputs;
+        self.domain_mut(owner)
+            .input_changed(source, old_inputs, inputs);
+        self.forward_msis()
+    }
+
+    pub fn load(&mut self, domain: InterruptDomain, offset: u64) -> u32 {
+        let inputs = self.inputs;
+        let machine = domain == InterruptDomain::Machine;
+        match offset {
+            AplicRegisters::MMSIADDRCFG if machine => self.mmsiaddrcfg,
+            AplicRegisters::MMSIADDRCFGH if machine => self.mmsiaddrcfgh,
+            AplicRegisters::SMSIADDRCFG if machine => self.smsiaddrcfg,
+            AplicRegisters::SMSIADDRCFGH if machine => self.smsiaddrcfgh,
+            _ => {
+                let hart_count = self.hart_count;
+                let domain = self.domain_mut(domain);
+                match offset {
+                    AplicRegisters::DOMAINCFG => domain.domaincfg | DomainCfgFields::READ_ONE,
+                    AplicRegisters::SOURCECFG1..=AplicRegisters::SOURCECFG1023 => domain
+                        .sourcecfg
+                        .get((offset / 4) as usize)
+                        .copied()
+                        .unwrap_or(0),
+                    AplicRegisters::SETIP0..=AplicRegisters::SETIP31 => {
+                        Domain::bits_register(domain.pending, (offset - AplicRegisters::SETIP0) / 4)
+                    }
+                    AplicRegisters::IN_CLRIP0..=AplicRegisters::IN_CLRIP31 => {
+                        let rectified = (1..=APLIC_NUM_SOURCES)
+                            .filter(|source| domain.rectified_input(*source, inputs))
+                            .fold(0, |rectified, source| rectified | 1 << source);
+                        Domain::bits_register(rectified, (offset - AplicRegisters::IN_CLRIP0) / 4)
+                    }
+                    AplicRegisters::SETIE0..=AplicRegisters::SETIE31 => {
+                        Domain::bits_register(domain.enabled, (offset - AplicRegisters::SETIE0) / 4)
+                    }
+                    AplicRegisters::GENMSI => domain.genmsi,
+                    AplicRegisters::TARGET1..=AplicRegisters::TARGET1023 => domain
+                        .target
+                        .get(((offset - AplicRegisters::GENMSI) / 4) as usize)
+                        .copied()
+                        .unwrap_or(0),
+                    _ if offset >= AplicRegisters::IDC0 => {
+                        let hart =
+                            ((offset - AplicRegisters::IDC0) / AplicRegisters::IDC_SIZE) as usize;
+                        match hart < hart_count {
+                            true => domain.load_idc(
+                                hart,
+                                (offset - AplicRegisters::IDC0) % AplicRegisters::IDC_SIZE,
+                                inputs,
+                            ),
+                            false => 0,
+                        }
+                    }
+                    //The setipnum, clripnum, setienum, clrienum and clrie registers are write-only
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    /// Writes a register of a domain and returns the MSIs that have to be sent
+    /// as a result
+    pub fn store(&mut self, domain: InterruptDomain, offset: u64, value: u32) -> Vec<Msi> {
+        let inputs = self.inputs;
+        let machine = domain == InterruptDomain::Machine;
+        let locked = self.mmsiaddrcfgh & MsiAddrCfgFields::L != 0;
+        match offset {
+            AplicRegisters::MMSIADDRCFG if machine && !locked => self.mmsiaddrcfg = value,
+            AplicRegisters::MMSIADDRCFGH if machine && !locked => {
+                self.mmsiaddrcfgh = value & MsiAddrCfgFields::MMSIADDRCFGH_MASK
+            }
+            AplicRegisters::SMSIADDRCFG if machine && !locked => self.smsiaddrcfg = value,
+            AplicRegisters::SMSIADDRCFGH if machine && !locked => {
+                self.smsiaddrcfgh = value & MsiAddrCfgFields::SMSIADDRCFGH_MASK
+            }
+            AplicRegisters::SOURCECFG1..=AplicRegisters::SOURCECFG1023 => {
+                self.write_sourcecfg(domain, (offset / 4) as usize, value)
+            }
+            AplicRegisters::GENMSI => {
+                let target =
+                    value & ((u32::MAX << TargetFields::HART_INDEX_SHIFT) | TargetFields::EIID);
+                self.domain_mut(domain).genmsi = target;
+                if self.domain(domain).msi_delivery() {
+                    return vec![self.msi(domain, target)];
+                }
+            }
+            _ => {
+                let hart_count = self.hart_count;
+                let domain = self.domain_mut(domain);
+                match offset {
+                    AplicRegisters::DOMAINCFG => {
+                        domain.domaincfg = value & DomainCfgFields::WRITE_MASK
+                    }
+                    AplicRegisters::SETIP0..=AplicRegisters::SETIP31 => {
+                        Domain::sources_of((offset - AplicRegisters::SETIP0) / 4, value)
+                            .for_each(|source| domain.set_pending(source, inputs))
+                    }
+                    AplicRegisters::SETIPNUM | AplicRegisters::SETIPNUM_LE => {
+                        domain.set_pending(value as usize, inputs)
+                    }
+                    AplicRegisters::SETIPNUM_BE => {
+                        domain.set_pending(value.swap_bytes() as usize, inputs)
+                    }
+                    AplicRegisters::IN_CLRIP0..=AplicRegisters::IN_CLRIP31 => {
+                        Domain::sources_of((offset - AplicRegisters::IN_CLRIP0) / 4, value)
+                            .for_each(|source| domain.clear_pending(source))
+                    }
+                    AplicRegisters::CLRIPNUM => domain.clear_pending(value as usize),
+                    AplicRegisters::SETIE0..=AplicRegisters::SETIE31 => {
+                        Domain::sources_of((offset - AplicRegisters::SETIE0) / 4, value)
+                            .for_each(|source| domain.set_enabled(source, true))
+                    }
+                    AplicRegisters::SETIENUM => domain.set_enabled(value as usize, true),
+                    AplicRegisters::CLRIE0..=AplicRegisters::CLRIE31 => {
+                        Domain::sources_of((offset - AplicRegisters::CLRIE0) / 4, value)
+                            .for_each(|source| domain.set_enabled(source, false))
+                    }
+                    AplicRegisters::CLRIENUM => domain.set_enabled(value as usize, false),
+                    AplicRegisters::TARGET1..=AplicRegisters::TARGET1023 => {
+                        let source = ((offset - AplicRegisters::GENMSI) / 4) as usize;
+                        if source <= APLIC_NUM_SOURCES {
+                            domain.write_target(source, value, hart_count);
+                        }
+                    }
+                    _ if offset >= AplicRegisters::IDC0 => {
+                        let hart =
+                            ((offset - AplicRegisters::IDC0) / AplicRegisters::IDC_SIZE) as usize;
+                        if hart < hart_count {
+                            domain.store_idc(
+                                hart,
+                                (offset - AplicRegisters::IDC0) % AplicRegisters::IDC_SIZE,
+                                value,
+                            );
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        self.forward_msis()
+    }
+
+    /// Only the root domain can delegate sources, changing the delegation of a
+    /// source resets its state in both domains
+    fn write_sourcecfg(&mut self, domain: InterruptDomain, source: usize, value: u32) {
+        if source > APLIC_NUM_SOURCES {
+            return;
+        }
+        let inputs = self.inputs;
+        match domain {
+            InterruptDomain::Machine if value & SourceCfgFields::D != 0 => {
+                //The child domain is the only one, with child index 0
+                if self.owner_of(source) == InterruptDomain::Machine {
+                    self.root
+                        .write_source_mode(source, SourceCfgFields::INACTIVE, inputs);
+                    self.root.sourcecfg[source] = SourceCfgFields::D;
+                }
+            }
+            InterruptDomain::Machine => {
+                if self.root.sourcecfg[source] & SourceCfgFields::D != 0 {
+                    self.child
+                        .write_source_mode(source, SourceCfgFields::INACTIVE, inputs);
+                }
+                self.root
+                    .write_source_mode(source, value & SourceCfgFields::SM, inputs);
+            }
+            InterruptDomain::Supervisor => {
+                if self.owner_of(source) == InterruptDomain::Supervisor {
+                    self.child
+                        .write_source_mode(source, value & SourceCfgFields::SM, inputs);
+                }
+            }
+        }
+    }
+
+    /// MSI for a target or genmsi register value, the address selects the
+    /// interrupt file of the target hart following the msiaddrcfg registers
+    fn msi(&self, domain: InterruptDomain, target: u32) -> Msi {
+        let field = |value: u32, shift: u32, mask: u32| ((value >> shift) & mask) as u64;
+        let hhxs = field(self.mmsiaddrcfgh, MsiAddrCfgFields::HHXS_SHIFT, 0x1f);
+        let hhxw = field(self.mmsiaddrcfgh, MsiAddrCfgFields::HHXW_SHIFT, 0b111);
+        let lhxw = field(self.mmsiaddrcfgh, MsiAddrCfgFields::LHXW_SHIFT, 0xf);
+        let (ppn_low, ppn_high, lhxs) = match domain {
+            InterruptDomain::Machine => (
+                self.mmsiaddrcfg,
+                self.mmsiaddrcfgh,
+                field(self.mmsiaddrcfgh, MsiAddrCfgFields::LHXS_SHIFT, 0b111),
+            ),
+            InterruptDomain::Supervisor => (
+                self.smsiaddrcfg,
+                self.smsiaddrcfgh,
+                field(self.smsiaddrcfgh, MsiAddrCfgFields::LHXS_SHIFT, 0b111),
+            ),
+        };
+        let ppn = ((ppn_high & MsiAddrCfgFields::PPN_HIGH) as u64) << 32 | ppn_low as u64;
+        let hart = (target >> TargetFields::HART_INDEX_SHIFT) as u64;
+        let group = (hart >> lhxw) & ((1 << hhxw) - 1);
+        let hart = hart & ((1 << lhxw) - 1);
+        Msi {
+            addr: (ppn | (group << (hhxs + 12)) | (hart << lhxs)) * PAGE_SIZE,
+            data: target & TargetFields::EIID,
+        }
+    }
+
+    /// Forwards the pending and enabled interrupts of the domains in MSI
+    /// delivery mode, clearing their pending bits
+    fn forward_msis(&mut self) -> Vec<Msi> {
+        let mut msis = Vec::new();
+        for domain in [InterruptDomain::Machine, InterruptDomain::Supervisor] {
+            let state = self.domain(domain);
+            if state.domaincfg & DomainCfgFields::IE == 0 || !state.msi_delivery() {
+                continue;
+            }
+            let mut forwarded = state.pending & state.enabled & state.active_sources();
+            while forwarded != 0 {
+                let source = forwarded.trailing_zeros() as usize;
+                forwarded &= forwarded - 1;
+                msis.push(self.msi(domain, self.domain(domain).target[source]));
+                self.domain_mut(domain).pending &= !(1 << source);
+            }
+        }
+        msis
+    }
+
+    /// The IDC of a hart asserts its external interrupt in a direct delivery domain
+    pub fn interrupt_pending(&self, hart: usize, domain: InterruptDomain) -> bool {
+        self.domain(domain).interrupt_pending(hart)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: usize = 3;
+
+    fn sourcecfg(source: usize) -> u64 {
+        AplicRegisters::SOURCECFG1 + (source as u64 - 1) * 4
+    }
+
+    fn target(source: usize) -> u64 {
+        AplicRegisters::TARGET1 + (source as u64 - 1) * 4
+    }
+
+    fn idc(hart: usize, register: u64) -> u64 {
+        AplicRegisters::IDC0 + hart as u64 * AplicRegisters::IDC_SIZE + register
+    }
+
+    /// APLIC with an enabled source of a domain targeting a hart
+    fn aplic(domain: InterruptDomain, mode: u32, domaincfg: u32, target_value: u32) -> Aplic {
+        let mut aplic = Aplic::new(2);
+        if domain == InterruptDomain::Supervisor {
+            aplic.store(
+                InterruptDomain::Machine,
+                sourcecfg(SOURCE),
+                SourceCfgFields::D,
+            );
+        }
+        aplic.store(domain, AplicRegisters::DOMAINCFG, domaincfg);
+        aplic.store(domain, sourcecfg(SOURCE), mode);
+        aplic.store(domain, target(SOURCE), target_value);
+        aplic.store(domain, AplicRegisters::SETIENUM, SOURCE as u32);
+        aplic
+    }
+
+    #[test]
+    fn direct_delivery_signals_the_target_hart() {
+        let hart_1 = 1 << TargetFields::HART_INDEX_SHIFT;
+        let mut aplic = aplic(
+            InterruptDomain::Machine,
+            SourceCfgFields::EDGE1,
+            DomainCfgFields::IE,
+            hart_1 | 2,
+        );
+        aplic.store(InterruptDomain::Machine, idc(1, IdcRegisters::IDELIVERY), 1);
+        assert!(aplic.set_input(SOURCE, true).is_empty());
+        assert!(aplic.interrupt_pending(1, InterruptDomain::Machine));
+        assert!(!aplic.interrupt_pending(0, InterruptDomain::Machine));
+        //A threshold at the priority of the source masks it
+        aplic.store(
+            InterruptDomain::Machine,
+            idc(1, IdcRegisters::ITHRESHOLD),
+            2,
+        );
+        assert!(!aplic.interrupt_pending(1, InterruptDomain::Machine));
+        aplic.store(
+            InterruptDomain::Machine,
+            idc(1, IdcRegisters::ITHRESHOLD),
+            0,
+        );
+        let topi = (SOURCE as u32) << 16 | 2;
+        assert_eq!(
+            aplic.load(InterruptDomain::Machine, idc(1, IdcRegisters::TOPI)),
+            topi
+        );
+        assert_eq!(
+            aplic.load(InterruptDomain::Machine, idc(1, IdcRegisters::CLAIMI)),
+            topi
+        );
+        assert!(!aplic.interrupt_pending(1, InterruptDomain::Machine));
+    }
+
+    #[test]
+    fn level_sources_follow_their_input() {
+        let mut aplic = aplic(
+            InterruptDomain::Machine,
+            SourceCfgFields::LEVEL0,
+            DomainCfgFields::IE,
+            1,
+        );
+        aplic.store(InterruptDomain::Machine, idc(0, IdcRegisters::IDELIVERY), 1);
+        //The input is low, an active low source is pending right away
+        assert!(aplic.interrupt_pending(0, InterruptDomain::Machine));
+        //Claiming doesn't clear the pending bit while the input is asserted
+        aplic.load(InterruptDomain::Machine, idc(0, IdcRegisters::CLAIMI));
+        assert!(aplic.interrupt_pending(0, InterruptDomain::Machine));
+        aplic.set_input(SOURCE, true);
+        assert!(!aplic.interrupt_pending(0, InterruptDomain::Machine));
+        //Software can't set a level source pending in direct delivery mode
+        aplic.store(
+            InterruptDomain::Machine,
+            AplicRegisters::SETIPNUM,
+            SOURCE as u32,
+        );
+        assert!(!aplic.interrupt_pending(0, InterruptDomain::Machine));
+    }
+
+    #[test]
+    fn msi_delivery_writes_the_interrupt_file_of_the_target() {
+        let hart_1 = 1 << TargetFields::HART_INDEX_SHIFT;
+        let mut aplic = aplic(
+            InterruptDomain::Machine,
+            SourceCfgFields::EDGE1,
+            DomainCfgFields::IE | DomainCfgFields::DM,
+            hart_1 | 7,
+        );
+        assert_eq!(
+            aplic.set_input(SOURCE, true),
+            vec![Msi {
+                addr: IMSIC_M_BASE_ADDR + PAGE_SIZE,
+                data: 7
+            }]
+        );
+        //The pending bit is cleared once the MSI is sent
+        assert_eq!(
+            aplic.load(InterruptDomain::Machine, AplicRegisters::SETIP0),
+            0
+        );
+        assert_eq!(
+            aplic.store(InterruptDomain::Machine, AplicRegisters::GENMSI, 9),
+            vec![Msi {
+                addr: IMSIC_M_BASE_ADDR,
+                data: 9
+            }]
+        );
+    }
+
+    #[test]
+    fn delegated_sources_belong_to_the_supervisor_domain() {
+        let mut aplic = aplic(
+            InterruptDomain::Supervisor,
+            SourceCfgFields::EDGE1,
+            DomainCfgFields::IE | DomainCfgFields::DM,
+            5,
+        );
+        assert_eq!(
+            aplic.load(InterruptDomain::Machine, sourcecfg(SOURCE)),
+            SourceCfgFields::D
+        );
+        assert_eq!(
+            aplic.load(InterruptDomain::Supervisor, sourcecfg(SOURCE)),
+            SourceCfgFields::EDGE1
+        );
+        assert_eq!(
+            aplic.set_input(SOURCE, true),
+            vec![Msi {
+                addr: IMSIC_S_BASE_ADDR,
+                data: 5
+            }]
+        );
+        //Taking the source back makes it inactive in the child domain
+        aplic.store(
+            InterruptDomain::Machine,
+            sourcecfg(SOURCE),
+            SourceCfgFields::EDGE1,
+        );
+        assert_eq!(
+            aplic.load(InterruptDomain::Supervisor, sourcecfg(SOURCE)),
+            0
+        );
+        assert_eq!(aplic.load(InterruptDomain::Supervisor, target(SOURCE)), 0);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{
+    consts::{IMSIC_M_BASE_ADDR, IMSIC_S_BASE_ADDR, PAGE_SIZE},
+    cpu::xlen::Xlen,
+};
+
+use super::InterruptDomain;
+
+/// Number of interrupt identities of each interrupt file, identity 0 is reserved
+pub const IMSIC_NUM_IDS: usize = 255;
+const EIP_WORDS: usize = (IMSIC_NUM_IDS + 1) / 64;
+/// eithreshold holds any identity between 0 and IMSIC_NUM_IDS
+const EITHRESHOLD_MASK: u64 = 0xff;
+
+/// Registers of an interrupt file accessed indirectly through miselect/siselect
+pub struct ImsicRegisters;
+impl ImsicRegisters {
+    pub const EIDELIVERY: u64 = 0x70;
+    pub const EITHRESHOLD: u64 = 0x72;
+    pub const EIP0: u64 = 0x80;
+    pub const EIP63: u64 = 0xbf;
+    pub const EIE0: u64 = 0xc0;
+    pub const EIE63: u64 = 0xff;
+}
+
+/// Memory mapped registers of the interrupt file pages
+struct ImsicMmioRegisters;
+impl ImsicMmioRegisters {
+    const SETEIPNUM_LE: u64 = 0x0;
+    const SETEIPNUM_BE: u64 = 0x4;
+}
+
+/// Interrupt file of a hart for one privilege level, pending identities with
+/// a lower number have higher priority
+#[derive(Default)]
+pub struct InterruptFile {
+    eidelivery: u64,
+    eithreshold: u64,
+    eip: [u64; EIP_WORDS],
+    eie: [u64; EIP_WORDS],
+}
+
+impl InterruptFile {
+    /// Marks an identity as pending, writes of unimplemented identities are ignored
+    pub fn set_pending(&mut self, identity: u32) {
+        let identity = identity as usize;
+        if identity != 0 && identity <= IMSIC_NUM_IDS {
+            self.eip[identity / 64] |= 1 << (identity % 64);
+        }
+    }
+
+    /// Highest priority pending and enabled identity below eithreshold, zero if none
+    pub fn top_identity(&self) -> u32 {
+        let pending = self.eip.iter().zip(self.eie.iter()).enumerate();
+        for (word, (eip, eie)) in pending {
+            if eip & eie != 0 {
+                let identity = (word * 64) as u64 + (eip & eie).trailing_zeros() as u64;
+                return match self.eithreshold != 0 && identity >= self.eithreshold {
+                    true => 0,
+                    false => identity as u32,
+                };
+            }
+        }
+        0
+    }
+
+    /// The external interrupt of the interrupt file is asserted while delivery
+    /// is enabled and there is a top identity
+    pub fn interrupt_pending(&self) -> bool {
+        self.eidelivery == 1 && self.top_identity() != 0
+    }
+
+    /// Value of the mtopei/stopei registers, the priority field equals the identity
+    pub fn topei(&self) -> u64 {
+        let identity = self.top_identity() as u64;
+        (identity << 16) | identity
+    }
+
+    /// Writing mtopei/stopei claims the top identity clearing its pending bit
+    pub fn claim_top(&mut self) {
+        let identity = self.top_identity() as usize;
+        self.eip[identity / 64] &= !(1 << (identity % 64));
+    }
+
+    /// On RV64 the odd eip/eie registers don't exist, the even ones hold 64 bits
+    pub fn is_register_implemented(select: u64, xlen: Xlen) -> bool {
+        match select {
+            ImsicRegisters::EIDELIVERY | ImsicRegisters::EITHRESHOLD => true,
+            ImsicRegisters::EIP0..=ImsicRegisters::EIE63 => {
+                xlen == Xlen::X32 || select.is_multiple_of(2)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn read_register(&self, select: u64, xlen: Xlen) -> u64 {
+        match select {
+            ImsicRegisters::EIDELIVERY => self.eidelivery,
+            ImsicRegisters::EITHRESHOLD => self.eithreshold,
+            ImsicRegisters::EIP0..=ImsicRegisters::EIP63 => {
+                Self::read_bits(&self.eip, select - ImsicRegisters::EIP0, xlen)
+            }
+            ImsicRegisters::EIE0..=ImsicRegisters::EIE63 => {
+                Self::read_bits(&self.eie, select - ImsicRegisters::EIE0, xlen)
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn write_register(&mut self, select: u64, value: u64, xlen: Xlen) {
+        match select {
+            //Delivery from an APLIC through the interrupt file is not supported
+            ImsicRegisters::EIDELIVERY => self.eidelivery = value & 1,
+            ImsicRegisters::EITHRESHOLD => self.eithreshold = value & EITHRESHOLD_MASK,
+            ImsicRegisters::EIP0..=ImsicRegisters::EIP63 => {
+                Self::write_bits(&mut self.eip, select - ImsicRegisters::EIP0, value, xlen)
+            }
+            ImsicRegisters::EIE0..=ImsicRegisters::EIE63 => {
+                Self::write_bits(&mut self.eie, select - ImsicRegisters::EIE0, value, xlen)
+            }
+            _ => (),
+        }
+    }
+
+    /// Reads the eip/eie register `index`, each register holds 32 identities
+    /// and the bits of unimplemented identities are read-only zero
+    fn read_bits(bits: &[u64; EIP_WORDS], index: u64, xlen: Xlen) -> u64 {
+        let first = index as usize * 32;
+        match (bits.get(first / 64), xlen) {
+            (None, _) => 0,
+            (Some(word), Xlen::X32) => (word >> (first % 64)) & 0xffff_ffff,
+            (Some(word), Xlen::X64) => *word,
+        }
+    }
+
+    fn write_bits(bits: &mut [u64; EIP_WORDS], index: u64, value: u64, xlen: Xlen) {
+        let first = index as usize * 32;
+        let Some(word) = bits.get_mut(first / 64) else {
+            return;
+        };
+        *word = match xlen {
+            Xlen::X32 => {
+                let shift = first % 64;
+                (*word & !(0xffff_ffff << shift)) | ((value & 0xffff_ffff) << shift)
+            }
+            Xlen::X64 => value,
+        };
+        //Identity 0 is not a valid interrupt
+        bits[0] &= !1;
+    }
+}
+
+/// Machine and supervisor level interrupt files of a hart
+#[derive(Default)]
+pub struct ImsicHart {
+    pub machine: InterruptFile,
+    pub supervisor: InterruptFile,
+}
+
+/// Incoming MSI controller, each hart has one interrupt file per privilege
+/// level mapped in its own page so MSIs can be written to it
+pub struct Imsic {
+    harts: Vec<ImsicHart>,
+}
+
+impl Imsic {
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            harts: (0..hart_count).map(|_| ImsicHart::default()).collect(),
+        }
+    }
+
+    pub fn file(&self, hart: usize, domain: InterruptDomain) -> &InterruptFile {
+        match domain {
+            InterruptDomain::Machine => &self.harts[hart].machine,
+            InterruptDomain::Supervisor => &self.harts[hart].supervisor,
+        }
+    }
+
+    pub fn file_mut(&mut self, hart: usize, domain: InterruptDomain) -> &mut InterruptFile {
+        match domain {
+            InterruptDomain::Machine => &mut self.harts[hart].machine,
+            InterruptDomain::Supervisor => &mut self.harts[hart].supervisor,
+        }
+    }
+
+    /// Size of the region holding the interrupt files of a privilege level
+    pub fn region_size(&self) -> u64 {
+        self.harts.len() as u64 * PAGE_SIZE
+    }
+
+    /// Privilege level, hart and page offset of an address in the interrupt file regions
+    fn locate(&self, addr: u64) -> Option<(InterruptDomain, usize, u64)> {
+        let (domain, offset) =
+            if (IMSIC_M_BASE_ADDR..IMSIC_M_BASE_ADDR + self.region_size()).contains(&addr) {
+                (InterruptDomain::Machine, addr - IMSIC_M_BASE_ADDR)
+            } else if (IMSIC_S_BASE_ADDR..IMSIC_S_BASE_ADDR + self.region_size()).contains(&addr) {
+                (InterruptDomain::Supervisor, addr - IMSIC_S_BASE_ADDR)
+            } else {
+                return None;
+            };
+        Some((domain, (offset / PAGE_SIZE) as usize, offset % PAGE_SIZE))
+    }
+
+    /// The seteipnum registers are write-only, reads return zero
+    pub fn load(&self, _addr: u64) -> u32 {
+        0
+    }
+
+    pub fn store(&mut self, addr: u64, value: u32) {
+        let Some((domain, hart, offset)) = self.locate(addr) else {
+            return;
+        };
+        let file = self.file_mut(hart, domain);
+        match offset {
+            ImsicMmioRegisters::SETEIPNUM_LE => file.set_pending(value),
+            ImsicMmioRegisters::SETEIPNUM_BE => file.set_pending(value.swap_bytes()),
+            _ => (),
+        }
+    }
+
+    /// The interrupt file of a hart asserts its external interrupt
+    pub fn interrupt_pending(&self, hart: usize, domain: InterruptDomain) -> bool {
+        self.file(hart, domain).interrupt_pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_identity_is_the_lowest_enabled_one_below_the_threshold() {
+        let mut file = InterruptFile::default();
+        file.write_register(ImsicRegisters::EIDELIVERY, 1, Xlen::X64);
+        file.set_pending(70);
+        file.set_pending(9);
+        assert!(!file.interrupt_pending());
+        file.write_register(ImsicRegisters::EIE0 + 2, 1 << (70 - 64), Xlen::X64);
+        file.write_register(ImsicRegisters::EIE0, 1 << 9, Xlen::X64);
+        assert_eq!(file.topei(), 9 << 16 | 9);
+        file.write_register(ImsicRegisters::EITHRESHOLD, 9, Xlen::X64);
+        assert_eq!(file.top_identity(), 0);
+        file.write_register(ImsicRegisters::EITHRESHOLD, 0, Xlen::X64);
+        file.claim_top();
+        assert_eq!(file.top_identity(), 70);
+        file.write_register(ImsicRegisters::EIDELIVERY, 0, Xlen::X64);
+        assert!(!file.interrupt_pending());
+    }
+
+    #[test]
+    fn eip_registers_hold_32_identities_on_rv32() {
+        let mut file = InterruptFile::default();
+        assert!(InterruptFile::is_register_implemented(
+            ImsicRegisters::EIP0 + 1,
+            Xlen::X32
+        ));
+        assert!(!InterruptFile::is_register_implemented(
+            ImsicRegisters::EIP0 + 1,
+            Xlen::X64
+        ));
+        file.write_register(ImsicRegisters::EIP0 + 1, 1, Xlen::X32);
+        assert_eq!(file.read_register(ImsicRegisters::EIP0, Xlen::X64), 1 << 32);
+        //Identity 0 is read-only zero
+        file.write_register(ImsicRegisters::EIP0, u64::MAX, Xlen::X32);
+        assert_eq!(
+            file.read_register(ImsicRegisters::EIP0, Xlen::X32),
+            !1 & 0xffff_ffff
+        );
+        file.set_pending(0);
+        file.set_pending(IMSIC_NUM_IDS as u32 + 1);
+        assert_eq!(file.read_register(ImsicRegisters::EIP0 + 6, Xlen::X64), 0);
+    }
+
+    #[test]
+    fn msis_select_the_file_of_the_hart_and_level() {
+        let mut imsic = Imsic::new(2);
+        imsic.store(IMSIC_S_BASE_ADDR + PAGE_SIZE, 12);
+        imsic.store(
+            IMSIC_M_BASE_ADDR + ImsicMmioRegisters::SETEIPNUM_BE,
+            5_u32.swap_bytes(),
+        );
+        let pending = |imsic: &Imsic, hart, domain| {
+            imsic
+                .file(hart, domain)
+                .read_register(ImsicRegisters::EIP0, Xlen::X64)
+        };
+        assert_eq!(pending(&imsic, 1, InterruptDomain::Supervisor), 1 << 12);
+        assert_eq!(pending(&imsic, 0, InterruptDomain::Machine), 1 << 5);
+        assert_eq!(pending(&imsic, 0, InterruptDomain::Supervisor), 0);
+        assert_eq!(pending(&imsic, 1, InterruptDomain::Machine), 0);
+    }
+}
@@ -0,0 +1,23 @@
+//! Advanced Interrupt Architecture, wired interrupts are collected by the APLIC
+//! and either signaled directly to the harts or forwarded as MSIs to the
+//! interrupt files of the IMSIC
+
+mod aplic;
+mod imsic;
+
+pub use aplic::{Aplic, APLIC_NUM_SOURCES};
+pub use imsic::{Imsic, InterruptFile, IMSIC_NUM_IDS};
+
+/// Privilege level of an APLIC interrupt domain or an IMSIC interrupt file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptDomain {
+    Machine,
+    Supervisor,
+}
+
+/// Message signaled interrupt, a 32 bit write of `data` to `addr`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Msi {
+    pub addr: u64,
+    pub data: u32,
+}
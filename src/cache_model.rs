@@ -0,0 +1,100 @@
+/// Cache related operations a hart can perform, either explicitly managing a
+/// cache block (Zicbom/Zicboz) or hinting a future access (Zicbop)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheBlockOperation {
+    Clean,
+    Flush,
+    Invalidate,
+    Zero,
+    PrefetchInstruction,
+    PrefetchRead,
+    PrefetchWrite,
+}
+
+impl CacheBlockOperation {
+    pub const ALL: [CacheBlockOperation; 7] = [
+        CacheBlockOperation::Clean,
+        CacheBlockOperation::Flush,
+        CacheBlockOperation::Invalidate,
+        CacheBlockOperation::Zero,
+        CacheBlockOperation::PrefetchInstruction,
+        CacheBlockOperation::PrefetchRead,
+        CacheBlockOperation::PrefetchWrite,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheBlockOperation::Clean => "cbo.clean",
+            CacheBlockOperation::Flush => "cbo.flush",
+            CacheBlockOperation::Invalidate => "cbo.inval",
+            CacheBlockOperation::Zero => "cbo.zero",
+            CacheBlockOperation::PrefetchInstruction => "prefetch.i",
+            CacheBlockOperation::PrefetchRead => "prefetch.r",
+            CacheBlockOperation::PrefetchWrite => "prefetch.w",
+        }
+    }
+}
+
+/// Hook for cache models that want to observe the cache block operations
+/// executed by the cpu. The emulator memory is always coherent, so these
+/// operations have no effect on it besides cbo.zero clearing the block.
+pub trait CacheModel {
+    fn observe(&mut self, operation: CacheBlockOperation, addr: u64);
+}
+
+/// Counts the operations of each kind executed by a hart, the counts are
+/// printed when the hart stops
+pub struct OperationCounter {
+    counts: [u64; CacheBlockOperation::ALL.len()],
+    /// Hart named in the heading of the counts, None with a single hart
+    hart: Option<usize>,
+}
+
+impl OperationCounter {
+    pub fn new(hart: Option<usize>) -> Self {
+        Self {
+            counts: [0; CacheBlockOperation::ALL.len()],
+            hart,
+        }
+    }
+
+    pub fn count(&self, operation: CacheBlockOperation) -> u64 {
+        self.counts[operation as usize]
+    }
+}
+
+impl CacheModel for OperationCounter {
+    fn observe(&mut self, operation: CacheBlockOperation, _addr: u64) {
+        self.counts[operation as usize] += 1;
+    }
+}
+
+impl Drop for OperationCounter {
+    fn drop(&mut self) {
+        let mut output = match self.hart {
+            Some(hart) => format!("hart {hart} cache block operations:\n"),
+            None => String::from("cache block operations:\n"),
+        };
+        for operation in CacheBlockOperation::ALL {
+            output += &format!("{:>10}: {:>12}\n", operation.name(), self.count(operation));
+        }
+        //A single write so the counts of the harts don't interleave
+        print!("{output}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_counts_each_operation() {
+        let mut counter = OperationCounter::new(None);
+        counter.observe(CacheBlockOperation::Zero, 0x8000_0000);
+        counter.observe(CacheBlockOperation::Zero, 0x8000_0040);
+        counter.observe(CacheBlockOperation::PrefetchRead, 0x8000_0000);
+        assert_eq!(counter.count(CacheBlockOperation::Zero), 2);
+        assert_eq!(counter.count(CacheBlockOperation::PrefetchRead), 1);
+        assert_eq!(counter.count(CacheBlockOperation::Flush), 0);
+    }
+}
@@ -1,4 +1,29 @@
 pub const BYTES_IN_MEGABYTE: u64 = 1024 * 1024;
 
 pub const DRAM_BASE_ADDR: u64 = 0x8000_0000_u64;
-pub const DRAM_SIZE: u64 = 128 * BYTES_IN_MEGABYTE;
\ No newline at end of file
+pub const DRAM_SIZE: u64 = 128 * BYTES_IN_MEGABYTE;
+
+pub const PAGE_SIZE: u64 = 4096;
+pub const DEFAULT_CACHE_BLOCK_SIZE: u64 = 64;
+pub const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+pub const DEFAULT_PMP_ENTRIES: usize = 16;
+
+/// Memory map of the AIA interrupt controllers, interrupt files take one page per hart
+pub const APLIC_M_BASE_ADDR: u64 = 0x0c00_0000;
+pub const APLIC_S_BASE_ADDR: u64 = 0x0d00_0000;
+pub const APLIC_SIZE: u64 = 0x8000;
+pub const IMSIC_M_BASE_ADDR: u64 = 0x2400_0000;
+pub const IMSIC_S_BASE_ADDR: u64 = 0x2800_0000;
+/// ACLINT machine level software interrupt device, one MSIP register per hart
+pub const ACLINT_MSWI_BASE_ADDR: u64 = 0x0200_0000;
+pub const ACLINT_MSWI_SIZE: u64 = 0x4000;
+/// ACLINT machine level timer device, one mtimecmp register per hart and mtime
+pub const ACLINT_MTIMER_BASE_ADDR: u64 = 0x0200_4000;
+pub const ACLINT_MTIMER_SIZE: u64 = 0x8000;
+/// SiFive test finisher, writing it powers the machine off
+pub const FINISHER_BASE_ADDR: u64 = 0x0010_0000;
+pub const FINISHER_SIZE: u64 = 0x1000;
+/// Reservation set of LR, a WRS waits for a store to the reserved granule
+pub const RESERVATION_GRANULE: u64 = 64;
+/// Stack space below the device tree given to each hart at boot
+pub const BOOT_STACK_SIZE: u64 = 0x10000;
@@ -0,0 +1,579 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    consts::PAGE_SIZE,
+    error::{AppErrors, AppResult},
+};
+
+#[cfg(feature = "jit")]
+use super::jit::{CompiledBlock, JIT_THRESHOLD};
+use super::{
+    instruction_excecutors::DecodedInstruction,
+    instructions::{decoder, implementations::CpuInstructionsOpCodes},
+    pmp::MemoryAccess,
+    privilege::PrivilegeMode,
+    side_effects::OperationSideEffect,
+    Cpu,
+};
+
+/// Longest run of instructions translated into a single block
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+
+/// How the main loop executes the guest code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionEngine {
+    /// Fetches and executes one instruction per step
+    Interpreter,
+    /// Executes basic blocks of pre-decoded instructions chained to their successors
+    BasicBlocks,
+    /// Basic blocks with the body of the hot blocks compiled to host code
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+impl ExecutionEngine {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "interpreter" => Some(ExecutionEngine::Interpreter),
+            "blocks" => Some(ExecutionEngine::BasicBlocks),
+            #[cfg(feature = "jit")]
+            "jit" => Some(ExecutionEngine::Jit),
+            _ => None,
+        }
+    }
+}
+
+/// Direct jump from the end of a block to the block of the next program counter,
+/// skipping the address translation and the block lookup
+#[derive(Clone, Copy)]
+struct BlockLink {
+    pc: u64,
+    privilege_mode: PrivilegeMode,
+    epoch: u64,
+    block: usize,
+}
+
+/// Straight-line instructions of a page ending at the first control transfer,
+/// system or fence instruction
+struct BasicBlock {
+    /// Physical address of the first instruction
+    addr: u64,
+    instructions: Rc<[DecodedInstruction]>,
+    /// The taken and fall through successors
+    links: [Option<BlockLink>; 2],
+    #[cfg(feature = "jit")]
+    executions: u32,
+    /// Host code of the instructions before the last one
+    #[cfg(feature = "jit")]
+    compiled: Option<CompiledBlock>,
+}
+
+/// Translated blocks indexed by the physical address of their first instruction
+pub(crate) struct BlockCache {
+    blocks: Vec<Option<BasicBlock>>,
+    free_slots: Vec<usize>,
+    by_addr: HashMap<u64, usize>,
+    /// Blocks starting in each RAM page
+    by_page: HashMap<usize, Vec<usize>>,
+    /// Links made in an older epoch are not followed, it changes when code is
+    /// invalidated and after system instructions that can change the address
+    /// translation (satp, PMP, SFENCE.VMA, ...)
+    pub(super) epoch: u64,
+    /// Block executed last, its end is linked to the next block
+    last: Option<usize>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            free_slots: Vec::new(),
+            by_addr: HashMap::new(),
+            by_page: HashMap::new(),
+            epoch: 0,
+            last: None,
+        }
+    }
+
+    fn insert(&mut self, page: usize, block: BasicBlock) -> usize {
+        let addr = block.addr;
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                self.blocks[index] = Some(block);
+                index
+            }
+            None => {
+                self.blocks.push(Some(block));
+                self.blocks.len() - 1
+            }
+        };
+        self.by_addr.insert(addr, index);
+        self.by_page.entry(page).or_default().push(index);
+        index
+    }
+
+    #[inline(always)]
+    fn block(&self, index: usize) -> &BasicBlock {
+        self.blocks[index].as_ref().expect("Block slot is free")
+    }
+
+    /// Follows the link of the last block to the block of `pc`
+    #[inline(always)]
+    fn linked(&self, last: usize, pc: u64, privilege_mode: PrivilegeMode) -> Option<usize> {
+        self.block(last)
+            .links
+            .iter()
+            .flatten()
+            .find(|link| {
+                link.pc == pc && link.privilege_mode == privilege_mode && link.epoch == self.epoch
+            })
+            .map(|link| link.block)
+    }
+
+    fn link(&mut self, last: usize, pc: u64, privilege_mode: PrivilegeMode, block: usize) {
+        let link = BlockLink {
+            pc,
+            privilege_mode,
+            epoch: self.epoch,
+            block,
+        };
+        let epoch = self.epoch;
+        let links = &mut self.blocks[last]
+            .as_mut()
+            .expect("Block slot is free")
+            .links;
+        //Reuse a stale slot before replacing the oldest link
+        match links
+            .iter_mut()
+            .find(|slot| slot.is_none_or(|slot| slot.epoch != epoch))
+        {
+            Some(slot) => *slot = Some(link),
+            None => *links = [links[1], Some(link)],
+        }
+    }
+
+    /// Drops the blocks starting in a RAM page
+    pub fn invalidate_page(&mut self, page: usize) {
+        let Some(indexes) = self.by_page.remove(&page) else {
+            return;
+        };
+        for index in indexes {
+            if let Some(block) = self.blocks[index].take() {
+                self.by_addr.remove(&block.addr);
+            }
+            self.free_slots.push(index);
+        }
+        self.drop_links();
+    }
+
+    /// Drops a single block, its slot is reused by the next block
+    fn remove(&mut self, index: usize) {
+        let Some(block) = self.blocks[index].take() else {
+            return;
+        };
+        self.by_addr.remove(&block.addr);
+        for indexes in self.by_page.values_mut() {
+            indexes.retain(|other| *other != index);
+        }
+        self.free_slots.push(index);
+        self.drop_links();
+    }
+
+    /// Stops following the links made so far, for address translation changes
+    pub fn drop_links(&mut self) {
+        self.epoch += 1;
+        self.last = None;
+    }
+
+    /// Drops the compiled code of every block, they are compiled again once hot
+    #[cfg(feature = "jit")]
+    pub fn forget_compiled(&mut self) {
+        for block in self.blocks.iter_mut().flatten() {
+            block.executions = 0;
+            block.compiled = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.free_slots.clear();
+        self.by_addr.clear();
+        self.by_page.clear();
+        self.drop_links();
+    }
+}
+
+/// Control transfers, system instructions and fences end a block
+fn ends_block(instruction: &DecodedInstruction) -> bool {
+    matches!(
+        decoder::get_op_code(instruction.raw()),
+        CpuInstructionsOpCodes::CONDITIONAL_BRANCHES
+            | CpuInstructionsOpCodes::CONTROL_JAL
+            | CpuInstructionsOpCodes::CONTROL_JALR
+            | CpuInstructionsOpCodes::SYSCALLS_CSR
+            | CpuInstructionsOpCodes::MEM_ORDERING
+    )
+}
+
+impl Cpu {
+    /// Drops the decoded instructions and blocks of the code pages written
+    /// since the last call, and the store TLB mappings once the stores to
+    /// another page are tracked
+    #[inline(always)]
+    pub fn invalidate_written_code(&mut self) {
+        if self.system_bus.take_new_tracked_pages() {
+            self.tlb.flush_stores();
+        }
+        if let Some(pages) = self.system_bus.take_written_code_pages() {
+            for page in pages {
+                self.instruction_cache.invalidate_page(page);
+                self.blocks.invalidate_page(page);
+            }
+        }
+    }
+
+    /// Drops every decoded instruction, on FENCE.I
+    pub fn invalidate_all_code(&mut self) {
+        self.instruction_cache.clear();
+        self.blocks.clear();
+    }
+
+    /// Executes the next instruction, or the next basic block with the block
+    /// engines. Exceptions are taken, the errors that can't be handled by the
+    /// guest are returned.
+    pub fn step(&mut self) -> AppResult<OperationSideEffect> {
+        if self.config.engine != ExecutionEngine::Interpreter {
+            return self.run_block();
+        }
+        self.take_pending_interrupt();
+        self.step_instruction()
+    }
+
+    /// Executes the instruction at the program counter, the pending interrupts
+    /// are left to the caller
+    pub fn step_instruction(&mut self) -> AppResult<OperationSideEffect> {
+        match self.fetch_next_instruction() {
+            Ok(instruction) => self.execute(instruction),
+            Err(err) => self
+                .trap_on_exception(err)
+                .map(|_| OperationSideEffect::None),
+        }
+    }
+
+    /// Executes the basic block at the program counter. Pending interrupts are
+    /// only taken between blocks, while a trigger is enabled a single
+    /// instruction is executed so every instruction is matched.
+    pub fn run_block(&mut self) -> AppResult<OperationSideEffect> {
+        self.invalidate_written_code();
+        self.take_pending_interrupt();
+        if self.triggers_armed() {
+            self.blocks.last = None;
+            return self.step_instruction();
+        }
+        let index = match self.next_block() {
+            Ok(index) => index,
+            Err(err) => {
+                self.blocks.last = None;
+                return self
+                    .trap_on_exception(err)
+                    .map(|_| OperationSideEffect::None);
+            }
+        };
+        let epoch = self.blocks.epoch;
+        let instructions = Rc::clone(&self.blocks.block(index).instructions);
+        let result = self.execute_block(index, &instructions);
+        //FENCE.I may have dropped the block while it was running
+        if self.blocks.epoch == epoch {
+            self.blocks.last = Some(index);
+        }
+        //The translation can't be trusted across a system instruction
+        if instructions.last().is_some_and(|instruction| {
+            decoder::get_op_code(instruction.raw()) == CpuInstructionsOpCodes::SYSCALLS_CSR
+        }) {
+            self.blocks.epoch += 1;
+        }
+        result
+    }
+
+    /// Returns the block at the program counter, following the link of the last
+    /// block or translating a new block
+    fn next_block(&mut self) -> AppResult<usize> {
+        let pc = self.program_counter;
+        let privilege_mode = self.privilege_mode;
+        let mut last = self.blocks.last.take();
+        if let Some(index) = last.and_then(|last| self.blocks.linked(last, pc, privilege_mode)) {
+            return Ok(index);
+        }
+        let addr = self.translate_address(pc, 4, MemoryAccess::Fetch)?;
+        //Device regions are not executable
+        if self.system_bus.is_mmio(addr) {
+            return Err(AppErrors::InstructionAccessFault { addr: pc });
+        }
+        let index = match self.blocks.by_addr.get(&addr).copied() {
+            Some(index) if self.is_block_executable(index) => index,
+            //The block was translated with other PMP permissions or privilege,
+            //it may be the last block which can't be linked once removed
+            Some(index) => {
+                self.blocks.remove(index);
+                last = None;
+                self.translate_block(addr)?
+            }
+            None => self.translate_block(addr)?,
+        };
+        if let Some(last) = last {
+            self.blocks.link(last, pc, privilege_mode, index);
+        }
+        Ok(index)
+    }
+
+    /// Whether the PMP still allows to fetch every instruction of a block, the
+    /// first one is checked by the translation of the program counter
+    fn is_block_executable(&self, index: usize) -> bool {
+        let block = self.blocks.block(index);
+        let size = block.instructions.len() as u64 * 4;
+        self.check_pmp(block.addr, size, MemoryAccess::Fetch)
+            .is_ok()
+            || (4..size).step_by(4).all(|offset| {
+                self.check_pmp(block.addr + offset, 4, MemoryAccess::Fetch)
+                    .is_ok()
+            })
+    }
+
+    /// Decodes the instructions from `addr` up to the end of the block, blocks
+    /// never cross a page so they have a single translation
+    fn translate_block(&mut self, addr: u64) -> AppResult<usize> {
+        let page_end = (addr | (PAGE_SIZE - 1)) + 1;
+        let mut instructions = Vec::new();
+        let mut instruction_addr = addr;
+        loop {
+            let raw_instruction = self.system_bus.load32(instruction_addr)?;
+            let instruction = self.decode(raw_instruction);
+            instructions.push(instruction);
+            instruction_addr += 4;
+            //The block ends before an instruction the PMP doesn't allow to
+            //fetch, so it faults once it is reached
+            if ends_block(&instruction)
+                || instruction_addr == page_end
+                || instructions.len() == MAX_BLOCK_INSTRUCTIONS
+                || self
+                    .check_pmp(instruction_addr, 4, MemoryAccess::Fetch)
+                    .is_err()
+            {
+                break;
+            }
+        }
+        self.mark_code_page(addr);
+        let page = self
+            .system_bus
+            .page_index(addr)
+            .expect("Blocks are translated from RAM");
+        Ok(self.blocks.insert(
+            page,
+            BasicBlock {
+                addr,
+                instructions: instructions.into(),
+                links: [None; 2],
+                #[cfg(feature = "jit")]
+                executions: 0,
+                #[cfg(feature = "jit")]
+                compiled: None,
+            },
+        ))
+    }
+
+    /// Runs the instructions of a block, only the last one can transfer control
+    /// so the others just advance the program counter. An exception ends the
+    /// block at the faulting instruction.
+    fn execute_block(
+        &mut self,
+        index: usize,
+        instructions: &[DecodedInstruction],
+    ) -> AppResult<OperationSideEffect> {
+        let start = self.program_counter;
+        let privilege_mode = self.privilege_mode;
+        let (last, body) = instructions.split_last().expect("Empty block");
+        #[cfg(feature = "jit")]
+        if let Some(compiled) = self.hot_block(index, body) {
+            return self.execute_compiled(compiled, body.len(), *last);
+        }
+        #[cfg(not(feature = "jit"))]
+        let _ = index;
+        for (index, instruction) in body.iter().enumerate() {
+            //Kept up to date for AUIPC and for the trap value of exceptions
+            self.program_counter = start + 4 * index as u64;
+            self.trace(instruction.raw());
+            let result = instruction.handler()(self, instruction.decoder);
+            self.update_counters(result.is_ok(), privilege_mode);
+            if let Err(err) = result {
+                self.trap_on_exception(err)?;
+                self.program_counter = self.effective_address(self.program_counter);
+                return Ok(OperationSideEffect::None);
+            }
+            //The rest of the block may have been overwritten
+            if self.system_bus.has_written_code_pages() {
+                self.program_counter = start + 4 * (index as u64 + 1);
+                return Ok(OperationSideEffect::None);
+            }
+        }
+        self.program_counter = start + 4 * body.len() as u64;
+        self.execute(*last)
+    }
+
+    /// Counts the executions of a block and compiles its body once it is hot
+    #[cfg(feature = "jit")]
+    fn hot_block(&mut self, index: usize, body: &[DecodedInstruction]) -> Option<CompiledBlock> {
+        if body.is_empty() || !self.can_run_compiled() {
+            return None;
+        }
+        let block = self.blocks.blocks[index]
+            .as_mut()
+            .expect("Block slot is free");
+        if block.executions == JIT_THRESHOLD {
+            return block.compiled;
+        }
+        block.executions += 1;
+        if block.executions == JIT_THRESHOLD {
+            let compiled = self.compile_block(body);
+            //Compiling may have reset the other blocks and this one
+            let block = self.blocks.blocks[index]
+                .as_mut()
+                .expect("Block slot is free");
+            block.executions = JIT_THRESHOLD;
+            block.compiled = compiled;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{config::CpuConfig, test_hart},
+    };
+
+    //addi x5, x5, 1
+    const INCREMENT: u32 = 0x0012_8293;
+    //blt x5, x6, -4
+    const LOOP: u32 = 0xfe62_cee3;
+    //jal x0, 0
+    const HALT: u32 = 0x0000_006f;
+
+    /// Hart running the basic block engine with a program at the start of the RAM
+    fn cpu(program: &[u32]) -> Cpu {
+        test_hart::cpu_with_program(
+            CpuConfig {
+                engine: ExecutionEngine::BasicBlocks,
+                ..CpuConfig::default()
+            },
+            program,
+        )
+    }
+
+    #[test]
+    fn blocks_are_translated_once_and_chained() {
+        let mut cpu = cpu(&[INCREMENT, LOOP, HALT]);
+        cpu.registers[6] = 10;
+        for _ in 0..10 {
+            cpu.run_block().unwrap();
+        }
+        assert_eq!(cpu.registers[5], 10);
+        assert_eq!(cpu.program_counter, DRAM_BASE_ADDR + 8);
+        assert_eq!(cpu.blocks.by_addr.len(), 1);
+        let looping = cpu.blocks.by_addr[&DRAM_BASE_ADDR];
+        assert_eq!(
+            cpu.blocks
+                .linked(looping, DRAM_BASE_ADDR, PrivilegeMode::Machine),
+            Some(looping)
+        );
+        //Links are not followed once dropped, nor for another privilege mode
+        assert_eq!(
+            cpu.blocks
+                .linked(looping, DRAM_BASE_ADDR, PrivilegeMode::Supervisor),
+            None
+        );
+        cpu.blocks.drop_links();
+        assert_eq!(
+            cpu.blocks
+                .linked(looping, DRAM_BASE_ADDR, PrivilegeMode::Machine),
+            None
+        );
+    }
+
+    #[test]
+    fn stores_to_the_running_block_end_it() {
+        //sw x7, 8(x10) replaces addi x6, x0, 1 by addi x6, x0, 2
+        let mut cpu = cpu(&[0x0075_2423, INCREMENT, 0x0010_0313, HALT]);
+        cpu.registers[7] = 0x0020_0313;
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        cpu.run_block().unwrap();
+        assert_eq!(cpu.program_counter, DRAM_BASE_ADDR + 4);
+        assert_eq!(cpu.registers[5], 0);
+        //The page is translated again from the next instruction
+        cpu.run_block().unwrap();
+        assert_eq!(cpu.registers[5], 1);
+        assert_eq!(cpu.registers[6], 2);
+        assert!(!cpu.blocks.by_addr.contains_key(&DRAM_BASE_ADDR));
+        assert!(cpu.blocks.by_addr.contains_key(&(DRAM_BASE_ADDR + 4)));
+    }
+
+    #[test]
+    fn fence_i_drops_every_block() {
+        //fence.i
+        let mut cpu = cpu(&[INCREMENT, 0x0000_100f, HALT]);
+        cpu.run_block().unwrap();
+        assert_eq!(cpu.program_counter, DRAM_BASE_ADDR + 8);
+        assert!(cpu.blocks.by_addr.is_empty());
+        assert!(cpu.blocks.blocks.is_empty());
+        cpu.run_block().unwrap();
+        assert_eq!(cpu.blocks.by_addr.len(), 1);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn compiled_code_is_freed_past_the_limit() {
+        let program = [
+            INCREMENT,
+            INCREMENT,
+            //blt x5, x6, -8
+            0xfe62_cce3,
+            //addi x7, x7, 1
+            0x0013_8393,
+            0x0013_8393,
+            //blt x7, x6, -8
+            0xfe63_cce3,
+            HALT,
+        ];
+        let mut cpu = test_hart::cpu_with_program(
+            CpuConfig {
+                engine: ExecutionEngine::Jit,
+                ..CpuConfig::default()
+            },
+            &program,
+        );
+        cpu.registers[6] = 100;
+        let compiled = |cpu: &Cpu, addr| {
+            cpu.blocks
+                .by_addr
+                .get(&addr)
+                .is_some_and(|index| cpu.blocks.block(*index).compiled.is_some())
+        };
+        while !compiled(&cpu, DRAM_BASE_ADDR) {
+            cpu.run_block().unwrap();
+        }
+        //The second loop can only be compiled once the first one is freed
+        let jit = cpu.jit.as_mut().unwrap();
+        let first_size = jit.code_size;
+        jit.code_limit = first_size;
+        while cpu.program_counter != DRAM_BASE_ADDR + 24 {
+            cpu.run_block().unwrap();
+        }
+        assert!(!compiled(&cpu, DRAM_BASE_ADDR));
+        assert!(compiled(&cpu, DRAM_BASE_ADDR + 12));
+        let block = cpu.blocks.by_addr[&DRAM_BASE_ADDR];
+        assert_eq!(cpu.blocks.block(block).executions, 0);
+        assert!(cpu.jit.as_ref().unwrap().code_size <= first_size);
+        assert_eq!((cpu.registers[5], cpu.registers[7]), (100, 100));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    consts::{DEFAULT_CACHE_BLOCK_SIZE, DEFAULT_PMP_ENTRIES, DRAM_BASE_ADDR, DRAM_SIZE},
+    memory::RamRegion,
+};
+
+use super::{
+    basic_blocks::ExecutionEngine,
+    extensions::{BaseIsa, IsaProfile},
+    misaligned::MisalignedAccessPolicy,
+    xlen::Xlen,
+};
+
+/// Values of the read-only machine information CSRs
+#[derive(Clone, Debug, Default)]
+pub struct MachineIds {
+    /// JEDEC manufacturer ID reported in mvendorid, 0 for a non-commercial implementation
+    pub vendor: u64,
+    /// Microarchitecture ID reported in marchid
+    pub architecture: u64,
+    /// Implementation version reported in mimpid
+    pub implementation: u64,
+    /// Address of the configuration data structure reported in mconfigptr
+    pub config_pointer: u64,
+}
+
+/// Machine parameters that can be tuned from the command line
+#[derive(Clone, Debug)]
+pub struct CpuConfig {
+    /// Size in bytes of the cache blocks operated on by the Zicbom, Zicboz
+    /// and Zicbop instructions, advertised to the guest in the device tree
+    pub cache_block_size: u64,
+    /// Counts the cache block operations of each hart, printed when the hart stops
+    pub cache_statistics: bool,
+    /// Profile selecting the set of enabled extensions
+    pub profile: IsaProfile,
+    /// Machine XLEN reported in misa.MXL, S and U modes can be narrowed to
+    /// 32 bits through mstatus.SXL/UXL on a 64-bit machine
+    pub xlen: Xlen,
+    /// Base integer ISA, the E base restricts the register file to x0-x15
+    pub base: BaseIsa,
+    /// Number of implemented PMP entries, 0, 16 or 64
+    pub pmp_entries: usize,
+    /// PMP granularity G, the smallest protected region is 2^(G+2) bytes
+    pub pmp_granularity: u32,
+    /// Raise a page fault on leaf entries with a non-zero PBMT field while
+    /// Svpbmt is disabled, otherwise the memory type is ignored
+    pub strict_pbmt: bool,
+    /// Handling of misaligned loads and stores
+    pub misaligned_access: MisalignedAccessPolicy,
+    /// Identification of the machine reported to the guest
+    pub machine_ids: MachineIds,
+    /// Interpreter, basic block engine or JIT
+    pub engine: ExecutionEngine,
+    /// Number of harts of the machine, by default each one runs on its own host thread
+    pub harts: usize,
+    /// RAM regions of the physical address space, one of them starts at the
+    /// system memory base address and holds the boot image
+    pub memory: Vec<RamRegion>,
+    /// Runs a reference hart with the interpreter next to the JIT and stops
+    /// at the first block after which their states differ
+    #[cfg(feature = "jit")]
+    pub lockstep: bool,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self {
+            cache_block_size: DEFAULT_CACHE_BLOCK_SIZE,
+            cache_statistics: false,
+            profile: IsaProfile::Max,
+            xlen: Xlen::X64,
+            base: BaseIsa::I,
+            pmp_entries: DEFAULT_PMP_ENTRIES,
+            pmp_granularity: 0,
+            strict_pbmt: false,
+            misaligned_access: MisalignedAccessPolicy::Emulate,
+            machine_ids: MachineIds::default(),
+            engine: ExecutionEngine::Interpreter,
+            harts: 1,
+            memory: vec![RamRegion {
+                base: DRAM_BASE_ADDR,
+                size: DRAM_SIZE,
+                file: None,
+                snapshot: false,
+                overwrite: false,
+            }],
+            #[cfg(feature = "jit")]
+            lockstep: false,
+        }
+    }
+}
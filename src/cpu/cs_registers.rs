@@ -1,11 +1,19 @@
-use std::usize;
+use crate::error::AppErrors;
 
-use super::Cpu;
+use super::{
+    extensions::Extension, hypervisor::HEDELEG_WRITE_MASK, interrupts::InterruptFields,
+    privilege::PrivilegeMode, softfloat::ExceptionFlags, xlen::Xlen, Cpu,
+};
+
+/// Ticks of the time counter between two synchronizations with the other harts
+const TIME_SYNC_INTERVAL: u64 = 1024;
 
 pub struct MachineLevelCSRegisters;
 impl MachineLevelCSRegisters {
     /// Machine status register.
     pub const MSTATUS: usize = 0x300;
+    /// ISA and extensions.
+    pub const MISA: usize = 0x301;
     /// Machine exception delefation register.
     pub const MEDELEG: usize = 0x302;
     /// Machine interrupt delefation register.
@@ -14,6 +22,41 @@ impl MachineLevelCSRegisters {
     pub const MIE: usize = 0x304;
     /// Machine trap-handler base address.
     pub const MTVEC: usize = 0x305;
+    /// Machine counter enable.
+    pub const MCOUNTEREN: usize = 0x306;
+    /// Machine virtual interrupt enables (Smaia).
+    pub const MVIEN: usize = 0x308;
+    /// Machine virtual interrupt-pending bits (Smaia).
+    pub const MVIP: usize = 0x309;
+    /// Machine environment configuration register.
+    pub const MENVCFG: usize = 0x30a;
+    /// Machine state enable registers (Smstateen).
+    pub const MSTATEEN0: usize = 0x30c;
+    pub const MSTATEEN3: usize = 0x30f;
+    /// Upper 32 bits of mstatus, RV32 only.
+    pub const MSTATUSH: usize = 0x310;
+    /// Upper 32 bits of mideleg, RV32 only (Smaia).
+    pub const MIDELEGH: usize = 0x313;
+    /// Upper 32 bits of mie, RV32 only (Smaia).
+    pub const MIEH: usize = 0x314;
+    /// Upper 32 bits of mvien, RV32 only (Smaia).
+    pub const MVIENH: usize = 0x318;
+    /// Upper 32 bits of mvip, RV32 only (Smaia).
+    pub const MVIPH: usize = 0x319;
+    /// Upper 32 bits of menvcfg, RV32 only.
+    pub const MENVCFGH: usize = 0x31a;
+    /// Upper 32 bits of mstateen0-3, RV32 only (Smstateen).
+    pub const MSTATEEN0H: usize = 0x31c;
+    pub const MSTATEEN3H: usize = 0x31f;
+    /// Machine cycle counter privilege mode filtering (Smcntrpmf).
+    pub const MCYCLECFG: usize = 0x321;
+    /// Machine instret counter privilege mode filtering (Smcntrpmf).
+    pub const MINSTRETCFG: usize = 0x322;
+    /// Machine performance-monitoring event selectors, read-only zero.
+    pub const MHPMEVENT3: usize = 0x323;
+    pub const MHPMEVENT31: usize = 0x33f;
+    /// Machine scratch register.
+    pub const MSCRATCH: usize = 0x340;
     /// Machine exception program counter.
     pub const MEPC: usize = 0x341;
     /// Machine trap cause.
@@ -22,6 +65,67 @@ impl MachineLevelCSRegisters {
     pub const MTVAL: usize = 0x343;
     /// Machine interrupt pending.
     pub const MIP: usize = 0x344;
+    /// Machine trap instruction, always written with zero (H).
+    pub const MTINST: usize = 0x34a;
+    /// Machine second trap value, guest physical address shifted right by 2 (H).
+    pub const MTVAL2: usize = 0x34b;
+    /// Machine indirect register select (Smaia).
+    pub const MISELECT: usize = 0x350;
+    /// Machine indirect register alias (Smaia).
+    pub const MIREG: usize = 0x351;
+    /// Upper 32 bits of mip, RV32 only (Smaia).
+    pub const MIPH: usize = 0x354;
+    /// Machine top external interrupt (Smaia).
+    pub const MTOPEI: usize = 0x35c;
+    /// Physical memory protection configuration, odd registers are RV32 only.
+    pub const PMPCFG0: usize = 0x3a0;
+    pub const PMPCFG15: usize = 0x3af;
+    /// Physical memory protection address registers.
+    pub const PMPADDR0: usize = 0x3b0;
+    pub const PMPADDR63: usize = 0x3ef;
+    /// Debug trigger select (Sdtrig).
+    pub const TSELECT: usize = 0x7a0;
+    /// Debug trigger data registers of the selected trigger (Sdtrig).
+    pub const TDATA1: usize = 0x7a1;
+    pub const TDATA2: usize = 0x7a2;
+    /// Debug trigger information (Sdtrig).
+    pub const TINFO: usize = 0x7a4;
+    /// Debug trigger control (Sdtrig).
+    pub const TCONTROL: usize = 0x7a5;
+    /// Upper 32 bits of mcyclecfg, RV32 only (Smcntrpmf).
+    pub const MCYCLECFGH: usize = 0x721;
+    /// Upper 32 bits of minstretcfg, RV32 only (Smcntrpmf).
+    pub const MINSTRETCFGH: usize = 0x722;
+    /// Machine security configuration register (Smepmp).
+    pub const MSECCFG: usize = 0x747;
+    /// Upper 32 bits of mseccfg, RV32 only.
+    pub const MSECCFGH: usize = 0x757;
+    /// Machine cycle counter.
+    pub const MCYCLE: usize = 0xb00;
+    /// Machine instructions-retired counter.
+    pub const MINSTRET: usize = 0xb02;
+    /// Machine performance-monitoring counters, read-only zero.
+    pub const MHPMCOUNTER3: usize = 0xb03;
+    pub const MHPMCOUNTER31: usize = 0xb1f;
+    /// Upper 32 bits of mcycle, RV32 only.
+    pub const MCYCLEH: usize = 0xb80;
+    /// Upper 32 bits of minstret, RV32 only.
+    pub const MINSTRETH: usize = 0xb82;
+    /// Upper 32 bits of mhpmcounter3-31, RV32 only.
+    pub const MHPMCOUNTER3H: usize = 0xb83;
+    pub const MHPMCOUNTER31H: usize = 0xb9f;
+    /// Vendor ID.
+    pub const MVENDORID: usize = 0xf11;
+    /// Architecture ID.
+    pub const MARCHID: usize = 0xf12;
+    /// Implementation ID.
+    pub const MIMPID: usize = 0xf13;
+    /// Hardware thread ID.
+    pub const MHARTID: usize = 0xf14;
+    /// Pointer to the configuration data structure.
+    pub const MCONFIGPTR: usize = 0xf15;
+    /// Machine top interrupt (Smaia).
+    pub const MTOPI: usize = 0xfb0;
 }
 
 pub struct SupervisorLevelCSRegisters;
@@ -30,8 +134,19 @@ impl SupervisorLevelCSRegisters {
     pub const SSTATUS: usize = 0x100;
     /// Supervisor interrupt-enable register.
     pub const SIE: usize = 0x104;
+    /// Upper 32 bits of sie, RV32 only (Ssaia).
+    pub const SIEH: usize = 0x114;
     /// Supervisor trap handler base address.
     pub const STVEC: usize = 0x105;
+    /// Supervisor counter enable.
+    pub const SCOUNTEREN: usize = 0x106;
+    /// Supervisor environment configuration register.
+    pub const SENVCFG: usize = 0x10a;
+    /// Supervisor state enable registers (Smstateen).
+    pub const SSTATEEN0: usize = 0x10c;
+    pub const SSTATEEN3: usize = 0x10f;
+    /// Supervisor scratch register.
+    pub const SSCRATCH: usize = 0x140;
     /// Supervisor exception program counter.
     pub const SEPC: usize = 0x141;
     /// Supervisor trap cause.
@@ -40,30 +155,1109 @@ impl SupervisorLevelCSRegisters {
     pub const STVAL: usize = 0x143;
     /// Supervisor interrupt pending.
     pub const SIP: usize = 0x144;
+    /// Supervisor timer compare (Sstc).
+    pub const STIMECMP: usize = 0x14d;
+    /// Supervisor indirect register select (Ssaia).
+    pub const SISELECT: usize = 0x150;
+    /// Supervisor indirect register alias (Ssaia).
+    pub const SIREG: usize = 0x151;
+    /// Upper 32 bits of sip, RV32 only (Ssaia).
+    pub const SIPH: usize = 0x154;
+    /// Supervisor top external interrupt (Ssaia).
+    pub const STOPEI: usize = 0x15c;
+    /// Upper 32 bits of stimecmp, RV32 only (Sstc).
+    pub const STIMECMPH: usize = 0x15d;
     /// Supervisor address translation and protection.
     pub const SATP: usize = 0x180;
+    /// Supervisor resource management configuration (Ssqosid).
+    pub const SRMCFG: usize = 0x181;
+    /// Supervisor top interrupt (Ssaia).
+    pub const STOPI: usize = 0xdb0;
+}
+
+pub struct HypervisorLevelCSRegisters;
+impl HypervisorLevelCSRegisters {
+    /// Virtual supervisor status register.
+    pub const VSSTATUS: usize = 0x200;
+    /// Virtual supervisor interrupt-enable register.
+    pub const VSIE: usize = 0x204;
+    /// Virtual supervisor trap handler base address.
+    pub const VSTVEC: usize = 0x205;
+    /// Virtual supervisor scratch register.
+    pub const VSSCRATCH: usize = 0x240;
+    /// Virtual supervisor exception program counter.
+    pub const VSEPC: usize = 0x241;
+    /// Virtual supervisor trap cause.
+    pub const VSCAUSE: usize = 0x242;
+    /// Virtual supervisor bad address or instruction.
+    pub const VSTVAL: usize = 0x243;
+    /// Virtual supervisor interrupt pending.
+    pub const VSIP: usize = 0x244;
+    /// Virtual supervisor address translation and protection.
+    pub const VSATP: usize = 0x280;
+    /// Hypervisor status register.
+    pub const HSTATUS: usize = 0x600;
+    /// Hypervisor exception delegation register.
+    pub const HEDELEG: usize = 0x602;
+    /// Hypervisor interrupt delegation register.
+    pub const HIDELEG: usize = 0x603;
+    /// Hypervisor interrupt-enable register.
+    pub const HIE: usize = 0x604;
+    /// Delta for the time counter read in VS and VU modes.
+    pub const HTIMEDELTA: usize = 0x605;
+    /// Hypervisor counter enable.
+    pub const HCOUNTEREN: usize = 0x606;
+    /// Hypervisor guest external interrupt-enable register.
+    pub const HGEIE: usize = 0x607;
+    /// Hypervisor environment configuration register.
+    pub const HENVCFG: usize = 0x60a;
+    /// Upper 32 bits of hedeleg, RV32 only.
+    pub const HEDELEGH: usize = 0x612;
+    /// Upper 32 bits of htimedelta, RV32 only.
+    pub const HTIMEDELTAH: usize = 0x615;
+    /// Upper 32 bits of henvcfg, RV32 only.
+    pub const HENVCFGH: usize = 0x61a;
+    /// Hypervisor bad guest physical address, shifted right by 2.
+    pub const HTVAL: usize = 0x643;
+    /// Hypervisor interrupt pending.
+    pub const HIP: usize = 0x644;
+    /// Hypervisor virtual interrupt pending.
+    pub const HVIP: usize = 0x645;
+    /// Hypervisor trap instruction, always written with zero.
+    pub const HTINST: usize = 0x64a;
+    /// Hypervisor guest address translation and protection.
+    pub const HGATP: usize = 0x680;
+    /// Hypervisor guest external interrupt pending.
+    pub const HGEIP: usize = 0xe12;
+}
+
+pub struct UserLevelCSRegisters;
+impl UserLevelCSRegisters {
+    /// Floating point accrued exceptions, view of fcsr (F).
+    pub const FFLAGS: usize = 0x001;
+    /// Floating point dynamic rounding mode, view of fcsr (F).
+    pub const FRM: usize = 0x002;
+    /// Floating point control and status register (F).
+    pub const FCSR: usize = 0x003;
+    /// Cycle counter, read-only shadow of mcycle.
+    pub const CYCLE: usize = 0xc00;
+    /// Real time counter, advances with the cycle counter.
+    pub const TIME: usize = 0xc01;
+    /// Instructions-retired counter, read-only shadow of minstret.
+    pub const INSTRET: usize = 0xc02;
+    /// Upper 32 bits of cycle, RV32 only.
+    pub const CYCLEH: usize = 0xc80;
+    /// Upper 32 bits of time, RV32 only.
+    pub const TIMEH: usize = 0xc81;
+    /// Upper 32 bits of instret, RV32 only.
+    pub const INSTRETH: usize = 0xc82;
+}
+
+/// Bit fields of the mstatus register, sstatus is a restricted view of it
+pub struct MStatusFields;
+impl MStatusFields {
+    pub const SIE: u64 = 1 << 1;
+    pub const MIE: u64 = 1 << 3;
+    pub const SPIE: u64 = 1 << 5;
+    pub const MPIE: u64 = 1 << 7;
+    pub const SPP: u64 = 1 << 8;
+    pub const MPP_SHIFT: u64 = 11;
+    pub const MPP: u64 = 0b11 << Self::MPP_SHIFT;
+    /// Floating point unit state, Off, Initial, Clean or Dirty
+    pub const FS_SHIFT: u64 = 13;
+    pub const FS: u64 = 0b11 << Self::FS_SHIFT;
+    pub const FS_DIRTY: u64 = 0b11 << Self::FS_SHIFT;
+    /// Modify privilege, loads and stores execute with the privilege held in MPP
+    pub const MPRV: u64 = 1 << 17;
+    /// Permit supervisor user memory access
+    pub const SUM: u64 = 1 << 18;
+    /// Make executable readable
+    pub const MXR: u64 = 1 << 19;
+    /// Trap virtual memory
+    pub const TVM: u64 = 1 << 20;
+    /// Timeout wait
+    pub const TW: u64 = 1 << 21;
+    /// Trap SRET
+    pub const TSR: u64 = 1 << 22;
+    pub const UXL_SHIFT: u64 = 32;
+    pub const UXL: u64 = 0b11 << Self::UXL_SHIFT;
+    pub const SXL_SHIFT: u64 = 34;
+    pub const SXL: u64 = 0b11 << Self::SXL_SHIFT;
+    /// Guest virtual address, the trap value written by the last trap into
+    /// M-mode is a guest virtual address (H)
+    pub const GVA: u64 = 1 << 38;
+    /// Previous virtualization mode, restored by MRET (H)
+    pub const MPV: u64 = 1 << 39;
+
+    /// Fields that can be modified by writing mstatus
+    pub const MSTATUS_WRITE_MASK: u64 = Self::SIE
+        | Self::MIE
+        | Self::SPIE
+        | Self::MPIE
+        | Self::SPP
+        | Self::MPP
+        | Self::MPRV
+        | Self::SUM
+        | Self::MXR
+        | Self::TVM
+        | Self::TW
+        | Self::TSR;
+    /// Fields visible through sstatus
+    pub const SSTATUS_READ_MASK: u64 =
+        Self::SIE | Self::SPIE | Self::SPP | Self::FS | Self::SUM | Self::MXR | Self::UXL;
+    /// Fields that can be modified by writing sstatus
+    pub const SSTATUS_WRITE_MASK: u64 = Self::SIE | Self::SPIE | Self::SPP | Self::SUM | Self::MXR;
+}
+
+/// Bit fields of the hstatus register
+pub struct HStatusFields;
+impl HStatusFields {
+    /// Guest virtual address, the trap value written by the last trap into
+    /// HS-mode is a guest virtual address
+    pub const GVA: u64 = 1 << 6;
+    /// Supervisor previous virtualization mode, restored by SRET
+    pub const SPV: u64 = 1 << 7;
+    /// Supervisor previous virtual privilege, the privilege of HLV and HSV
+    pub const SPVP: u64 = 1 << 8;
+    /// HLV and HSV are allowed in U-mode
+    pub const HU: u64 = 1 << 9;
+    /// Trap virtual memory, satp accesses and SFENCE.VMA of VS-mode
+    pub const VTVM: u64 = 1 << 20;
+    /// Timeout wait of VS-mode
+    pub const VTW: u64 = 1 << 21;
+    /// Trap SRET of VS-mode
+    pub const VTSR: u64 = 1 << 22;
+    /// XLEN of VS-mode, same position as mstatus.UXL
+    pub const VSXL_SHIFT: u64 = 32;
+
+    /// VSBE is read-only zero and VGEIN too as there are no guest external
+    /// interrupt files
+    pub const WRITE_MASK: u64 =
+        Self::GVA | Self::SPV | Self::SPVP | Self::HU | Self::VTVM | Self::VTW | Self::VTSR;
+}
+
+/// Fields of the fcsr register, fflags and frm are views of it
+pub struct FloatCsrFields;
+impl FloatCsrFields {
+    pub const FFLAGS: u64 = ExceptionFlags::ALL;
+    pub const FRM_SHIFT: u64 = 5;
+    pub const FRM: u64 = 0b111 << Self::FRM_SHIFT;
+}
+
+/// Bits of the mcounteren and scounteren registers
+pub struct CounterEnableFields;
+impl CounterEnableFields {
+    pub const CY: u64 = 1 << 0;
+    pub const TM: u64 = 1 << 1;
+    pub const IR: u64 = 1 << 2;
+
+    /// Only the cycle, time and instret counters are implemented
+    pub const WRITE_MASK: u64 = Self::CY | Self::TM | Self::IR;
+}
+
+/// Bit fields shared by the menvcfg and senvcfg registers
+pub struct EnvCfgFields;
+impl EnvCfgFields {
+    /// Cache block invalidate instruction enable, 2 bit field
+    pub const CBIE_SHIFT: u64 = 4;
+    pub const CBIE: u64 = 0b11 << Self::CBIE_SHIFT;
+    /// CBIE value making cbo.inval perform a flush
+    pub const CBIE_FLUSH: u64 = 0b01;
+    /// CBIE value making cbo.inval perform an invalidation
+    pub const CBIE_INVALIDATE: u64 = 0b11;
+    /// Cache block clean and flush instructions enable
+    pub const CBCFE: u64 = 1 << 6;
+    /// Cache block zero instruction enable
+    pub const CBZE: u64 = 1 << 7;
+
+    /// Page-based memory types enable, menvcfg only (Svpbmt)
+    pub const PBMTE: u64 = 1 << 62;
+    /// Supervisor timer compare enable, menvcfg only (Sstc)
+    pub const STCE: u64 = 1 << 63;
+
+    pub const WRITE_MASK: u64 = Self::CBIE | Self::CBCFE | Self::CBZE;
+}
+
+/// Bits of the mstateen registers, only mstateen0 controls state other than
+/// the matching sstateen register
+pub struct StateEnableFields;
+impl StateEnableFields {
+    /// Resource management configuration, srmcfg (Ssqosid)
+    pub const SRMCFG: u64 = 1 << 55;
+    /// IMSIC state, stopei (Ssaia)
+    pub const IMSIC: u64 = 1 << 58;
+    /// AIA state not controlled by the CSRIND and IMSIC bits (Ssaia)
+    pub const AIA: u64 = 1 << 59;
+    /// Indirect register access, siselect and sireg (Ssaia)
+    pub const CSRIND: u64 = 1 << 60;
+    /// Environment configuration, senvcfg
+    pub const ENVCFG: u64 = 1 << 62;
+    /// State enable, the matching sstateen register
+    pub const SE0: u64 = 1 << 63;
+}
+
+/// Bits of the mcyclecfg and minstretcfg registers, counting is inhibited
+/// while the hart runs in a mode whose bit is set
+pub struct CounterConfigFields;
+impl CounterConfigFields {
+    pub const UINH: u64 = 1 << 60;
+    pub const SINH: u64 = 1 << 61;
+    pub const MINH: u64 = 1 << 62;
+
+    /// The overflow bit is read-only zero without Sscofpmf
+    pub const WRITE_MASK: u64 = Self::UINH | Self::SINH | Self::MINH;
+
+    pub fn inhibit(privilege_mode: PrivilegeMode) -> u64 {
+        match privilege_mode {
+            PrivilegeMode::Machine => Self::MINH,
+            PrivilegeMode::Supervisor => Self::SINH,
+            PrivilegeMode::User => Self::UINH,
+        }
+    }
+}
+
+/// Fields of the srmcfg register
+pub struct ResourceConfigFields;
+impl ResourceConfigFields {
+    /// Resource control ID
+    pub const RCID: u64 = 0xfff;
+    /// Monitoring counter ID
+    pub const MCID: u64 = 0xfff << 16;
+
+    pub const WRITE_MASK: u64 = Self::RCID | Self::MCID;
 }
 
 impl Cpu {
-    fn load_csr(&self, addr: usize) -> u64 {
+    pub fn load_csr(&self, addr: usize) -> u64 {
         match addr {
+            MachineLevelCSRegisters::MSTATUS => {
+                self.cs_registers[MachineLevelCSRegisters::MSTATUS] | self.state_dirty_bit()
+            }
+            SupervisorLevelCSRegisters::SSTATUS => {
+                (self.cs_registers[MachineLevelCSRegisters::MSTATUS]
+                    & MStatusFields::SSTATUS_READ_MASK)
+                    | self.state_dirty_bit()
+            }
+            HypervisorLevelCSRegisters::VSSTATUS => {
+                (self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS]
+                    & MStatusFields::SSTATUS_READ_MASK)
+                    | self.virtual_state_dirty_bit()
+            }
+            UserLevelCSRegisters::FFLAGS => {
+                self.cs_registers[UserLevelCSRegisters::FCSR] & FloatCsrFields::FFLAGS
+            }
+            UserLevelCSRegisters::FRM => {
+                (self.cs_registers[UserLevelCSRegisters::FCSR] & FloatCsrFields::FRM)
+                    >> FloatCsrFields::FRM_SHIFT
+            }
+            //The VS-level interrupts delegated to HS-mode are only visible in hie
             SupervisorLevelCSRegisters::SIE => {
                 self.cs_registers[MachineLevelCSRegisters::MIE]
                     & self.cs_registers[MachineLevelCSRegisters::MIDELEG]
+                    & InterruptFields::MIDELEG_WRITE_MASK
             }
+            MachineLevelCSRegisters::MIP
+            | SupervisorLevelCSRegisters::SIP
+            | MachineLevelCSRegisters::MVIEN
+            | MachineLevelCSRegisters::MVIP
+            | MachineLevelCSRegisters::MIREG
+            | SupervisorLevelCSRegisters::SIREG
+            | MachineLevelCSRegisters::MTOPEI
+            | SupervisorLevelCSRegisters::STOPEI
+            | MachineLevelCSRegisters::MTOPI
+            | SupervisorLevelCSRegisters::STOPI
+            | HypervisorLevelCSRegisters::HIE
+            | HypervisorLevelCSRegisters::HIP
+            | HypervisorLevelCSRegisters::VSIE
+            | HypervisorLevelCSRegisters::VSIP => self.load_interrupt_csr(addr),
+            UserLevelCSRegisters::CYCLE => self.cs_registers[MachineLevelCSRegisters::MCYCLE],
+            //VS and VU modes see the time counter shifted by htimedelta
+            UserLevelCSRegisters::TIME if self.virtualization => self.cs_registers
+                [UserLevelCSRegisters::TIME]
+                .wrapping_add(self.cs_registers[HypervisorLevelCSRegisters::HTIMEDELTA]),
+            UserLevelCSRegisters::INSTRET => self.cs_registers[MachineLevelCSRegisters::MINSTRET],
+            MachineLevelCSRegisters::PMPCFG0..=MachineLevelCSRegisters::PMPADDR63
+            | MachineLevelCSRegisters::MSECCFG => self.load_pmp_csr(addr),
+            MachineLevelCSRegisters::TSELECT..=MachineLevelCSRegisters::TCONTROL => {
+                self.load_trigger_csr(addr)
+            }
+            MachineLevelCSRegisters::MVENDORID => self.config.machine_ids.vendor,
+            MachineLevelCSRegisters::MARCHID => self.config.machine_ids.architecture,
+            MachineLevelCSRegisters::MIMPID => self.config.machine_ids.implementation,
+            MachineLevelCSRegisters::MHARTID => self.hart_id as u64,
+            MachineLevelCSRegisters::MCONFIGPTR => self.config.machine_ids.config_pointer,
             _ => self.cs_registers[addr],
         }
     }
 
-    fn store_csr(&mut self, addr: usize, value: u64) {
+    pub fn store_csr(&mut self, addr: usize, value: u64) {
         match addr {
+            MachineLevelCSRegisters::MSTATUS => {
+                let mut value = value;
+                //MPP is WARL, the reserved 0b10 encoding is legalized to U-mode
+                if (value & MStatusFields::MPP) >> MStatusFields::MPP_SHIFT == 0b10 {
+                    value &= !MStatusFields::MPP;
+                }
+                let write_mask = MStatusFields::MSTATUS_WRITE_MASK
+                    | self.float_state_field()
+                    | self.hypervisor_status_fields()
+                    | self.writable_xl_fields(value, MStatusFields::UXL | MStatusFields::SXL);
+                self.cs_registers[addr] =
+                    (self.cs_registers[addr] & !write_mask) | (value & write_mask);
+                self.update_xlen();
+            }
+            SupervisorLevelCSRegisters::SSTATUS => {
+                let write_mask = MStatusFields::SSTATUS_WRITE_MASK
+                    | self.float_state_field()
+                    | self.writable_xl_fields(value, MStatusFields::UXL);
+                let mstatus = &mut self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+                *mstatus = (*mstatus & !write_mask) | (value & write_mask);
+                self.update_xlen();
+            }
+            HypervisorLevelCSRegisters::VSSTATUS => {
+                let write_mask = MStatusFields::SSTATUS_WRITE_MASK
+                    | self.float_state_field()
+                    | self.writable_xl_fields(value, MStatusFields::UXL);
+                let vsstatus = &mut self.cs_registers[addr];
+                *vsstatus = (*vsstatus & !write_mask) | (value & write_mask);
+                self.update_xlen();
+            }
+            HypervisorLevelCSRegisters::HSTATUS => {
+                //VSXL has the same position and encoding as mstatus.UXL
+                let write_mask =
+                    HStatusFields::WRITE_MASK | self.writable_xl_fields(value, MStatusFields::UXL);
+                let hstatus = &mut self.cs_registers[addr];
+                *hstatus = (*hstatus & !write_mask) | (value & write_mask);
+            }
+            HypervisorLevelCSRegisters::HEDELEG => {
+                self.cs_registers[addr] = value & HEDELEG_WRITE_MASK
+            }
             SupervisorLevelCSRegisters::SIE => {
-                self.cs_registers[MachineLevelCSRegisters::MIE] = (self.cs_registers
-                    [MachineLevelCSRegisters::MIE]
-                    & !self.cs_registers[MachineLevelCSRegisters::MIDELEG])
-                    | (value & self.cs_registers[MachineLevelCSRegisters::MIDELEG]);
+                let write_mask = self.cs_registers[MachineLevelCSRegisters::MIDELEG]
+                    & InterruptFields::MIDELEG_WRITE_MASK;
+                let mie = &mut self.cs_registers[MachineLevelCSRegisters::MIE];
+                *mie = (*mie & !write_mask) | (value & write_mask);
+            }
+            MachineLevelCSRegisters::MIE
+            | MachineLevelCSRegisters::MIDELEG
+            | MachineLevelCSRegisters::MIP
+            | SupervisorLevelCSRegisters::SIP
+            | MachineLevelCSRegisters::MVIEN
+            | MachineLevelCSRegisters::MVIP
+            | MachineLevelCSRegisters::MISELECT
+            | SupervisorLevelCSRegisters::SISELECT
+            | MachineLevelCSRegisters::MIREG
+            | SupervisorLevelCSRegisters::SIREG
+            | MachineLevelCSRegisters::MTOPEI
+            | SupervisorLevelCSRegisters::STOPEI
+            | HypervisorLevelCSRegisters::HIDELEG
+            | HypervisorLevelCSRegisters::HIE
+            | HypervisorLevelCSRegisters::HIP
+            | HypervisorLevelCSRegisters::HVIP
+            | HypervisorLevelCSRegisters::HGEIE
+            | HypervisorLevelCSRegisters::VSIE
+            | HypervisorLevelCSRegisters::VSIP => self.store_interrupt_csr(addr, value),
+            MachineLevelCSRegisters::MENVCFG
+            | SupervisorLevelCSRegisters::SENVCFG
+            | HypervisorLevelCSRegisters::HENVCFG => {
+                let write_mask = match addr {
+                    MachineLevelCSRegisters::MENVCFG => {
+                        EnvCfgFields::WRITE_MASK | self.menvcfg_extension_fields()
+                    }
+                    HypervisorLevelCSRegisters::HENVCFG => {
+                        EnvCfgFields::WRITE_MASK | self.henvcfg_extension_fields()
+                    }
+                    _ => EnvCfgFields::WRITE_MASK,
+                };
+                let mut value = value & write_mask;
+                //CBIE is WARL, the reserved 0b10 encoding is legalized to disabled
+                if (value & EnvCfgFields::CBIE) >> EnvCfgFields::CBIE_SHIFT == 0b10 {
+                    value &= !EnvCfgFields::CBIE;
+                }
+                self.cs_registers[addr] = value;
+            }
+            SupervisorLevelCSRegisters::SATP => self.store_satp(value),
+            HypervisorLevelCSRegisters::VSATP => self.store_vsatp(value),
+            HypervisorLevelCSRegisters::HGATP => self.store_hgatp(value),
+            UserLevelCSRegisters::FFLAGS
+            | UserLevelCSRegisters::FRM
+            | UserLevelCSRegisters::FCSR => {
+                let (field, shift) = match addr {
+                    UserLevelCSRegisters::FFLAGS => (FloatCsrFields::FFLAGS, 0),
+                    UserLevelCSRegisters::FRM => (FloatCsrFields::FRM, FloatCsrFields::FRM_SHIFT),
+                    _ => (FloatCsrFields::FFLAGS | FloatCsrFields::FRM, 0),
+                };
+                let fcsr = &mut self.cs_registers[UserLevelCSRegisters::FCSR];
+                *fcsr = (*fcsr & !field) | ((value << shift) & field);
+                self.mark_float_state_dirty();
+            }
+            MachineLevelCSRegisters::MISA => self.store_misa(value),
+            //No performance-monitoring events are implemented
+            MachineLevelCSRegisters::MHPMEVENT3..=MachineLevelCSRegisters::MHPMEVENT31
+            | MachineLevelCSRegisters::MHPMCOUNTER3..=MachineLevelCSRegisters::MHPMCOUNTER31 => {}
+            MachineLevelCSRegisters::PMPCFG0..=MachineLevelCSRegisters::PMPADDR63
+            | MachineLevelCSRegisters::MSECCFG => self.store_pmp_csr(addr, value),
+            MachineLevelCSRegisters::TSELECT..=MachineLevelCSRegisters::TCONTROL => {
+                self.store_trigger_csr(addr, value)
+            }
+            MachineLevelCSRegisters::MSTATEEN0..=MachineLevelCSRegisters::MSTATEEN3 => {
+                self.cs_registers[addr] = value & self.mstateen_write_mask(addr)
+            }
+            //No state controlled by the sstateen registers is implemented
+            SupervisorLevelCSRegisters::SSTATEEN0..=SupervisorLevelCSRegisters::SSTATEEN3 => (),
+            MachineLevelCSRegisters::MCYCLECFG | MachineLevelCSRegisters::MINSTRETCFG => {
+                self.cs_registers[addr] = value & CounterConfigFields::WRITE_MASK
+            }
+            SupervisorLevelCSRegisters::SRMCFG => {
+                self.cs_registers[addr] = value & ResourceConfigFields::WRITE_MASK
             }
+            MachineLevelCSRegisters::MCOUNTEREN
+            | SupervisorLevelCSRegisters::SCOUNTEREN
+            | HypervisorLevelCSRegisters::HCOUNTEREN => {
+                self.cs_registers[addr] = value & CounterEnableFields::WRITE_MASK
+            }
+            //IALIGN is 32 bits, so the two lower bits of the epc registers are always zero
+            MachineLevelCSRegisters::MEPC
+            | SupervisorLevelCSRegisters::SEPC
+            | HypervisorLevelCSRegisters::VSEPC => self.cs_registers[addr] = value & !0b11,
             _ => self.cs_registers[addr] = value,
         }
     }
+
+    /// Returns the XL fields in `fields` that hold a supported XLEN in `value`,
+    /// writes with a reserved encoding leave the field unchanged. The fields
+    /// are read-only zero when the machine XLEN is 32.
+    fn writable_xl_fields(&self, value: u64, fields: u64) -> u64 {
+        if self.config.xlen == Xlen::X32 {
+            return 0;
+        }
+        [MStatusFields::UXL, MStatusFields::SXL]
+            .into_iter()
+            .filter(|field| fields & field != 0)
+            .filter(|field| Xlen::from_bits((value & field) >> field.trailing_zeros()).is_some())
+            .fold(0, |mask, field| mask | field)
+    }
+
+    /// Returns the CSR holding the full 64-bit value of the RV32 CSRs that
+    /// alias its upper 32 bits
+    fn high_half_of(addr: usize) -> Option<usize> {
+        match addr {
+            MachineLevelCSRegisters::MSTATUSH => Some(MachineLevelCSRegisters::MSTATUS),
+            MachineLevelCSRegisters::MENVCFGH => Some(MachineLevelCSRegisters::MENVCFG),
+            SupervisorLevelCSRegisters::STIMECMPH => Some(SupervisorLevelCSRegisters::STIMECMP),
+            MachineLevelCSRegisters::MIDELEGH => Some(MachineLevelCSRegisters::MIDELEG),
+            MachineLevelCSRegisters::MIEH => Some(MachineLevelCSRegisters::MIE),
+            MachineLevelCSRegisters::MIPH => Some(MachineLevelCSRegisters::MIP),
+            MachineLevelCSRegisters::MVIENH => Some(MachineLevelCSRegisters::MVIEN),
+            MachineLevelCSRegisters::MVIPH => Some(MachineLevelCSRegisters::MVIP),
+            SupervisorLevelCSRegisters::SIEH => Some(SupervisorLevelCSRegisters::SIE),
+            SupervisorLevelCSRegisters::SIPH => Some(SupervisorLevelCSRegisters::SIP),
+            MachineLevelCSRegisters::MCYCLEH => Some(MachineLevelCSRegisters::MCYCLE),
+            MachineLevelCSRegisters::MINSTRETH => Some(MachineLevelCSRegisters::MINSTRET),
+            MachineLevelCSRegisters::MHPMCOUNTER3H..=MachineLevelCSRegisters::MHPMCOUNTER31H => {
+                Some(
+                    addr - MachineLevelCSRegisters::MHPMCOUNTER3H
+                        + MachineLevelCSRegisters::MHPMCOUNTER3,
+                )
+            }
+            MachineLevelCSRegisters::MSECCFGH => Some(MachineLevelCSRegisters::MSECCFG),
+            MachineLevelCSRegisters::MSTATEEN0H..=MachineLevelCSRegisters::MSTATEEN3H => Some(
+                addr - MachineLevelCSRegisters::MSTATEEN0H + MachineLevelCSRegisters::MSTATEEN0,
+            ),
+            MachineLevelCSRegisters::MCYCLECFGH => Some(MachineLevelCSRegisters::MCYCLECFG),
+            MachineLevelCSRegisters::MINSTRETCFGH => Some(MachineLevelCSRegisters::MINSTRETCFG),
+            HypervisorLevelCSRegisters::HEDELEGH => Some(HypervisorLevelCSRegisters::HEDELEG),
+            HypervisorLevelCSRegisters::HTIMEDELTAH => Some(HypervisorLevelCSRegisters::HTIMEDELTA),
+            HypervisorLevelCSRegisters::HENVCFGH => Some(HypervisorLevelCSRegisters::HENVCFG),
+            UserLevelCSRegisters::CYCLEH => Some(UserLevelCSRegisters::CYCLE),
+            UserLevelCSRegisters::TIMEH => Some(UserLevelCSRegisters::TIME),
+            UserLevelCSRegisters::INSTRETH => Some(UserLevelCSRegisters::INSTRET),
+            _ => None,
+        }
+    }
+
+    /// Reads a CSR as seen by the Zicsr instructions, when XLEN is 32 only the
+    /// lower half of the CSR is visible and the upper half is read through its
+    /// RV32 only alias
+    pub fn load_csr_xlen(&self, addr: usize) -> u64 {
+        match (self.xlen, Self::high_half_of(addr)) {
+            (Xlen::X64, _) => self.load_csr(addr),
+            (Xlen::X32, Some(full)) => self.load_csr(full) >> 32,
+            (Xlen::X32, None) => self.load_csr(addr) & 0xffff_ffff,
+        }
+    }
+
+    /// Writes a CSR as seen by the Zicsr instructions, when XLEN is 32 the
+    /// half that is not written keeps its value
+    pub fn store_csr_xlen(&mut self, addr: usize, value: u64) {
+        match (self.xlen, Self::high_half_of(addr)) {
+            (Xlen::X64, _) => self.store_csr(addr, value),
+            (Xlen::X32, Some(full)) => {
+                let low = self.load_csr(full) & 0xffff_ffff;
+                self.store_csr(full, (value << 32) | low)
+            }
+            (Xlen::X32, None) => {
+                let high = self.load_csr(addr) & !0xffff_ffff;
+                self.store_csr(addr, high | (value & 0xffff_ffff))
+            }
+        }
+    }
+
+    /// Checks the privilege level and read-only bits encoded in the CSR address,
+    /// returns false if the access should raise an illegal instruction exception.
+    /// VS and VU modes are checked as HS-mode, see check_csr_access.
+    pub fn is_csr_access_allowed(&self, addr: usize, write: bool) -> bool {
+        let required_privilege = ((addr >> 8) & 0b11) as u8;
+        let read_only = (addr >> 10) & 0b11 == 0b11;
+        if !Self::is_csr_implemented(addr) {
+            return false;
+        }
+        if self.xlen == Xlen::X64 && Self::high_half_of(addr).is_some() {
+            return false;
+        }
+        //On RV64 each even pmpcfg register holds the configuration of 8 entries
+        let pmpcfg = MachineLevelCSRegisters::PMPCFG0..=MachineLevelCSRegisters::PMPCFG15;
+        if self.xlen == Xlen::X64 && pmpcfg.contains(&addr) && addr % 2 == 1 {
+            return false;
+        }
+        //HS-mode also accesses the hypervisor and VS CSRs
+        let privilege = match self.csr_privilege() {
+            PrivilegeMode::Supervisor => 0b10,
+            privilege => privilege as u8,
+        };
+        privilege >= required_privilege
+            && !(write && read_only)
+            && self.is_counter_enabled(addr)
+            && self.is_aia_csr_accessible(addr)
+            && self.is_supervisor_csr_accessible(addr)
+            && self.is_trigger_csr_accessible(addr)
+            && self.is_extension_csr_implemented(addr)
+            && self.is_state_enabled(addr)
+            && self.is_float_csr_enabled(addr)
+    }
+
+    /// Every CSR address known to the hart, the CSRs of optional extensions are
+    /// further restricted to the enabled extensions
+    fn is_csr_implemented(addr: usize) -> bool {
+        matches!(
+            addr,
+            MachineLevelCSRegisters::MSTATUS..=MachineLevelCSRegisters::MCOUNTEREN
+                | MachineLevelCSRegisters::MVIEN..=MachineLevelCSRegisters::MENVCFG
+                | MachineLevelCSRegisters::MSTATEEN0..=MachineLevelCSRegisters::MSTATEEN3
+                | MachineLevelCSRegisters::MSTATUSH
+                | MachineLevelCSRegisters::MIDELEGH
+                | MachineLevelCSRegisters::MIEH
+                | MachineLevelCSRegisters::MVIENH
+                | MachineLevelCSRegisters::MVIPH
+                | MachineLevelCSRegisters::MENVCFGH
+                | MachineLevelCSRegisters::MSTATEEN0H..=MachineLevelCSRegisters::MSTATEEN3H
+                | MachineLevelCSRegisters::MCYCLECFG..=MachineLevelCSRegisters::MHPMEVENT31
+                | MachineLevelCSRegisters::MSCRATCH..=MachineLevelCSRegisters::MIP
+                | MachineLevelCSRegisters::MTINST
+                | MachineLevelCSRegisters::MTVAL2
+                | MachineLevelCSRegisters::MISELECT
+                | MachineLevelCSRegisters::MIREG
+                | MachineLevelCSRegisters::MIPH
+                | MachineLevelCSRegisters::MTOPEI
+                | MachineLevelCSRegisters::PMPCFG0..=MachineLevelCSRegisters::PMPADDR63
+                | MachineLevelCSRegisters::MCYCLECFGH
+                | MachineLevelCSRegisters::MINSTRETCFGH
+                | MachineLevelCSRegisters::MSECCFG
+                | MachineLevelCSRegisters::MSECCFGH
+                | MachineLevelCSRegisters::TSELECT..=MachineLevelCSRegisters::TCONTROL
+                | MachineLevelCSRegisters::MCYCLE
+                | MachineLevelCSRegisters::MINSTRET..=MachineLevelCSRegisters::MHPMCOUNTER31
+                | MachineLevelCSRegisters::MCYCLEH
+                | MachineLevelCSRegisters::MINSTRETH..=MachineLevelCSRegisters::MHPMCOUNTER31H
+                | MachineLevelCSRegisters::MTOPI
+                | MachineLevelCSRegisters::MVENDORID..=MachineLevelCSRegisters::MCONFIGPTR
+                | SupervisorLevelCSRegisters::SSTATUS
+                | SupervisorLevelCSRegisters::SIE..=SupervisorLevelCSRegisters::SCOUNTEREN
+                | SupervisorLevelCSRegisters::SENVCFG
+                | SupervisorLevelCSRegisters::SSTATEEN0..=SupervisorLevelCSRegisters::SSTATEEN3
+                | SupervisorLevelCSRegisters::SIEH
+                | SupervisorLevelCSRegisters::SSCRATCH..=SupervisorLevelCSRegisters::SIP
+                | SupervisorLevelCSRegisters::STIMECMP
+                | SupervisorLevelCSRegisters::SISELECT
+                | SupervisorLevelCSRegisters::SIREG
+                | SupervisorLevelCSRegisters::SIPH
+                | SupervisorLevelCSRegisters::STOPEI
+                | SupervisorLevelCSRegisters::STIMECMPH
+                | SupervisorLevelCSRegisters::SATP
+                | SupervisorLevelCSRegisters::SRMCFG
+                | SupervisorLevelCSRegisters::STOPI
+                | HypervisorLevelCSRegisters::VSSTATUS
+                | HypervisorLevelCSRegisters::VSIE..=HypervisorLevelCSRegisters::VSTVEC
+                | HypervisorLevelCSRegisters::VSSCRATCH..=HypervisorLevelCSRegisters::VSIP
+                | HypervisorLevelCSRegisters::VSATP
+                | HypervisorLevelCSRegisters::HSTATUS
+                | HypervisorLevelCSRegisters::HEDELEG..=HypervisorLevelCSRegisters::HGEIE
+                | HypervisorLevelCSRegisters::HENVCFG
+                | HypervisorLevelCSRegisters::HEDELEGH
+                | HypervisorLevelCSRegisters::HTIMEDELTAH
+                | HypervisorLevelCSRegisters::HENVCFGH
+                | HypervisorLevelCSRegisters::HTVAL..=HypervisorLevelCSRegisters::HVIP
+                | HypervisorLevelCSRegisters::HTINST
+                | HypervisorLevelCSRegisters::HGATP
+                | HypervisorLevelCSRegisters::HGEIP
+                | UserLevelCSRegisters::FFLAGS..=UserLevelCSRegisters::FCSR
+                | UserLevelCSRegisters::CYCLE..=UserLevelCSRegisters::INSTRET
+                | UserLevelCSRegisters::CYCLEH..=UserLevelCSRegisters::INSTRETH
+        )
+    }
+
+    /// Only the A, F and D extensions can be disabled through misa, setting
+    /// their bit again enables them if they are part of the configured profile.
+    /// D depends on F so it is disabled along with it. The other fields are
+    /// read-only.
+    fn store_misa(&mut self, value: u64) {
+        let configured = self.config.profile.extensions();
+        for extension in Extension::MISA_WRITABLE {
+            if configured.contains(extension) {
+                self.extensions = match value & extension.misa_bit() != 0 {
+                    true => self.extensions.with(extension),
+                    false => self.extensions.without(extension),
+                };
+            }
+        }
+        if !self.extensions.contains(Extension::F) {
+            self.extensions = self.extensions.without(Extension::D);
+        }
+        if !self.extensions.contains(Extension::A) {
+            self.reservation = None;
+        }
+        self.cs_registers[MachineLevelCSRegisters::MISA] =
+            self.extensions.misa(self.config.xlen, self.config.base);
+    }
+
+    /// The floating point, hypervisor, state enable, counter filtering and QoS
+    /// identifier CSRs exist only when their extension is enabled
+    fn is_extension_csr_implemented(&self, addr: usize) -> bool {
+        let extension = match addr {
+            MachineLevelCSRegisters::MTINST
+            | MachineLevelCSRegisters::MTVAL2
+            | HypervisorLevelCSRegisters::VSSTATUS..=HypervisorLevelCSRegisters::VSATP
+            | HypervisorLevelCSRegisters::HSTATUS..=HypervisorLevelCSRegisters::HGATP
+            | HypervisorLevelCSRegisters::HGEIP => Extension::H,
+            UserLevelCSRegisters::FFLAGS..=UserLevelCSRegisters::FCSR => Extension::F,
+            MachineLevelCSRegisters::MSTATEEN0..=MachineLevelCSRegisters::MSTATEEN3
+            | MachineLevelCSRegisters::MSTATEEN0H..=MachineLevelCSRegisters::MSTATEEN3H
+            | SupervisorLevelCSRegisters::SSTATEEN0..=SupervisorLevelCSRegisters::SSTATEEN3 => {
+                Extension::Smstateen
+            }
+            MachineLevelCSRegisters::MCYCLECFG
+            | MachineLevelCSRegisters::MINSTRETCFG
+            | MachineLevelCSRegisters::MCYCLECFGH
+            | MachineLevelCSRegisters::MINSTRETCFGH => Extension::Smcntrpmf,
+            SupervisorLevelCSRegisters::SRMCFG => Extension::Ssqosid,
+            _ => return true,
+        };
+        self.extensions.contains(extension)
+    }
+
+    /// With Smstateen the state of newer extensions is only accessible below
+    /// M-mode once enabled in mstateen0, and sstateen0-3 require the SE0 bit of
+    /// the matching mstateen. Without the hypervisor extension a disabled
+    /// access raises an illegal instruction exception.
+    fn is_state_enabled(&self, addr: usize) -> bool {
+        if self.privilege_mode == PrivilegeMode::Machine
+            || !self.extensions.contains(Extension::Smstateen)
+        {
+            return true;
+        }
+        let (mstateen, field) = match addr {
+            SupervisorLevelCSRegisters::SSTATEEN0..=SupervisorLevelCSRegisters::SSTATEEN3 => (
+                addr - SupervisorLevelCSRegisters::SSTATEEN0 + MachineLevelCSRegisters::MSTATEEN0,
+                StateEnableFields::SE0,
+            ),
+            SupervisorLevelCSRegisters::SENVCFG => (
+                MachineLevelCSRegisters::MSTATEEN0,
+                StateEnableFields::ENVCFG,
+            ),
+            SupervisorLevelCSRegisters::SISELECT | SupervisorLevelCSRegisters::SIREG => (
+                MachineLevelCSRegisters::MSTATEEN0,
+                StateEnableFields::CSRIND,
+            ),
+            SupervisorLevelCSRegisters::SIEH
+            | SupervisorLevelCSRegisters::SIPH
+            | SupervisorLevelCSRegisters::STOPI => {
+                (MachineLevelCSRegisters::MSTATEEN0, StateEnableFields::AIA)
+            }
+            SupervisorLevelCSRegisters::STOPEI => {
+                (MachineLevelCSRegisters::MSTATEEN0, StateEnableFields::IMSIC)
+            }
+            SupervisorLevelCSRegisters::SRMCFG => (
+                MachineLevelCSRegisters::MSTATEEN0,
+                StateEnableFields::SRMCFG,
+            ),
+            _ => return true,
+        };
+        self.cs_registers[mstateen] & field != 0
+    }
+
+    /// The floating point CSRs can't be accessed while mstatus.FS is Off
+    fn is_float_csr_enabled(&self, addr: usize) -> bool {
+        !matches!(
+            addr,
+            UserLevelCSRegisters::FFLAGS..=UserLevelCSRegisters::FCSR
+        ) || self.is_float_state_enabled()
+    }
+
+    /// Whether mstatus.FS allows the floating point state to be accessed, in
+    /// VS and VU modes vsstatus.FS has to allow it as well
+    #[inline(always)]
+    pub fn is_float_state_enabled(&self) -> bool {
+        self.cs_registers[MachineLevelCSRegisters::MSTATUS] & MStatusFields::FS != 0
+            && (!self.virtualization
+                || self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] & MStatusFields::FS != 0)
+    }
+
+    /// Writes to the floating point registers and fcsr set mstatus.FS to Dirty,
+    /// and vsstatus.FS in VS and VU modes
+    #[inline(always)]
+    pub fn mark_float_state_dirty(&mut self) {
+        self.cs_registers[MachineLevelCSRegisters::MSTATUS] |= MStatusFields::FS_DIRTY;
+        if self.virtualization {
+            self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] |= MStatusFields::FS_DIRTY;
+        }
+    }
+
+    /// FS is WARL, it is read-only zero without the F extension
+    fn float_state_field(&self) -> u64 {
+        match self.extensions.contains(Extension::F) {
+            true => MStatusFields::FS,
+            false => 0,
+        }
+    }
+
+    /// The SD bit of mstatus and sstatus, its position depends on the XLEN.
+    /// It summarizes FS as no other extension state is implemented.
+    fn state_dirty_bit(&self) -> u64 {
+        match self.cs_registers[MachineLevelCSRegisters::MSTATUS] & MStatusFields::FS {
+            MStatusFields::FS_DIRTY => 1 << (self.xlen.bits() - 1),
+            _ => 0,
+        }
+    }
+
+    /// The SD bit of vsstatus, its position depends on the XLEN of VS-mode
+    fn virtual_state_dirty_bit(&self) -> u64 {
+        match self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] & MStatusFields::FS {
+            MStatusFields::FS_DIRTY => {
+                1 << (self.virtual_xlen_of(PrivilegeMode::Supervisor).bits() - 1)
+            }
+            _ => 0,
+        }
+    }
+
+    /// MPV and GVA exist only with the hypervisor extension
+    fn hypervisor_status_fields(&self) -> u64 {
+        match self.extensions.contains(Extension::H) {
+            true => MStatusFields::MPV | MStatusFields::GVA,
+            false => 0,
+        }
+    }
+
+    /// mstateen bits that control implemented state
+    fn mstateen_write_mask(&self, addr: usize) -> u64 {
+        if addr != MachineLevelCSRegisters::MSTATEEN0 {
+            return StateEnableFields::SE0;
+        }
+        [
+            (
+                Extension::Ssaia,
+                StateEnableFields::CSRIND | StateEnableFields::AIA | StateEnableFields::IMSIC,
+            ),
+            (Extension::Ssqosid, StateEnableFields::SRMCFG),
+        ]
+        .into_iter()
+        .filter(|(extension, _)| self.extensions.contains(*extension))
+        .fold(
+            StateEnableFields::SE0 | StateEnableFields::ENVCFG,
+            |fields, (_, field)| fields | field,
+        )
+    }
+
+    /// satp traps in HS-mode when mstatus.TVM is set, stimecmp exists with Sstc
+    /// and below M-mode it also requires the time counter and menvcfg.STCE
+    fn is_supervisor_csr_accessible(&self, addr: usize) -> bool {
+        let machine = self.privilege_mode == PrivilegeMode::Machine;
+        match addr {
+            SupervisorLevelCSRegisters::SATP => {
+                machine
+                    || self.virtualization
+                    || self.cs_registers[MachineLevelCSRegisters::MSTATUS] & MStatusFields::TVM == 0
+            }
+            SupervisorLevelCSRegisters::STIMECMP | SupervisorLevelCSRegisters::STIMECMPH => {
+                self.extensions.contains(Extension::Sstc)
+                    && (machine
+                        || (self.cs_registers[MachineLevelCSRegisters::MCOUNTEREN]
+                            & CounterEnableFields::TM
+                            != 0
+                            && self.cs_registers[MachineLevelCSRegisters::MENVCFG]
+                                & EnvCfgFields::STCE
+                                != 0))
+            }
+            _ => true,
+        }
+    }
+
+    /// henvcfg fields that exist only when their extension is enabled, STCE is
+    /// read-only zero as vstimecmp is not implemented
+    fn henvcfg_extension_fields(&self) -> u64 {
+        match self.extensions.contains(Extension::Svpbmt) {
+            true => EnvCfgFields::PBMTE,
+            false => 0,
+        }
+    }
+
+    /// menvcfg fields that exist only when their extension is enabled
+    fn menvcfg_extension_fields(&self) -> u64 {
+        [
+            (Extension::Svpbmt, EnvCfgFields::PBMTE),
+            (Extension::Sstc, EnvCfgFields::STCE),
+        ]
+        .into_iter()
+        .filter(|(extension, _)| self.extensions.contains(*extension))
+        .fold(0, |fields, (_, field)| fields | field)
+    }
+
+    /// The unprivileged counters are only accessible below M-mode if they are
+    /// enabled in mcounteren, and also in scounteren for U-mode
+    fn is_counter_enabled(&self, addr: usize) -> bool {
+        let counter = match addr {
+            UserLevelCSRegisters::CYCLE..=UserLevelCSRegisters::INSTRET
+            | UserLevelCSRegisters::CYCLEH..=UserLevelCSRegisters::INSTRETH => addr & 0x1f,
+            _ => return true,
+        };
+        let mcounteren = self.cs_registers[MachineLevelCSRegisters::MCOUNTEREN];
+        let scounteren = self.cs_registers[SupervisorLevelCSRegisters::SCOUNTEREN];
+        let enabled = match self.csr_privilege() {
+            PrivilegeMode::Machine => u64::MAX,
+            PrivilegeMode::Supervisor => mcounteren,
+            PrivilegeMode::User => mcounteren & scounteren,
+        };
+        (enabled >> counter) & 1 == 1
+    }
+
+    /// Advances the cycle and time counters, and the instret counter if the
+    /// instruction retired. Time ticks once per cycle so runs stay deterministic,
+    /// each hart catches up with the fastest one every TIME_SYNC_INTERVAL ticks.
+    /// With Smcntrpmf mcycle and minstret don't count in the inhibited modes.
+    #[inline(always)]
+    pub fn update_counters(&mut self, retired: bool, privilege_mode: PrivilegeMode) {
+        let inhibit = CounterConfigFields::inhibit(privilege_mode);
+        self.executed_instructions += 1;
+        let time = &mut self.cs_registers[UserLevelCSRegisters::TIME];
+        *time = time.wrapping_add(1);
+        if time.is_multiple_of(TIME_SYNC_INTERVAL) {
+            self.synchronize_time();
+        }
+        if self.cs_registers[MachineLevelCSRegisters::MCYCLECFG] & inhibit == 0 {
+            let mcycle = &mut self.cs_registers[MachineLevelCSRegisters::MCYCLE];
+            *mcycle = mcycle.wrapping_add(1);
+        }
+        if retired && self.cs_registers[MachineLevelCSRegisters::MINSTRETCFG] & inhibit == 0 {
+            let minstret = &mut self.cs_registers[MachineLevelCSRegisters::MINSTRET];
+            *minstret = minstret.wrapping_add(1);
+        }
+    }
+
+    /// Returns the envcfg bits in `mask` that are enabled for the current privilege
+    /// mode, M-mode is not restricted by any envcfg register and henvcfg also
+    /// restricts VS and VU modes
+    pub fn effective_envcfg(&self, mask: u64) -> u64 {
+        let menvcfg = self.cs_registers[MachineLevelCSRegisters::MENVCFG];
+        let senvcfg = self.cs_registers[SupervisorLevelCSRegisters::SENVCFG];
+        let henvcfg = match self.virtualization {
+            true => self.cs_registers[HypervisorLevelCSRegisters::HENVCFG],
+            false => u64::MAX,
+        };
+        match self.privilege_mode {
+            PrivilegeMode::Machine => mask,
+            PrivilegeMode::Supervisor => menvcfg & henvcfg & mask,
+            PrivilegeMode::User => menvcfg & henvcfg & senvcfg & mask,
+        }
+    }
+
+    /// Exception raised by an instruction disabled by the envcfg bits in `mask`,
+    /// it is a virtual instruction exception when menvcfg enables it and only
+    /// henvcfg or senvcfg disable it in VS and VU modes
+    pub fn envcfg_exception(&self, mask: u64, instruction: u32) -> AppErrors {
+        let menvcfg = self.cs_registers[MachineLevelCSRegisters::MENVCFG];
+        match self.virtualization && menvcfg & mask != 0 {
+            true => AppErrors::VirtualInstruction { instruction },
+            false => AppErrors::IllegalInstruction { instruction },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::{
+            config::{CpuConfig, MachineIds},
+            extensions::IsaProfile,
+            test_hart::{self, execute},
+            Cpu,
+        },
+        error::AppErrors,
+    };
+
+    #[test]
+    fn mstateen0_enables_the_supervisor_state() {
+        let mut cpu = test_hart::cpu();
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        let gated = [
+            SupervisorLevelCSRegisters::SENVCFG,
+            SupervisorLevelCSRegisters::SRMCFG,
+            SupervisorLevelCSRegisters::SISELECT,
+            SupervisorLevelCSRegisters::STOPEI,
+            SupervisorLevelCSRegisters::SSTATEEN0,
+        ];
+        for csr in gated {
+            assert!(!cpu.is_csr_access_allowed(csr, false), "{csr:#x}");
+        }
+        cpu.store_csr(MachineLevelCSRegisters::MSTATEEN0, u64::MAX);
+        for csr in gated {
+            assert!(cpu.is_csr_access_allowed(csr, false), "{csr:#x}");
+        }
+        //sstateen1 is controlled by the SE0 bit of mstateen1
+        assert!(!cpu.is_csr_access_allowed(SupervisorLevelCSRegisters::SSTATEEN0 + 1, false));
+        cpu.store_csr(MachineLevelCSRegisters::MSTATEEN0 + 1, u64::MAX);
+        assert_eq!(
+            cpu.load_csr(MachineLevelCSRegisters::MSTATEEN0 + 1),
+            StateEnableFields::SE0
+        );
+        assert!(cpu.is_csr_access_allowed(SupervisorLevelCSRegisters::SSTATEEN0 + 1, false));
+    }
+
+    #[test]
+    fn mstateen0_only_holds_the_bits_of_enabled_extensions() {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            profile: IsaProfile::Rva20,
+            ..CpuConfig::default()
+        });
+        cpu.store_csr(MachineLevelCSRegisters::MSTATEEN0, u64::MAX);
+        let mstateen0 = cpu.load_csr(MachineLevelCSRegisters::MSTATEEN0);
+        assert_eq!(mstateen0 & StateEnableFields::SRMCFG, 0);
+        assert_eq!(mstateen0 & StateEnableFields::IMSIC, 0);
+        assert_ne!(mstateen0 & StateEnableFields::ENVCFG, 0);
+    }
+
+    #[test]
+    fn counters_are_inhibited_in_the_filtered_modes() {
+        let mut cpu = test_hart::cpu();
+        cpu.store_csr(MachineLevelCSRegisters::MCYCLECFG, u64::MAX);
+        assert_eq!(
+            cpu.load_csr(MachineLevelCSRegisters::MCYCLECFG),
+            CounterConfigFields::WRITE_MASK
+        );
+        cpu.store_csr(
+            MachineLevelCSRegisters::MCYCLECFG,
+            CounterConfigFields::SINH,
+        );
+        cpu.store_csr(
+            MachineLevelCSRegisters::MINSTRETCFG,
+            CounterConfigFields::UINH,
+        );
+        let counters = |cpu: &Cpu| {
+            (
+                cpu.cs_registers[MachineLevelCSRegisters::MCYCLE],
+                cpu.cs_registers[MachineLevelCSRegisters::MINSTRET],
+            )
+        };
+        let start = counters(&cpu);
+        cpu.update_counters(true, PrivilegeMode::Supervisor);
+        assert_eq!(counters(&cpu), (start.0, start.1 + 1));
+        cpu.update_counters(true, PrivilegeMode::User);
+        assert_eq!(counters(&cpu), (start.0 + 1, start.1 + 1));
+        cpu.update_counters(false, PrivilegeMode::Machine);
+        assert_eq!(counters(&cpu), (start.0 + 2, start.1 + 1));
+    }
+
+    #[test]
+    fn srmcfg_holds_the_rcid_and_mcid() {
+        let mut cpu = test_hart::cpu();
+        cpu.store_csr(SupervisorLevelCSRegisters::SRMCFG, u64::MAX);
+        assert_eq!(
+            cpu.load_csr(SupervisorLevelCSRegisters::SRMCFG),
+            ResourceConfigFields::WRITE_MASK
+        );
+    }
+
+    #[test]
+    fn misa_writes_disable_and_enable_extensions() {
+        let mut cpu = test_hart::cpu();
+        let misa = cpu.load_csr(MachineLevelCSRegisters::MISA);
+        let a = Extension::A.misa_bit();
+        //amoadd.w x5, x0, (x10)
+        let amoadd = 0x0005_22af;
+        cpu.registers[10] = 0x8000_0000;
+        cpu.store_csr(MachineLevelCSRegisters::MISA, misa & !a);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MISA), misa & !a);
+        assert!(matches!(
+            execute(&mut cpu, amoadd),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        cpu.store_csr(MachineLevelCSRegisters::MISA, misa);
+        assert!(execute(&mut cpu, amoadd).is_ok());
+        //D is disabled along with F
+        let f = Extension::F.misa_bit();
+        let d = Extension::D.misa_bit();
+        cpu.store_csr(MachineLevelCSRegisters::MISA, misa & !f);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MISA) & (f | d), 0);
+        assert!(!cpu.is_csr_access_allowed(UserLevelCSRegisters::FCSR, false));
+        cpu.store_csr(MachineLevelCSRegisters::MISA, misa);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MISA), misa);
+    }
+
+    #[test]
+    fn misa_read_only_fields_ignore_writes() {
+        let mut cpu = test_hart::cpu();
+        let misa = cpu.load_csr(MachineLevelCSRegisters::MISA);
+        assert_eq!(misa >> 62, Xlen::X64 as u64);
+        //Only A, F and D can be cleared
+        cpu.store_csr(MachineLevelCSRegisters::MISA, 0);
+        let writable = Extension::MISA_WRITABLE
+            .iter()
+            .fold(0, |bits, extension| bits | extension.misa_bit());
+        assert_eq!(
+            cpu.load_csr(MachineLevelCSRegisters::MISA),
+            misa & !writable
+        );
+        //Extensions outside the configured profile can't be enabled
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            profile: IsaProfile::Rv64iafd,
+            ..CpuConfig::default()
+        });
+        let misa = cpu.load_csr(MachineLevelCSRegisters::MISA);
+        cpu.store_csr(MachineLevelCSRegisters::MISA, u64::MAX);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MISA), misa);
+        assert_eq!(misa & Extension::H.misa_bit(), 0);
+    }
+
+    #[test]
+    fn machine_information_registers_are_read_only() {
+        let config = CpuConfig {
+            machine_ids: MachineIds {
+                vendor: 0x489,
+                architecture: 5,
+                implementation: 7,
+                config_pointer: 0x1000,
+            },
+            harts: 2,
+            ..CpuConfig::default()
+        };
+        let cpu = Cpu::new(test_hart::machine(&config), 1, config);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MVENDORID), 0x489);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MARCHID), 5);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MIMPID), 7);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MCONFIGPTR), 0x1000);
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MHARTID), 1);
+        for csr in MachineLevelCSRegisters::MVENDORID..=MachineLevelCSRegisters::MCONFIGPTR {
+            assert!(cpu.is_csr_access_allowed(csr, false));
+            assert!(!cpu.is_csr_access_allowed(csr, true));
+        }
+    }
 }
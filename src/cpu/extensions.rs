@@ -0,0 +1,422 @@
+use super::xlen::Xlen;
+
+/// Base integer ISA, E is the embedded variant with 16 integer registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BaseIsa {
+    I,
+    E,
+}
+
+impl BaseIsa {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "i" => Some(BaseIsa::I),
+            "e" => Some(BaseIsa::E),
+            _ => None,
+        }
+    }
+
+    pub fn register_count(&self) -> u8 {
+        match self {
+            BaseIsa::I => 32,
+            BaseIsa::E => 16,
+        }
+    }
+
+    fn letter(&self) -> char {
+        match self {
+            BaseIsa::I => 'i',
+            BaseIsa::E => 'e',
+        }
+    }
+}
+
+/// Optional ISA extensions implemented by the emulator, the base integer ISA
+/// is always available
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    A,
+    F,
+    D,
+    H,
+    Zicbom,
+    Zicbop,
+    Zicboz,
+    Zicond,
+    Zicsr,
+    Zifencei,
+    Zihintpause,
+    Zabha,
+    Zacas,
+    Zama16b,
+    Zawrs,
+    Zfa,
+    Zfh,
+    Zfhmin,
+    Sdtrig,
+    Smaia,
+    Smcntrpmf,
+    Smepmp,
+    Smstateen,
+    Ssaia,
+    Ssqosid,
+    Sstc,
+    Svade,
+    Svinval,
+    Svnapot,
+    Svpbmt,
+}
+
+impl Extension {
+    /// Every extension, sorted as they must appear in the ISA string
+    pub const ALL: [Extension; 30] = [
+        Extension::A,
+        Extension::F,
+        Extension::D,
+        Extension::H,
+        Extension::Zicbom,
+        Extension::Zicbop,
+        Extension::Zicboz,
+        Extension::Zicond,
+        Extension::Zicsr,
+        Extension::Zifencei,
+        Extension::Zihintpause,
+        Extension::Zabha,
+        Extension::Zacas,
+        Extension::Zama16b,
+        Extension::Zawrs,
+        Extension::Zfa,
+        Extension::Zfh,
+        Extension::Zfhmin,
+        Extension::Sdtrig,
+        Extension::Smaia,
+        Extension::Smcntrpmf,
+        Extension::Smepmp,
+        Extension::Smstateen,
+        Extension::Ssaia,
+        Extension::Ssqosid,
+        Extension::Sstc,
+        Extension::Svade,
+        Extension::Svinval,
+        Extension::Svnapot,
+        Extension::Svpbmt,
+    ];
+
+    /// Single letter extensions that can be disabled at runtime through misa
+    pub const MISA_WRITABLE: [Extension; 3] = [Extension::A, Extension::F, Extension::D];
+
+    /// Bit of misa reporting a single letter extension, 0 for the others
+    pub fn misa_bit(&self) -> u64 {
+        match self.name().as_bytes() {
+            [letter] => 1 << (letter.to_ascii_uppercase() - b'A'),
+            _ => 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Extension::A => "a",
+            Extension::F => "f",
+            Extension::D => "d",
+            Extension::H => "h",
+            Extension::Zicbom => "zicbom",
+            Extension::Zicbop => "zicbop",
+            Extension::Zicboz => "zicboz",
+            Extension::Zicond => "zicond",
+            Extension::Zicsr => "zicsr",
+            Extension::Zifencei => "zifencei",
+            Extension::Zihintpause => "zihintpause",
+            Extension::Zabha => "zabha",
+            Extension::Zacas => "zacas",
+            Extension::Zama16b => "zama16b",
+            Extension::Zawrs => "zawrs",
+            Extension::Zfa => "zfa",
+            Extension::Zfh => "zfh",
+            Extension::Zfhmin => "zfhmin",
+            Extension::Sdtrig => "sdtrig",
+            Extension::Smaia => "smaia",
+            Extension::Smcntrpmf => "smcntrpmf",
+            Extension::Smepmp => "smepmp",
+            Extension::Smstateen => "smstateen",
+            Extension::Ssaia => "ssaia",
+            Extension::Ssqosid => "ssqosid",
+            Extension::Sstc => "sstc",
+            Extension::Svade => "svade",
+            Extension::Svinval => "svinval",
+            Extension::Svnapot => "svnapot",
+            Extension::Svpbmt => "svpbmt",
+        }
+    }
+}
+
+/// Set of enabled extensions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extensions(u64);
+
+impl Extensions {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn with(self, extension: Extension) -> Self {
+        Self(self.0 | 1 << extension as u64)
+    }
+
+    pub const fn without(self, extension: Extension) -> Self {
+        Self(self.0 & !(1 << extension as u64))
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, extension: Extension) -> bool {
+        self.0 & (1 << extension as u64) != 0
+    }
+
+    /// ISA string as expected by the riscv,isa device tree property
+    pub fn isa_string(&self, xlen: Xlen, base: BaseIsa) -> String {
+        Extension::ALL
+            .iter()
+            .filter(|extension| self.contains(**extension))
+            .fold(
+                format!("rv{}{}", xlen.bits(), base.letter()),
+                |isa, extension| match extension.name().len() {
+                    1 => isa + extension.name(),
+                    _ => isa + "_" + extension.name(),
+                },
+            )
+    }
+
+    /// Value of the misa register for a machine XLEN and base ISA, S/U modes are
+    /// always supported
+    pub fn misa(&self, xlen: Xlen, base: BaseIsa) -> u64 {
+        let letter = |letter: char| 1_u64 << (letter.to_ascii_uppercase() as u8 - b'A');
+        let misa = ((xlen as u64) << (xlen.bits() - 2))
+            | letter(base.letter())
+            | letter('S')
+            | letter('U');
+        Extension::ALL
+            .iter()
+            .filter(|extension| self.contains(**extension))
+            .fold(misa, |misa, extension| misa | extension.misa_bit())
+    }
+}
+
+/// Named sets of extensions, the mandatory extensions of a profile that the
+/// emulator doesn't implement are left out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IsaProfile {
+    /// The G extensions without M, which is not implemented
+    Rv64iafd,
+    Rva20,
+    Rva22,
+    Rva23,
+    /// Every implemented extension
+    Max,
+}
+
+impl IsaProfile {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rv64iafd" => Some(IsaProfile::Rv64iafd),
+            "rva20" => Some(IsaProfile::Rva20),
+            "rva22" => Some(IsaProfile::Rva22),
+            "rva23" => Some(IsaProfile::Rva23),
+            "max" => Some(IsaProfile::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IsaProfile::Rv64iafd => "rv64iafd",
+            IsaProfile::Rva20 => "rva20",
+            IsaProfile::Rva22 => "rva22",
+            IsaProfile::Rva23 => "rva23",
+            IsaProfile::Max => "max",
+        }
+    }
+
+    /// Mandatory extensions of the profile that the emulator doesn't implement
+    pub fn unimplemented_extensions(&self) -> &'static [&'static str] {
+        match self {
+            IsaProfile::Rv64iafd | IsaProfile::Max => &[],
+            IsaProfile::Rva20 => &["m", "c"],
+            IsaProfile::Rva22 => &["m", "c", "zba", "zbb", "zbs", "zkt"],
+            IsaProfile::Rva23 => &[
+                "m",
+                "c",
+                "v",
+                "zba",
+                "zbb",
+                "zbs",
+                "zkt",
+                "zcb",
+                "zcmop",
+                "zimop",
+                "zihintntl",
+                "zvfhmin",
+                "zvbb",
+                "zvkt",
+                "supm",
+                "ssnpm",
+                "sscofpmf",
+            ],
+        }
+    }
+
+    /// Extensions enabled by the profile
+    pub fn extensions(&self) -> Extensions {
+        let rv64iafd = Extensions::empty()
+            .with(Extension::A)
+            .with(Extension::F)
+            .with(Extension::D)
+            .with(Extension::Zicsr)
+            .with(Extension::Zifencei);
+        let rva20 = rv64iafd.with(Extension::Svade);
+        let rva22 = rva20
+            .with(Extension::Zihintpause)
+            .with(Extension::Zicbom)
+            .with(Extension::Zicbop)
+            .with(Extension::Zicboz)
+            .with(Extension::Zfhmin)
+            .with(Extension::Svinval)
+            .with(Extension::Svpbmt);
+        let rva23 = rva22
+            .with(Extension::H)
+            .with(Extension::Zicond)
+            .with(Extension::Zawrs)
+            .with(Extension::Zfa)
+            .with(Extension::Zama16b)
+            .with(Extension::Sstc)
+            .with(Extension::Svnapot);
+        match self {
+            IsaProfile::Rv64iafd => rv64iafd,
+            IsaProfile::Rva20 => rva20,
+            IsaProfile::Rva22 => rva22,
+            IsaProfile::Rva23 => rva23,
+            IsaProfile::Max => Extension::ALL
+                .iter()
+                .fold(Extensions::empty(), |extensions, extension| {
+                    extensions.with(*extension)
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::{
+            config::CpuConfig,
+            test_hart::{self, execute},
+        },
+        error::AppErrors,
+    };
+
+    /// Single letter extensions reported in misa, in alphabetical order
+    fn misa_letters(misa: u64) -> String {
+        (b'a'..=b'z')
+            .filter(|letter| misa & (1 << (letter - b'a')) != 0)
+            .map(char::from)
+            .collect()
+    }
+
+    #[test]
+    fn profiles_select_the_extensions_misa_and_isa_string() {
+        let profiles = [
+            (IsaProfile::Rv64iafd, "rv64iafd_zicsr_zifencei", "adfisu"),
+            (
+                IsaProfile::Rva20,
+                "rv64iafd_zicsr_zifencei_svade",
+                "adfisu",
+            ),
+            (
+                IsaProfile::Rva22,
+                "rv64iafd_zicbom_zicbop_zicboz_zicsr_zifencei_zihintpause_zfhmin_svade_svinval_svpbmt",
+                "adfisu",
+            ),
+            (
+                IsaProfile::Rva23,
+                "rv64iafdh_zicbom_zicbop_zicboz_zicond_zicsr_zifencei_zihintpause_zama16b_zawrs_zfa\
+                 _zfhmin_sstc_svade_svinval_svnapot_svpbmt",
+                "adfhisu",
+            ),
+        ];
+        for (profile, isa, letters) in profiles {
+            let extensions = profile.extensions();
+            assert_eq!(IsaProfile::from_name(profile.name()), Some(profile));
+            assert_eq!(extensions.isa_string(Xlen::X64, BaseIsa::I), isa);
+            let misa = extensions.misa(Xlen::X64, BaseIsa::I);
+            assert_eq!(misa >> 62, 2);
+            assert_eq!(misa_letters(misa), letters);
+            //The ISA string lists exactly the enabled extensions
+            let mut names = isa.split('_');
+            let single_letters = &names.next().unwrap()["rv64i".len()..];
+            let names: Vec<_> = names.collect();
+            for extension in Extension::ALL {
+                let listed = match extension.name().len() {
+                    1 => single_letters.contains(extension.name()),
+                    _ => names.contains(&extension.name()),
+                };
+                assert_eq!(
+                    extensions.contains(extension),
+                    listed,
+                    "{extension:?} in {isa}"
+                );
+            }
+        }
+        let max = IsaProfile::Max.extensions();
+        assert!(Extension::ALL
+            .iter()
+            .all(|extension| max.contains(*extension)));
+        assert_eq!(misa_letters(max.misa(Xlen::X64, BaseIsa::I)), "adfhisu");
+        let names: Vec<_> = Extension::ALL
+            .iter()
+            .map(|extension| extension.name())
+            .filter(|name| name.len() > 1)
+            .collect();
+        assert_eq!(
+            max.isa_string(Xlen::X64, BaseIsa::I),
+            format!("rv64iafdh_{}", names.join("_"))
+        );
+    }
+
+    #[test]
+    fn e_base_is_reported_in_misa_and_the_isa_string() {
+        let extensions = IsaProfile::Max.extensions();
+        let misa = extensions.misa(Xlen::X32, BaseIsa::E);
+        assert_ne!(misa & (1 << (b'E' - b'A')), 0);
+        assert_eq!(misa & (1 << (b'I' - b'A')), 0);
+        assert!(extensions
+            .isa_string(Xlen::X32, BaseIsa::E)
+            .starts_with("rv32e"));
+    }
+
+    #[test]
+    fn e_base_rejects_the_upper_registers() {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            base: BaseIsa::E,
+            ..CpuConfig::default()
+        });
+        cpu.registers[13] = 2;
+        cpu.registers[14] = 3;
+        //add x15, x14, x13
+        execute(&mut cpu, 0x00d7_07b3).unwrap();
+        assert_eq!(cpu.registers[15], 5);
+        for raw in [
+            //add x16, x1, x2
+            0x0020_8833,
+            //add x1, x17, x2
+            0x0028_80b3,
+            //lw x1, 0(x31)
+            0x000f_a083,
+            //csrr x20, mstatus
+            0x3000_2a73,
+        ] {
+            assert!(matches!(
+                execute(&mut cpu, raw),
+                Err(AppErrors::IllegalInstruction { instruction }) if instruction == raw
+            ));
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use crate::error::{AppErrors, AppResult};
+
+use super::{
+    cs_registers::{
+        HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+        SupervisorLevelCSRegisters, UserLevelCSRegisters,
+    },
+    pmp::MemoryAccess,
+    privilege::PrivilegeMode,
+    trap::ExceptionCause,
+    Cpu,
+};
+
+/// Exceptions that can be delegated to VS-mode through hedeleg, the
+/// environment calls from HS, VS and M modes and the guest page faults and
+/// virtual instruction exceptions are always handled by HS-mode
+pub const HEDELEG_WRITE_MASK: u64 = (1 << ExceptionCause::INSTRUCTION_ADDRESS_MISALIGNED)
+    | (1 << ExceptionCause::INSTRUCTION_ACCESS_FAULT)
+    | (1 << ExceptionCause::ILLEGAL_INSTRUCTION)
+    | (1 << ExceptionCause::BREAKPOINT)
+    | (1 << ExceptionCause::LOAD_ADDRESS_MISALIGNED)
+    | (1 << ExceptionCause::LOAD_ACCESS_FAULT)
+    | (1 << ExceptionCause::STORE_ADDRESS_MISALIGNED)
+    | (1 << ExceptionCause::STORE_ACCESS_FAULT)
+    | (1 << ExceptionCause::ENV_CALL_FROM_U)
+    | (1 << ExceptionCause::INSTRUCTION_PAGE_FAULT)
+    | (1 << ExceptionCause::LOAD_PAGE_FAULT)
+    | (1 << ExceptionCause::STORE_PAGE_FAULT);
+
+/// Access of a hypervisor virtual-machine load or store, made with the
+/// translation and privilege of VS or VU mode as selected by hstatus.SPVP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HypervisorAccess {
+    /// HLV and HSV, the pages have to be readable or writable
+    Data,
+    /// HLVX, the pages have to be executable instead of readable
+    Execute,
+}
+
+impl Cpu {
+    /// Privilege checked against the CSR address, VS and VU modes are checked
+    /// as HS-mode and the virtual accesses are further restricted by
+    /// check_csr_access
+    #[inline(always)]
+    pub fn csr_privilege(&self) -> PrivilegeMode {
+        match self.virtualization {
+            true => PrivilegeMode::Supervisor,
+            false => self.privilege_mode,
+        }
+    }
+
+    /// In VS and VU modes the supervisor CSRs with a VS counterpart access
+    /// the VS CSR instead
+    #[inline(always)]
+    pub fn virtual_csr(&self, addr: usize) -> usize {
+        match addr {
+            SupervisorLevelCSRegisters::SSTATUS
+            | SupervisorLevelCSRegisters::SIE
+            | SupervisorLevelCSRegisters::STVEC
+            | SupervisorLevelCSRegisters::SSCRATCH
+            | SupervisorLevelCSRegisters::SEPC
+            | SupervisorLevelCSRegisters::SCAUSE
+            | SupervisorLevelCSRegisters::STVAL
+            | SupervisorLevelCSRegisters::SIP
+            | SupervisorLevelCSRegisters::SATP
+                if self.virtualization =>
+            {
+                addr + 0x100
+            }
+            _ => addr,
+        }
+    }
+
+    /// Accesses that HS-mode would be allowed to make but VS or VU modes are
+    /// not, they raise a virtual instruction exception instead of an illegal
+    /// instruction exception
+    fn is_virtual_csr_access_allowed(&self, addr: usize) -> bool {
+        let required_privilege = (addr >> 8) & 0b11;
+        let counter = addr & 0x1f;
+        let hcounteren = self.cs_registers[HypervisorLevelCSRegisters::HCOUNTEREN];
+        let scounteren = self.cs_registers[SupervisorLevelCSRegisters::SCOUNTEREN];
+        let hstatus = self.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        let user = self.privilege_mode == PrivilegeMode::User;
+        match addr {
+            //The hypervisor and VS CSRs are only accessed from HS-mode
+            _ if required_privilege == 0b10 => false,
+            _ if user && required_privilege == 0b01 => false,
+            UserLevelCSRegisters::CYCLE..=UserLevelCSRegisters::INSTRET
+            | UserLevelCSRegisters::CYCLEH..=UserLevelCSRegisters::INSTRETH => {
+                let enabled = match user {
+                    true => hcounteren & scounteren,
+                    false => hcounteren,
+                };
+                (enabled >> counter) & 1 == 1
+            }
+            SupervisorLevelCSRegisters::SATP => hstatus & HStatusFields::VTVM == 0,
+            //vstimecmp and the virtualized AIA CSRs are not implemented
+            SupervisorLevelCSRegisters::STIMECMP
+            | SupervisorLevelCSRegisters::STIMECMPH
+            | SupervisorLevelCSRegisters::SISELECT
+            | SupervisorLevelCSRegisters::SIREG
+            | SupervisorLevelCSRegisters::STOPEI
+            | SupervisorLevelCSRegisters::STOPI
+            | SupervisorLevelCSRegisters::SIEH
+            | SupervisorLevelCSRegisters::SIPH
+            | SupervisorLevelCSRegisters::SRMCFG => false,
+            _ => true,
+        }
+    }
+
+    /// Checks a CSR access, raises an illegal instruction exception if HS-mode
+    /// could not make it and a virtual instruction exception if only the
+    /// virtualization prevents it
+    pub fn check_csr_access(&self, addr: usize, write: bool, instruction: u32) -> AppResult<()> {
+        if !self.is_csr_access_allowed(addr, write) {
+            return Err(AppErrors::IllegalInstruction { instruction });
+        }
+        match self.virtualization && !self.is_virtual_csr_access_allowed(addr) {
+            true => Err(AppErrors::VirtualInstruction { instruction }),
+            false => Ok(()),
+        }
+    }
+
+    /// Enters or leaves VS and VU modes, the cached translations and the links
+    /// between blocks belong to the previous address space
+    pub fn set_virtualization(&mut self, virtualization: bool) {
+        if self.virtualization != virtualization {
+            self.virtualization = virtualization;
+            self.tlb.flush();
+            self.blocks.drop_links();
+        }
+    }
+
+    /// Whether an access goes through the two-stage translation: the accesses
+    /// of VS and VU modes, the hypervisor loads and stores and the data
+    /// accesses of M-mode with MPRV when mstatus.MPV is set
+    #[inline(always)]
+    pub fn is_access_virtualized(&self, access: MemoryAccess) -> bool {
+        if access == MemoryAccess::Fetch {
+            return self.virtualization;
+        }
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let modified_privilege = self.privilege_mode == PrivilegeMode::Machine
+            && status & MStatusFields::MPRV != 0
+            && status & MStatusFields::MPV != 0
+            && (status & MStatusFields::MPP) >> MStatusFields::MPP_SHIFT
+                != PrivilegeMode::Machine as u64;
+        self.virtualization || self.hypervisor_access.is_some() || modified_privilege
+    }
+
+    /// Privilege of the hypervisor loads and stores, VS-mode if hstatus.SPVP is
+    /// set and VU-mode otherwise
+    #[inline(always)]
+    pub fn hypervisor_access_privilege(&self) -> PrivilegeMode {
+        match self.cs_registers[HypervisorLevelCSRegisters::HSTATUS] & HStatusFields::SPVP {
+            0 => PrivilegeMode::User,
+            _ => PrivilegeMode::Supervisor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{
+        config::CpuConfig,
+        cs_registers::{CounterEnableFields, EnvCfgFields},
+        extensions::{Extension, IsaProfile},
+        interrupts::{InterruptCause, InterruptFields},
+        test_hart,
+    };
+
+    /// csrrs x1, csr, x0
+    fn csr_read(csr: usize) -> u32 {
+        ((csr as u32) << 20) | (0b010 << 12) | (1 << 7) | 0b1110011
+    }
+
+    /// csrrw x0, csr, x1
+    fn csr_write(csr: usize) -> u32 {
+        ((csr as u32) << 20) | (1 << 15) | (0b001 << 12) | 0b1110011
+    }
+
+    fn guest(privilege_mode: PrivilegeMode) -> Cpu {
+        let mut cpu = test_hart::cpu();
+        cpu.privilege_mode = privilege_mode;
+        cpu.set_virtualization(true);
+        cpu.update_xlen();
+        cpu
+    }
+
+    #[test]
+    fn supervisor_csrs_are_redirected_to_the_vs_csrs() {
+        let mut cpu = guest(PrivilegeMode::Supervisor);
+        cpu.registers[1] = 0x8000_1000;
+        test_hart::execute(&mut cpu, csr_write(SupervisorLevelCSRegisters::STVEC)).unwrap();
+        assert_eq!(
+            cpu.cs_registers[HypervisorLevelCSRegisters::VSTVEC],
+            0x8000_1000
+        );
+        assert_eq!(cpu.cs_registers[SupervisorLevelCSRegisters::STVEC], 0);
+        cpu.cs_registers[HypervisorLevelCSRegisters::VSSCRATCH] = 0x55;
+        test_hart::execute(&mut cpu, csr_read(SupervisorLevelCSRegisters::SSCRATCH)).unwrap();
+        assert_eq!(cpu.registers[1], 0x55);
+    }
+
+    #[test]
+    fn virtual_modes_raise_virtual_instruction_exceptions() {
+        let mut cpu = guest(PrivilegeMode::Supervisor);
+        //stimecmp is enabled for HS-mode but vstimecmp is not implemented
+        cpu.cs_registers[MachineLevelCSRegisters::MENVCFG] |= EnvCfgFields::STCE;
+        cpu.cs_registers[MachineLevelCSRegisters::MCOUNTEREN] |= CounterEnableFields::TM;
+        for csr in [
+            HypervisorLevelCSRegisters::HSTATUS,
+            HypervisorLevelCSRegisters::VSSTATUS,
+            SupervisorLevelCSRegisters::STIMECMP,
+        ] {
+            assert!(matches!(
+                test_hart::execute(&mut cpu, csr_read(csr)),
+                Err(AppErrors::VirtualInstruction { .. })
+            ));
+        }
+        //M-mode CSRs are illegal from HS-mode as well
+        assert!(matches!(
+            test_hart::execute(&mut cpu, csr_read(MachineLevelCSRegisters::MSTATUS)),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS] |= HStatusFields::VTVM;
+        assert!(matches!(
+            test_hart::execute(&mut cpu, csr_read(SupervisorLevelCSRegisters::SATP)),
+            Err(AppErrors::VirtualInstruction { .. })
+        ));
+
+        let mut cpu = guest(PrivilegeMode::User);
+        assert!(matches!(
+            test_hart::execute(&mut cpu, csr_read(SupervisorLevelCSRegisters::SSTATUS)),
+            Err(AppErrors::VirtualInstruction { .. })
+        ));
+    }
+
+    #[test]
+    fn counters_are_delegated_through_hcounteren() {
+        let mut cpu = guest(PrivilegeMode::User);
+        let cycle = CounterEnableFields::CY;
+        cpu.cs_registers[MachineLevelCSRegisters::MCOUNTEREN] = cycle;
+        let read_cycle = csr_read(UserLevelCSRegisters::CYCLE);
+        assert!(matches!(
+            test_hart::execute(&mut cpu, read_cycle),
+            Err(AppErrors::VirtualInstruction { .. })
+        ));
+        cpu.cs_registers[HypervisorLevelCSRegisters::HCOUNTEREN] = cycle;
+        assert!(matches!(
+            test_hart::execute(&mut cpu, read_cycle),
+            Err(AppErrors::VirtualInstruction { .. })
+        ));
+        cpu.cs_registers[SupervisorLevelCSRegisters::SCOUNTEREN] = cycle;
+        assert!(test_hart::execute(&mut cpu, read_cycle).is_ok());
+        //Without mcounteren the access is illegal for HS-mode too
+        cpu.cs_registers[MachineLevelCSRegisters::MCOUNTEREN] = 0;
+        assert!(matches!(
+            test_hart::execute(&mut cpu, read_cycle),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+    }
+
+    #[test]
+    fn hypervisor_csrs_require_the_extension() {
+        let cpu = test_hart::cpu_with(CpuConfig {
+            profile: IsaProfile::Rva22,
+            ..CpuConfig::default()
+        });
+        assert!(!cpu.extensions.contains(Extension::H));
+        assert!(!cpu.is_csr_access_allowed(HypervisorLevelCSRegisters::HSTATUS, false));
+        assert!(test_hart::cpu().is_csr_access_allowed(HypervisorLevelCSRegisters::HSTATUS, true));
+    }
+
+    #[test]
+    fn exceptions_are_delegated_to_vs_mode() {
+        let mut cpu = guest(PrivilegeMode::User);
+        cpu.program_counter = 0x1000;
+        cpu.cs_registers[HypervisorLevelCSRegisters::VSTVEC] = 0x2000;
+        let breakpoint = 1 << ExceptionCause::BREAKPOINT;
+        cpu.cs_registers[MachineLevelCSRegisters::MEDELEG] = breakpoint;
+        cpu.cs_registers[HypervisorLevelCSRegisters::HEDELEG] = breakpoint;
+        cpu.trap_on_exception(AppErrors::Breakpoint { addr: 0x1000 })
+            .unwrap();
+        assert!(cpu.virtualization);
+        assert_eq!(cpu.privilege_mode, PrivilegeMode::Supervisor);
+        assert_eq!(cpu.program_counter, 0x2000);
+        assert_eq!(cpu.cs_registers[HypervisorLevelCSRegisters::VSEPC], 0x1000);
+        assert_eq!(
+            cpu.cs_registers[HypervisorLevelCSRegisters::VSCAUSE],
+            ExceptionCause::BREAKPOINT
+        );
+        assert_eq!(cpu.cs_registers[SupervisorLevelCSRegisters::SCAUSE], 0);
+        //SRET in VS-mode returns to VU-mode through vsstatus and vsepc
+        test_hart::execute(&mut cpu, 0x1020_0073).unwrap();
+        assert!(cpu.virtualization);
+        assert_eq!(cpu.privilege_mode, PrivilegeMode::User);
+        assert_eq!(cpu.program_counter, 0x1000);
+    }
+
+    #[test]
+    fn guest_page_faults_are_taken_in_hs_mode() {
+        let mut cpu = guest(PrivilegeMode::Supervisor);
+        cpu.program_counter = 0x1000;
+        cpu.cs_registers[SupervisorLevelCSRegisters::STVEC] = 0x3000;
+        cpu.cs_registers[MachineLevelCSRegisters::MEDELEG] =
+            1 << ExceptionCause::LOAD_GUEST_PAGE_FAULT;
+        //hedeleg can't delegate the guest page faults
+        cpu.store_csr(HypervisorLevelCSRegisters::HEDELEG, u64::MAX);
+        cpu.trap_on_exception(AppErrors::LoadGuestPageFault {
+            addr: 0x4000,
+            guest_physical: 0x8_0000,
+        })
+        .unwrap();
+        assert!(!cpu.virtualization);
+        assert_eq!(cpu.privilege_mode, PrivilegeMode::Supervisor);
+        assert_eq!(cpu.program_counter, 0x3000);
+        assert_eq!(
+            cpu.cs_registers[SupervisorLevelCSRegisters::SCAUSE],
+            ExceptionCause::LOAD_GUEST_PAGE_FAULT
+        );
+        assert_eq!(cpu.cs_registers[SupervisorLevelCSRegisters::STVAL], 0x4000);
+        assert_eq!(
+            cpu.cs_registers[HypervisorLevelCSRegisters::HTVAL],
+            0x2_0000
+        );
+        let hstatus = cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        let expected = HStatusFields::SPV | HStatusFields::SPVP | HStatusFields::GVA;
+        assert_eq!(hstatus & expected, expected);
+        //SRET returns to VS-mode
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] |= MStatusFields::SPP;
+        test_hart::execute(&mut cpu, 0x1020_0073).unwrap();
+        assert!(cpu.virtualization);
+        assert_eq!(cpu.privilege_mode, PrivilegeMode::Supervisor);
+        assert_eq!(cpu.program_counter, 0x1000);
+    }
+
+    #[test]
+    fn machine_traps_save_the_virtualization_mode() {
+        let mut cpu = guest(PrivilegeMode::Supervisor);
+        cpu.program_counter = 0x1000;
+        let ecall = test_hart::execute(&mut cpu, 0x0000_0073);
+        assert!(matches!(
+            ecall,
+            Err(AppErrors::EnvironmentCall {
+                virtualized: true,
+                ..
+            })
+        ));
+        if let Err(err) = ecall {
+            cpu.trap_on_exception(err).unwrap();
+        }
+        assert!(!cpu.virtualization);
+        assert_eq!(cpu.privilege_mode, PrivilegeMode::Machine);
+        assert_eq!(
+            cpu.cs_registers[MachineLevelCSRegisters::MCAUSE],
+            ExceptionCause::ENV_CALL_FROM_VS
+        );
+        let status = cpu.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        assert_eq!(
+            status & (MStatusFields::MPV | MStatusFields::GVA),
+            MStatusFields::MPV
+        );
+        //MRET goes back to VS-mode and clears MPV
+        test_hart::execute(&mut cpu, 0x3020_0073).unwrap();
+        assert!(cpu.virtualization);
+        assert_eq!(cpu.privilege_mode, PrivilegeMode::Supervisor);
+        assert_eq!(
+            cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] & MStatusFields::MPV,
+            0
+        );
+    }
+
+    #[test]
+    fn virtual_interrupts_are_taken_in_vs_mode() {
+        let mut cpu = test_hart::cpu();
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu.cs_registers[HypervisorLevelCSRegisters::VSTVEC] = 0x2000;
+        cpu.store_csr(HypervisorLevelCSRegisters::HIDELEG, InterruptFields::VSTI);
+        cpu.store_csr(HypervisorLevelCSRegisters::HIE, InterruptFields::VSTI);
+        cpu.store_csr(HypervisorLevelCSRegisters::HVIP, InterruptFields::VSTI);
+        //vsip shows the VS timer interrupt at the supervisor timer bit
+        assert_eq!(
+            cpu.load_csr(HypervisorLevelCSRegisters::VSIP),
+            InterruptFields::STI
+        );
+        assert_eq!(
+            cpu.load_csr(SupervisorLevelCSRegisters::SIP) & InterruptFields::VSTI,
+            0
+        );
+        //HS-mode doesn't take the interrupts delegated to VS-mode
+        cpu.take_pending_interrupt();
+        assert!(!cpu.virtualization);
+        assert_eq!(cpu.cs_registers[HypervisorLevelCSRegisters::VSCAUSE], 0);
+        //VS-mode masks them with vsstatus.SIE
+        cpu.set_virtualization(true);
+        cpu.take_pending_interrupt();
+        assert_eq!(cpu.cs_registers[HypervisorLevelCSRegisters::VSCAUSE], 0);
+        cpu.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] |= MStatusFields::SIE;
+        cpu.take_pending_interrupt();
+        assert_eq!(
+            cpu.cs_registers[HypervisorLevelCSRegisters::VSCAUSE],
+            (1 << 63) | InterruptCause::SUPERVISOR_TIMER
+        );
+        assert_eq!(cpu.program_counter, 0x2000);
+    }
+}
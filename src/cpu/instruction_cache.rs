@@ -0,0 +1,157 @@
+use crate::consts::PAGE_SIZE;
+
+use super::{
+    instruction_excecutors::DecodedInstruction, instructions::DEFAULT_INSTRUCTION_SIZE_BYTES,
+};
+
+const INSTRUCTIONS_PER_PAGE: usize = PAGE_SIZE as usize / DEFAULT_INSTRUCTION_SIZE_BYTES;
+
+type CachedPage = [Option<DecodedInstruction>; INSTRUCTIONS_PER_PAGE];
+
+/// Decoded instructions of the RAM pages the hart executed from, indexed by
+/// the RAM page index and the physical address. Pages are dropped when the
+/// system bus reports a store to them, and the whole cache on FENCE.I.
+pub struct InstructionCache {
+    /// Grown up to the highest page executed from
+    pages: Vec<Option<Box<CachedPage>>>,
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        Self { pages: Vec::new() }
+    }
+
+    /// Slot in its page of an instruction address, None for addresses not
+    /// aligned to an instruction
+    #[inline(always)]
+    fn slot(addr: u64) -> Option<usize> {
+        match addr.is_multiple_of(DEFAULT_INSTRUCTION_SIZE_BYTES as u64) {
+            true => Some((addr % PAGE_SIZE) as usize / DEFAULT_INSTRUCTION_SIZE_BYTES),
+            false => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, page: usize, addr: u64) -> Option<DecodedInstruction> {
+        self.pages.get(page)?.as_ref()?[Self::slot(addr)?]
+    }
+
+    /// Caches a decoded instruction, returns false if the address can't be cached
+    pub fn insert(&mut self, page: usize, addr: u64, instruction: DecodedInstruction) -> bool {
+        let Some(slot) = Self::slot(addr) else {
+            return false;
+        };
+        if self.pages.len() <= page {
+            self.pages.resize_with(page + 1, || None);
+        }
+        self.pages[page].get_or_insert_with(|| Box::new([None; INSTRUCTIONS_PER_PAGE]))[slot] =
+            Some(instruction);
+        true
+    }
+
+    /// Drops the instructions of a RAM page
+    pub fn invalidate_page(&mut self, page: usize) {
+        if let Some(cached_page) = self.pages.get_mut(page) {
+            *cached_page = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.iter_mut().for_each(|page| *page = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{config::CpuConfig, test_hart, Cpu},
+        memory::MemoryOpSize,
+    };
+
+    /// addi x5, x0, 1
+    const ADDI_1: u32 = 0x0010_0293;
+    /// addi x5, x0, 2
+    const ADDI_2: u32 = 0x0020_0293;
+
+    fn raw(cache: &InstructionCache, page: usize, addr: u64) -> Option<u32> {
+        cache.get(page, addr).map(|instruction| instruction.raw())
+    }
+
+    /// Runs the instruction at the start of the RAM
+    fn step_at_base(cpu: &mut Cpu) {
+        cpu.program_counter = DRAM_BASE_ADDR;
+        cpu.step().unwrap();
+    }
+
+    #[test]
+    fn pages_are_invalidated_separately() {
+        let cpu = test_hart::cpu();
+        let mut cache = InstructionCache::new();
+        assert!(cache.insert(0, DRAM_BASE_ADDR + 8, cpu.decode(ADDI_1)));
+        assert!(cache.insert(3, DRAM_BASE_ADDR + 3 * PAGE_SIZE, cpu.decode(ADDI_2)));
+        //Instructions are 4-byte aligned without the C extension
+        assert!(!cache.insert(0, DRAM_BASE_ADDR + 2, cpu.decode(ADDI_1)));
+        assert_eq!(raw(&cache, 0, DRAM_BASE_ADDR + 8), Some(ADDI_1));
+        assert_eq!(raw(&cache, 0, DRAM_BASE_ADDR + 4), None);
+        assert_eq!(raw(&cache, 1, DRAM_BASE_ADDR + PAGE_SIZE), None);
+        cache.invalidate_page(0);
+        assert_eq!(raw(&cache, 0, DRAM_BASE_ADDR + 8), None);
+        assert_eq!(raw(&cache, 3, DRAM_BASE_ADDR + 3 * PAGE_SIZE), Some(ADDI_2));
+        cache.clear();
+        assert_eq!(raw(&cache, 3, DRAM_BASE_ADDR + 3 * PAGE_SIZE), None);
+    }
+
+    #[test]
+    fn stores_to_decoded_instructions_invalidate_them() {
+        let mut cpu = test_hart::cpu();
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, ADDI_1 as u64)
+            .unwrap();
+        step_at_base(&mut cpu);
+        assert_eq!(cpu.registers[5], 1);
+        assert!(cpu.system_bus.is_code_page(DRAM_BASE_ADDR));
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, ADDI_2 as u64)
+            .unwrap();
+        step_at_base(&mut cpu);
+        assert_eq!(cpu.registers[5], 2);
+    }
+
+    #[test]
+    fn stores_of_other_harts_invalidate_the_instructions() {
+        let config = CpuConfig {
+            harts: 2,
+            ..CpuConfig::default()
+        };
+        let machine = test_hart::machine(&config);
+        let mut cpu = Cpu::new(machine.clone(), 0, config.clone());
+        let mut other = Cpu::new(machine, 1, config);
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, ADDI_1 as u64)
+            .unwrap();
+        step_at_base(&mut cpu);
+        other
+            .system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, ADDI_2 as u64)
+            .unwrap();
+        assert!(cpu.system_bus.has_written_code_pages());
+        step_at_base(&mut cpu);
+        assert_eq!(cpu.registers[5], 2);
+    }
+
+    #[test]
+    fn fence_i_drops_every_instruction() {
+        let mut cpu = test_hart::cpu();
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, ADDI_1 as u64)
+            .unwrap();
+        step_at_base(&mut cpu);
+        let page = cpu.system_bus.page_index(DRAM_BASE_ADDR).unwrap();
+        assert!(cpu.instruction_cache.get(page, DRAM_BASE_ADDR).is_some());
+        //fence.i
+        test_hart::execute(&mut cpu, 0x0000_100f).unwrap();
+        assert!(cpu.instruction_cache.get(page, DRAM_BASE_ADDR).is_none());
+    }
+}
@@ -1,4 +1,9 @@
-use super::InstructionRawGetter;
+use crate::{
+    cpu::instructions::implementations::CpuInstructionsOpCodes,
+    error::{AppErrors, AppResult},
+};
+
+use super::{get_op_code, InstructionRawGetter};
 
 #[allow(dead_code)]
 pub enum InstructionFormat {
@@ -10,8 +15,16 @@ pub enum InstructionFormat {
     J,
 }
 
+/// Decoder of a 32 bit instruction, the register fields and the immediate of
+/// the format used by the opcode are extracted once when the decoder is created
+#[derive(Clone, Copy)]
 pub struct Instrunction32Decoder {
     instruction: u32,
+    register_count: u8,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+    imm: u64,
 }
 
 impl InstructionRawGetter for Instrunction32Decoder {
@@ -20,24 +33,87 @@ impl InstructionRawGetter for Instrunction32Decoder {
         self.instruction
     }
 }
+impl RegisterFileDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_register_count(&self) -> u8 {
+        self.register_count
+    }
+}
 impl OpcodeDecoder for Instrunction32Decoder {}
-impl RdDecoder for Instrunction32Decoder {}
+impl RdDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_rd_field(&self) -> u8 {
+        self.rd
+    }
+}
 impl Funct3Decoder for Instrunction32Decoder {}
-impl Rs1Decoder for Instrunction32Decoder {}
-impl Rs2Decoder for Instrunction32Decoder {}
+impl Rs1Decoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_rs1_field(&self) -> u8 {
+        self.rs1
+    }
+}
+impl Rs2Decoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_rs2_field(&self) -> u8 {
+        self.rs2
+    }
+}
 impl Funct7Decoder for Instrunction32Decoder {}
+impl Rs3Decoder for Instrunction32Decoder {}
 
 impl RTypeDecoder for Instrunction32Decoder {}
-impl ITypeDecoder for Instrunction32Decoder {}
-impl STypeDecoder for Instrunction32Decoder {}
-impl UTypeDecoder for Instrunction32Decoder {}
-impl JTypeDecoder for Instrunction32Decoder {}
-impl BTypeDecoder for Instrunction32Decoder {}
+impl R4TypeDecoder for Instrunction32Decoder {}
+impl ITypeDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_i_imm(&self) -> u64 {
+        self.imm
+    }
+}
+impl STypeDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_s_imm(&self) -> u64 {
+        self.imm
+    }
+}
+impl UTypeDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_u_imm(&self) -> u64 {
+        self.imm
+    }
+}
+impl JTypeDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_j_imm(&self) -> u64 {
+        self.imm
+    }
+}
+impl BTypeDecoder for Instrunction32Decoder {
+    #[inline(always)]
+    fn get_b_imm(&self) -> u64 {
+        self.imm
+    }
+}
 
 impl Instrunction32Decoder {
     #[inline(always)]
-    pub fn new(instruction: u32) -> Self {
-        Self { instruction }
+    pub fn new(instruction: u32, register_count: u8) -> Self {
+        let imm = match get_op_code(instruction) {
+            CpuInstructionsOpCodes::STORE | CpuInstructionsOpCodes::STORE_FP => s_imm(instruction),
+            CpuInstructionsOpCodes::CONDITIONAL_BRANCHES => b_imm(instruction),
+            CpuInstructionsOpCodes::INT_REG_IMMEDIATE_LUI
+            | CpuInstructionsOpCodes::INT_REG_IMMEDIATE_AUIPC => u_imm(instruction),
+            CpuInstructionsOpCodes::CONTROL_JAL => j_imm(instruction),
+            _ => i_imm(instruction),
+        };
+        Self {
+            instruction,
+            register_count,
+            rd: ((instruction >> 7) & 0x1f) as u8,
+            rs1: ((instruction >> 15) & 0x1f) as u8,
+            rs2: ((instruction >> 20) & 0x1f) as u8,
+            imm,
+        }
     }
 
     #[inline(always)]
@@ -61,11 +137,32 @@ pub trait OpcodeDecoder: InstructionRawGetter {
         (self.get_raw_instruction() & 0x7f) as u8
     }
 }
-pub trait RdDecoder: InstructionRawGetter {
+/// Size of the integer register file the register fields are checked against,
+/// 16 for the E base ISA
+pub trait RegisterFileDecoder: InstructionRawGetter {
+    fn get_register_count(&self) -> u8;
+
+    /// Returns the register index held in a register field, encodings naming a
+    /// register outside of the register file are illegal
+    #[inline(always)]
+    fn check_register(&self, register: u8) -> AppResult<usize> {
+        match register < self.get_register_count() {
+            true => Ok(register as usize),
+            false => Err(AppErrors::IllegalInstruction {
+                instruction: self.get_raw_instruction(),
+            }),
+        }
+    }
+}
+pub trait RdDecoder: RegisterFileDecoder {
     #[inline(always)]
     fn get_rd_field(&self) -> u8 {
         ((self.get_raw_instruction() >> 7) & 0x1f) as u8
     }
+    #[inline(always)]
+    fn get_rd_register(&self) -> AppResult<usize> {
+        self.check_register(self.get_rd_field())
+    }
 }
 pub trait Funct3Decoder: InstructionRawGetter {
     #[inline(always)]
@@ -73,22 +170,38 @@ pub trait Funct3Decoder: InstructionRawGetter {
         ((self.get_raw_instruction()) >> 12 & 0x07) as u8
     }
 }
-pub trait Rs1Decoder: InstructionRawGetter {
+pub trait Rs1Decoder: RegisterFileDecoder {
     #[inline(always)]
     fn get_rs1_field(&self) -> u8 {
         ((self.get_raw_instruction() >> 15) & 0x1f) as u8
     }
+    #[inline(always)]
+    fn get_rs1_register(&self) -> AppResult<usize> {
+        self.check_register(self.get_rs1_field())
+    }
 }
-pub trait Rs2Decoder: InstructionRawGetter {
+pub trait Rs2Decoder: RegisterFileDecoder {
     #[inline(always)]
     fn get_rs2_field(&self) -> u8 {
         ((self.get_raw_instruction() >> 20) & 0x1f) as u8
     }
+    #[inline(always)]
+    fn get_rs2_register(&self) -> AppResult<usize> {
+        self.check_register(self.get_rs2_field())
+    }
 }
 pub trait Funct7Decoder: InstructionRawGetter {
     #[inline(always)]
     fn get_funct7_field(&self) -> u8 {
-        ((self.get_raw_instruction() >> 25) & 0x3f) as u8
+        ((self.get_raw_instruction() >> 25) & 0x7f) as u8
+    }
+}
+/// Third source register of the fused multiply-add instructions, it always
+/// names a floating point register
+pub trait Rs3Decoder: InstructionRawGetter {
+    #[inline(always)]
+    fn get_rs3_field(&self) -> u8 {
+        ((self.get_raw_instruction() >> 27) & 0x1f) as u8
     }
 }
 // Standard formats decoder traits
@@ -97,12 +210,14 @@ pub trait RTypeDecoder:
 {
 }
 
+pub trait R4TypeDecoder: RTypeDecoder + Rs3Decoder {}
+
 pub trait ITypeDecoder:
     InstructionRawGetter + OpcodeDecoder + RdDecoder + Funct3Decoder + Rs1Decoder
 {
     #[inline(always)]
     fn get_i_imm(&self) -> u64 {
-        ((self.get_raw_instruction() as i32 as i64) >> 20) as u64
+        i_imm(self.get_raw_instruction())
     }
 }
 
@@ -111,26 +226,21 @@ pub trait STypeDecoder:
 {
     #[inline(always)]
     fn get_s_imm(&self) -> u64 {
-        ((self.get_raw_instruction() & 0xfe00_0000) as i32 as i64 >> 20) as u64
-            | ((self.get_raw_instruction() >> 7) & 0x1f) as u64
+        s_imm(self.get_raw_instruction())
     }
 }
 
 pub trait UTypeDecoder: InstructionRawGetter + OpcodeDecoder + RdDecoder {
     #[inline(always)]
     fn get_u_imm(&self) -> u64 {
-        // & 0xffff_f000_u32 just in case this mask sets the lower 12 bits to 0
-        (self.get_raw_instruction() & 0xffff_f000_u32) as i32 as i64 as u64
+        u_imm(self.get_raw_instruction())
     }
 }
 
 pub trait JTypeDecoder: InstructionRawGetter + OpcodeDecoder + RdDecoder {
     #[inline(always)]
     fn get_j_imm(&self) -> u64 {
-        (((self.get_raw_instruction() & 0x80000000) as i32 as i64 >>11) as u64) //Bit [20]
-        | (self.get_raw_instruction() & 0xff000) as u64 //Bits [19:12]
-        | ((self.get_raw_instruction() & 0x100000) >> 9) as u64 // Bit [11]
-        | ((self.get_raw_instruction() & 0x7fe00000) >> 20) as u64 // Bits [10:1]
+        j_imm(self.get_raw_instruction())
     }
 }
 
@@ -139,9 +249,39 @@ pub trait BTypeDecoder:
 {
     #[inline(always)]
     fn get_b_imm(&self) -> u64 {
-        ((self.get_raw_instruction() & 0x8000_0000) as i32 as i64 >> 19) as u64 // Bit [12]
-        | ((self.get_raw_instruction() as u64 & 0x80) << 4) // Bit [11]
-        | ((self.get_raw_instruction() as u64 >> 20) & 0x7e0)   // Bit [10:5];
-        | (self.get_raw_instruction() as u64 >> 7) & 0x1e // Bit [4:1]
+        b_imm(self.get_raw_instruction())
     }
 }
+
+// Sign-extended immediates of the standard formats
+#[inline(always)]
+fn i_imm(instruction: u32) -> u64 {
+    ((instruction as i32 as i64) >> 20) as u64
+}
+
+#[inline(always)]
+fn s_imm(instruction: u32) -> u64 {
+    ((instruction & 0xfe00_0000) as i32 as i64 >> 20) as u64 | ((instruction >> 7) & 0x1f) as u64
+}
+
+#[inline(always)]
+fn u_imm(instruction: u32) -> u64 {
+    // & 0xffff_f000_u32 just in case this mask sets the lower 12 bits to 0
+    (instruction & 0xffff_f000_u32) as i32 as i64 as u64
+}
+
+#[inline(always)]
+fn j_imm(instruction: u32) -> u64 {
+    (((instruction & 0x80000000) as i32 as i64 >>11) as u64) //Bit [20]
+    | (instruction & 0xff000) as u64 //Bits [19:12]
+    | ((instruction & 0x100000) >> 9) as u64 // Bit [11]
+    | ((instruction & 0x7fe00000) >> 20) as u64 // Bits [10:1]
+}
+
+#[inline(always)]
+fn b_imm(instruction: u32) -> u64 {
+    ((instruction & 0x8000_0000) as i32 as i64 >> 19) as u64 // Bit [12]
+    | ((instruction as u64 & 0x80) << 4) // Bit [11]
+    | ((instruction as u64 >> 20) & 0x7e0)   // Bit [10:5];
+    | (instruction as u64 >> 7) & 0x1e // Bit [4:1]
+}
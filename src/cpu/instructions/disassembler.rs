@@ -0,0 +1,238 @@
+use std::fmt::{self, Display, Formatter};
+
+use super::{
+    decoder::b32::{BTypeDecoder, ITypeDecoder, Instrunction32Decoder, JTypeDecoder, STypeDecoder},
+    table::{self, Operands, INSTRUCTIONS},
+};
+
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const FLOAT_REGISTER_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// Constants loaded by the Zfa fli instructions, indexed by rs1
+const FLI_OPERANDS: [&str; 32] = [
+    "-1.0",
+    "min",
+    "1.52587890625e-05",
+    "3.0517578125e-05",
+    "0.00390625",
+    "0.0078125",
+    "0.0625",
+    "0.125",
+    "0.25",
+    "0.3125",
+    "0.375",
+    "0.4375",
+    "0.5",
+    "0.625",
+    "0.75",
+    "0.875",
+    "1.0",
+    "1.25",
+    "1.5",
+    "1.75",
+    "2.0",
+    "2.5",
+    "3.0",
+    "4.0",
+    "8.0",
+    "16.0",
+    "128.0",
+    "256.0",
+    "32768.0",
+    "65536.0",
+    "inf",
+    "nan",
+];
+
+/// Width suffixes of the atomic instructions, indexed by funct3
+const ATOMIC_WIDTHS: [&str; 8] = [".b", ".h", ".w", ".d", ".q", ".?", ".?", ".?"];
+
+/// Assembly syntax of an instruction at an address, branch and jump targets
+/// are printed as absolute addresses
+pub struct Disassembly {
+    pub pc: u64,
+    pub instruction: u32,
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let raw = self.instruction;
+        let description = &INSTRUCTIONS[table::lookup(raw) as usize];
+        //The register fields are printed as they are, the register count only
+        //matters for execution
+        let decoder = Instrunction32Decoder::new(raw, 32);
+        let register = |shift: u32| REGISTER_NAMES[((raw >> shift) & 0x1f) as usize];
+        let (rd, rs1, rs2) = (register(7), register(15), register(20));
+        let float_register = |shift: u32| FLOAT_REGISTER_NAMES[((raw >> shift) & 0x1f) as usize];
+        let (fd, fs1, fs2, fs3) = (
+            float_register(7),
+            float_register(15),
+            float_register(20),
+            float_register(27),
+        );
+        let mnemonic = description.mnemonic;
+        match description.operands {
+            Operands::None => write!(f, "{mnemonic}"),
+            Operands::Register => write!(f, "{mnemonic} {rd}, {rs1}, {rs2}"),
+            Operands::Immediate => {
+                let imm = decoder.get_i_imm() as i64;
+                write!(f, "{mnemonic} {rd}, {rs1}, {imm}")
+            }
+            Operands::Shift => write!(f, "{mnemonic} {rd}, {rs1}, {}", (raw >> 20) & 0x3f),
+            Operands::Upper => write!(f, "{mnemonic} {rd}, {:#x}", raw >> 12),
+            Operands::Jump => {
+                let target = self.pc.wrapping_add(decoder.get_j_imm());
+                write!(f, "{mnemonic} {rd}, {target:x}")
+            }
+            Operands::Branch => {
+                let target = self.pc.wrapping_add(decoder.get_b_imm());
+                write!(f, "{mnemonic} {rs1}, {rs2}, {target:x}")
+            }
+            Operands::Load => {
+                let offset = decoder.get_i_imm() as i64;
+                write!(f, "{mnemonic} {rd}, {offset}({rs1})")
+            }
+            Operands::Store => {
+                let offset = decoder.get_s_imm() as i64;
+                write!(f, "{mnemonic} {rs2}, {offset}({rs1})")
+            }
+            Operands::Csr => write!(f, "{mnemonic} {rd}, {:#x}, {rs1}", raw >> 20),
+            Operands::CsrImmediate => {
+                let uimm = (raw >> 15) & 0x1f;
+                write!(f, "{mnemonic} {rd}, {:#x}, {uimm}", raw >> 20)
+            }
+            Operands::Atomic | Operands::LoadReserved => {
+                let width = ATOMIC_WIDTHS[((raw >> 12) & 0x7) as usize];
+                let ordering = match (raw >> 25) & 0b11 {
+                    0b00 => "",
+                    0b01 => ".rl",
+                    0b10 => ".aq",
+                    _ => ".aqrl",
+                };
+                match description.operands {
+                    Operands::Atomic => {
+                        write!(f, "{mnemonic}{width}{ordering} {rd}, {rs2}, ({rs1})")
+                    }
+                    _ => write!(f, "{mnemonic}{width}{ordering} {rd}, ({rs1})"),
+                }
+            }
+            Operands::HypervisorLoad => write!(f, "{mnemonic} {rd}, ({rs1})"),
+            Operands::HypervisorStore => write!(f, "{mnemonic} {rs2}, ({rs1})"),
+            Operands::Fence => write!(f, "{mnemonic} {rs1}, {rs2}"),
+            Operands::MemoryFence => {
+                let set = |bits: u32| {
+                    let set: String = ["i", "o", "r", "w"]
+                        .iter()
+                        .enumerate()
+                        .filter(|(index, _)| bits & (0b1000 >> index) != 0)
+                        .map(|(_, access)| *access)
+                        .collect();
+                    match set.is_empty() {
+                        true => "0".to_string(),
+                        false => set,
+                    }
+                };
+                let suffix = match raw >> 28 {
+                    0b1000 => ".tso",
+                    _ => "",
+                };
+                write!(
+                    f,
+                    "{mnemonic}{suffix} {}, {}",
+                    set(raw >> 24 & 0xf),
+                    set(raw >> 20 & 0xf)
+                )
+            }
+            Operands::CacheBlock => write!(f, "{mnemonic} ({rs1})"),
+            Operands::Prefetch => {
+                let offset = (decoder.get_i_imm() & !0x1f) as i64;
+                write!(f, "{mnemonic} {offset}({rs1})")
+            }
+            Operands::FloatLoad => {
+                let offset = decoder.get_i_imm() as i64;
+                write!(f, "{mnemonic} {fd}, {offset}({rs1})")
+            }
+            Operands::FloatStore => {
+                let offset = decoder.get_s_imm() as i64;
+                write!(f, "{mnemonic} {fs2}, {offset}({rs1})")
+            }
+            Operands::FloatRegister => write!(f, "{mnemonic} {fd}, {fs1}, {fs2}"),
+            Operands::FloatUnary => write!(f, "{mnemonic} {fd}, {fs1}"),
+            Operands::FloatFused => write!(f, "{mnemonic} {fd}, {fs1}, {fs2}, {fs3}"),
+            Operands::FloatCompare => write!(f, "{mnemonic} {rd}, {fs1}, {fs2}"),
+            Operands::FloatToInteger => write!(f, "{mnemonic} {rd}, {fs1}"),
+            Operands::IntegerToFloat => write!(f, "{mnemonic} {fd}, {rs1}"),
+            Operands::IntegerPairToFloat => write!(f, "{mnemonic} {fd}, {rs1}, {rs2}"),
+            Operands::FloatImmediate => {
+                let value = FLI_OPERANDS[((raw >> 15) & 0x1f) as usize];
+                write!(f, "{mnemonic} {fd}, {value}")
+            }
+            Operands::Unknown => match raw & 0b11 {
+                0b11 => write!(f, ".word {raw:#010x}"),
+                _ => write!(f, ".half {:#06x}", raw & 0xffff),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(pc: u64, instruction: u32) -> String {
+        Disassembly { pc, instruction }.to_string()
+    }
+
+    #[test]
+    fn operands_follow_the_assembly_syntax() {
+        let cases = [
+            (0x0012_8293, "addi t0, t0, 1"),
+            (0xfff2_8293, "addi t0, t0, -1"),
+            (0x0075_2423, "sw t2, 8(a0)"),
+            (0xfe62_cee3, "blt t0, t1, ffc"),
+            (0x0000_006f, "jal zero, 1000"),
+            (0x0055_b32f, "amoadd.d t1, t0, (a1)"),
+            (0x0655_a32f, "amoadd.w.aqrl t1, t0, (a1)"),
+            (0x1005_32af, "lr.d t0, (a0)"),
+            (0xf140_2573, "csrrs a0, 0xf14, zero"),
+            (0x0330_000f, "fence rw, rw"),
+            (0x8330_000f, "fence.tso rw, rw"),
+            (0x1200_0073, "sfence.vma zero, zero"),
+            (0x0000_0000, ".half 0x0000"),
+            (0xffff_ffff, ".word 0xffffffff"),
+        ];
+        for (instruction, text) in cases {
+            assert_eq!(disassemble(0x1000, instruction), text);
+        }
+    }
+
+    #[test]
+    fn every_instruction_prints_its_mnemonic() {
+        for description in INSTRUCTIONS
+            .iter()
+            .filter(|description| description.operands != Operands::Unknown)
+        {
+            let text = disassemble(0, description.encoding.bits | !description.encoding.mask);
+            let printed = text.split(' ').next().unwrap();
+            //Only the atomics and fences append suffixes to the mnemonic
+            let suffixed = matches!(
+                description.operands,
+                Operands::Atomic | Operands::LoadReserved | Operands::MemoryFence
+            );
+            assert!(
+                printed == description.mnemonic
+                    || suffixed && printed.starts_with(&format!("{}.", description.mnemonic)),
+                "{text}"
+            );
+        }
+    }
+}
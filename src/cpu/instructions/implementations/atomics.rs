@@ -0,0 +1,526 @@
+use crate::{
+    cpu::{
+        extensions::Extension, instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::RTypeDecoder, pmp::MemoryAccess,
+        side_effects::OperationSideEffect, xlen::Xlen, Cpu,
+    },
+    error::{AppErrors, AppResult},
+    memory::MemoryOpSize,
+};
+
+use super::SubFunctions;
+
+///Funct3 field, access width
+impl SubFunctions {
+    /// Byte (Zabha)
+    pub const AMO_B: u8 = 0b000;
+    /// Half Word (Zabha)
+    pub const AMO_H: u8 = 0b001;
+    /// Word (32-bit)
+    pub const AMO_W: u8 = 0b010;
+    /// Double Word (64-bit)
+    pub const AMO_D: u8 = 0b011;
+    /// Quad Word (128-bit), only used by amocas.q
+    pub const AMO_Q: u8 = 0b100;
+}
+
+///Funct5 field, the upper bits of funct7 without the aq/rl ordering bits
+impl SubFunctions {
+    pub const AMOADD: u8 = 0b00000;
+    pub const AMOSWAP: u8 = 0b00001;
+    /// Load Reserved
+    pub const LR: u8 = 0b00010;
+    /// Store Conditional
+    pub const SC: u8 = 0b00011;
+    pub const AMOXOR: u8 = 0b00100;
+    /// Compare and Swap (Zacas)
+    pub const AMOCAS: u8 = 0b00101;
+    pub const AMOOR: u8 = 0b01000;
+    pub const AMOAND: u8 = 0b01100;
+    pub const AMOMIN: u8 = 0b10000;
+    pub const AMOMAX: u8 = 0b10100;
+    pub const AMOMINU: u8 = 0b11000;
+    pub const AMOMAXU: u8 = 0b11100;
+}
+
+impl InstructionsExecutor {
+    /// Decodes the access width and operation of the atomic memory instructions.
+    /// Every atomic operation is sequentially consistent with respect to the
+    /// other harts, so the aq/rl bits are ignored.
+    #[inline(always)]
+    pub fn atomic(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        let funct5 = instruction.get_funct7_field() >> 2;
+        cpu.require_extension(Extension::A, raw_instruction)?;
+        if funct5 == SubFunctions::AMOCAS {
+            cpu.require_extension(Extension::Zacas, raw_instruction)?;
+        }
+
+        let size = match instruction.get_funct3_field() {
+            SubFunctions::AMO_B | SubFunctions::AMO_H
+                if funct5 == SubFunctions::LR || funct5 == SubFunctions::SC =>
+            {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: raw_instruction,
+                });
+            }
+            SubFunctions::AMO_B => {
+                cpu.require_extension(Extension::Zabha, raw_instruction)?;
+                MemoryOpSize::B8
+            }
+            SubFunctions::AMO_H => {
+                cpu.require_extension(Extension::Zabha, raw_instruction)?;
+                MemoryOpSize::B16
+            }
+            SubFunctions::AMO_W => MemoryOpSize::B32,
+            SubFunctions::AMO_D if cpu.xlen == Xlen::X64 => MemoryOpSize::B64,
+            //amocas.d on RV32 and amocas.q on RV64 operate on register pairs
+            SubFunctions::AMO_D if funct5 == SubFunctions::AMOCAS => {
+                return Self::amocas_pair(cpu, instruction);
+            }
+            SubFunctions::AMO_Q if funct5 == SubFunctions::AMOCAS && cpu.xlen == Xlen::X64 => {
+                return Self::amocas_pair(cpu, instruction);
+            }
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: raw_instruction,
+                })
+            }
+        };
+
+        match funct5 {
+            SubFunctions::LR => Self::lr(cpu, instruction, size),
+            SubFunctions::SC => Self::sc(cpu, instruction, size),
+            SubFunctions::AMOCAS => Self::amocas(cpu, instruction, size),
+            _ => Self::amo(cpu, instruction, size, funct5),
+        }
+    }
+
+    /// Loads the value at the rs1 address sign-extended into rd and registers
+    /// a reservation on that address holding the loaded value
+    #[inline(always)]
+    fn lr(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+        size: MemoryOpSize,
+    ) -> AppResult<OperationSideEffect> {
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        if !addr.is_multiple_of(size.bytes()) {
+            return Err(AppErrors::LoadAddressMisaligned { addr });
+        }
+        let addr = cpu.translate_address(addr, size.bytes(), MemoryAccess::Load)?;
+        let value = cpu.system_bus.fetch_update(addr, size.clone(), |_| None)?;
+        cpu.reservation = Some((addr, size.clone(), value));
+        cpu.write_reg(instruction.get_rd_register()?, size.sign_extend(value))
+    }
+
+    /// Stores rs2 at the rs1 address if a reservation is still held for it, rd is
+    /// set to 0 on success and 1 on failure. The reservation is always released.
+    /// It is lost once the memory no longer holds the value loaded by LR, so
+    /// other harts writing back the same value go unnoticed.
+    #[inline(always)]
+    fn sc(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+        size: MemoryOpSize,
+    ) -> AppResult<OperationSideEffect> {
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        if !addr.is_multiple_of(size.bytes()) {
+            return Err(AppErrors::StoreAddressMisaligned { addr });
+        }
+        let addr = cpu.translate_address(addr, size.bytes(), MemoryAccess::Store)?;
+        let value = cpu.read_reg(instruction.get_rs2_register()?);
+        let stored = match cpu.reservation.take() {
+            Some((reserved, _, expected)) if reserved == addr => {
+                let mut stored = false;
+                cpu.system_bus.fetch_update(addr, size, |loaded| {
+                    stored = loaded == expected;
+                    stored.then_some(value)
+                })?;
+                stored
+            }
+            _ => false,
+        };
+        cpu.write_reg(instruction.get_rd_register()?, !stored as u64)
+    }
+
+    /// Loads the value at the rs1 address into rd and stores back the result of
+    /// the operation between the loaded value and rs2
+    #[inline(always)]
+    fn amo(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+        size: MemoryOpSize,
+        funct5: u8,
+    ) -> AppResult<OperationSideEffect> {
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        cpu.check_atomic_alignment(addr, size.bytes())?;
+        let addr = cpu.translate_address(addr, size.bytes(), MemoryAccess::Store)?;
+        let source = size.sign_extend(cpu.read_reg(instruction.get_rs2_register()?));
+        let operation: fn(u64, u64, &MemoryOpSize) -> u64 = match funct5 {
+            SubFunctions::AMOSWAP => |_, source, _| source,
+            SubFunctions::AMOADD => |loaded, source, _| loaded.wrapping_add(source),
+            SubFunctions::AMOXOR => |loaded, source, _| loaded ^ source,
+            SubFunctions::AMOAND => |loaded, source, _| loaded & source,
+            SubFunctions::AMOOR => |loaded, source, _| loaded | source,
+            SubFunctions::AMOMIN => |loaded, source, _| (loaded as i64).min(source as i64) as u64,
+            SubFunctions::AMOMAX => |loaded, source, _| (loaded as i64).max(source as i64) as u64,
+            SubFunctions::AMOMINU => {
+                |loaded, source, size| size.zero_extend(loaded).min(size.zero_extend(source))
+            }
+            SubFunctions::AMOMAXU => {
+                |loaded, source, size| size.zero_extend(loaded).max(size.zero_extend(source))
+            }
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: instruction.get_raw_instruction(),
+                })
+            }
+        };
+        let loaded = cpu.system_bus.fetch_update(addr, size.clone(), |loaded| {
+            Some(operation(size.sign_extend(loaded), source, &size))
+        })?;
+        cpu.write_reg(instruction.get_rd_register()?, size.sign_extend(loaded))
+    }
+
+    /// Zacas compare and swap, loads the value at the rs1 address into rd and
+    /// stores rs2 if the loaded value was equal to the original value of rd
+    #[inline(always)]
+    fn amocas(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+        size: MemoryOpSize,
+    ) -> AppResult<OperationSideEffect> {
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        cpu.check_atomic_alignment(addr, size.bytes())?;
+        let addr = cpu.translate_address(addr, size.bytes(), MemoryAccess::Store)?;
+        let rd = instruction.get_rd_register()?;
+        let expected = size.zero_extend(cpu.read_reg(rd));
+        let value = cpu.read_reg(instruction.get_rs2_register()?);
+        let loaded = cpu.system_bus.fetch_update(addr, size.clone(), |loaded| {
+            (loaded == expected).then_some(value)
+        })?;
+        cpu.write_reg(rd, size.sign_extend(loaded))
+    }
+
+    /// Zacas compare and swap of twice the XLEN, rd and rs2 name even/odd register
+    /// pairs holding the low and high halves. Using x0 as a pair reads zero for
+    /// both halves and discards the result.
+    #[inline(always)]
+    fn amocas_pair(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let rd = instruction.get_rd_register()?;
+        let rs2 = instruction.get_rs2_register()?;
+        if !rd.is_multiple_of(2) || !rs2.is_multiple_of(2) {
+            return Err(AppErrors::IllegalInstruction {
+                instruction: instruction.get_raw_instruction(),
+            });
+        }
+        let half = match cpu.xlen {
+            Xlen::X32 => MemoryOpSize::B32,
+            Xlen::X64 => MemoryOpSize::B64,
+        };
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        cpu.check_atomic_alignment(addr, 2 * half.bytes())?;
+        let addr = cpu.translate_address(addr, 2 * half.bytes(), MemoryAccess::Store)?;
+        let register_pair = |cpu: &Cpu, register: usize| match register {
+            0 => (0, 0),
+            _ => (
+                half.zero_extend(cpu.read_reg(register)),
+                half.zero_extend(cpu.read_reg(register + 1)),
+            ),
+        };
+        let (expected_low, expected_high) = register_pair(cpu, rd);
+        let (low, high) = register_pair(cpu, rs2);
+        //The pair is compared and swapped as a single access of twice the XLEN
+        let loaded = match cpu.xlen {
+            Xlen::X32 => {
+                let loaded = cpu
+                    .system_bus
+                    .fetch_update(addr, MemoryOpSize::B64, |loaded| {
+                        (loaded == expected_low | expected_high << 32).then_some(low | high << 32)
+                    })?;
+                (loaded as u32 as u64, loaded >> 32)
+            }
+            Xlen::X64 => {
+                let loaded = cpu.system_bus.compare_exchange_128(
+                    addr,
+                    expected_low as u128 | (expected_high as u128) << 64,
+                    low as u128 | (high as u128) << 64,
+                )?;
+                (loaded as u64, (loaded >> 64) as u64)
+            }
+        };
+        if rd != 0 {
+            cpu.write_reg(rd, loaded.0)?;
+            cpu.write_reg(rd + 1, loaded.1)?;
+        }
+        Ok(OperationSideEffect::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{
+            config::CpuConfig,
+            instructions::decoder::b32::Instrunction32Decoder,
+            test_hart::{self, cpu},
+        },
+    };
+
+    const ATOMIC_OPCODE: u32 = 0b0101111;
+
+    fn execute(
+        cpu: &mut Cpu,
+        funct5: u8,
+        funct3: u8,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+    ) -> AppResult<OperationSideEffect> {
+        let raw_instruction = (funct5 as u32) << 27
+            | rs2 << 20
+            | rs1 << 15
+            | (funct3 as u32) << 12
+            | rd << 7
+            | ATOMIC_OPCODE;
+        let instruction = Instrunction32Decoder::new(raw_instruction, cpu.register_count());
+        InstructionsExecutor::atomic(cpu, instruction)
+    }
+
+    #[test]
+    fn sc_succeeds_on_held_reservation() {
+        let mut cpu = cpu();
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B64, 7)
+            .unwrap();
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        cpu.registers[11] = 42;
+        execute(&mut cpu, SubFunctions::LR, SubFunctions::AMO_D, 5, 10, 0).unwrap();
+        assert_eq!(cpu.registers[5], 7);
+        execute(&mut cpu, SubFunctions::SC, SubFunctions::AMO_D, 6, 10, 11).unwrap();
+        assert_eq!(cpu.registers[6], 0);
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR).unwrap(), 42);
+        //The reservation was released by the first SC
+        cpu.registers[11] = 43;
+        execute(&mut cpu, SubFunctions::SC, SubFunctions::AMO_D, 6, 10, 11).unwrap();
+        assert_eq!(cpu.registers[6], 1);
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR).unwrap(), 42);
+    }
+
+    #[test]
+    fn sc_fails_after_intervening_store() {
+        let mut cpu = cpu();
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, 7)
+            .unwrap();
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        cpu.registers[11] = 42;
+        execute(&mut cpu, SubFunctions::LR, SubFunctions::AMO_W, 5, 10, 0).unwrap();
+        //Another hart stores to the reserved word
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B32, 8)
+            .unwrap();
+        execute(&mut cpu, SubFunctions::SC, SubFunctions::AMO_W, 6, 10, 11).unwrap();
+        assert_eq!(cpu.registers[6], 1);
+        assert_eq!(cpu.system_bus.load32(DRAM_BASE_ADDR).unwrap(), 8);
+        //A reservation on another address does not cover the SC address
+        execute(&mut cpu, SubFunctions::LR, SubFunctions::AMO_W, 5, 10, 0).unwrap();
+        cpu.registers[12] = DRAM_BASE_ADDR + 8;
+        execute(&mut cpu, SubFunctions::SC, SubFunctions::AMO_W, 6, 12, 11).unwrap();
+        assert_eq!(cpu.registers[6], 1);
+        assert_eq!(cpu.system_bus.load32(DRAM_BASE_ADDR + 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn amocas_q_swaps_register_pairs() {
+        let mut cpu = cpu();
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B64, 1)
+            .unwrap();
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR + 8, MemoryOpSize::B64, 2)
+            .unwrap();
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        //Only the high half differs, the memory is left alone
+        cpu.registers[4] = 1;
+        cpu.registers[5] = 3;
+        cpu.registers[6] = 10;
+        cpu.registers[7] = 20;
+        execute(
+            &mut cpu,
+            SubFunctions::AMOCAS,
+            SubFunctions::AMO_Q,
+            4,
+            10,
+            6,
+        )
+        .unwrap();
+        assert_eq!((cpu.registers[4], cpu.registers[5]), (1, 2));
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR + 8).unwrap(), 2);
+        //rd now holds the loaded pair, so the swap succeeds
+        execute(
+            &mut cpu,
+            SubFunctions::AMOCAS,
+            SubFunctions::AMO_Q,
+            4,
+            10,
+            6,
+        )
+        .unwrap();
+        assert_eq!((cpu.registers[4], cpu.registers[5]), (1, 2));
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR).unwrap(), 10);
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR + 8).unwrap(), 20);
+        //x0 as rd compares against zero in both halves and discards the result
+        cpu.registers[10] = DRAM_BASE_ADDR + 16;
+        execute(
+            &mut cpu,
+            SubFunctions::AMOCAS,
+            SubFunctions::AMO_Q,
+            0,
+            10,
+            6,
+        )
+        .unwrap();
+        assert_eq!(cpu.registers[0], 0);
+        assert_eq!(cpu.registers[1], 0);
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR + 16).unwrap(), 10);
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR + 24).unwrap(), 20);
+        //x0 as rs2 stores zero in both halves
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        cpu.registers[4] = 10;
+        cpu.registers[5] = 20;
+        execute(
+            &mut cpu,
+            SubFunctions::AMOCAS,
+            SubFunctions::AMO_Q,
+            4,
+            10,
+            0,
+        )
+        .unwrap();
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR).unwrap(), 0);
+        assert_eq!(cpu.system_bus.load64(DRAM_BASE_ADDR + 8).unwrap(), 0);
+    }
+
+    #[test]
+    fn amocas_q_rejects_odd_registers_and_misaligned_addresses() {
+        let mut cpu = cpu();
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        assert!(matches!(
+            execute(
+                &mut cpu,
+                SubFunctions::AMOCAS,
+                SubFunctions::AMO_Q,
+                5,
+                10,
+                6
+            ),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        assert!(matches!(
+            execute(
+                &mut cpu,
+                SubFunctions::AMOCAS,
+                SubFunctions::AMO_Q,
+                4,
+                10,
+                7
+            ),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        cpu.registers[10] = DRAM_BASE_ADDR + 8;
+        assert!(execute(
+            &mut cpu,
+            SubFunctions::AMOCAS,
+            SubFunctions::AMO_Q,
+            4,
+            10,
+            6
+        )
+        .is_err());
+    }
+
+    /// Runs the body on each hart of a machine, every hart on its own host
+    /// thread, and returns the first hart once they are all done
+    fn on_harts(count: usize, body: impl Fn(&mut Cpu) + Sync) -> Cpu {
+        let config = CpuConfig {
+            harts: count,
+            ..CpuConfig::default()
+        };
+        let machine = test_hart::machine(&config);
+        thread::scope(|scope| {
+            for hart in 0..count {
+                let (machine, config, body) = (machine.clone(), config.clone(), &body);
+                scope.spawn(move || body(&mut Cpu::new(machine, hart, config)));
+            }
+        });
+        Cpu::new(machine, 0, config)
+    }
+
+    #[test]
+    fn amos_are_atomic_across_harts() {
+        const ITERATIONS: u64 = 20_000;
+        let mut cpu = on_harts(4, |cpu| {
+            //Words, doublewords and halfwords sharing a word with a byte
+            cpu.registers[10] = DRAM_BASE_ADDR;
+            cpu.registers[11] = DRAM_BASE_ADDR + 8;
+            cpu.registers[12] = DRAM_BASE_ADDR + 16;
+            cpu.registers[13] = DRAM_BASE_ADDR + 18;
+            cpu.registers[14] = 1;
+            for _ in 0..ITERATIONS {
+                for (funct3, rs1) in [
+                    (SubFunctions::AMO_W, 10),
+                    (SubFunctions::AMO_D, 11),
+                    (SubFunctions::AMO_H, 12),
+                    (SubFunctions::AMO_B, 13),
+                ] {
+                    execute(cpu, SubFunctions::AMOADD, funct3, 0, rs1, 14).unwrap();
+                }
+            }
+        });
+        assert_eq!(
+            cpu.system_bus.load32(DRAM_BASE_ADDR).unwrap() as u64,
+            4 * ITERATIONS
+        );
+        assert_eq!(
+            cpu.system_bus.load64(DRAM_BASE_ADDR + 8).unwrap(),
+            4 * ITERATIONS
+        );
+        assert_eq!(
+            cpu.system_bus.load16(DRAM_BASE_ADDR + 16).unwrap() as u64,
+            4 * ITERATIONS % (1 << 16)
+        );
+        assert_eq!(
+            cpu.system_bus.load8(DRAM_BASE_ADDR + 18).unwrap() as u64,
+            4 * ITERATIONS % (1 << 8)
+        );
+        assert_eq!(cpu.system_bus.load8(DRAM_BASE_ADDR + 19).unwrap(), 0);
+    }
+
+    #[test]
+    fn lr_sc_increments_are_atomic_across_harts() {
+        const ITERATIONS: u64 = 5_000;
+        let mut cpu = on_harts(2, |cpu| {
+            cpu.registers[10] = DRAM_BASE_ADDR;
+            let mut increments = 0;
+            while increments < ITERATIONS {
+                execute(cpu, SubFunctions::LR, SubFunctions::AMO_D, 5, 10, 0).unwrap();
+                cpu.registers[5] += 1;
+                execute(cpu, SubFunctions::SC, SubFunctions::AMO_D, 6, 10, 5).unwrap();
+                increments += 1 - cpu.registers[6];
+            }
+        });
+        assert_eq!(
+            cpu.system_bus.load64(DRAM_BASE_ADDR).unwrap(),
+            2 * ITERATIONS
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{
+    cache_model::CacheBlockOperation,
+    cpu::{
+        cs_registers::EnvCfgFields, extensions::Extension,
+        instruction_excecutors::InstructionsExecutor, instructions::decoder::b32::ITypeDecoder,
+        pmp::MemoryAccess, side_effects::OperationSideEffect, Cpu,
+    },
+    error::{AppErrors, AppResult},
+    memory::MemoryOpSize,
+};
+
+use super::SubFunctions;
+
+impl SubFunctions {
+    /// Cache block operations share the MISC-MEM opcode with FENCE, funct3 field
+    pub const CBO: u8 = 0b010;
+    /// Cache block operation selector held in the funct12 field
+    pub const CBO_INVAL: u16 = 0b000;
+    pub const CBO_CLEAN: u16 = 0b001;
+    pub const CBO_FLUSH: u16 = 0b010;
+    pub const CBO_ZERO: u16 = 0b100;
+    /// Prefetch hints are ORI instructions with rd=x0, selected by imm[4:0]
+    pub const PREFETCH_I: u64 = 0b00000;
+    pub const PREFETCH_R: u64 = 0b00001;
+    pub const PREFETCH_W: u64 = 0b00011;
+}
+
+impl InstructionsExecutor {
+    /// Zicbom cbo.inval, depending on the envcfg CBIE field it either invalidates
+    /// the cache block or performs a flush instead
+    #[inline(always)]
+    pub fn cbo_inval(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zicbom, instruction.get_raw_instruction())?;
+        let operation = match cpu.effective_envcfg(EnvCfgFields::CBIE) >> EnvCfgFields::CBIE_SHIFT {
+            EnvCfgFields::CBIE_INVALIDATE => CacheBlockOperation::Invalidate,
+            EnvCfgFields::CBIE_FLUSH => CacheBlockOperation::Flush,
+            _ => {
+                return Err(
+                    cpu.envcfg_exception(EnvCfgFields::CBIE, instruction.get_raw_instruction())
+                )
+            }
+        };
+        let addr = cpu.cache_block_address(&instruction)?;
+        let addr = cpu.translate_cache_block_management_address(addr)?;
+        cpu.system_bus.notify_cache_block_operation(operation, addr);
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Zicbom cbo.clean, writes back the cache block if it has been modified
+    #[inline(always)]
+    pub fn cbo_clean(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        Self::cbo_clean_flush(cpu, instruction, CacheBlockOperation::Clean)
+    }
+
+    /// Zicbom cbo.flush, writes back the cache block if needed and invalidates it
+    #[inline(always)]
+    pub fn cbo_flush(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        Self::cbo_clean_flush(cpu, instruction, CacheBlockOperation::Flush)
+    }
+
+    /// Zicboz cbo.zero, stores zeros to the full cache block holding the rs1 address
+    #[inline(always)]
+    pub fn cbo_zero(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zicboz, instruction.get_raw_instruction())?;
+        if cpu.effective_envcfg(EnvCfgFields::CBZE) == 0 {
+            return Err(cpu.envcfg_exception(EnvCfgFields::CBZE, instruction.get_raw_instruction()));
+        }
+        let virtual_addr = cpu.cache_block_address(&instruction)?;
+        let addr = cpu.translate_address(
+            virtual_addr,
+            cpu.config.cache_block_size,
+            MemoryAccess::Store,
+        )?;
+        for offset in (0..cpu.config.cache_block_size).step_by(8) {
+            //The bus only knows the physical address, the trap value has to
+            //be the virtual address of the faulting store
+            cpu.system_bus
+                .store(addr + offset, MemoryOpSize::B64, 0)
+                .map_err(|err| match err {
+                    AppErrors::StoreAccessFault { .. } | AppErrors::AddressNotFound => {
+                        AppErrors::StoreAccessFault {
+                            addr: virtual_addr + offset,
+                        }
+                    }
+                    err => err,
+                })?;
+        }
+        cpu.system_bus
+            .notify_cache_block_operation(CacheBlockOperation::Zero, addr);
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Zicbop prefetch.i/r/w, these are only hints so no exception is ever raised,
+    /// any other ORI with rd=x0 is still a regular hint that does nothing
+    #[inline(always)]
+    pub fn prefetch(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        if !cpu.extensions.contains(Extension::Zicbop) {
+            return Ok(OperationSideEffect::None);
+        }
+        let imm = instruction.get_i_imm();
+        let operation = match imm & 0x1f {
+            SubFunctions::PREFETCH_I => CacheBlockOperation::PrefetchInstruction,
+            SubFunctions::PREFETCH_R => CacheBlockOperation::PrefetchRead,
+            SubFunctions::PREFETCH_W => CacheBlockOperation::PrefetchWrite,
+            _ => return Ok(OperationSideEffect::None),
+        };
+        let addr = cpu.effective_address(
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_add(imm & !0x1f),
+        ) & !(cpu.config.cache_block_size - 1);
+        //Prefetches of addresses that would fault are dropped
+        let access = match operation {
+            CacheBlockOperation::PrefetchInstruction => MemoryAccess::Fetch,
+            _ => MemoryAccess::Load,
+        };
+        let Ok(addr) = cpu.translate_address(addr, cpu.config.cache_block_size, access) else {
+            return Ok(OperationSideEffect::None);
+        };
+        cpu.system_bus.notify_cache_block_operation(operation, addr);
+        Ok(OperationSideEffect::None)
+    }
+
+    #[inline(always)]
+    fn cbo_clean_flush(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+        operation: CacheBlockOperation,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zicbom, instruction.get_raw_instruction())?;
+        if cpu.effective_envcfg(EnvCfgFields::CBCFE) == 0 {
+            return Err(
+                cpu.envcfg_exception(EnvCfgFields::CBCFE, instruction.get_raw_instruction())
+            );
+        }
+        let addr = cpu.cache_block_address(&instruction)?;
+        let addr = cpu.translate_cache_block_management_address(addr)?;
+        cpu.system_bus.notify_cache_block_operation(operation, addr);
+        Ok(OperationSideEffect::None)
+    }
+}
+
+impl Cpu {
+    /// Cache block operations take the address in rs1 without offset, aligned down
+    /// to the start of the block holding it
+    #[inline(always)]
+    fn cache_block_address(&self, instruction: &impl ITypeDecoder) -> AppResult<u64> {
+        Ok(
+            self.effective_address(self.read_reg(instruction.get_rs1_register()?))
+                & !(self.config.cache_block_size - 1),
+        )
+    }
+
+    /// Cache block management instructions can access a block whenever a load
+    /// or a store could, faults are always reported as store/AMO faults
+    #[inline(always)]
+    fn translate_cache_block_management_address(&mut self, addr: u64) -> AppResult<u64> {
+        let size = self.config.cache_block_size;
+        self.translate_address(addr, size, MemoryAccess::Load)
+            .or_else(|_| self.translate_address(addr, size, MemoryAccess::Store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        cache_model::CacheModel,
+        consts::{DRAM_BASE_ADDR, PAGE_SIZE},
+        cpu::{
+            config::CpuConfig,
+            cs_registers::MachineLevelCSRegisters,
+            instructions::implementations::CpuInstructionsOpCodes,
+            mmu::{PteFields, SatpFields},
+            privilege::PrivilegeMode,
+            test_hart::{cpu, cpu_with, execute},
+        },
+    };
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<(CacheBlockOperation, u64)>>>);
+
+    impl CacheModel for Recorder {
+        fn observe(&mut self, operation: CacheBlockOperation, addr: u64) {
+            self.0.lock().unwrap().push((operation, addr));
+        }
+    }
+
+    fn cbo(operation: u16, rs1: u32) -> u32 {
+        (operation as u32) << 20
+            | rs1 << 15
+            | (SubFunctions::CBO as u32) << 12
+            | CpuInstructionsOpCodes::MEM_ORDERING as u32
+    }
+
+    fn prefetch(hint: u64, rs1: u32) -> u32 {
+        (hint as u32) << 20
+            | rs1 << 15
+            | (SubFunctions::ORI as u32) << 12
+            | CpuInstructionsOpCodes::INT_REG_IMMEDIATE as u32
+    }
+
+    #[test]
+    fn zeroes_the_whole_block() {
+        let mut cpu = cpu();
+        for offset in (0..0x100).step_by(8) {
+            cpu.system_bus
+                .store(DRAM_BASE_ADDR + offset, MemoryOpSize::B64, u64::MAX)
+                .unwrap();
+        }
+        cpu.registers[5] = DRAM_BASE_ADDR + 0x48;
+        execute(&mut cpu, cbo(SubFunctions::CBO_ZERO, 5)).unwrap();
+        for offset in (0..0x100).step_by(8) {
+            let expected = match offset {
+                0x40..=0x7f => 0,
+                _ => u64::MAX,
+            };
+            assert_eq!(
+                cpu.system_bus.load64(DRAM_BASE_ADDR + offset).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn cache_model_observes_block_addresses() {
+        let mut cpu = cpu();
+        let recorder = Recorder::default();
+        cpu.system_bus
+            .attach_cache_model(Box::new(recorder.clone()));
+        cpu.registers[5] = DRAM_BASE_ADDR + 0x1234;
+        for operation in [
+            SubFunctions::CBO_CLEAN,
+            SubFunctions::CBO_FLUSH,
+            SubFunctions::CBO_INVAL,
+            SubFunctions::CBO_ZERO,
+        ] {
+            execute(&mut cpu, cbo(operation, 5)).unwrap();
+        }
+        for hint in [
+            SubFunctions::PREFETCH_I,
+            SubFunctions::PREFETCH_R,
+            SubFunctions::PREFETCH_W,
+        ] {
+            execute(&mut cpu, prefetch(hint, 5)).unwrap();
+        }
+        let block = DRAM_BASE_ADDR + 0x1200;
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                (CacheBlockOperation::Clean, block),
+                (CacheBlockOperation::Flush, block),
+                (CacheBlockOperation::Invalidate, block),
+                (CacheBlockOperation::Zero, block),
+                (CacheBlockOperation::PrefetchInstruction, block),
+                (CacheBlockOperation::PrefetchRead, block),
+                (CacheBlockOperation::PrefetchWrite, block),
+            ]
+        );
+    }
+
+    #[test]
+    fn envcfg_gates_the_operations_below_machine_mode() {
+        let mut cpu = cpu_with(CpuConfig {
+            pmp_entries: 0,
+            ..CpuConfig::default()
+        });
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu.registers[5] = DRAM_BASE_ADDR;
+        for operation in [
+            SubFunctions::CBO_CLEAN,
+            SubFunctions::CBO_FLUSH,
+            SubFunctions::CBO_INVAL,
+            SubFunctions::CBO_ZERO,
+        ] {
+            assert!(matches!(
+                execute(&mut cpu, cbo(operation, 5)),
+                Err(AppErrors::IllegalInstruction { .. })
+            ));
+        }
+        cpu.cs_registers[MachineLevelCSRegisters::MENVCFG] = EnvCfgFields::CBIE_FLUSH
+            << EnvCfgFields::CBIE_SHIFT
+            | EnvCfgFields::CBCFE
+            | EnvCfgFields::CBZE;
+        let recorder = Recorder::default();
+        cpu.system_bus
+            .attach_cache_model(Box::new(recorder.clone()));
+        execute(&mut cpu, cbo(SubFunctions::CBO_INVAL, 5)).unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [(CacheBlockOperation::Flush, DRAM_BASE_ADDR)]
+        );
+    }
+
+    #[test]
+    fn zero_bus_faults_report_the_virtual_address() {
+        let mut cpu = cpu_with(CpuConfig {
+            pmp_entries: 0,
+            ..CpuConfig::default()
+        });
+        //The first GiB of virtual memory maps to the hole below the RAM
+        let root = DRAM_BASE_ADDR + 0x10000;
+        let gigapage = ((0x4000_0000 / PAGE_SIZE) << PteFields::PPN_SHIFT)
+            | PteFields::V
+            | PteFields::R
+            | PteFields::W
+            | PteFields::A
+            | PteFields::D;
+        cpu.system_bus
+            .store(root, MemoryOpSize::B64, gigapage)
+            .unwrap();
+        cpu.store_satp((SatpFields::SV39 << SatpFields::MODE_SHIFT) | (root / PAGE_SIZE));
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu.cs_registers[MachineLevelCSRegisters::MENVCFG] = EnvCfgFields::CBZE;
+        cpu.registers[5] = 0x1040;
+        assert!(matches!(
+            execute(&mut cpu, cbo(SubFunctions::CBO_ZERO, 5)),
+            Err(AppErrors::StoreAccessFault { addr: 0x1040 })
+        ));
+    }
+}
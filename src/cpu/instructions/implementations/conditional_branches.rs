@@ -25,18 +25,9 @@ impl InstructionsExecutor {
     /// program execution in that address
     #[inline(always)]
     pub fn beq(cpu: &mut Cpu, instruction: impl BTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.program_counter = cpu
-            .program_counter
-            .wrapping_add(
-                (cpu.registers[instruction.get_rs1_field() as usize]
-                    == cpu.registers[instruction.get_rs2_field() as usize]) as u64
-                    * (instruction
-                        .get_b_imm()
-                        .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64)),
-            )
-            .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
-        //Subtracting the instruction size as we have advanced the program counter beforehand
-        Ok(OperationSideEffect::SkipPCIncrease)
+        let taken = cpu.read_reg(instruction.get_rs1_register()?)
+            == cpu.read_reg(instruction.get_rs2_register()?);
+        Self::branch(cpu, instruction, taken)
     }
 
     /// Compares the values held on rs1 and rs2, if the values are not equal
@@ -44,18 +35,9 @@ impl InstructionsExecutor {
     /// program execution in that address
     #[inline(always)]
     pub fn bne(cpu: &mut Cpu, instruction: impl BTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.program_counter = cpu
-            .program_counter
-            .wrapping_add(
-                (cpu.registers[instruction.get_rs1_field() as usize]
-                    != cpu.registers[instruction.get_rs2_field() as usize]) as u64
-                    * (instruction
-                        .get_b_imm()
-                        //Subtracting the instruction size as we have advanced the program counter beforehand
-                        .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64)),
-            )
-            .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
-        Ok(OperationSideEffect::SkipPCIncrease)
+        let taken = cpu.read_reg(instruction.get_rs1_register()?)
+            != cpu.read_reg(instruction.get_rs2_register()?);
+        Self::branch(cpu, instruction, taken)
     }
 
     /// Compares the signed values held on rs1 and rs2, if rs1 is less than rs2
@@ -63,19 +45,9 @@ impl InstructionsExecutor {
     /// program execution in that address
     #[inline(always)]
     pub fn blt(cpu: &mut Cpu, instruction: impl BTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.program_counter = cpu
-            .program_counter
-            .wrapping_add(
-                ((cpu.registers[instruction.get_rs1_field() as usize] as i64)
-                    < (cpu.registers[instruction.get_rs2_field() as usize] as i64))
-                    as u64
-                    * (instruction
-                        .get_b_imm()
-                        //Subtracting the instruction size as we have advanced the program counter beforehand
-                        .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64)),
-            )
-            .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
-        Ok(OperationSideEffect::SkipPCIncrease)
+        let taken = (cpu.read_reg(instruction.get_rs1_register()?) as i64)
+            < (cpu.read_reg(instruction.get_rs2_register()?) as i64);
+        Self::branch(cpu, instruction, taken)
     }
 
     /// Compares the unsigned values held on rs1 and rs2, if rs1 is less than rs2
@@ -83,18 +55,9 @@ impl InstructionsExecutor {
     /// program execution in that address
     #[inline(always)]
     pub fn bltu(cpu: &mut Cpu, instruction: impl BTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.program_counter = cpu
-            .program_counter
-            .wrapping_add(
-                (cpu.registers[instruction.get_rs1_field() as usize]
-                    < cpu.registers[instruction.get_rs2_field() as usize]) as u64
-                    * (instruction
-                        .get_b_imm()
-                        //Subtracting the instruction size as we have advanced the program counter beforehand
-                        .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64)),
-            )
-            .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
-        Ok(OperationSideEffect::SkipPCIncrease)
+        let taken = cpu.read_reg(instruction.get_rs1_register()?)
+            < cpu.read_reg(instruction.get_rs2_register()?);
+        Self::branch(cpu, instruction, taken)
     }
 
     /// Compares the signed values held on rs1 and rs2, if rs1 is greater than or equals rs2
@@ -102,19 +65,9 @@ impl InstructionsExecutor {
     /// program execution in that address
     #[inline(always)]
     pub fn bge(cpu: &mut Cpu, instruction: impl BTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.program_counter = cpu
-            .program_counter
-            .wrapping_add(
-                ((cpu.registers[instruction.get_rs1_field() as usize] as i64)
-                    >= (cpu.registers[instruction.get_rs2_field() as usize] as i64))
-                    as u64
-                    * (instruction
-                        .get_b_imm()
-                        //Subtracting the instruction size as we have advanced the program counter beforehand
-                        .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64)),
-            )
-            .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
-        Ok(OperationSideEffect::SkipPCIncrease)
+        let taken = (cpu.read_reg(instruction.get_rs1_register()?) as i64)
+            >= (cpu.read_reg(instruction.get_rs2_register()?) as i64);
+        Self::branch(cpu, instruction, taken)
     }
 
     /// Compares the unsigned values held on rs1 and rs2, if rs1 is greater than
@@ -122,17 +75,29 @@ impl InstructionsExecutor {
     /// continue program execution in that address
     #[inline(always)]
     pub fn bgeu(cpu: &mut Cpu, instruction: impl BTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.program_counter = cpu
-            .program_counter
-            .wrapping_add(
-                (cpu.registers[instruction.get_rs1_field() as usize]
-                    >= cpu.registers[instruction.get_rs2_field() as usize]) as u64
-                    * (instruction
-                        .get_b_imm()
-                        //Subtracting the instruction size as we have advanced the program counter beforehand
-                        .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64)),
-            )
-            .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
+        let taken = cpu.read_reg(instruction.get_rs1_register()?)
+            >= cpu.read_reg(instruction.get_rs2_register()?);
+        Self::branch(cpu, instruction, taken)
+    }
+
+    /// Adds the immediate value to the program counter if the branch is taken,
+    /// otherwise continues with the next instruction
+    #[inline(always)]
+    fn branch(
+        cpu: &mut Cpu,
+        instruction: impl BTypeDecoder,
+        taken: bool,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.program_counter = match taken {
+            true => {
+                let target = cpu.program_counter.wrapping_add(instruction.get_b_imm());
+                cpu.check_jump_target(target)?;
+                target
+            }
+            false => cpu
+                .program_counter
+                .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64),
+        };
         Ok(OperationSideEffect::SkipPCIncrease)
     }
 }
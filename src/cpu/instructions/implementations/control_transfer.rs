@@ -18,13 +18,15 @@ impl InstructionsExecutor {
     /// return to the next instruction later
     #[inline(always)]
     pub fn jal(cpu: &mut Cpu, instruction: impl JTypeDecoder) -> AppResult<OperationSideEffect> {
+        let target = cpu.program_counter.wrapping_add(instruction.get_j_imm());
+        cpu.check_jump_target(target)?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
+            instruction.get_rd_register()?,
             cpu.program_counter
                 .wrapping_add(DEFAULT_INSTRUCTION_SIZE_BYTES as u64),
         )
         .unwrap();
-        cpu.program_counter = cpu.program_counter.wrapping_add(instruction.get_j_imm());
+        cpu.program_counter = target;
         // .wrapping_sub(DEFAULT_INSTRUCTION_SIZE_BYTES as u64);
         Ok(OperationSideEffect::SkipPCIncrease)
     }
@@ -33,11 +35,14 @@ impl InstructionsExecutor {
     /// to the rd register
     #[inline(always)]
     pub fn jalr(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.write_reg(instruction.get_rd_field() as usize, cpu.program_counter)
-            .unwrap();
-        cpu.program_counter = cpu.registers[instruction.get_rs1_field() as usize]
+        let target = cpu
+            .read_reg(instruction.get_rs1_register()?)
             .wrapping_add(instruction.get_i_imm())
             & !0x1_u64;
+        cpu.check_jump_target(target)?;
+        cpu.write_reg(instruction.get_rd_register()?, cpu.program_counter)
+            .unwrap();
+        cpu.program_counter = target;
         Ok(OperationSideEffect::SkipPCIncrease)
     }
 }
@@ -0,0 +1,1105 @@
+use crate::{
+    cpu::{
+        cs_registers::{FloatCsrFields, UserLevelCSRegisters},
+        extensions::Extension,
+        instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::{ITypeDecoder, R4TypeDecoder, RTypeDecoder, STypeDecoder},
+        side_effects::OperationSideEffect,
+        softfloat::{FloatFormat, RoundingMode, SoftFloat},
+        xlen::Xlen,
+        Cpu,
+    },
+    error::{AppErrors, AppResult},
+    memory::MemoryOpSize,
+};
+
+use super::{CpuInstructionsOpCodes, SubFunctions};
+
+/// Width of the floating point registers, narrower values are NaN-boxed
+/// into them. Without D the upper half is only observable through the NaN
+/// checks of single precision operands.
+const FLEN: FloatFormat = FloatFormat::DOUBLE;
+
+///Funct3 field of the floating point loads and stores, access width
+impl SubFunctions {
+    /// Half precision (Zfhmin)
+    pub const FLH: u8 = 0b001;
+    /// Single precision
+    pub const FLW: u8 = 0b010;
+    /// Double precision (D)
+    pub const FLD: u8 = 0b011;
+    /// Half precision (Zfhmin)
+    pub const FSH: u8 = 0b001;
+    /// Single precision
+    pub const FSW: u8 = 0b010;
+    /// Double precision (D)
+    pub const FSD: u8 = 0b011;
+}
+
+///Fmt field, the low bits of funct7 holding the format of the operands
+impl SubFunctions {
+    /// Single precision
+    pub const FMT_S: u8 = 0b00;
+    /// Double precision (D)
+    pub const FMT_D: u8 = 0b01;
+    /// Half precision (Zfh)
+    pub const FMT_H: u8 = 0b10;
+}
+
+///Funct5 field of OP-FP, the upper bits of funct7 without the format
+impl SubFunctions {
+    pub const FP_ADD: u8 = 0b00000;
+    pub const FP_SUB: u8 = 0b00001;
+    pub const FP_MUL: u8 = 0b00010;
+    pub const FP_DIV: u8 = 0b00011;
+    pub const FP_SIGN_INJECTION: u8 = 0b00100;
+    pub const FP_MIN_MAX: u8 = 0b00101;
+    /// Conversion between formats, rs2 holds the source format
+    pub const FP_CONVERT: u8 = 0b01000;
+    pub const FP_SQRT: u8 = 0b01011;
+    pub const FP_COMPARE: u8 = 0b10100;
+    /// Conversion to an integer, rs2 selects its width and signedness
+    pub const FP_TO_INTEGER: u8 = 0b11000;
+    /// Conversion from an integer, rs2 selects its width and signedness
+    pub const FP_FROM_INTEGER: u8 = 0b11010;
+    /// Bit move to an integer register and classification
+    pub const FP_MOVE_TO_INTEGER: u8 = 0b11100;
+    /// Bit move from an integer register
+    pub const FP_MOVE_FROM_INTEGER: u8 = 0b11110;
+}
+
+///Funct3 field of the OP-FP instructions without a rounding mode
+impl SubFunctions {
+    pub const FSGNJ: u8 = 0b000;
+    pub const FSGNJN: u8 = 0b001;
+    pub const FSGNJX: u8 = 0b010;
+    pub const FMIN: u8 = 0b000;
+    pub const FMAX: u8 = 0b001;
+    pub const FLE: u8 = 0b000;
+    pub const FLT: u8 = 0b001;
+    pub const FEQ: u8 = 0b010;
+    pub const FMV_X: u8 = 0b000;
+    pub const FCLASS: u8 = 0b001;
+    /// Minimum propagating NaNs (Zfa)
+    pub const FMINM: u8 = 0b010;
+    /// Maximum propagating NaNs (Zfa)
+    pub const FMAXM: u8 = 0b011;
+    /// Quiet less or equal (Zfa)
+    pub const FLEQ: u8 = 0b100;
+    /// Quiet less than (Zfa)
+    pub const FLTQ: u8 = 0b101;
+    /// Fixed rounding mode of fcvtmod.w.d, towards zero
+    pub const FCVTMOD_RM: u8 = 0b001;
+}
+
+///Rs2 field of the Zfa instructions sharing their funct5 with another one
+impl SubFunctions {
+    /// fli, shares FP_MOVE_FROM_INTEGER with the bit moves
+    pub const FLI: u8 = 0b00001;
+    /// fmvh.x.d, shares FP_MOVE_TO_INTEGER with the bit moves
+    pub const FMVH: u8 = 0b00001;
+    /// fround, shares FP_CONVERT with the conversions between formats
+    pub const FROUND: u8 = 0b00100;
+    /// froundnx, same as fround but raising inexact
+    pub const FROUNDNX: u8 = 0b00101;
+    /// fcvtmod.w.d, shares FP_TO_INTEGER with the conversions to integers
+    pub const FCVTMOD_W: u8 = 0b01000;
+}
+
+///Funct5 field of fmvp.d.x (Zfa), the RV32 move of an integer register pair
+impl SubFunctions {
+    pub const FP_MOVE_PAIR_FROM_INTEGER: u8 = 0b10110;
+}
+
+/// Values loaded by fli, indexed by rs1. Index 1 is the smallest positive
+/// normal value and index 31 the canonical NaN of the format, the others are
+/// rounded to the format so 2^16 is infinite in half precision.
+const FLI_VALUES: [f64; 32] = [
+    -1.0,
+    f64::MIN_POSITIVE,
+    1.0 / 65536.0,
+    1.0 / 32768.0,
+    1.0 / 256.0,
+    1.0 / 128.0,
+    0.0625,
+    0.125,
+    0.25,
+    0.3125,
+    0.375,
+    0.4375,
+    0.5,
+    0.625,
+    0.75,
+    0.875,
+    1.0,
+    1.25,
+    1.5,
+    1.75,
+    2.0,
+    2.5,
+    3.0,
+    4.0,
+    8.0,
+    16.0,
+    128.0,
+    256.0,
+    32768.0,
+    65536.0,
+    f64::INFINITY,
+    f64::NAN,
+];
+
+///Rs2 field of the integer conversions
+impl SubFunctions {
+    /// Signed word
+    pub const FCVT_W: u8 = 0b00000;
+    /// Unsigned word
+    pub const FCVT_WU: u8 = 0b00001;
+    /// Signed double word (RV64)
+    pub const FCVT_L: u8 = 0b00010;
+    /// Unsigned double word (RV64)
+    pub const FCVT_LU: u8 = 0b00011;
+}
+
+impl InstructionsExecutor {
+    /// Loads a value NaN-boxed into the rd floating point register
+    #[inline(always)]
+    pub fn load_fp(cpu: &mut Cpu, decoder: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let raw_instruction = decoder.get_raw_instruction();
+        cpu.require_float_state(raw_instruction)?;
+        let format = match decoder.get_funct3_field() {
+            SubFunctions::FLW => FloatFormat::SINGLE,
+            SubFunctions::FLD => cpu.double_precision(raw_instruction)?,
+            SubFunctions::FLH => cpu.half_precision(raw_instruction, Extension::Zfhmin)?,
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: raw_instruction,
+                })
+            }
+        };
+        let addr: u64 = cpu.effective_address(
+            cpu.read_reg(decoder.get_rs1_register()?)
+                .wrapping_add(decoder.get_i_imm()),
+        );
+        let value = cpu.load_data(addr, format.bits() as u64 / 8)?;
+        cpu.write_float(decoder.get_rd_field(), format, value);
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Stores the low bits of the rs2 floating point register, the NaN-boxing
+    /// is not checked
+    #[inline(always)]
+    pub fn store_fp(cpu: &mut Cpu, decoder: impl STypeDecoder) -> AppResult<OperationSideEffect> {
+        let raw_instruction = decoder.get_raw_instruction();
+        cpu.require_float_state(raw_instruction)?;
+        let size = match decoder.get_funct3_field() {
+            SubFunctions::FSW => MemoryOpSize::B32,
+            SubFunctions::FSD => {
+                cpu.double_precision(raw_instruction)?;
+                MemoryOpSize::B64
+            }
+            SubFunctions::FSH => {
+                cpu.half_precision(raw_instruction, Extension::Zfhmin)?;
+                MemoryOpSize::B16
+            }
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: raw_instruction,
+                })
+            }
+        };
+        let addr: u64 = cpu.effective_address(
+            cpu.read_reg(decoder.get_rs1_register()?)
+                .wrapping_add(decoder.get_s_imm()),
+        );
+        let value = cpu.float_registers[decoder.get_rs2_field() as usize];
+        cpu.store_data(addr, size, value)?;
+        Ok(OperationSideEffect::None)
+    }
+
+    /// rs1 * rs2 + rs3 with a single rounding, FMSUB subtracts rs3, FNMSUB and
+    /// FNMADD negate the product
+    #[inline(always)]
+    pub fn fused_multiply_add(
+        cpu: &mut Cpu,
+        instruction: impl R4TypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let (negate_product, negate_addend) = match instruction.get_opcode() {
+            CpuInstructionsOpCodes::FMADD => (false, false),
+            CpuInstructionsOpCodes::FMSUB => (false, true),
+            CpuInstructionsOpCodes::FNMSUB => (true, false),
+            _ => (true, true),
+        };
+        let negate = |value: u64, negate: bool| match negate {
+            true => value ^ format.sign(),
+            false => value,
+        };
+        let a = negate(
+            cpu.read_float(instruction.get_rs1_field(), format),
+            negate_product,
+        );
+        let b = cpu.read_float(instruction.get_rs2_field(), format);
+        let c = negate(
+            cpu.read_float(instruction.get_rs3_field(), format),
+            negate_addend,
+        );
+        let result = arithmetic.fused_multiply_add(a, b, c);
+        cpu.complete_float(&instruction, format, result, arithmetic.flags)
+    }
+
+    #[inline(always)]
+    pub fn fadd(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        Self::float_binary(cpu, instruction, SoftFloat::add)
+    }
+
+    #[inline(always)]
+    pub fn fsub(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        Self::float_binary(cpu, instruction, SoftFloat::sub)
+    }
+
+    #[inline(always)]
+    pub fn fmul(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        Self::float_binary(cpu, instruction, SoftFloat::mul)
+    }
+
+    #[inline(always)]
+    pub fn fdiv(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        Self::float_binary(cpu, instruction, SoftFloat::div)
+    }
+
+    /// FMIN returns the smaller operand and FMAX the larger, a NaN operand is
+    /// ignored. The Zfa FMINM and FMAXM return the canonical NaN instead.
+    #[inline(always)]
+    pub fn fmin_max(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        match instruction.get_funct3_field() {
+            SubFunctions::FMIN => Self::float_binary(cpu, instruction, SoftFloat::min),
+            SubFunctions::FMAX => Self::float_binary(cpu, instruction, SoftFloat::max),
+            funct3 => {
+                cpu.require_extension(Extension::Zfa, instruction.get_raw_instruction())?;
+                match funct3 {
+                    SubFunctions::FMINM => Self::float_binary(cpu, instruction, SoftFloat::minimum),
+                    _ => Self::float_binary(cpu, instruction, SoftFloat::maximum),
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn fsqrt(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let result = arithmetic.sqrt(cpu.read_float(instruction.get_rs1_field(), format));
+        cpu.complete_float(&instruction, format, result, arithmetic.flags)
+    }
+
+    /// Moves the magnitude of rs1 with the sign of rs2 (FSGNJ), its opposite
+    /// (FSGNJN) or the xor of both signs (FSGNJX)
+    #[inline(always)]
+    pub fn fsgnj(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let a = cpu.read_float(instruction.get_rs1_field(), format);
+        let b = cpu.read_float(instruction.get_rs2_field(), format);
+        let sign = match instruction.get_funct3_field() {
+            SubFunctions::FSGNJ => b,
+            SubFunctions::FSGNJN => !b,
+            _ => a ^ b,
+        } & format.sign();
+        cpu.write_float(
+            instruction.get_rd_field(),
+            format,
+            (a & !format.sign()) | sign,
+        );
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Writes 1 to rd if the comparison between rs1 and rs2 holds, FEQ and the
+    /// Zfa FLEQ and FLTQ only raise invalid for signaling NaNs
+    #[inline(always)]
+    pub fn fcompare(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let funct3 = instruction.get_funct3_field();
+        if matches!(funct3, SubFunctions::FLEQ | SubFunctions::FLTQ) {
+            cpu.require_extension(Extension::Zfa, instruction.get_raw_instruction())?;
+        }
+        let mut arithmetic = SoftFloat::new(format, RoundingMode::NearestEven);
+        let a = cpu.read_float(instruction.get_rs1_field(), format);
+        let b = cpu.read_float(instruction.get_rs2_field(), format);
+        let result = match funct3 {
+            SubFunctions::FEQ => arithmetic.eq(a, b),
+            SubFunctions::FLT => arithmetic.lt(a, b),
+            SubFunctions::FLE => arithmetic.le(a, b),
+            SubFunctions::FLTQ => arithmetic.lt_quiet(a, b),
+            _ => arithmetic.le_quiet(a, b),
+        };
+        cpu.accrue_float_flags(arithmetic.flags);
+        cpu.write_reg(instruction.get_rd_register()?, result as u64)
+    }
+
+    /// Writes the class of rs1 to rd as a one-hot mask
+    #[inline(always)]
+    pub fn fclass(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let arithmetic = SoftFloat::new(format, RoundingMode::NearestEven);
+        let class = arithmetic.classify(cpu.read_float(instruction.get_rs1_field(), format));
+        cpu.write_reg(instruction.get_rd_register()?, class)
+    }
+
+    /// Converts rs1 to a signed or unsigned word or double word, out of range
+    /// values saturate. Words are sign-extended to XLEN.
+    #[inline(always)]
+    pub fn fcvt_to_integer(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let (signed, bits) = cpu.integer_conversion(&instruction)?;
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let a = cpu.read_float(instruction.get_rs1_field(), format);
+        let result = arithmetic.round_to_integer(a, signed, bits);
+        cpu.accrue_float_flags(arithmetic.flags);
+        cpu.write_reg(instruction.get_rd_register()?, result)
+    }
+
+    /// Converts the signed or unsigned word or double word held in rs1
+    #[inline(always)]
+    pub fn fcvt_from_integer(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let (signed, bits) = cpu.integer_conversion(&instruction)?;
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let value = cpu.read_reg(instruction.get_rs1_register()?);
+        let value = match bits {
+            32 => value as u32 as u64,
+            _ => value,
+        };
+        let negative = signed && value >> (bits - 1) & 1 == 1;
+        let magnitude = match negative {
+            true => ((value << (64 - bits)) as i64 >> (64 - bits)).unsigned_abs(),
+            false => value,
+        };
+        let result = arithmetic.round_from_integer(negative, magnitude);
+        cpu.complete_float(&instruction, format, result, arithmetic.flags)
+    }
+
+    /// Converts rs1 between two formats, rs2 holds the source format. The
+    /// conversions with half precision only need Zfhmin.
+    #[inline(always)]
+    pub fn fcvt_float(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        let format = cpu.float_format(&instruction, Extension::Zfhmin)?;
+        let source = match instruction.get_rs2_field() {
+            SubFunctions::FMT_S => FloatFormat::SINGLE,
+            SubFunctions::FMT_D => cpu.double_precision(raw_instruction)?,
+            SubFunctions::FMT_H => cpu.half_precision(raw_instruction, Extension::Zfhmin)?,
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: raw_instruction,
+                })
+            }
+        };
+        if source == format {
+            return Err(AppErrors::IllegalInstruction {
+                instruction: raw_instruction,
+            });
+        }
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let a = cpu.read_float(instruction.get_rs1_field(), source);
+        let result = arithmetic.convert(a, source);
+        cpu.complete_float(&instruction, format, result, arithmetic.flags)
+    }
+
+    /// Moves the low bits of rs1 sign-extended to rd, the NaN-boxing is not
+    /// checked
+    #[inline(always)]
+    pub fn fmv_to_integer(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_move_format(&instruction)?;
+        let value = cpu.float_registers[instruction.get_rs1_field() as usize];
+        let shift = 64 - format.bits();
+        cpu.write_reg(
+            instruction.get_rd_register()?,
+            ((value << shift) as i64 >> shift) as u64,
+        )
+    }
+
+    /// Moves the low bits of the rs1 integer register NaN-boxed to rd
+    #[inline(always)]
+    pub fn fmv_from_integer(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_move_format(&instruction)?;
+        let value = cpu.read_reg(instruction.get_rs1_register()?) & format.mask();
+        cpu.write_float(instruction.get_rd_field(), format, value);
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Zfa fli, loads the constant selected by rs1
+    #[inline(always)]
+    pub fn fli(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        cpu.require_extension(Extension::Zfa, instruction.get_raw_instruction())?;
+        let value = match instruction.get_rs1_field() {
+            1 => format.min_normal(),
+            31 => format.canonical_nan(),
+            index => SoftFloat::new(format, RoundingMode::NearestEven)
+                .convert(FLI_VALUES[index as usize].to_bits(), FloatFormat::DOUBLE),
+        };
+        cpu.write_float(instruction.get_rd_field(), format, value);
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Zfa fround and froundnx, round rs1 to an integral value in the same
+    /// format, only froundnx raises inexact
+    #[inline(always)]
+    pub fn fround(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        cpu.require_extension(Extension::Zfa, instruction.get_raw_instruction())?;
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let a = cpu.read_float(instruction.get_rs1_field(), format);
+        let exact = instruction.get_rs2_field() == SubFunctions::FROUNDNX;
+        let result = arithmetic.round_to_integral(a, exact);
+        cpu.complete_float(&instruction, format, result, arithmetic.flags)
+    }
+
+    /// Zfa fcvtmod.w.d, truncates a double to a word modulo 2^32 as the
+    /// conversions of JavaScript do
+    #[inline(always)]
+    pub fn fcvtmod_w_d(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        cpu.require_extension(Extension::Zfa, instruction.get_raw_instruction())?;
+        let mut arithmetic = SoftFloat::new(format, RoundingMode::TowardZero);
+        let result =
+            arithmetic.modular_to_word(cpu.read_float(instruction.get_rs1_field(), format));
+        cpu.accrue_float_flags(arithmetic.flags);
+        cpu.write_reg(instruction.get_rd_register()?, result)
+    }
+
+    /// Zfa fmvh.x.d, moves the upper half of a double to rd on RV32
+    #[inline(always)]
+    pub fn fmvh_x_d(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.float_pair_move(&instruction)?;
+        let value = cpu.float_registers[instruction.get_rs1_field() as usize];
+        cpu.write_reg(instruction.get_rd_register()?, value >> 32)
+    }
+
+    /// Zfa fmvp.d.x, moves rs1 to the lower half and rs2 to the upper half of
+    /// a double on RV32
+    #[inline(always)]
+    pub fn fmvp_d_x(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.float_pair_move(&instruction)?;
+        let low = cpu.read_reg(instruction.get_rs1_register()?) as u32 as u64;
+        let high = cpu.read_reg(instruction.get_rs2_register()?) as u32 as u64;
+        cpu.write_float(
+            instruction.get_rd_field(),
+            FloatFormat::DOUBLE,
+            high << 32 | low,
+        );
+        Ok(OperationSideEffect::None)
+    }
+
+    #[inline(always)]
+    fn float_binary(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+        operation: fn(&mut SoftFloat, u64, u64) -> u64,
+    ) -> AppResult<OperationSideEffect> {
+        let format = cpu.float_format(&instruction, Extension::Zfh)?;
+        let mut arithmetic = cpu.float_arithmetic(&instruction, format)?;
+        let a = cpu.read_float(instruction.get_rs1_field(), format);
+        let b = cpu.read_float(instruction.get_rs2_field(), format);
+        let result = operation(&mut arithmetic, a, b);
+        cpu.complete_float(&instruction, format, result, arithmetic.flags)
+    }
+}
+
+impl Cpu {
+    /// Floating point instructions are illegal without F and while mstatus.FS
+    /// is Off
+    #[inline(always)]
+    fn require_float_state(&self, instruction: u32) -> AppResult<()> {
+        self.require_extension(Extension::F, instruction)?;
+        match self.is_float_state_enabled() {
+            true => Ok(()),
+            false => Err(AppErrors::IllegalInstruction { instruction }),
+        }
+    }
+
+    /// Half precision needs Zfh, or Zfhmin for the loads, stores, moves and
+    /// conversions to single precision, which Zfh includes
+    #[inline(always)]
+    fn half_precision(&self, instruction: u32, extension: Extension) -> AppResult<FloatFormat> {
+        match self.extensions.contains(Extension::Zfh) {
+            true => Ok(FloatFormat::HALF),
+            false => self
+                .require_extension(extension, instruction)
+                .map(|_| FloatFormat::HALF),
+        }
+    }
+
+    /// Double precision needs D
+    #[inline(always)]
+    fn double_precision(&self, instruction: u32) -> AppResult<FloatFormat> {
+        self.require_extension(Extension::D, instruction)
+            .map(|_| FloatFormat::DOUBLE)
+    }
+
+    /// Format held in the fmt field of an instruction, half precision is
+    /// available with `extension`
+    #[inline(always)]
+    fn float_format(
+        &self,
+        instruction: &impl RTypeDecoder,
+        extension: Extension,
+    ) -> AppResult<FloatFormat> {
+        let raw_instruction = instruction.get_raw_instruction();
+        self.require_float_state(raw_instruction)?;
+        match instruction.get_funct7_field() & 0b11 {
+            SubFunctions::FMT_S => Ok(FloatFormat::SINGLE),
+            SubFunctions::FMT_D => self.double_precision(raw_instruction),
+            SubFunctions::FMT_H => self.half_precision(raw_instruction, extension),
+            _ => Err(AppErrors::IllegalInstruction {
+                instruction: raw_instruction,
+            }),
+        }
+    }
+
+    /// Format of the bit moves between the register files, a double precision
+    /// value only fits in an integer register on RV64
+    #[inline(always)]
+    fn float_move_format(&self, instruction: &impl RTypeDecoder) -> AppResult<FloatFormat> {
+        match self.float_format(instruction, Extension::Zfhmin)? {
+            FloatFormat::DOUBLE if self.xlen != Xlen::X64 => Err(AppErrors::IllegalInstruction {
+                instruction: instruction.get_raw_instruction(),
+            }),
+            format => Ok(format),
+        }
+    }
+
+    /// The Zfa moves of a double through a pair of integer registers only
+    /// exist on RV32
+    #[inline(always)]
+    fn float_pair_move(&self, instruction: &impl RTypeDecoder) -> AppResult<()> {
+        let raw_instruction = instruction.get_raw_instruction();
+        self.float_format(instruction, Extension::Zfh)?;
+        self.require_extension(Extension::Zfa, raw_instruction)?;
+        match self.xlen {
+            Xlen::X32 => Ok(()),
+            _ => Err(AppErrors::IllegalInstruction {
+                instruction: raw_instruction,
+            }),
+        }
+    }
+
+    /// Arithmetic with the rounding mode of the rm field, or of frm when it
+    /// selects the dynamic rounding mode. Reserved rounding modes are illegal.
+    #[inline(always)]
+    fn float_arithmetic(
+        &self,
+        instruction: &impl RTypeDecoder,
+        format: FloatFormat,
+    ) -> AppResult<SoftFloat> {
+        let rm = match instruction.get_funct3_field() as u64 {
+            RoundingMode::DYNAMIC => {
+                (self.cs_registers[UserLevelCSRegisters::FCSR] & FloatCsrFields::FRM)
+                    >> FloatCsrFields::FRM_SHIFT
+            }
+            rm => rm,
+        };
+        match RoundingMode::from_bits(rm) {
+            Some(rounding) => Ok(SoftFloat::new(format, rounding)),
+            None => Err(AppErrors::IllegalInstruction {
+                instruction: instruction.get_raw_instruction(),
+            }),
+        }
+    }
+
+    /// Signedness and width of the integer of a conversion, double words only
+    /// exist on RV64
+    #[inline(always)]
+    fn integer_conversion(&self, instruction: &impl RTypeDecoder) -> AppResult<(bool, u32)> {
+        match instruction.get_rs2_field() {
+            SubFunctions::FCVT_W => Ok((true, 32)),
+            SubFunctions::FCVT_WU => Ok((false, 32)),
+            SubFunctions::FCVT_L if self.xlen == Xlen::X64 => Ok((true, 64)),
+            SubFunctions::FCVT_LU if self.xlen == Xlen::X64 => Ok((false, 64)),
+            _ => Err(AppErrors::IllegalInstruction {
+                instruction: instruction.get_raw_instruction(),
+            }),
+        }
+    }
+
+    /// Value of a floating point register in a format, values that are not
+    /// properly NaN-boxed read as the canonical NaN
+    #[inline(always)]
+    fn read_float(&self, register: u8, format: FloatFormat) -> u64 {
+        let value = self.float_registers[register as usize];
+        let boxing = FLEN.mask() & !format.mask();
+        match value & boxing == boxing {
+            true => value & format.mask(),
+            false => format.canonical_nan(),
+        }
+    }
+
+    /// Writes a value NaN-boxed to FLEN into a floating point register
+    #[inline(always)]
+    fn write_float(&mut self, register: u8, format: FloatFormat, value: u64) {
+        self.float_registers[register as usize] = (FLEN.mask() & !format.mask()) | value;
+        self.mark_float_state_dirty();
+    }
+
+    /// Accrues the exception flags raised by an instruction into fflags
+    #[inline(always)]
+    fn accrue_float_flags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.cs_registers[UserLevelCSRegisters::FCSR] |= flags;
+            self.mark_float_state_dirty();
+        }
+    }
+
+    /// Writes the result of an arithmetic instruction to rd and accrues its flags
+    #[inline(always)]
+    fn complete_float(
+        &mut self,
+        instruction: &impl RTypeDecoder,
+        format: FloatFormat,
+        result: u64,
+        flags: u64,
+    ) -> AppResult<OperationSideEffect> {
+        self.accrue_float_flags(flags);
+        self.write_float(instruction.get_rd_field(), format, result);
+        Ok(OperationSideEffect::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{
+        cs_registers::{MStatusFields, MachineLevelCSRegisters},
+        softfloat::ExceptionFlags,
+        test_hart::{self, execute},
+    };
+
+    const DYNAMIC: u8 = RoundingMode::DYNAMIC as u8;
+
+    /// Hart with the floating point state enabled
+    fn cpu() -> Cpu {
+        let mut cpu = test_hart::cpu();
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] |= 1 << MStatusFields::FS_SHIFT;
+        cpu
+    }
+
+    fn op_fp(funct5: u8, fmt: u8, funct3: u8, rd: u32, rs1: u32, rs2: u32) -> u32 {
+        ((funct5 << 2 | fmt) as u32) << 25
+            | rs2 << 20
+            | rs1 << 15
+            | (funct3 as u32) << 12
+            | rd << 7
+            | CpuInstructionsOpCodes::OP_FP as u32
+    }
+
+    #[test]
+    fn float_state_off_is_illegal() {
+        let mut cpu = cpu();
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] &= !MStatusFields::FS;
+        let fadd = op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_S, 0, 1, 2, 3);
+        assert!(matches!(
+            execute(&mut cpu, fadd),
+            Err(AppErrors::IllegalInstruction { instruction }) if instruction == fadd
+        ));
+        assert!(!cpu.is_csr_access_allowed(UserLevelCSRegisters::FCSR, false));
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] |= 1 << MStatusFields::FS_SHIFT;
+        execute(&mut cpu, fadd).unwrap();
+        //Writing a floating point register sets FS and SD
+        assert_eq!(
+            cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] & MStatusFields::FS,
+            MStatusFields::FS_DIRTY
+        );
+        assert_eq!(cpu.load_csr(MachineLevelCSRegisters::MSTATUS) >> 63, 1);
+    }
+
+    #[test]
+    fn half_precision_is_nan_boxed() {
+        let mut cpu = cpu();
+        cpu.registers[1] = 0x3c00;
+        cpu.registers[2] = 0x4000_0000;
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_MOVE_FROM_INTEGER,
+                SubFunctions::FMT_H,
+                0,
+                1,
+                1,
+                0,
+            ),
+        )
+        .unwrap();
+        assert_eq!(cpu.float_registers[1], 0xffff_ffff_ffff_3c00);
+        //A single precision value is not a properly NaN-boxed half
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_MOVE_FROM_INTEGER,
+                SubFunctions::FMT_S,
+                0,
+                2,
+                2,
+                0,
+            ),
+        )
+        .unwrap();
+        execute(
+            &mut cpu,
+            op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_H, 0, 3, 1, 2),
+        )
+        .unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_ffff_7e00);
+        //1.0 + 1.0 and its conversion to single precision
+        execute(
+            &mut cpu,
+            op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_H, 0, 3, 1, 1),
+        )
+        .unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_ffff_4000);
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_CONVERT,
+                SubFunctions::FMT_S,
+                0,
+                4,
+                3,
+                SubFunctions::FMT_H as u32,
+            ),
+        )
+        .unwrap();
+        assert_eq!(cpu.float_registers[4], 0xffff_ffff_4000_0000);
+        //Moves to an integer register sign-extend the raw low bits
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_MOVE_TO_INTEGER,
+                SubFunctions::FMT_H,
+                0,
+                5,
+                2,
+                0,
+            ),
+        )
+        .unwrap();
+        assert_eq!(cpu.registers[5], 0);
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_MOVE_TO_INTEGER,
+                SubFunctions::FMT_H,
+                0,
+                5,
+                3,
+                0,
+            ),
+        )
+        .unwrap();
+        assert_eq!(cpu.registers[5], 0x4000);
+    }
+
+    #[test]
+    fn dynamic_rounding_and_accrued_flags() {
+        let mut cpu = cpu();
+        //1.0 and 3.0
+        cpu.float_registers[1] = 0xffff_ffff_3f80_0000;
+        cpu.float_registers[2] = 0xffff_ffff_4040_0000;
+        let fdiv = |rm| op_fp(SubFunctions::FP_DIV, SubFunctions::FMT_S, rm, 3, 1, 2);
+        execute(&mut cpu, fdiv(DYNAMIC)).unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_3eaa_aaab);
+        assert_eq!(
+            cpu.load_csr(UserLevelCSRegisters::FFLAGS),
+            ExceptionFlags::NX
+        );
+        //Round towards zero through frm
+        cpu.store_csr(UserLevelCSRegisters::FRM, 0b001);
+        execute(&mut cpu, fdiv(DYNAMIC)).unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_3eaa_aaaa);
+        //Reserved rounding modes are illegal, in rm or in frm
+        assert!(execute(&mut cpu, fdiv(0b101)).is_err());
+        cpu.store_csr(UserLevelCSRegisters::FRM, 0b110);
+        assert!(execute(&mut cpu, fdiv(DYNAMIC)).is_err());
+        execute(&mut cpu, fdiv(0b000)).unwrap();
+        //Out of range conversions saturate and raise invalid
+        cpu.store_csr(UserLevelCSRegisters::FFLAGS, 0);
+        cpu.float_registers[4] = 0xffff_ffff_ff80_0000;
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_TO_INTEGER,
+                SubFunctions::FMT_S,
+                0,
+                5,
+                4,
+                SubFunctions::FCVT_WU as u32,
+            ),
+        )
+        .unwrap();
+        assert_eq!(cpu.registers[5], 0);
+        assert_eq!(
+            cpu.load_csr(UserLevelCSRegisters::FFLAGS),
+            ExceptionFlags::NV
+        );
+    }
+
+    #[test]
+    fn double_precision_boxes_narrower_values() {
+        let mut cpu = cpu();
+        //1.5 in double precision, 1.5 in single precision
+        cpu.registers[1] = 0x3ff8_0000_0000_0000;
+        cpu.registers[2] = 0x3fc0_0000;
+        let fmv_d_x = op_fp(
+            SubFunctions::FP_MOVE_FROM_INTEGER,
+            SubFunctions::FMT_D,
+            0,
+            1,
+            1,
+            0,
+        );
+        execute(&mut cpu, fmv_d_x).unwrap();
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_MOVE_FROM_INTEGER,
+                SubFunctions::FMT_S,
+                0,
+                2,
+                2,
+                0,
+            ),
+        )
+        .unwrap();
+        //The single precision value widened and added to the double
+        execute(
+            &mut cpu,
+            op_fp(
+                SubFunctions::FP_CONVERT,
+                SubFunctions::FMT_D,
+                0,
+                3,
+                2,
+                SubFunctions::FMT_S as u32,
+            ),
+        )
+        .unwrap();
+        execute(
+            &mut cpu,
+            op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_D, 0, 3, 1, 3),
+        )
+        .unwrap();
+        assert_eq!(cpu.float_registers[3], 0x4008_0000_0000_0000);
+        //A double is not a properly NaN-boxed single
+        execute(
+            &mut cpu,
+            op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_S, 0, 4, 1, 1),
+        )
+        .unwrap();
+        assert_eq!(cpu.float_registers[4], 0xffff_ffff_7fc0_0000);
+        //Doubles only move to and from the integer registers on RV64
+        cpu.xlen = Xlen::X32;
+        assert!(matches!(
+            execute(&mut cpu, fmv_d_x),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+    }
+
+    #[test]
+    fn misa_disables_float_extensions() {
+        let mut cpu = cpu();
+        let fadd_d = op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_D, 0, 1, 2, 3);
+        let fadd_s = op_fp(SubFunctions::FP_ADD, SubFunctions::FMT_S, 0, 1, 2, 3);
+        let misa = cpu.load_csr(MachineLevelCSRegisters::MISA);
+        cpu.store_csr(
+            MachineLevelCSRegisters::MISA,
+            misa & !Extension::D.misa_bit(),
+        );
+        assert!(execute(&mut cpu, fadd_d).is_err());
+        execute(&mut cpu, fadd_s).unwrap();
+        //D depends on F, clearing F disables both
+        cpu.store_csr(
+            MachineLevelCSRegisters::MISA,
+            misa & !Extension::F.misa_bit(),
+        );
+        assert_eq!(
+            cpu.load_csr(MachineLevelCSRegisters::MISA)
+                & (Extension::F.misa_bit() | Extension::D.misa_bit()),
+            0
+        );
+        assert!(execute(&mut cpu, fadd_s).is_err());
+        assert!(!cpu.is_csr_access_allowed(UserLevelCSRegisters::FCSR, false));
+        cpu.store_csr(MachineLevelCSRegisters::MISA, misa);
+        execute(&mut cpu, fadd_d).unwrap();
+    }
+
+    #[test]
+    fn zfa_constants_and_rounding() {
+        let mut cpu = cpu();
+        let fli = |fmt, rd, index| op_fp(SubFunctions::FP_MOVE_FROM_INTEGER, fmt, 0, rd, index, 1);
+        for (fmt, index, expected) in [
+            (SubFunctions::FMT_S, 16, 0xffff_ffff_3f80_0000),
+            (SubFunctions::FMT_D, 1, 0x0010_0000_0000_0000),
+            (SubFunctions::FMT_D, 31, 0x7ff8_0000_0000_0000),
+            //2^-16 is subnormal and 2^16 overflows in half precision
+            (SubFunctions::FMT_H, 2, 0xffff_ffff_ffff_0100),
+            (SubFunctions::FMT_H, 29, 0xffff_ffff_ffff_7c00),
+        ] {
+            execute(&mut cpu, fli(fmt, 1, index)).unwrap();
+            assert_eq!(cpu.float_registers[1], expected, "fli {index}");
+        }
+        //2.5 rounds to even, only froundnx raises inexact
+        execute(&mut cpu, fli(SubFunctions::FMT_S, 1, 21)).unwrap();
+        let fround = |rs2| op_fp(SubFunctions::FP_CONVERT, SubFunctions::FMT_S, 0, 2, 1, rs2);
+        execute(&mut cpu, fround(SubFunctions::FROUND as u32)).unwrap();
+        assert_eq!(cpu.float_registers[2], 0xffff_ffff_4000_0000);
+        assert_eq!(cpu.load_csr(UserLevelCSRegisters::FFLAGS), 0);
+        execute(&mut cpu, fround(SubFunctions::FROUNDNX as u32)).unwrap();
+        assert_eq!(cpu.float_registers[2], 0xffff_ffff_4000_0000);
+        assert_eq!(
+            cpu.load_csr(UserLevelCSRegisters::FFLAGS),
+            ExceptionFlags::NX
+        );
+        //Without Zfa the encodings are illegal
+        cpu.extensions = cpu.extensions.without(Extension::Zfa);
+        assert!(matches!(
+            execute(&mut cpu, fli(SubFunctions::FMT_S, 1, 16)),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+    }
+
+    #[test]
+    fn zfa_nan_propagation_and_quiet_comparisons() {
+        let mut cpu = cpu();
+        //1.0 and a quiet NaN
+        cpu.float_registers[1] = 0xffff_ffff_3f80_0000;
+        cpu.float_registers[2] = 0xffff_ffff_7fc0_0001;
+        let min_max = |funct3| {
+            op_fp(
+                SubFunctions::FP_MIN_MAX,
+                SubFunctions::FMT_S,
+                funct3,
+                3,
+                1,
+                2,
+            )
+        };
+        execute(&mut cpu, min_max(SubFunctions::FMIN)).unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_3f80_0000);
+        execute(&mut cpu, min_max(SubFunctions::FMINM)).unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_7fc0_0000);
+        execute(&mut cpu, min_max(SubFunctions::FMAXM)).unwrap();
+        assert_eq!(cpu.float_registers[3], 0xffff_ffff_7fc0_0000);
+        assert_eq!(cpu.load_csr(UserLevelCSRegisters::FFLAGS), 0);
+        //The quiet comparisons only raise invalid for signaling NaNs
+        let compare = |funct3| {
+            op_fp(
+                SubFunctions::FP_COMPARE,
+                SubFunctions::FMT_S,
+                funct3,
+                5,
+                1,
+                2,
+            )
+        };
+        for funct3 in [SubFunctions::FLEQ, SubFunctions::FLTQ] {
+            execute(&mut cpu, compare(funct3)).unwrap();
+            assert_eq!(cpu.registers[5], 0);
+            assert_eq!(cpu.load_csr(UserLevelCSRegisters::FFLAGS), 0);
+        }
+        execute(&mut cpu, compare(SubFunctions::FLE)).unwrap();
+        assert_eq!(
+            cpu.load_csr(UserLevelCSRegisters::FFLAGS),
+            ExceptionFlags::NV
+        );
+        cpu.float_registers[2] = 0xffff_ffff_4000_0000;
+        execute(&mut cpu, compare(SubFunctions::FLTQ)).unwrap();
+        assert_eq!(cpu.registers[5], 1);
+    }
+
+    #[test]
+    fn fcvtmod_wraps_modulo_2_32() {
+        let mut cpu = cpu();
+        let fcvtmod = op_fp(
+            SubFunctions::FP_TO_INTEGER,
+            SubFunctions::FMT_D,
+            SubFunctions::FCVTMOD_RM,
+            5,
+            1,
+            SubFunctions::FCVTMOD_W as u32,
+        );
+        for (value, expected, flags) in [
+            (-3.75, -3_i64 as u64, ExceptionFlags::NX),
+            (2_f64.powi(32) + 5.5, 5, ExceptionFlags::NV),
+            (2_f64.powi(31), -(1_i64 << 31) as u64, ExceptionFlags::NV),
+            (-(2_f64.powi(31)), -(1_i64 << 31) as u64, 0),
+            (f64::NAN, 0, ExceptionFlags::NV),
+        ] {
+            cpu.float_registers[1] = value.to_bits();
+            cpu.store_csr(UserLevelCSRegisters::FFLAGS, 0);
+            execute(&mut cpu, fcvtmod).unwrap();
+            assert_eq!(cpu.registers[5], expected, "{value}");
+            assert_eq!(cpu.load_csr(UserLevelCSRegisters::FFLAGS), flags, "{value}");
+        }
+        //Only the round towards zero encoding exists
+        assert!(execute(&mut cpu, fcvtmod & !(0b111 << 12)).is_err());
+    }
+
+    #[test]
+    fn zfa_register_pair_moves_on_rv32() {
+        let mut cpu = cpu();
+        cpu.registers[2] = 0x5555_4444;
+        cpu.registers[3] = 0x7777_6666;
+        let fmvp = op_fp(
+            SubFunctions::FP_MOVE_PAIR_FROM_INTEGER,
+            SubFunctions::FMT_D,
+            0,
+            1,
+            2,
+            3,
+        );
+        let fmvh = op_fp(
+            SubFunctions::FP_MOVE_TO_INTEGER,
+            SubFunctions::FMT_D,
+            0,
+            4,
+            1,
+            SubFunctions::FMVH as u32,
+        );
+        assert!(execute(&mut cpu, fmvp).is_err());
+        cpu.xlen = Xlen::X32;
+        execute(&mut cpu, fmvp).unwrap();
+        assert_eq!(cpu.float_registers[1], 0x7777_6666_5555_4444);
+        execute(&mut cpu, fmvh).unwrap();
+        assert_eq!(cpu.registers[4] as u32, 0x7777_6666);
+    }
+}
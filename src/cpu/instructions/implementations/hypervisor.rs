@@ -0,0 +1,342 @@
+use crate::{
+    cpu::{
+        cs_registers::{
+            HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+        },
+        extensions::Extension,
+        hypervisor::HypervisorAccess,
+        instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::RTypeDecoder,
+        pmp::MemoryAccess,
+        privilege::PrivilegeMode,
+        side_effects::OperationSideEffect,
+        Cpu,
+    },
+    error::{AppErrors, AppResult},
+    memory::MemoryOpSize,
+};
+
+use super::SubFunctions;
+
+impl SubFunctions {
+    /// Hypervisor virtual-machine loads and stores, funct3 field
+    pub const HYPERVISOR_LOAD_STORE: u8 = 0b100;
+    /// Hypervisor virtual-machine loads, funct7 field
+    pub const HLV_B: u8 = 0b0110000;
+    pub const HLV_H: u8 = 0b0110010;
+    pub const HLV_W: u8 = 0b0110100;
+    pub const HLV_D: u8 = 0b0110110;
+    /// Hypervisor virtual-machine stores, funct7 field
+    pub const HSV_B: u8 = 0b0110001;
+    pub const HSV_H: u8 = 0b0110011;
+    pub const HSV_W: u8 = 0b0110101;
+    pub const HSV_D: u8 = 0b0110111;
+    /// Selects the hypervisor load variant, rs2 field
+    pub const HLV_SIGNED: u8 = 0b00000;
+    pub const HLV_UNSIGNED: u8 = 0b00001;
+    pub const HLVX: u8 = 0b00011;
+    /// Hypervisor memory-management fences, funct7 field
+    pub const HFENCE_VVMA: u8 = 0b0010001;
+    pub const HFENCE_GVMA: u8 = 0b0110001;
+    /// Svinval invalidations of the guest translations, funct7 field
+    pub const HINVAL_VVMA: u8 = 0b0010011;
+    pub const HINVAL_GVMA: u8 = 0b0110011;
+}
+
+impl InstructionsExecutor {
+    /// HLV and HLVX, load with the address translation and protection of VS or
+    /// VU mode as selected by hstatus.SPVP. HLVX reads executable pages.
+    #[inline(always)]
+    pub fn hlv(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        cpu.check_hypervisor_load_store(raw_instruction)?;
+        let variant = instruction.get_rs2_field();
+        let size = match instruction.get_funct7_field() {
+            SubFunctions::HLV_B => 1,
+            SubFunctions::HLV_H => 2,
+            SubFunctions::HLV_W => 4,
+            _ => 8,
+        };
+        //hlv.wu and hlv.d only exist on RV64
+        if size == 8 || (size == 4 && variant == SubFunctions::HLV_UNSIGNED) {
+            cpu.require_xlen64(raw_instruction)?;
+        }
+        let access = match variant {
+            SubFunctions::HLVX => HypervisorAccess::Execute,
+            _ => HypervisorAccess::Data,
+        };
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        let value = cpu.hypervisor_load(addr, size, access)?;
+        let value = match (variant, size) {
+            (SubFunctions::HLV_SIGNED, 1) => value as i8 as i64 as u64,
+            (SubFunctions::HLV_SIGNED, 2) => value as i16 as i64 as u64,
+            (SubFunctions::HLV_SIGNED, 4) => value as i32 as i64 as u64,
+            _ => value,
+        };
+        cpu.write_reg(instruction.get_rd_register()?, value)
+    }
+
+    /// HSV, store with the address translation and protection of VS or VU
+    /// mode as selected by hstatus.SPVP
+    #[inline(always)]
+    pub fn hsv(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        cpu.check_hypervisor_load_store(raw_instruction)?;
+        let size = match instruction.get_funct7_field() {
+            SubFunctions::HSV_B => MemoryOpSize::B8,
+            SubFunctions::HSV_H => MemoryOpSize::B16,
+            SubFunctions::HSV_W => MemoryOpSize::B32,
+            _ => {
+                cpu.require_xlen64(raw_instruction)?;
+                MemoryOpSize::B64
+            }
+        };
+        let addr = cpu.effective_address(cpu.read_reg(instruction.get_rs1_register()?));
+        let value = cpu.read_reg(instruction.get_rs2_register()?);
+        cpu.hypervisor_store(addr, size, value)?;
+        Ok(OperationSideEffect::None)
+    }
+
+    /// HFENCE.VVMA and Svinval HINVAL.VVMA, invalidate the VS-stage
+    /// translations. Every cached translation is dropped whatever the address
+    /// and ASID.
+    #[inline(always)]
+    pub fn hfence_vvma(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        if instruction.get_funct7_field() == SubFunctions::HINVAL_VVMA {
+            cpu.require_extension(Extension::Svinval, raw_instruction)?;
+        }
+        cpu.check_hypervisor_fence(raw_instruction, false)?;
+        cpu.flush_translations();
+        Ok(OperationSideEffect::None)
+    }
+
+    /// HFENCE.GVMA and Svinval HINVAL.GVMA, invalidate the G-stage
+    /// translations. Every cached translation is dropped whatever the address
+    /// and VMID.
+    #[inline(always)]
+    pub fn hfence_gvma(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        if instruction.get_funct7_field() == SubFunctions::HINVAL_GVMA {
+            cpu.require_extension(Extension::Svinval, raw_instruction)?;
+        }
+        cpu.check_hypervisor_fence(raw_instruction, true)?;
+        cpu.flush_translations();
+        Ok(OperationSideEffect::None)
+    }
+}
+
+impl Cpu {
+    /// The hypervisor loads and stores raise a virtual instruction exception
+    /// in VS and VU modes, in U-mode they are illegal unless hstatus.HU is set
+    #[inline(always)]
+    fn check_hypervisor_load_store(&self, instruction: u32) -> AppResult<()> {
+        self.require_extension(Extension::H, instruction)?;
+        let hstatus = self.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        match (self.privilege_mode, self.virtualization) {
+            (_, true) => Err(AppErrors::VirtualInstruction { instruction }),
+            (PrivilegeMode::User, false) if hstatus & HStatusFields::HU == 0 => {
+                Err(AppErrors::IllegalInstruction { instruction })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The hypervisor fences raise a virtual instruction exception in VS and
+    /// VU modes and are illegal in U-mode. HFENCE.GVMA is also illegal in
+    /// HS-mode while mstatus.TVM is set.
+    #[inline(always)]
+    fn check_hypervisor_fence(&self, instruction: u32, guest_stage: bool) -> AppResult<()> {
+        self.require_extension(Extension::H, instruction)?;
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        match (self.privilege_mode, self.virtualization) {
+            (_, true) => Err(AppErrors::VirtualInstruction { instruction }),
+            (PrivilegeMode::User, false) => Err(AppErrors::IllegalInstruction { instruction }),
+            (PrivilegeMode::Supervisor, false)
+                if guest_stage && status & MStatusFields::TVM != 0 =>
+            {
+                Err(AppErrors::IllegalInstruction { instruction })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads `size` bytes at a guest virtual address. The access bypasses the
+    /// data TLB, which only maps the addresses of the current mode. The
+    /// hypervisor access stays recorded if it faults, so the trap can report
+    /// the address as a guest virtual address.
+    fn hypervisor_load(
+        &mut self,
+        addr: u64,
+        size: u64,
+        access: HypervisorAccess,
+    ) -> AppResult<u64> {
+        self.hypervisor_access = Some(access);
+        self.check_access_alignment(addr, size, MemoryAccess::Load)?;
+        let (physical, next_page) = self.translate_data_access(addr, size, MemoryAccess::Load)?;
+        let value = self.load_physical(physical, next_page, size)?;
+        self.check_access_triggers(MemoryAccess::Load, addr, size, Some(value))?;
+        self.hypervisor_access = None;
+        Ok(value)
+    }
+
+    /// Writes the `size` lower bytes of a value at a guest virtual address,
+    /// same as hypervisor_load
+    fn hypervisor_store(&mut self, addr: u64, size: MemoryOpSize, value: u64) -> AppResult<()> {
+        self.hypervisor_access = Some(HypervisorAccess::Data);
+        self.check_access_alignment(addr, size.bytes(), MemoryAccess::Store)?;
+        let (physical, next_page) =
+            self.translate_data_access(addr, size.bytes(), MemoryAccess::Store)?;
+        self.check_access_triggers(
+            MemoryAccess::Store,
+            addr,
+            size.bytes(),
+            Some(size.zero_extend(value)),
+        )?;
+        self.store_physical(physical, next_page, size, value)?;
+        self.hypervisor_access = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{
+            config::CpuConfig,
+            instructions::implementations::CpuInstructionsOpCodes,
+            mmu::SatpFields,
+            test_hart::{self, execute},
+        },
+    };
+
+    /// HS-mode hart without PMP entries, so that memory is accessible below
+    /// M-mode
+    fn cpu() -> Cpu {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            pmp_entries: 0,
+            ..CpuConfig::default()
+        });
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu
+    }
+
+    fn hypervisor_load(funct7: u8, variant: u8, rd: u32, rs1: u32) -> u32 {
+        ((funct7 as u32) << 25)
+            | ((variant as u32) << 20)
+            | (rs1 << 15)
+            | ((SubFunctions::HYPERVISOR_LOAD_STORE as u32) << 12)
+            | (rd << 7)
+            | CpuInstructionsOpCodes::SYSCALLS_CSR as u32
+    }
+
+    fn hypervisor_store(funct7: u8, rs2: u32, rs1: u32) -> u32 {
+        ((funct7 as u32) << 25)
+            | (rs2 << 20)
+            | (rs1 << 15)
+            | ((SubFunctions::HYPERVISOR_LOAD_STORE as u32) << 12)
+            | CpuInstructionsOpCodes::SYSCALLS_CSR as u32
+    }
+
+    #[test]
+    fn loads_and_stores_through_the_guest_translation() {
+        let mut cpu = cpu();
+        cpu.registers[1] = DRAM_BASE_ADDR + 0x1000;
+        cpu.registers[2] = 0xffff_ff80;
+        execute(&mut cpu, hypervisor_store(SubFunctions::HSV_W, 2, 1)).unwrap();
+        execute(
+            &mut cpu,
+            hypervisor_load(SubFunctions::HLV_W, SubFunctions::HLV_SIGNED, 3, 1),
+        )
+        .unwrap();
+        assert_eq!(cpu.registers[3], 0xffff_ffff_ffff_ff80);
+        execute(
+            &mut cpu,
+            hypervisor_load(SubFunctions::HLV_B, SubFunctions::HLV_UNSIGNED, 3, 1),
+        )
+        .unwrap();
+        assert_eq!(cpu.registers[3], 0x80);
+        assert!(cpu.hypervisor_access.is_none());
+        assert!(!cpu.virtualization);
+    }
+
+    #[test]
+    fn g_stage_faults_report_a_guest_virtual_address() {
+        let mut cpu = cpu();
+        //Empty Sv39x4 root table, every guest physical address faults
+        cpu.store_hgatp(
+            (SatpFields::SV39 << SatpFields::MODE_SHIFT) | ((DRAM_BASE_ADDR + 0x4_0000) >> 12),
+        );
+        cpu.registers[1] = 0x1000;
+        let load = execute(
+            &mut cpu,
+            hypervisor_load(SubFunctions::HLV_D, SubFunctions::HLV_SIGNED, 3, 1),
+        );
+        assert!(matches!(
+            load,
+            Err(AppErrors::LoadGuestPageFault {
+                addr: 0x1000,
+                guest_physical: 0x1000
+            })
+        ));
+        if let Err(err) = load {
+            cpu.trap_on_exception(err).unwrap();
+        }
+        let status = cpu.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        assert_ne!(status & MStatusFields::GVA, 0);
+        assert_eq!(status & MStatusFields::MPV, 0);
+        assert_eq!(
+            cpu.cs_registers[MachineLevelCSRegisters::MTVAL2],
+            0x1000 >> 2
+        );
+    }
+
+    #[test]
+    fn hypervisor_instructions_are_privileged() {
+        let load = hypervisor_load(SubFunctions::HLV_W, SubFunctions::HLV_SIGNED, 3, 1);
+        //hfence.gvma x0, x0
+        let fence = 0x6200_0073;
+        let mut cpu = cpu();
+        cpu.registers[1] = DRAM_BASE_ADDR;
+        cpu.privilege_mode = PrivilegeMode::User;
+        assert!(matches!(
+            execute(&mut cpu, load),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        assert!(matches!(
+            execute(&mut cpu, fence),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        //hstatus.HU lets U-mode use the loads and stores but not the fences
+        cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS] |= HStatusFields::HU;
+        execute(&mut cpu, load).unwrap();
+        for privilege_mode in [PrivilegeMode::User, PrivilegeMode::Supervisor] {
+            cpu.privilege_mode = privilege_mode;
+            cpu.set_virtualization(true);
+            assert!(matches!(
+                execute(&mut cpu, load),
+                Err(AppErrors::VirtualInstruction { .. })
+            ));
+            assert!(matches!(
+                execute(&mut cpu, fence),
+                Err(AppErrors::VirtualInstruction { .. })
+            ));
+        }
+        cpu.set_virtualization(false);
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] |= MStatusFields::TVM;
+        assert!(matches!(
+            execute(&mut cpu, fence),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        //hfence.vvma x0, x0 is not trapped by TVM
+        execute(&mut cpu, 0x2200_0073).unwrap();
+    }
+}
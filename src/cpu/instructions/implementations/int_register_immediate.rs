@@ -46,116 +46,127 @@ impl InstructionsExecutor {
     #[inline(always)]
     pub fn addi(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_add(instruction.get_i_imm() as u64),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_add(instruction.get_i_imm()),
         )
     }
     ///Set less than immediate
     #[inline(always)]
     pub fn slti(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let value = (cpu.registers[instruction.get_rs1_field() as usize] as i64)
+        let value = (cpu.read_reg(instruction.get_rs1_register()?) as i64)
             < (instruction.get_i_imm() as i64);
-        cpu.write_reg(instruction.get_rd_field() as usize, value as u64)
+        cpu.write_reg(instruction.get_rd_register()?, value as u64)
     }
 
     ///Set less than immediate unsigned
     #[inline(always)]
     pub fn sltiu(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let value = cpu.registers[instruction.get_rs1_field() as usize] < instruction.get_i_imm();
-        cpu.write_reg(instruction.get_rd_field() as usize, value as u64)
+        let value = cpu.read_reg(instruction.get_rs1_register()?) < instruction.get_i_imm();
+        cpu.write_reg(instruction.get_rd_register()?, value as u64)
     }
 
     #[inline(always)]
     pub fn ori(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize] | instruction.get_i_imm(),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?) | instruction.get_i_imm(),
         )
     }
 
     #[inline(always)]
     pub fn xori(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize] ^ instruction.get_i_imm(),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?) ^ instruction.get_i_imm(),
         )
     }
 
     #[inline(always)]
     pub fn andi(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize] & instruction.get_i_imm(),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?) & instruction.get_i_imm(),
         )
     }
 
     #[inline(always)]
     pub fn slli(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let shamt = (instruction.get_i_imm() & 0x3f) as u32; // shamt is encoded in the lower 6bit of the imm for RV64I
+        // shamt is encoded in the lower 6bit of the imm for RV64I and 5bit for RV32I
+        let shamt =
+            cpu.immediate_shift_amount(instruction.get_i_imm(), instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize].wrapping_shl(shamt),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_shl(shamt),
         )
     }
 
     #[inline(always)]
     pub fn slliw(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         let shamt = (instruction.get_i_imm() & 0x1f) as u32; // shamt is encoded in the lower 5bit of the imm for RV64I
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize].wrapping_shl(shamt) as i32 as i64
-                as u64,
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_shl(shamt) as i32 as i64 as u64,
         )
     }
 
     #[inline(always)]
     pub fn srli(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let shamt = (instruction.get_i_imm() & 0x3f) as u32; // shamt is encoded in the lower 6bit of the imm for RV64I
+        // shamt is encoded in the lower 6bit of the imm for RV64I and 5bit for RV32I
+        let shamt =
+            cpu.immediate_shift_amount(instruction.get_i_imm(), instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize].wrapping_shr(shamt),
+            instruction.get_rd_register()?,
+            cpu.zero_extend_xlen(cpu.read_reg(instruction.get_rs1_register()?))
+                .wrapping_shr(shamt),
         )
     }
 
     #[inline(always)]
     pub fn srliw(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let shamt = (instruction.get_i_imm() & 0x1f) as u32; // shamt is encoded in the lower 6bit of the imm for RV64I
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
+        let shamt = (instruction.get_i_imm() & 0x1f) as u32; // shamt is encoded in the lower 5bit of the imm for RV64I
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize].wrapping_shr(shamt) as i32 as i64
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as u32).wrapping_shr(shamt) as i32 as i64
                 as u64,
         )
     }
 
     #[inline(always)]
     pub fn srai(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let shamt = (instruction.get_i_imm() & 0x3f) as u32; // shamt is encoded in the lower 6bit of the imm for RV64I
+        // shamt is encoded in the lower 6bit of the imm for RV64I and 5bit for RV32I
+        let shamt =
+            cpu.immediate_shift_amount(instruction.get_i_imm(), instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize] as i64).wrapping_shr(shamt) as u64,
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as i64).wrapping_shr(shamt) as u64,
         )
     }
 
     #[inline(always)]
     pub fn sraiw(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         let shamt = (instruction.get_i_imm() & 0x1f) as u32; // shamt is encoded in the lower 6bit of the imm for RV64I
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize] as i32).wrapping_shr(shamt) as i64
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as i32).wrapping_shr(shamt) as i64
                 as u64,
         )
     }
 
     #[inline(always)]
     pub fn lui(cpu: &mut Cpu, instruction: impl UTypeDecoder) -> AppResult<OperationSideEffect> {
-        cpu.write_reg(instruction.get_rd_field() as usize, instruction.get_u_imm())
+        cpu.write_reg(instruction.get_rd_register()?, instruction.get_u_imm())
     }
 
     #[inline(always)]
     pub fn auipc(cpu: &mut Cpu, instruction: impl UTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
+            instruction.get_rd_register()?,
             cpu.program_counter
                 .wrapping_add(instruction.get_u_imm())
                 //subtract the isntruction size as we move the program counter by that at the begining of the execution
@@ -165,10 +176,40 @@ impl InstructionsExecutor {
 
     #[inline(always)]
     pub fn addiw(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
                 .wrapping_add(instruction.get_i_imm()) as i32 as i64 as u64,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::test_hart::{cpu, execute};
+
+    /// slliw x7, x5, 1
+    const SLLIW: u32 = 0x0012_939b;
+    /// srliw x7, x5, 1
+    const SRLIW: u32 = 0x0012_d39b;
+    /// sraiw x7, x5, 1
+    const SRAIW: u32 = 0x4012_d39b;
+
+    #[test]
+    fn word_shifts_ignore_the_upper_bits() {
+        let mut cpu = cpu();
+        for (instruction, rs1, expected) in [
+            (SRLIW, 0x1_0000_0000, 0),
+            (SRLIW, 0xffff_ffff_8000_0000, 0x4000_0000),
+            (SRLIW, 0x3_0000_0002, 1),
+            (SRAIW, 0x1_0000_0000, 0),
+            (SRAIW, 0x8000_0000, 0xffff_ffff_c000_0000),
+            (SLLIW, 0x1_4000_0000, 0xffff_ffff_8000_0000),
+        ] {
+            cpu.registers[5] = rs1;
+            execute(&mut cpu, instruction).unwrap();
+            assert_eq!(cpu.registers[7], expected, "{instruction:#x} of {rs1:#x}");
+        }
+    }
+}
@@ -32,9 +32,9 @@ impl InstructionsExecutor {
     #[inline(always)]
     pub fn add(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_add(cpu.registers[instruction.get_rs2_field() as usize]),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_add(cpu.read_reg(instruction.get_rs2_register()?)),
         )
     }
     /// Substract the value held on rs2 to rs1 and sets to rd:
@@ -42,9 +42,9 @@ impl InstructionsExecutor {
     #[inline(always)]
     pub fn sub(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_sub(cpu.registers[instruction.get_rs2_field() as usize]),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_sub(cpu.read_reg(instruction.get_rs2_register()?)),
         )
     }
     /// Compares the values held in registers as signed by rs1 < rs2
@@ -52,9 +52,9 @@ impl InstructionsExecutor {
     #[inline(always)]
     pub fn slt(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            ((cpu.registers[instruction.get_rs1_field() as usize] as i64)
-                < (cpu.registers[instruction.get_rs2_field() as usize] as i64)) as u64,
+            instruction.get_rd_register()?,
+            ((cpu.read_reg(instruction.get_rs1_register()?) as i64)
+                < (cpu.read_reg(instruction.get_rs2_register()?) as i64)) as u64,
         )
     }
     /// Compares the values held in registers as unsigned by rs1 < rs2
@@ -62,99 +62,106 @@ impl InstructionsExecutor {
     #[inline(always)]
     pub fn sltu(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize]
-                < cpu.registers[instruction.get_rs2_field() as usize]) as u64,
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?)
+                < cpu.read_reg(instruction.get_rs2_register()?)) as u64,
         )
     }
     ///Bitwise AND
     #[inline(always)]
     pub fn and(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize]
-                & cpu.registers[instruction.get_rs2_field() as usize]) as u64,
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                & cpu.read_reg(instruction.get_rs2_register()?),
         )
     }
     ///Bitwise OR
     #[inline(always)]
     pub fn or(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize]
-                | cpu.registers[instruction.get_rs2_field() as usize]) as u64,
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                | cpu.read_reg(instruction.get_rs2_register()?),
         )
     }
     ///Bitwise XOR
     #[inline(always)]
     pub fn xor(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize]
-                ^ cpu.registers[instruction.get_rs2_field() as usize]) as u64,
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                ^ cpu.read_reg(instruction.get_rs2_register()?),
         )
     }
     /// Performs a logical left shift on rs1 by the shift amount
-    /// in the first 6 bits held in rs2; rd = rs1 << (rs2 & 0x3f)
+    /// in the first 6 bits (5 for RV32I) held in rs2; rd = rs1 << (rs2 & 0x3f)
     #[inline(always)]
     pub fn sll(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let shamt =
+            (cpu.read_reg(instruction.get_rs2_register()?) & cpu.shift_amount_mask()) as u32;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_shl((cpu.registers[instruction.get_rs2_field() as usize] & 0x3f) as u32),
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_shl(shamt),
         )
     }
     /// Performs a logical left shift on rs1 by the shift amount
     /// in the first 5 bits held in rs2; rd = rs1 << (rs2 & 0x3f)
     #[inline(always)]
     pub fn sllw(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize] as u32)
-                .wrapping_shl((cpu.registers[instruction.get_rs2_field() as usize] & 0x1f) as u32)
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as u32)
+                .wrapping_shl((cpu.read_reg(instruction.get_rs2_register()?) & 0x1f) as u32)
                 as i32 as i64 as u64,
         )
     }
     /// Performs a logical right shift on rs1 by the shift amount
-    /// in the first 6 bits held in rs2; rd = rs1 >> (rs2 & 0x3f)
+    /// in the first 6 bits (5 for RV32I) held in rs2; rd = rs1 >> (rs2 & 0x3f)
     #[inline(always)]
     pub fn srl(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let shamt =
+            (cpu.read_reg(instruction.get_rs2_register()?) & cpu.shift_amount_mask()) as u32;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_shr((cpu.registers[instruction.get_rs2_field() as usize] & 0x3f) as u32),
+            instruction.get_rd_register()?,
+            cpu.zero_extend_xlen(cpu.read_reg(instruction.get_rs1_register()?))
+                .wrapping_shr(shamt),
         )
     }
     /// Performs a logical right shift on rs1 by the shift amount
     /// in the first 5 bits held in rs2; rd = rs1 >> (rs2 & 0x3f)
     #[inline(always)]
     pub fn srlw(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize] as u32)
-                .wrapping_shr((cpu.registers[instruction.get_rs2_field() as usize] & 0x1f) as u32)
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as u32)
+                .wrapping_shr((cpu.read_reg(instruction.get_rs2_register()?) & 0x1f) as u32)
                 as i32 as i64 as u64,
         )
     }
     /// Performs a arimetric right shift (sign-extended) on rs1 by the shift amount
-    /// in the first 6 bits held in rs2; rd = (rs1 as i64) >> (rs2 & 0x3f)
+    /// in the first 6 bits (5 for RV32I) held in rs2; rd = (rs1 as i64) >> (rs2 & 0x3f)
     #[inline(always)]
     pub fn sra(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        let shamt =
+            (cpu.read_reg(instruction.get_rs2_register()?) & cpu.shift_amount_mask()) as u32;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize] as i64)
-                .wrapping_shr((cpu.registers[instruction.get_rs2_field() as usize] & 0x3f) as u32)
-                as u64,
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as i64).wrapping_shr(shamt) as u64,
         )
     }
     /// Performs a arimetric right shift (sign-extended) on rs1 by the shift amount
     /// in the first 5 bits held in rs2; rd = (rs1 as i64) >> (rs2 & 0x3f)
     #[inline(always)]
     pub fn sraw(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            (cpu.registers[instruction.get_rs1_field() as usize] as i32)
-                .wrapping_shr((cpu.registers[instruction.get_rs2_field() as usize] & 0x1f) as u32)
+            instruction.get_rd_register()?,
+            (cpu.read_reg(instruction.get_rs1_register()?) as i32)
+                .wrapping_shr((cpu.read_reg(instruction.get_rs2_register()?) & 0x1f) as u32)
                 as i64 as u64,
         )
     }
@@ -164,11 +171,12 @@ impl InstructionsExecutor {
     /// sign extends the value to 64
     #[inline(always)]
     pub fn addw(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_add(cpu.registers[instruction.get_rs2_field() as usize])
-                as i32 as i64 as u64,
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_add(cpu.read_reg(instruction.get_rs2_register()?)) as i32
+                as i64 as u64,
         )
     }
     /// Substract the value held on rs2 to rs1 and sets to rd:
@@ -177,11 +185,12 @@ impl InstructionsExecutor {
     /// sign extends the value to 64
     #[inline(always)]
     pub fn subw(cpu: &mut Cpu, instruction: impl RTypeDecoder) -> AppResult<OperationSideEffect> {
+        cpu.require_xlen64(instruction.get_raw_instruction())?;
         cpu.write_reg(
-            instruction.get_rd_field() as usize,
-            cpu.registers[instruction.get_rs1_field() as usize]
-                .wrapping_sub(cpu.registers[instruction.get_rs2_field() as usize])
-                as i32 as i64 as u64,
+            instruction.get_rd_register()?,
+            cpu.read_reg(instruction.get_rs1_register()?)
+                .wrapping_sub(cpu.read_reg(instruction.get_rs2_register()?)) as i32
+                as i64 as u64,
         )
     }
 }
@@ -1,7 +1,7 @@
 use crate::{
     cpu::{
         instruction_excecutors::InstructionsExecutor, instructions::decoder::b32::ITypeDecoder,
-        side_effects::OperationSideEffect, Cpu,
+        pmp::MemoryAccess, side_effects::OperationSideEffect, xlen::Xlen, Cpu,
     },
     error::{AppErrors, AppResult},
 };
@@ -29,46 +29,54 @@ impl SubFunctions {
 impl InstructionsExecutor {
     #[inline(always)]
     pub fn load(cpu: &mut Cpu, decoder: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
-        let addr: u64 =
-            cpu.registers[decoder.get_rs1_field() as usize].wrapping_add(decoder.get_i_imm());
-        match decoder.get_funct3_field() {
-            SubFunctions::LB => match cpu.system_bus.load8(addr) {
-                Ok(value) => {
-                    cpu.write_reg(decoder.get_rd_field() as usize, value as i8 as i64 as u64)
-                }
-                Err(err) => Err(err),
-            },
-            SubFunctions::LH => match cpu.system_bus.load16(addr) {
-                Ok(value) => {
-                    cpu.write_reg(decoder.get_rd_field() as usize, value as i16 as i64 as u64)
-                }
-                Err(err) => Err(err),
-            },
-            SubFunctions::LW => match cpu.system_bus.load32(addr) {
-                Ok(value) => {
-                    cpu.write_reg(decoder.get_rd_field() as usize, value as i32 as i64 as u64)
-                }
-                Err(err) => Err(err),
-            },
-            SubFunctions::LD => match cpu.system_bus.load64(addr) {
-                Ok(value) => cpu.write_reg(decoder.get_rd_field() as usize, value),
-                Err(err) => Err(err),
-            },
-            SubFunctions::LBU => match cpu.system_bus.load8(addr) {
-                Ok(value) => cpu.write_reg(decoder.get_rd_field() as usize, value as u64),
-                Err(err) => Err(err),
-            },
-            SubFunctions::LHU => match cpu.system_bus.load16(addr) {
-                Ok(value) => cpu.write_reg(decoder.get_rd_field() as usize, value as u64),
-                Err(err) => Err(err),
-            },
-            SubFunctions::LWU => match cpu.system_bus.load32(addr) {
-                Ok(value) => cpu.write_reg(decoder.get_rd_field() as usize, value as u64),
-                Err(err) => Err(err),
-            },
-            _ => Err(AppErrors::InstructionNotImplemented {
-                instruction: decoder.get_raw_instruction(),
-            }),
-        }
+        let addr: u64 = cpu.effective_address(
+            cpu.read_reg(decoder.get_rs1_register()?)
+                .wrapping_add(decoder.get_i_imm()),
+        );
+        let size = match decoder.get_funct3_field() {
+            SubFunctions::LD | SubFunctions::LWU if cpu.xlen == Xlen::X32 => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: decoder.get_raw_instruction(),
+                })
+            }
+            SubFunctions::LB | SubFunctions::LBU => 1,
+            SubFunctions::LH | SubFunctions::LHU => 2,
+            SubFunctions::LW | SubFunctions::LWU => 4,
+            SubFunctions::LD => 8,
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: decoder.get_raw_instruction(),
+                })
+            }
+        };
+        let value = cpu.load_data(addr, size)?;
+        let value = match decoder.get_funct3_field() {
+            SubFunctions::LB => value as i8 as i64 as u64,
+            SubFunctions::LH => value as i16 as i64 as u64,
+            SubFunctions::LW => value as i32 as i64 as u64,
+            _ => value,
+        };
+        cpu.write_reg(decoder.get_rd_register()?, value)
+    }
+}
+
+impl Cpu {
+    /// Reads `size` bytes at the virtual address of a load instruction, data
+    /// triggers see the loaded value before it is written to the destination
+    #[inline(always)]
+    pub fn load_data(&mut self, addr: u64, size: u64) -> AppResult<u64> {
+        self.check_access_alignment(addr, size, MemoryAccess::Load)?;
+        let value = match self.tlb_load(addr, size) {
+            Some(value) => value,
+            None => {
+                let (physical, next_page) =
+                    self.translate_data_access(addr, size, MemoryAccess::Load)?;
+                let value = self.load_physical(physical, next_page, size)?;
+                self.fill_tlb(addr, MemoryAccess::Load);
+                value
+            }
+        };
+        self.check_access_triggers(MemoryAccess::Load, addr, size, Some(value))?;
+        Ok(value)
     }
 }
@@ -0,0 +1,106 @@
+use crate::{
+    cpu::{
+        cs_registers::{
+            HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+        },
+        extensions::Extension,
+        instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::RTypeDecoder,
+        privilege::PrivilegeMode,
+        side_effects::OperationSideEffect,
+        Cpu,
+    },
+    error::{AppErrors, AppResult},
+};
+
+use super::SubFunctions;
+
+impl SubFunctions {
+    /// Supervisor memory-management fence, funct7 field
+    pub const SFENCE_VMA: u8 = 0b0001001;
+    /// Svinval invalidation of the translations of an address space, funct7 field
+    pub const SINVAL_VMA: u8 = 0b0001011;
+    /// Svinval sfence.w.inval and sfence.inval.ir, funct7 field
+    pub const SFENCE_INVAL: u8 = 0b0001100;
+    /// Selects between sfence.w.inval and sfence.inval.ir, rs2 field
+    pub const SFENCE_W_INVAL: u8 = 0b00000;
+    pub const SFENCE_INVAL_IR: u8 = 0b00001;
+}
+
+impl InstructionsExecutor {
+    /// Orders the page table updates before the following implicit accesses.
+    /// Every cached translation is dropped whatever the address and ASID.
+    #[inline(always)]
+    pub fn sfence_vma(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.check_address_translation_fence(instruction.get_raw_instruction(), true)?;
+        cpu.flush_translations();
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Svinval sinval.vma, invalidates the same translations as sfence.vma
+    /// without ordering the page table updates
+    #[inline(always)]
+    pub fn sinval_vma(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Svinval, instruction.get_raw_instruction())?;
+        cpu.check_address_translation_fence(instruction.get_raw_instruction(), true)?;
+        cpu.flush_translations();
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Svinval sfence.w.inval and sfence.inval.ir, order the stores and implicit
+    /// accesses around a batch of sinval.vma. They are not affected by mstatus.TVM.
+    #[inline(always)]
+    pub fn sfence_inval(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Svinval, instruction.get_raw_instruction())?;
+        cpu.check_address_translation_fence(instruction.get_raw_instruction(), false)?;
+        cpu.flush_translations();
+        Ok(OperationSideEffect::None)
+    }
+}
+
+impl Cpu {
+    /// Drops the translations cached by the TLB and by the links between
+    /// blocks, which skip the translation of the next program counter
+    pub fn flush_translations(&mut self) {
+        self.tlb.flush();
+        self.blocks.drop_links();
+    }
+
+    /// The address translation fences are illegal in U-mode, and the ones
+    /// naming an address space also in S-mode while mstatus.TVM is set. They
+    /// raise a virtual instruction exception in VU-mode, and in VS-mode while
+    /// hstatus.VTVM is set. In VS-mode they act on the VS-stage translations.
+    #[inline(always)]
+    fn check_address_translation_fence(
+        &self,
+        instruction: u32,
+        trapped_by_tvm: bool,
+    ) -> AppResult<()> {
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let hstatus = self.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        let (illegal, virtual_instruction) = match (self.privilege_mode, self.virtualization) {
+            (PrivilegeMode::Machine, _) => (false, false),
+            (PrivilegeMode::Supervisor, false) => {
+                (trapped_by_tvm && status & MStatusFields::TVM != 0, false)
+            }
+            (PrivilegeMode::Supervisor, true) => {
+                (false, trapped_by_tvm && hstatus & HStatusFields::VTVM != 0)
+            }
+            (PrivilegeMode::User, virtualization) => (!virtualization, virtualization),
+        };
+        match (illegal, virtual_instruction) {
+            (true, _) => Err(AppErrors::IllegalInstruction { instruction }),
+            (_, true) => Err(AppErrors::VirtualInstruction { instruction }),
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,5 +1,46 @@
+use crate::{
+    cpu::{
+        extensions::Extension, instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::ITypeDecoder, side_effects::OperationSideEffect, Cpu,
+    },
+    error::AppResult,
+};
+
 use super::SubFunctions;
 
 impl SubFunctions {
     pub const FENCE: u8 = 0b000;
+    /// Instruction fetch fence (Zifencei)
+    pub const FENCE_I: u8 = 0b001;
+    /// Zihintpause pause hint, a FENCE with pred=W and succ=0 (fm, rs1 and rd are zero)
+    pub const PAUSE: u32 = 0x0100_000f;
+}
+
+impl InstructionsExecutor {
+    /// Orders the memory accesses of the hart as seen by the other harts, the
+    /// predecessor and successor sets are widened to every access
+    #[inline(always)]
+    pub fn fence(_: &mut Cpu, _: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Hints that the hart is in a spin-wait loop, the host cpu gets the same hint
+    #[inline(always)]
+    pub fn pause(_: &mut Cpu, _: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        std::hint::spin_loop();
+        Ok(OperationSideEffect::None)
+    }
+
+    /// Makes the stores before the fence visible to the following instruction
+    /// fetches by dropping the decoded instructions
+    #[inline(always)]
+    pub fn fence_i(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zifencei, instruction.get_raw_instruction())?;
+        cpu.invalidate_all_code();
+        Ok(OperationSideEffect::None)
+    }
 }
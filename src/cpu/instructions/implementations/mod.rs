@@ -1,11 +1,18 @@
+pub mod atomics;
+pub mod cache_block;
 pub mod conditional_branches;
 pub mod control_transfer;
+pub mod float;
+pub mod hypervisor;
 pub mod int_register_immediate;
 pub mod int_registers;
 pub mod load;
+pub mod memory_management;
 pub mod memory_ordering;
 pub mod store;
 pub mod syscalls;
+pub mod zawrs;
+pub mod zicond;
 pub mod zicsr;
 
 pub struct SubFunctions;
@@ -25,4 +32,12 @@ impl CpuInstructionsOpCodes {
     pub const LOAD: u8 = 0x03;
     pub const STORE: u8 = 0x23;
     pub const SYSCALLS_CSR: u8 = 0b1110011;
+    pub const ATOMIC: u8 = 0b0101111;
+    pub const LOAD_FP: u8 = 0b0000111;
+    pub const STORE_FP: u8 = 0b0100111;
+    pub const OP_FP: u8 = 0b1010011;
+    pub const FMADD: u8 = 0b1000011;
+    pub const FMSUB: u8 = 0b1000111;
+    pub const FNMSUB: u8 = 0b1001011;
+    pub const FNMADD: u8 = 0b1001111;
 }
@@ -1,3 +1,12 @@
+use crate::{
+    cpu::{
+        instruction_excecutors::InstructionsExecutor, instructions::decoder::b32::STypeDecoder,
+        pmp::MemoryAccess, side_effects::OperationSideEffect, xlen::Xlen, Cpu,
+    },
+    error::{AppErrors, AppResult},
+    memory::MemoryOpSize,
+};
+
 use super::SubFunctions;
 
 ///Funct3 field Sub-instructions
@@ -11,3 +20,54 @@ impl SubFunctions {
     /// Store Double Word (64-bit)
     pub const SD: u8 = 0b011;
 }
+
+impl InstructionsExecutor {
+    #[inline(always)]
+    pub fn store(cpu: &mut Cpu, decoder: impl STypeDecoder) -> AppResult<OperationSideEffect> {
+        let addr: u64 = cpu.effective_address(
+            cpu.read_reg(decoder.get_rs1_register()?)
+                .wrapping_add(decoder.get_s_imm()),
+        );
+        let size = match decoder.get_funct3_field() {
+            SubFunctions::SB => MemoryOpSize::B8,
+            SubFunctions::SH => MemoryOpSize::B16,
+            SubFunctions::SW => MemoryOpSize::B32,
+            SubFunctions::SD if cpu.xlen == Xlen::X64 => MemoryOpSize::B64,
+            SubFunctions::SD => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: decoder.get_raw_instruction(),
+                })
+            }
+            _ => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: decoder.get_raw_instruction(),
+                })
+            }
+        };
+        cpu.store_data(addr, size, cpu.read_reg(decoder.get_rs2_register()?))?;
+        Ok(OperationSideEffect::None)
+    }
+}
+
+impl Cpu {
+    /// Writes the `size` lower bytes of a value at the virtual address of a
+    /// store instruction
+    #[inline(always)]
+    pub fn store_data(&mut self, addr: u64, size: MemoryOpSize, value: u64) -> AppResult<()> {
+        self.check_access_alignment(addr, size.bytes(), MemoryAccess::Store)?;
+        if self.tlb_store(addr, size.bytes(), value) {
+            return Ok(());
+        }
+        let (physical, next_page) =
+            self.translate_data_access(addr, size.bytes(), MemoryAccess::Store)?;
+        self.check_access_triggers(
+            MemoryAccess::Store,
+            addr,
+            size.bytes(),
+            Some(size.zero_extend(value)),
+        )?;
+        self.store_physical(physical, next_page, size, value)?;
+        self.fill_tlb(addr, MemoryAccess::Store);
+        Ok(())
+    }
+}
@@ -1,6 +1,176 @@
+use crate::{
+    cpu::{
+        cs_registers::{
+            HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+            SupervisorLevelCSRegisters,
+        },
+        instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::ITypeDecoder,
+        privilege::PrivilegeMode,
+        side_effects::OperationSideEffect,
+        Cpu,
+    },
+    error::{AppErrors, AppResult},
+};
+
 use super::SubFunctions;
 
 impl SubFunctions {
     pub const ECALL: u16 = 0b000000000000;
     pub const EBREAK: u16 = 0b000000000001;
-}
\ No newline at end of file
+    /// Supervisor trap return, funct12 field
+    pub const SRET: u16 = 0b000100000010;
+    /// Machine trap return, funct12 field
+    pub const MRET: u16 = 0b001100000010;
+    /// Wait for interrupt, funct12 field
+    pub const WFI: u16 = 0b000100000101;
+}
+
+impl InstructionsExecutor {
+    /// Raises the environment call exception of the current privilege mode,
+    /// the epc is the address of the ECALL itself
+    #[inline(always)]
+    pub fn ecall(cpu: &mut Cpu, _: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        Err(AppErrors::EnvironmentCall {
+            privilege: cpu.privilege_mode,
+            virtualized: cpu.virtualization,
+        })
+    }
+
+    /// Raises the breakpoint exception with the address of the EBREAK as tval
+    #[inline(always)]
+    pub fn ebreak(cpu: &mut Cpu, _: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        Err(AppErrors::Breakpoint {
+            addr: cpu.program_counter,
+        })
+    }
+
+    /// Returns from a machine mode trap handler to the privilege held in mstatus.MPP
+    /// and the address held in mepc, and to VS or VU mode if mstatus.MPV is set
+    #[inline(always)]
+    pub fn mret(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        if cpu.privilege_mode != PrivilegeMode::Machine {
+            return Err(AppErrors::IllegalInstruction {
+                instruction: instruction.get_raw_instruction(),
+            });
+        }
+        let status = cpu.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let previous_privilege =
+            PrivilegeMode::from_bits((status & MStatusFields::MPP) >> MStatusFields::MPP_SHIFT);
+
+        let mut new_status =
+            (status & !(MStatusFields::MIE | MStatusFields::MPP)) | MStatusFields::MPIE;
+        if status & MStatusFields::MPIE != 0 {
+            new_status |= MStatusFields::MIE;
+        }
+        if previous_privilege != PrivilegeMode::Machine {
+            new_status &= !MStatusFields::MPRV;
+        }
+        new_status &= !MStatusFields::MPV;
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] = new_status;
+        cpu.triggers.return_from_machine_trap();
+        cpu.set_virtualization(
+            previous_privilege != PrivilegeMode::Machine && status & MStatusFields::MPV != 0,
+        );
+        cpu.privilege_mode = previous_privilege;
+        cpu.update_xlen();
+        cpu.program_counter = cpu.cs_registers[MachineLevelCSRegisters::MEPC];
+        Ok(OperationSideEffect::SkipPCIncrease)
+    }
+
+    /// Returns from a supervisor mode trap handler to the privilege held in
+    /// sstatus.SPP and the address held in sepc, traps if mstatus.TSR is set.
+    /// HS-mode returns to VS or VU mode if hstatus.SPV is set, while VS-mode
+    /// returns through vsstatus and vsepc unless hstatus.VTSR is set.
+    #[inline(always)]
+    pub fn sret(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let raw_instruction = instruction.get_raw_instruction();
+        let status = cpu.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let hstatus = cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        match (cpu.privilege_mode, cpu.virtualization) {
+            (PrivilegeMode::Machine, _) => {}
+            (PrivilegeMode::Supervisor, false) if status & MStatusFields::TSR == 0 => {}
+            (PrivilegeMode::Supervisor, true) if hstatus & HStatusFields::VTSR == 0 => {
+                return Self::virtual_sret(cpu);
+            }
+            (_, true) => {
+                return Err(AppErrors::VirtualInstruction {
+                    instruction: raw_instruction,
+                })
+            }
+            (_, false) => {
+                return Err(AppErrors::IllegalInstruction {
+                    instruction: raw_instruction,
+                })
+            }
+        }
+        let previous_privilege = if status & MStatusFields::SPP != 0 {
+            PrivilegeMode::Supervisor
+        } else {
+            PrivilegeMode::User
+        };
+
+        let mut new_status = (status
+            & !(MStatusFields::SIE | MStatusFields::SPP | MStatusFields::MPRV))
+            | MStatusFields::SPIE;
+        if status & MStatusFields::SPIE != 0 {
+            new_status |= MStatusFields::SIE;
+        }
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] = new_status;
+        cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS] &= !HStatusFields::SPV;
+        cpu.set_virtualization(hstatus & HStatusFields::SPV != 0);
+        cpu.privilege_mode = previous_privilege;
+        cpu.update_xlen();
+        cpu.program_counter = cpu.cs_registers[SupervisorLevelCSRegisters::SEPC];
+        Ok(OperationSideEffect::SkipPCIncrease)
+    }
+
+    /// SRET executed in VS-mode, returns to the virtual privilege held in
+    /// vsstatus.SPP and the address held in vsepc
+    fn virtual_sret(cpu: &mut Cpu) -> AppResult<OperationSideEffect> {
+        let status = cpu.cs_registers[HypervisorLevelCSRegisters::VSSTATUS];
+        let previous_privilege = if status & MStatusFields::SPP != 0 {
+            PrivilegeMode::Supervisor
+        } else {
+            PrivilegeMode::User
+        };
+        let mut new_status =
+            (status & !(MStatusFields::SIE | MStatusFields::SPP)) | MStatusFields::SPIE;
+        if status & MStatusFields::SPIE != 0 {
+            new_status |= MStatusFields::SIE;
+        }
+        cpu.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] = new_status;
+        cpu.privilege_mode = previous_privilege;
+        cpu.update_xlen();
+        cpu.program_counter = cpu.cs_registers[HypervisorLevelCSRegisters::VSEPC];
+        Ok(OperationSideEffect::SkipPCIncrease)
+    }
+
+    /// Stalls the hart until an interrupt is pending, the wait happens once the
+    /// instruction retires so the interrupt is taken with the epc after the WFI.
+    /// Traps in U-mode, and in S-mode when mstatus.TW is set, as the wait is not
+    /// bounded in time. In VU-mode, and in VS-mode when hstatus.VTW is set, it
+    /// raises a virtual instruction exception unless mstatus.TW makes it illegal.
+    #[inline(always)]
+    pub fn wfi(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let instruction = instruction.get_raw_instruction();
+        let status = cpu.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let hstatus = cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        let trapped = match cpu.privilege_mode {
+            PrivilegeMode::Machine => false,
+            PrivilegeMode::Supervisor => status & MStatusFields::TW != 0,
+            PrivilegeMode::User => !cpu.virtualization || status & MStatusFields::TW != 0,
+        };
+        if trapped {
+            return Err(AppErrors::IllegalInstruction { instruction });
+        }
+        let virtual_trapped = match cpu.privilege_mode {
+            PrivilegeMode::Supervisor => hstatus & HStatusFields::VTW != 0,
+            _ => true,
+        };
+        if cpu.virtualization && virtual_trapped {
+            return Err(AppErrors::VirtualInstruction { instruction });
+        }
+        Ok(OperationSideEffect::WaitForInterrupt)
+    }
+}
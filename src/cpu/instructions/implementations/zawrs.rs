@@ -0,0 +1,221 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    consts::RESERVATION_GRANULE,
+    cpu::{
+        cs_registers::{
+            HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+            UserLevelCSRegisters,
+        },
+        extensions::Extension,
+        instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::ITypeDecoder,
+        interrupts::{elapsed_ticks, ticks_duration},
+        privilege::PrivilegeMode,
+        side_effects::OperationSideEffect,
+        Cpu,
+    },
+    error::AppResult,
+};
+
+use super::SubFunctions;
+
+impl SubFunctions {
+    /// Wait on reservation set with no timeout, funct12 field
+    pub const WRS_NTO: u16 = 0b000000001101;
+    /// Wait on reservation set with short timeout, funct12 field
+    pub const WRS_STO: u16 = 0b000000011101;
+}
+
+/// Short timeout of WRS.STO
+const WRS_SHORT_TIMEOUT: Duration = Duration::from_micros(100);
+/// Stores through store TLB mappings made before the wait started don't wake
+/// the hart up, the reserved value is checked again at this period
+const WRS_RECHECK_PERIOD: Duration = Duration::from_millis(1);
+
+impl InstructionsExecutor {
+    /// Stalls the hart while its reservation set is valid, the stall ends once
+    /// the reserved granule is written or an interrupt enabled in mie is
+    /// pending. mstatus.TW only traps a WRS.NTO that does not complete in a
+    /// bounded time, so below M-mode it is bounded by the short timeout instead.
+    /// The same goes for hstatus.VTW in VS and VU modes.
+    #[inline(always)]
+    pub fn wrs_nto(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zawrs, instruction.get_raw_instruction())?;
+        let status = cpu.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let hstatus = cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+        Ok(OperationSideEffect::WaitForStore {
+            bounded: (cpu.privilege_mode != PrivilegeMode::Machine
+                && status & MStatusFields::TW != 0)
+                || (cpu.virtualization && hstatus & HStatusFields::VTW != 0),
+        })
+    }
+
+    /// Same as WRS.NTO, but bounded by a short implementation defined timeout
+    #[inline(always)]
+    pub fn wrs_sto(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zawrs, instruction.get_raw_instruction())?;
+        Ok(OperationSideEffect::WaitForStore { bounded: true })
+    }
+}
+
+impl Cpu {
+    /// Idles the hart after a WRS until its reservation set is written, an
+    /// interrupt enabled in mie is pending or a timer deadline is reached.
+    /// Stores of the other harts to the reserved granule and writes to the
+    /// mtimecmp register of the hart wake the host thread up. Returns right
+    /// away without a reservation.
+    pub fn wait_for_store(&mut self, bounded: bool) {
+        let Some((addr, size, value)) = self.reservation.clone() else {
+            return;
+        };
+        let mie = self.cs_registers[MachineLevelCSRegisters::MIE];
+        let time = self.cs_registers[UserLevelCSRegisters::TIME];
+        let start = Instant::now();
+        let timeout = bounded.then_some(start + WRS_SHORT_TIMEOUT);
+
+        let machine = self.system_bus.machine();
+        machine.watch_granule(self.hart_id, addr & !(RESERVATION_GRANULE - 1));
+        let elapsed = loop {
+            let elapsed = elapsed_ticks(start);
+            let ticks = self
+                .timer_deadline()
+                .map_or(u64::MAX, |deadline| deadline.saturating_sub(time));
+            if elapsed >= ticks {
+                break ticks;
+            }
+            let reserved = machine
+                .memory
+                .fetch_update(addr, size.clone(), |_| None)
+                .is_ok_and(|loaded| loaded == value);
+            if !reserved
+                || machine.is_stopped()
+                || self.load_interrupt_csr(MachineLevelCSRegisters::MIP) & mie != 0
+            {
+                break elapsed;
+            }
+            let period = timeout.map_or(WRS_RECHECK_PERIOD, |timeout| {
+                timeout.saturating_duration_since(Instant::now())
+            });
+            if period.is_zero() {
+                break elapsed;
+            }
+            //Parking can end spuriously, the stores to the granule unpark the thread
+            thread::park_timeout(
+                period
+                    .min(WRS_RECHECK_PERIOD)
+                    .min(ticks_duration(ticks - elapsed)),
+            );
+        };
+        machine.unwatch_granule(self.hart_id);
+        self.advance_time(time + elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{
+            config::CpuConfig,
+            instructions::implementations::CpuInstructionsOpCodes,
+            interrupts::InterruptFields,
+            test_hart::{self, execute},
+        },
+        memory::MemoryOpSize,
+    };
+
+    fn wrs(funct12: u16) -> u32 {
+        (funct12 as u32) << 20 | CpuInstructionsOpCodes::SYSCALLS_CSR as u32
+    }
+
+    /// lr.w a1, (a0)
+    fn reserve(cpu: &mut Cpu, addr: u64) {
+        cpu.registers[10] = addr;
+        let lr_w = (SubFunctions::LR as u32) << 27
+            | 10 << 15
+            | 0b010 << 12
+            | 11 << 7
+            | CpuInstructionsOpCodes::ATOMIC as u32;
+        execute(cpu, lr_w).unwrap();
+    }
+
+    #[test]
+    fn tw_bounds_wrs_nto_below_machine_mode() {
+        let mut cpu = test_hart::cpu();
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] |= MStatusFields::TW;
+        assert!(matches!(
+            execute(&mut cpu, wrs(SubFunctions::WRS_NTO)),
+            Ok(OperationSideEffect::WaitForStore { bounded: false })
+        ));
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        assert!(matches!(
+            execute(&mut cpu, wrs(SubFunctions::WRS_NTO)),
+            Ok(OperationSideEffect::WaitForStore { bounded: true })
+        ));
+        cpu.privilege_mode = PrivilegeMode::Machine;
+        assert!(matches!(
+            execute(&mut cpu, wrs(SubFunctions::WRS_STO)),
+            Ok(OperationSideEffect::WaitForStore { bounded: true })
+        ));
+    }
+
+    #[test]
+    fn store_of_another_hart_ends_the_wait() {
+        let config = CpuConfig {
+            harts: 2,
+            ..CpuConfig::default()
+        };
+        let machine = test_hart::machine(&config);
+        let mut cpu = Cpu::new(machine.clone(), 0, config.clone());
+        reserve(&mut cpu, DRAM_BASE_ADDR + 4);
+        let delay = Duration::from_millis(20);
+        let start = Instant::now();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut other = Cpu::new(machine.clone(), 1, config.clone());
+                thread::sleep(delay);
+                //The reservation holds while the reserved value is unchanged
+                other
+                    .system_bus
+                    .store(DRAM_BASE_ADDR + 5, MemoryOpSize::B8, 1)
+                    .unwrap();
+            });
+            cpu.wait_for_store(false);
+        });
+        assert!(start.elapsed() >= delay);
+    }
+
+    #[test]
+    fn short_timeout_and_interrupts_end_the_wait() {
+        let mut cpu = test_hart::cpu();
+        reserve(&mut cpu, DRAM_BASE_ADDR);
+        let start = Instant::now();
+        cpu.wait_for_store(true);
+        assert!(start.elapsed() >= WRS_SHORT_TIMEOUT);
+        assert!(cpu.reservation.is_some());
+        //An interrupt pending and enabled in mie ends even an unbounded wait,
+        //regardless of mstatus.MIE
+        cpu.cs_registers[MachineLevelCSRegisters::MIE] = InterruptFields::SSI;
+        cpu.cs_registers[MachineLevelCSRegisters::MIP] = InterruptFields::SSI;
+        cpu.wait_for_store(false);
+    }
+
+    #[test]
+    fn no_reservation_does_not_wait() {
+        let mut cpu = test_hart::cpu();
+        let start = Instant::now();
+        cpu.wait_for_store(false);
+        assert!(start.elapsed() < WRS_SHORT_TIMEOUT * 10);
+    }
+}
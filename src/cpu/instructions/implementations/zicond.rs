@@ -0,0 +1,46 @@
+use crate::{
+    cpu::{
+        extensions::Extension, instruction_excecutors::InstructionsExecutor,
+        instructions::decoder::b32::RTypeDecoder, side_effects::OperationSideEffect, Cpu,
+    },
+    error::AppResult,
+};
+
+use super::SubFunctions;
+
+impl SubFunctions {
+    pub const CZERO_EQZ: (u8, u8) = (0b101, 0b0000111);
+    pub const CZERO_NEZ: (u8, u8) = (0b111, 0b0000111);
+}
+
+impl InstructionsExecutor {
+    /// Moves zero to rd if rs2 is equal to zero, otherwise moves rs1 to rd
+    #[inline(always)]
+    pub fn czero_eqz(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zicond, instruction.get_raw_instruction())?;
+        let condition = cpu.read_reg(instruction.get_rs2_register()?);
+        let value = match condition {
+            0 => 0,
+            _ => cpu.read_reg(instruction.get_rs1_register()?),
+        };
+        cpu.write_reg(instruction.get_rd_register()?, value)
+    }
+
+    /// Moves zero to rd if rs2 is not equal to zero, otherwise moves rs1 to rd
+    #[inline(always)]
+    pub fn czero_nez(
+        cpu: &mut Cpu,
+        instruction: impl RTypeDecoder,
+    ) -> AppResult<OperationSideEffect> {
+        cpu.require_extension(Extension::Zicond, instruction.get_raw_instruction())?;
+        let condition = cpu.read_reg(instruction.get_rs2_register()?);
+        let value = match condition {
+            0 => cpu.read_reg(instruction.get_rs1_register()?),
+            _ => 0,
+        };
+        cpu.write_reg(instruction.get_rd_register()?, value)
+    }
+}
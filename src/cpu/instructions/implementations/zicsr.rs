@@ -1,13 +1,85 @@
-use crate::cpu::instruction_excecutors::InstructionsExecutor;
+use crate::{
+    cpu::{
+        instruction_excecutors::InstructionsExecutor, instructions::decoder::b32::ITypeDecoder,
+        side_effects::OperationSideEffect, Cpu,
+    },
+    error::AppResult,
+};
 
 use super::SubFunctions;
 
 impl SubFunctions {
     ///funct3 only
     pub const CSRRW: u8 = 0b001;
+    pub const CSRRS: u8 = 0b010;
+    pub const CSRRC: u8 = 0b011;
+    pub const CSRRWI: u8 = 0b101;
+    pub const CSRRSI: u8 = 0b110;
+    pub const CSRRCI: u8 = 0b111;
 }
 
-impl InstructionsExecutor{
+impl InstructionsExecutor {
+    /// Atomically swaps the values in the CSR and rs1
+    #[inline(always)]
+    pub fn csrrw(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let source = cpu.read_reg(instruction.get_rs1_register()?);
+        Self::csr_operation(cpu, instruction, true, |_, _| source)
+    }
 
-    // pub fn csrrw()
-}
\ No newline at end of file
+    /// Reads the CSR and sets the bits held in rs1, the CSR is not written if rs1 is x0
+    #[inline(always)]
+    pub fn csrrs(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let source = cpu.read_reg(instruction.get_rs1_register()?);
+        let write = instruction.get_rs1_field() != 0;
+        Self::csr_operation(cpu, instruction, write, |value, _| value | source)
+    }
+
+    /// Reads the CSR and clears the bits held in rs1, the CSR is not written if rs1 is x0
+    #[inline(always)]
+    pub fn csrrc(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let source = cpu.read_reg(instruction.get_rs1_register()?);
+        let write = instruction.get_rs1_field() != 0;
+        Self::csr_operation(cpu, instruction, write, |value, _| value & !source)
+    }
+
+    /// Same as csrrw using the 5 bit zero-extended immediate encoded in the rs1 field
+    #[inline(always)]
+    pub fn csrrwi(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        Self::csr_operation(cpu, instruction, true, |_, uimm| uimm)
+    }
+
+    /// Same as csrrs using the 5 bit zero-extended immediate encoded in the rs1 field
+    #[inline(always)]
+    pub fn csrrsi(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let write = instruction.get_rs1_field() != 0;
+        Self::csr_operation(cpu, instruction, write, |value, uimm| value | uimm)
+    }
+
+    /// Same as csrrc using the 5 bit zero-extended immediate encoded in the rs1 field
+    #[inline(always)]
+    pub fn csrrci(cpu: &mut Cpu, instruction: impl ITypeDecoder) -> AppResult<OperationSideEffect> {
+        let write = instruction.get_rs1_field() != 0;
+        Self::csr_operation(cpu, instruction, write, |value, uimm| value & !uimm)
+    }
+
+    /// Reads the CSR into rd and writes back the result of `operation`, which receives
+    /// the old CSR value and the uimm field
+    #[inline(always)]
+    fn csr_operation(
+        cpu: &mut Cpu,
+        instruction: impl ITypeDecoder,
+        write: bool,
+        operation: impl Fn(u64, u64) -> u64,
+    ) -> AppResult<OperationSideEffect> {
+        let csr = (instruction.get_i_imm() & 0xfff) as usize;
+        cpu.check_csr_access(csr, write, instruction.get_raw_instruction())?;
+        let csr = cpu.virtual_csr(csr);
+        let rd = instruction.get_rd_register()?;
+        let old_value = cpu.load_csr_xlen(csr);
+        if write {
+            let uimm = instruction.get_rs1_field() as u64;
+            cpu.store_csr_xlen(csr, operation(old_value, uimm));
+        }
+        cpu.write_reg(rd, old_value)
+    }
+}
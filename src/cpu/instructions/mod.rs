@@ -1,3 +1,5 @@
 pub mod decoder;
+pub mod disassembler;
 pub mod implementations;
+pub mod table;
 pub const DEFAULT_INSTRUCTION_SIZE_BYTES: usize = 4;
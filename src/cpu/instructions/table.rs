@@ -0,0 +1,1724 @@
+use std::sync::OnceLock;
+
+use crate::cpu::instruction_excecutors::{InstructionHandler, InstructionsExecutor};
+
+use super::implementations::{CpuInstructionsOpCodes, SubFunctions};
+
+/// Bits identifying an instruction, a raw instruction matches if
+/// `raw & mask == bits`
+#[derive(Clone, Copy)]
+pub struct Encoding {
+    pub mask: u32,
+    pub bits: u32,
+}
+
+/// Operands of an instruction and how the disassembler prints them
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    /// ecall
+    None,
+    /// add rd, rs1, rs2
+    Register,
+    /// addi rd, rs1, imm
+    Immediate,
+    /// slli rd, rs1, shamt
+    Shift,
+    /// lui rd, imm[31:12]
+    Upper,
+    /// jal rd, target
+    Jump,
+    /// beq rs1, rs2, target
+    Branch,
+    /// lw rd, offset(rs1), also jalr
+    Load,
+    /// sw rs2, offset(rs1)
+    Store,
+    /// csrrw rd, csr, rs1
+    Csr,
+    /// csrrwi rd, csr, uimm
+    CsrImmediate,
+    /// amoadd.w.aqrl rd, rs2, (rs1), the width and ordering suffixes are
+    /// appended to the mnemonic
+    Atomic,
+    /// lr.w.aqrl rd, (rs1)
+    LoadReserved,
+    /// hlv.w rd, (rs1)
+    HypervisorLoad,
+    /// hsv.w rs2, (rs1)
+    HypervisorStore,
+    /// sfence.vma rs1, rs2
+    Fence,
+    /// fence pred, succ
+    MemoryFence,
+    /// cbo.zero (rs1)
+    CacheBlock,
+    /// prefetch.r offset(rs1)
+    Prefetch,
+    /// flw fd, offset(rs1)
+    FloatLoad,
+    /// fsw fs2, offset(rs1)
+    FloatStore,
+    /// fadd.s fd, fs1, fs2
+    FloatRegister,
+    /// fsqrt.s fd, fs1, also conversions between formats
+    FloatUnary,
+    /// fmadd.s fd, fs1, fs2, fs3
+    FloatFused,
+    /// feq.s rd, fs1, fs2
+    FloatCompare,
+    /// fcvt.w.s rd, fs1
+    FloatToInteger,
+    /// fcvt.s.w fd, rs1
+    IntegerToFloat,
+    /// fmvp.d.x fd, rs1, rs2
+    IntegerPairToFloat,
+    /// fli.s fd, 1.0
+    FloatImmediate,
+    /// Encodings without an instruction, printed as a raw word
+    Unknown,
+}
+
+/// Entry of the instruction table, the single description of an instruction
+/// for the decoder, the interpreter and the disassembler
+pub struct InstructionDescription {
+    pub mnemonic: &'static str,
+    pub encoding: Encoding,
+    pub operands: Operands,
+    pub handler: InstructionHandler,
+}
+
+const fn describe(
+    mnemonic: &'static str,
+    encoding: Encoding,
+    operands: Operands,
+    handler: InstructionHandler,
+) -> InstructionDescription {
+    InstructionDescription {
+        mnemonic,
+        encoding,
+        operands,
+        handler,
+    }
+}
+
+/// Encodings that don't name an instruction, executed by the handler of
+/// the error they raise
+const fn unknown(encoding: Encoding, handler: InstructionHandler) -> InstructionDescription {
+    describe("", encoding, Operands::Unknown, handler)
+}
+
+const OPCODE_MASK: u32 = 0x7f;
+const FUNCT3_MASK: u32 = 0x7 << 12;
+const FUNCT7_MASK: u32 = 0x7f << 25;
+const RD_MASK: u32 = 0x1f << 7;
+const RS2_MASK: u32 = 0x1f << 20;
+
+const fn opcode(opcode: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK,
+        bits: opcode as u32,
+    }
+}
+
+const fn funct3(opcode: u8, funct3: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK,
+        bits: opcode as u32 | (funct3 as u32) << 12,
+    }
+}
+
+const fn funct7(opcode: u8, (funct3, funct7): (u8, u8)) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | FUNCT7_MASK,
+        bits: opcode as u32 | (funct3 as u32) << 12 | (funct7 as u32) << 25,
+    }
+}
+
+/// Immediate shifts, the top 6 bits of the immediate select the shift
+const fn shift((funct3, variant): (u8, u8)) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | 0x3f << 26,
+        bits: CpuInstructionsOpCodes::INT_REG_IMMEDIATE as u32
+            | (funct3 as u32) << 12
+            | (variant as u32) << 26,
+    }
+}
+
+/// ORI with rd=x0 and the prefetch operation in the low bits of the immediate
+const fn prefetch(operation: u64) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | RD_MASK | 0x1f << 20,
+        bits: CpuInstructionsOpCodes::INT_REG_IMMEDIATE as u32
+            | (SubFunctions::ORI as u32) << 12
+            | (operation as u32) << 20,
+    }
+}
+
+/// Atomic memory operations, the width in funct3 and the aq/rl bits are
+/// decoded by the handler
+const fn funct5(funct5: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | 0x1f << 27,
+        bits: CpuInstructionsOpCodes::ATOMIC as u32 | (funct5 as u32) << 27,
+    }
+}
+
+/// Cache block operations, selected by the immediate of the CBO funct3
+const fn cbo(operation: u16) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | 0xfff << 20,
+        bits: CpuInstructionsOpCodes::MEM_ORDERING as u32
+            | (SubFunctions::CBO as u32) << 12
+            | (operation as u32) << 20,
+    }
+}
+
+/// System instructions selected by funct12, rd and rs1 are ignored
+const fn funct12(funct12: u16) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | 0xfff << 20,
+        bits: CpuInstructionsOpCodes::SYSCALLS_CSR as u32 | (funct12 as u32) << 20,
+    }
+}
+
+const fn sfence_inval(rs2: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | FUNCT7_MASK | RS2_MASK,
+        bits: CpuInstructionsOpCodes::SYSCALLS_CSR as u32
+            | (SubFunctions::SFENCE_INVAL as u32) << 25
+            | (rs2 as u32) << 20,
+    }
+}
+
+/// Hypervisor virtual-machine loads, rs2 selects the signed, unsigned or
+/// execute variant
+const fn hypervisor_load(funct7: u8, variant: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | FUNCT7_MASK | RS2_MASK,
+        bits: CpuInstructionsOpCodes::SYSCALLS_CSR as u32
+            | (SubFunctions::HYPERVISOR_LOAD_STORE as u32) << 12
+            | (funct7 as u32) << 25
+            | (variant as u32) << 20,
+    }
+}
+
+/// OP-FP instructions selected by funct5 and the format, funct3 holds the
+/// rounding mode
+const fn fp(funct5: u8, fmt: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT7_MASK,
+        bits: CpuInstructionsOpCodes::OP_FP as u32 | ((funct5 << 2 | fmt) as u32) << 25,
+    }
+}
+
+/// OP-FP instructions without a rounding mode, selected by funct3
+const fn fp_funct3(funct5: u8, fmt: u8, funct3: u8) -> Encoding {
+    funct7(CpuInstructionsOpCodes::OP_FP, (funct3, funct5 << 2 | fmt))
+}
+
+/// OP-FP instructions with a single source, rs2 selects the operation
+const fn fp_unary(funct5: u8, fmt: u8, rs2: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT7_MASK | RS2_MASK,
+        bits: fp(funct5, fmt).bits | (rs2 as u32) << 20,
+    }
+}
+
+/// Moves between the register files and classification, selected by funct3
+const fn fp_move(funct5: u8, fmt: u8, funct3: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | FUNCT7_MASK | RS2_MASK,
+        bits: fp_funct3(funct5, fmt, funct3).bits,
+    }
+}
+
+/// OP-FP instructions with a single source selected by rs2 and a fixed funct3
+const fn fp_unary_funct3(funct5: u8, fmt: u8, rs2: u8, funct3: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | FUNCT3_MASK | FUNCT7_MASK | RS2_MASK,
+        bits: fp_unary(funct5, fmt, rs2).bits | (funct3 as u32) << 12,
+    }
+}
+
+/// Fused multiply-add opcodes, selected by the format in the low bits of funct7
+const fn fused(opcode: u8, fmt: u8) -> Encoding {
+    Encoding {
+        mask: OPCODE_MASK | 0b11 << 25,
+        bits: opcode as u32 | (fmt as u32) << 25,
+    }
+}
+
+const fn exact(instruction: u32) -> Encoding {
+    Encoding {
+        mask: u32::MAX,
+        bits: instruction,
+    }
+}
+
+/// 16-bit encodings of a quadrant of the compressed instruction space
+const fn quadrant(quadrant: u32) -> Encoding {
+    Encoding {
+        mask: 0b11,
+        bits: quadrant,
+    }
+}
+
+type Ops = CpuInstructionsOpCodes;
+type Funcs = SubFunctions;
+type Exec = InstructionsExecutor;
+
+/// Every implemented instruction. The first entry matching an instruction
+/// wins, so special cases come before the encodings they are carved out of
+/// and each opcode ends with the entry handling the encodings left over.
+pub static INSTRUCTIONS: &[InstructionDescription] = &[
+    //RV32I/RV64I register-immediate
+    describe(
+        "addi",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::ADDI),
+        Operands::Immediate,
+        Exec::addi,
+    ),
+    describe(
+        "slti",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::SLTI),
+        Operands::Immediate,
+        Exec::slti,
+    ),
+    describe(
+        "sltiu",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::SLTIU),
+        Operands::Immediate,
+        Exec::sltiu,
+    ),
+    describe(
+        "xori",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::XORI),
+        Operands::Immediate,
+        Exec::xori,
+    ),
+    describe(
+        "prefetch.i",
+        prefetch(Funcs::PREFETCH_I),
+        Operands::Prefetch,
+        Exec::prefetch,
+    ),
+    describe(
+        "prefetch.r",
+        prefetch(Funcs::PREFETCH_R),
+        Operands::Prefetch,
+        Exec::prefetch,
+    ),
+    describe(
+        "prefetch.w",
+        prefetch(Funcs::PREFETCH_W),
+        Operands::Prefetch,
+        Exec::prefetch,
+    ),
+    //The other ORI hints with rd=x0 are reserved for future prefetches
+    describe(
+        "ori",
+        Encoding {
+            mask: OPCODE_MASK | FUNCT3_MASK | RD_MASK,
+            bits: Ops::INT_REG_IMMEDIATE as u32 | (Funcs::ORI as u32) << 12,
+        },
+        Operands::Immediate,
+        Exec::prefetch,
+    ),
+    describe(
+        "ori",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::ORI),
+        Operands::Immediate,
+        Exec::ori,
+    ),
+    describe(
+        "andi",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::ANDI),
+        Operands::Immediate,
+        Exec::andi,
+    ),
+    describe(
+        "slli",
+        funct3(Ops::INT_REG_IMMEDIATE, Funcs::SLLI),
+        Operands::Shift,
+        Exec::slli,
+    ),
+    describe("srli", shift(Funcs::SRLI), Operands::Shift, Exec::srli),
+    describe("srai", shift(Funcs::SRAI), Operands::Shift, Exec::srai),
+    unknown(opcode(Ops::INT_REG_IMMEDIATE), Exec::illegal),
+    describe(
+        "lui",
+        opcode(Ops::INT_REG_IMMEDIATE_LUI),
+        Operands::Upper,
+        Exec::lui,
+    ),
+    describe(
+        "auipc",
+        opcode(Ops::INT_REG_IMMEDIATE_AUIPC),
+        Operands::Upper,
+        Exec::auipc,
+    ),
+    //RV32I/RV64I register-register and Zicond
+    describe(
+        "add",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::ADD),
+        Operands::Register,
+        Exec::add,
+    ),
+    describe(
+        "sub",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::SUB),
+        Operands::Register,
+        Exec::sub,
+    ),
+    describe(
+        "slt",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::SLT),
+        Operands::Register,
+        Exec::slt,
+    ),
+    describe(
+        "sltu",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::SLTU),
+        Operands::Register,
+        Exec::sltu,
+    ),
+    describe(
+        "and",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::AND),
+        Operands::Register,
+        Exec::and,
+    ),
+    describe(
+        "or",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::OR),
+        Operands::Register,
+        Exec::or,
+    ),
+    describe(
+        "xor",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::XOR),
+        Operands::Register,
+        Exec::xor,
+    ),
+    describe(
+        "sll",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::SLL),
+        Operands::Register,
+        Exec::sll,
+    ),
+    describe(
+        "srl",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::SRL),
+        Operands::Register,
+        Exec::srl,
+    ),
+    describe(
+        "sra",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::SRA),
+        Operands::Register,
+        Exec::sra,
+    ),
+    describe(
+        "czero.eqz",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::CZERO_EQZ),
+        Operands::Register,
+        Exec::czero_eqz,
+    ),
+    describe(
+        "czero.nez",
+        funct7(Ops::INT_REG_REG_RV32I, Funcs::CZERO_NEZ),
+        Operands::Register,
+        Exec::czero_nez,
+    ),
+    unknown(opcode(Ops::INT_REG_REG_RV32I), Exec::illegal),
+    //Loads and stores, the handlers decode the width
+    describe(
+        "lb",
+        funct3(Ops::LOAD, Funcs::LB),
+        Operands::Load,
+        Exec::load,
+    ),
+    describe(
+        "lh",
+        funct3(Ops::LOAD, Funcs::LH),
+        Operands::Load,
+        Exec::load,
+    ),
+    describe(
+        "lw",
+        funct3(Ops::LOAD, Funcs::LW),
+        Operands::Load,
+        Exec::load,
+    ),
+    describe(
+        "ld",
+        funct3(Ops::LOAD, Funcs::LD),
+        Operands::Load,
+        Exec::load,
+    ),
+    describe(
+        "lbu",
+        funct3(Ops::LOAD, Funcs::LBU),
+        Operands::Load,
+        Exec::load,
+    ),
+    describe(
+        "lhu",
+        funct3(Ops::LOAD, Funcs::LHU),
+        Operands::Load,
+        Exec::load,
+    ),
+    describe(
+        "lwu",
+        funct3(Ops::LOAD, Funcs::LWU),
+        Operands::Load,
+        Exec::load,
+    ),
+    unknown(opcode(Ops::LOAD), Exec::load),
+    describe(
+        "sb",
+        funct3(Ops::STORE, Funcs::SB),
+        Operands::Store,
+        Exec::store,
+    ),
+    describe(
+        "sh",
+        funct3(Ops::STORE, Funcs::SH),
+        Operands::Store,
+        Exec::store,
+    ),
+    describe(
+        "sw",
+        funct3(Ops::STORE, Funcs::SW),
+        Operands::Store,
+        Exec::store,
+    ),
+    describe(
+        "sd",
+        funct3(Ops::STORE, Funcs::SD),
+        Operands::Store,
+        Exec::store,
+    ),
+    unknown(opcode(Ops::STORE), Exec::store),
+    //A, Zabha and Zacas
+    describe(
+        "lr",
+        funct5(Funcs::LR),
+        Operands::LoadReserved,
+        Exec::atomic,
+    ),
+    describe("sc", funct5(Funcs::SC), Operands::Atomic, Exec::atomic),
+    describe(
+        "amoswap",
+        funct5(Funcs::AMOSWAP),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amoadd",
+        funct5(Funcs::AMOADD),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amoxor",
+        funct5(Funcs::AMOXOR),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amoand",
+        funct5(Funcs::AMOAND),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amoor",
+        funct5(Funcs::AMOOR),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amomin",
+        funct5(Funcs::AMOMIN),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amomax",
+        funct5(Funcs::AMOMAX),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amominu",
+        funct5(Funcs::AMOMINU),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amomaxu",
+        funct5(Funcs::AMOMAXU),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    describe(
+        "amocas",
+        funct5(Funcs::AMOCAS),
+        Operands::Atomic,
+        Exec::atomic,
+    ),
+    unknown(opcode(Ops::ATOMIC), Exec::atomic),
+    //Control transfers
+    describe("jal", opcode(Ops::CONTROL_JAL), Operands::Jump, Exec::jal),
+    describe(
+        "jalr",
+        opcode(Ops::CONTROL_JALR),
+        Operands::Load,
+        Exec::jalr,
+    ),
+    describe(
+        "beq",
+        funct3(Ops::CONDITIONAL_BRANCHES, Funcs::BEQ),
+        Operands::Branch,
+        Exec::beq,
+    ),
+    describe(
+        "bne",
+        funct3(Ops::CONDITIONAL_BRANCHES, Funcs::BNE),
+        Operands::Branch,
+        Exec::bne,
+    ),
+    describe(
+        "blt",
+        funct3(Ops::CONDITIONAL_BRANCHES, Funcs::BLT),
+        Operands::Branch,
+        Exec::blt,
+    ),
+    describe(
+        "bge",
+        funct3(Ops::CONDITIONAL_BRANCHES, Funcs::BGE),
+        Operands::Branch,
+        Exec::bge,
+    ),
+    describe(
+        "bltu",
+        funct3(Ops::CONDITIONAL_BRANCHES, Funcs::BLTU),
+        Operands::Branch,
+        Exec::bltu,
+    ),
+    describe(
+        "bgeu",
+        funct3(Ops::CONDITIONAL_BRANCHES, Funcs::BGEU),
+        Operands::Branch,
+        Exec::bgeu,
+    ),
+    unknown(opcode(Ops::CONDITIONAL_BRANCHES), Exec::illegal),
+    //RV64I word operations
+    describe(
+        "addiw",
+        funct3(Ops::INT_REG_IMMEDIATE_RV64I, Funcs::ADDIW.0),
+        Operands::Immediate,
+        Exec::addiw,
+    ),
+    describe(
+        "slliw",
+        funct7(Ops::INT_REG_IMMEDIATE_RV64I, Funcs::SLLIW),
+        Operands::Shift,
+        Exec::slliw,
+    ),
+    describe(
+        "srliw",
+        funct7(Ops::INT_REG_IMMEDIATE_RV64I, Funcs::SRLIW),
+        Operands::Shift,
+        Exec::srliw,
+    ),
+    describe(
+        "sraiw",
+        funct7(Ops::INT_REG_IMMEDIATE_RV64I, Funcs::SRAIW),
+        Operands::Shift,
+        Exec::sraiw,
+    ),
+    unknown(opcode(Ops::INT_REG_IMMEDIATE_RV64I), Exec::illegal),
+    describe(
+        "addw",
+        funct7(Ops::INT_REG_REG_RV64I, Funcs::ADDW),
+        Operands::Register,
+        Exec::addw,
+    ),
+    describe(
+        "subw",
+        funct7(Ops::INT_REG_REG_RV64I, Funcs::SUBW),
+        Operands::Register,
+        Exec::subw,
+    ),
+    describe(
+        "sllw",
+        funct7(Ops::INT_REG_REG_RV64I, Funcs::SLLW),
+        Operands::Register,
+        Exec::sllw,
+    ),
+    describe(
+        "srlw",
+        funct7(Ops::INT_REG_REG_RV64I, Funcs::SRLW),
+        Operands::Register,
+        Exec::srlw,
+    ),
+    describe(
+        "sraw",
+        funct7(Ops::INT_REG_REG_RV64I, Funcs::SRAW),
+        Operands::Register,
+        Exec::sraw,
+    ),
+    unknown(opcode(Ops::INT_REG_REG_RV64I), Exec::illegal),
+    //Fences, Zifencei, Zihintpause and Zicbom/Zicboz
+    describe("pause", exact(Funcs::PAUSE), Operands::None, Exec::pause),
+    describe(
+        "fence",
+        funct3(Ops::MEM_ORDERING, Funcs::FENCE),
+        Operands::MemoryFence,
+        Exec::fence,
+    ),
+    describe(
+        "fence.i",
+        funct3(Ops::MEM_ORDERING, Funcs::FENCE_I),
+        Operands::None,
+        Exec::fence_i,
+    ),
+    describe(
+        "cbo.inval",
+        cbo(Funcs::CBO_INVAL),
+        Operands::CacheBlock,
+        Exec::cbo_inval,
+    ),
+    describe(
+        "cbo.clean",
+        cbo(Funcs::CBO_CLEAN),
+        Operands::CacheBlock,
+        Exec::cbo_clean,
+    ),
+    describe(
+        "cbo.flush",
+        cbo(Funcs::CBO_FLUSH),
+        Operands::CacheBlock,
+        Exec::cbo_flush,
+    ),
+    describe(
+        "cbo.zero",
+        cbo(Funcs::CBO_ZERO),
+        Operands::CacheBlock,
+        Exec::cbo_zero,
+    ),
+    unknown(funct3(Ops::MEM_ORDERING, Funcs::CBO), Exec::illegal),
+    //Privileged instructions, Svinval, hypervisor and Zawrs
+    describe(
+        "sfence.vma",
+        funct7(Ops::SYSCALLS_CSR, (0, Funcs::SFENCE_VMA)),
+        Operands::Fence,
+        Exec::sfence_vma,
+    ),
+    describe(
+        "sinval.vma",
+        funct7(Ops::SYSCALLS_CSR, (0, Funcs::SINVAL_VMA)),
+        Operands::Fence,
+        Exec::sinval_vma,
+    ),
+    describe(
+        "sfence.w.inval",
+        sfence_inval(Funcs::SFENCE_W_INVAL),
+        Operands::None,
+        Exec::sfence_inval,
+    ),
+    describe(
+        "sfence.inval.ir",
+        sfence_inval(Funcs::SFENCE_INVAL_IR),
+        Operands::None,
+        Exec::sfence_inval,
+    ),
+    //Hypervisor
+    describe(
+        "hlv.b",
+        hypervisor_load(Funcs::HLV_B, Funcs::HLV_SIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlv.bu",
+        hypervisor_load(Funcs::HLV_B, Funcs::HLV_UNSIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlv.h",
+        hypervisor_load(Funcs::HLV_H, Funcs::HLV_SIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlv.hu",
+        hypervisor_load(Funcs::HLV_H, Funcs::HLV_UNSIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlvx.hu",
+        hypervisor_load(Funcs::HLV_H, Funcs::HLVX),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlv.w",
+        hypervisor_load(Funcs::HLV_W, Funcs::HLV_SIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlv.wu",
+        hypervisor_load(Funcs::HLV_W, Funcs::HLV_UNSIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlvx.wu",
+        hypervisor_load(Funcs::HLV_W, Funcs::HLVX),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hlv.d",
+        hypervisor_load(Funcs::HLV_D, Funcs::HLV_SIGNED),
+        Operands::HypervisorLoad,
+        Exec::hlv,
+    ),
+    describe(
+        "hsv.b",
+        funct7(
+            Ops::SYSCALLS_CSR,
+            (Funcs::HYPERVISOR_LOAD_STORE, Funcs::HSV_B),
+        ),
+        Operands::HypervisorStore,
+        Exec::hsv,
+    ),
+    describe(
+        "hsv.h",
+        funct7(
+            Ops::SYSCALLS_CSR,
+            (Funcs::HYPERVISOR_LOAD_STORE, Funcs::HSV_H),
+        ),
+        Operands::HypervisorStore,
+        Exec::hsv,
+    ),
+    describe(
+        "hsv.w",
+        funct7(
+            Ops::SYSCALLS_CSR,
+            (Funcs::HYPERVISOR_LOAD_STORE, Funcs::HSV_W),
+        ),
+        Operands::HypervisorStore,
+        Exec::hsv,
+    ),
+    describe(
+        "hsv.d",
+        funct7(
+            Ops::SYSCALLS_CSR,
+            (Funcs::HYPERVISOR_LOAD_STORE, Funcs::HSV_D),
+        ),
+        Operands::HypervisorStore,
+        Exec::hsv,
+    ),
+    describe(
+        "hfence.vvma",
+        funct7(Ops::SYSCALLS_CSR, (0, Funcs::HFENCE_VVMA)),
+        Operands::Fence,
+        Exec::hfence_vvma,
+    ),
+    describe(
+        "hfence.gvma",
+        funct7(Ops::SYSCALLS_CSR, (0, Funcs::HFENCE_GVMA)),
+        Operands::Fence,
+        Exec::hfence_gvma,
+    ),
+    describe(
+        "hinval.vvma",
+        funct7(Ops::SYSCALLS_CSR, (0, Funcs::HINVAL_VVMA)),
+        Operands::Fence,
+        Exec::hfence_vvma,
+    ),
+    describe(
+        "hinval.gvma",
+        funct7(Ops::SYSCALLS_CSR, (0, Funcs::HINVAL_GVMA)),
+        Operands::Fence,
+        Exec::hfence_gvma,
+    ),
+    describe("ecall", funct12(Funcs::ECALL), Operands::None, Exec::ecall),
+    describe(
+        "ebreak",
+        funct12(Funcs::EBREAK),
+        Operands::None,
+        Exec::ebreak,
+    ),
+    describe("mret", funct12(Funcs::MRET), Operands::None, Exec::mret),
+    describe("sret", funct12(Funcs::SRET), Operands::None, Exec::sret),
+    describe("wfi", funct12(Funcs::WFI), Operands::None, Exec::wfi),
+    describe(
+        "wrs.nto",
+        funct12(Funcs::WRS_NTO),
+        Operands::None,
+        Exec::wrs_nto,
+    ),
+    describe(
+        "wrs.sto",
+        funct12(Funcs::WRS_STO),
+        Operands::None,
+        Exec::wrs_sto,
+    ),
+    //Zicsr
+    describe(
+        "csrrw",
+        funct3(Ops::SYSCALLS_CSR, Funcs::CSRRW),
+        Operands::Csr,
+        Exec::csrrw,
+    ),
+    describe(
+        "csrrs",
+        funct3(Ops::SYSCALLS_CSR, Funcs::CSRRS),
+        Operands::Csr,
+        Exec::csrrs,
+    ),
+    describe(
+        "csrrc",
+        funct3(Ops::SYSCALLS_CSR, Funcs::CSRRC),
+        Operands::Csr,
+        Exec::csrrc,
+    ),
+    describe(
+        "csrrwi",
+        funct3(Ops::SYSCALLS_CSR, Funcs::CSRRWI),
+        Operands::CsrImmediate,
+        Exec::csrrwi,
+    ),
+    describe(
+        "csrrsi",
+        funct3(Ops::SYSCALLS_CSR, Funcs::CSRRSI),
+        Operands::CsrImmediate,
+        Exec::csrrsi,
+    ),
+    describe(
+        "csrrci",
+        funct3(Ops::SYSCALLS_CSR, Funcs::CSRRCI),
+        Operands::CsrImmediate,
+        Exec::csrrci,
+    ),
+    //F, D, Zfh and Zfhmin
+    describe(
+        "flw",
+        funct3(Ops::LOAD_FP, Funcs::FLW),
+        Operands::FloatLoad,
+        Exec::load_fp,
+    ),
+    describe(
+        "flh",
+        funct3(Ops::LOAD_FP, Funcs::FLH),
+        Operands::FloatLoad,
+        Exec::load_fp,
+    ),
+    describe(
+        "fld",
+        funct3(Ops::LOAD_FP, Funcs::FLD),
+        Operands::FloatLoad,
+        Exec::load_fp,
+    ),
+    unknown(opcode(Ops::LOAD_FP), Exec::illegal),
+    describe(
+        "fsw",
+        funct3(Ops::STORE_FP, Funcs::FSW),
+        Operands::FloatStore,
+        Exec::store_fp,
+    ),
+    describe(
+        "fsh",
+        funct3(Ops::STORE_FP, Funcs::FSH),
+        Operands::FloatStore,
+        Exec::store_fp,
+    ),
+    describe(
+        "fsd",
+        funct3(Ops::STORE_FP, Funcs::FSD),
+        Operands::FloatStore,
+        Exec::store_fp,
+    ),
+    unknown(opcode(Ops::STORE_FP), Exec::illegal),
+    describe(
+        "fmadd.s",
+        fused(Ops::FMADD, Funcs::FMT_S),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fmadd.h",
+        fused(Ops::FMADD, Funcs::FMT_H),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fmadd.d",
+        fused(Ops::FMADD, Funcs::FMT_D),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    unknown(opcode(Ops::FMADD), Exec::illegal),
+    describe(
+        "fmsub.s",
+        fused(Ops::FMSUB, Funcs::FMT_S),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fmsub.h",
+        fused(Ops::FMSUB, Funcs::FMT_H),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fmsub.d",
+        fused(Ops::FMSUB, Funcs::FMT_D),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    unknown(opcode(Ops::FMSUB), Exec::illegal),
+    describe(
+        "fnmsub.s",
+        fused(Ops::FNMSUB, Funcs::FMT_S),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fnmsub.h",
+        fused(Ops::FNMSUB, Funcs::FMT_H),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fnmsub.d",
+        fused(Ops::FNMSUB, Funcs::FMT_D),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    unknown(opcode(Ops::FNMSUB), Exec::illegal),
+    describe(
+        "fnmadd.s",
+        fused(Ops::FNMADD, Funcs::FMT_S),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fnmadd.h",
+        fused(Ops::FNMADD, Funcs::FMT_H),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    describe(
+        "fnmadd.d",
+        fused(Ops::FNMADD, Funcs::FMT_D),
+        Operands::FloatFused,
+        Exec::fused_multiply_add,
+    ),
+    unknown(opcode(Ops::FNMADD), Exec::illegal),
+    describe(
+        "fadd.s",
+        fp(Funcs::FP_ADD, Funcs::FMT_S),
+        Operands::FloatRegister,
+        Exec::fadd,
+    ),
+    describe(
+        "fsub.s",
+        fp(Funcs::FP_SUB, Funcs::FMT_S),
+        Operands::FloatRegister,
+        Exec::fsub,
+    ),
+    describe(
+        "fmul.s",
+        fp(Funcs::FP_MUL, Funcs::FMT_S),
+        Operands::FloatRegister,
+        Exec::fmul,
+    ),
+    describe(
+        "fdiv.s",
+        fp(Funcs::FP_DIV, Funcs::FMT_S),
+        Operands::FloatRegister,
+        Exec::fdiv,
+    ),
+    describe(
+        "fsqrt.s",
+        fp_unary(Funcs::FP_SQRT, Funcs::FMT_S, 0),
+        Operands::FloatUnary,
+        Exec::fsqrt,
+    ),
+    describe(
+        "fsgnj.s",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_S, Funcs::FSGNJ),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fsgnjn.s",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_S, Funcs::FSGNJN),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fsgnjx.s",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_S, Funcs::FSGNJX),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fmin.s",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_S, Funcs::FMIN),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fmax.s",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_S, Funcs::FMAX),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "feq.s",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_S, Funcs::FEQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "flt.s",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_S, Funcs::FLT),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fle.s",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_S, Funcs::FLE),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fcvt.w.s",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_S, Funcs::FCVT_W),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.wu.s",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_S, Funcs::FCVT_WU),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.l.s",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_S, Funcs::FCVT_L),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.lu.s",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_S, Funcs::FCVT_LU),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.s.w",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_S, Funcs::FCVT_W),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.s.wu",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_S, Funcs::FCVT_WU),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.s.l",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_S, Funcs::FCVT_L),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.s.lu",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_S, Funcs::FCVT_LU),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fmv.x.w",
+        fp_move(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_S, Funcs::FMV_X),
+        Operands::FloatToInteger,
+        Exec::fmv_to_integer,
+    ),
+    describe(
+        "fclass.s",
+        fp_move(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_S, Funcs::FCLASS),
+        Operands::FloatToInteger,
+        Exec::fclass,
+    ),
+    describe(
+        "fmv.w.x",
+        fp_move(Funcs::FP_MOVE_FROM_INTEGER, Funcs::FMT_S, 0),
+        Operands::IntegerToFloat,
+        Exec::fmv_from_integer,
+    ),
+    describe(
+        "fadd.d",
+        fp(Funcs::FP_ADD, Funcs::FMT_D),
+        Operands::FloatRegister,
+        Exec::fadd,
+    ),
+    describe(
+        "fsub.d",
+        fp(Funcs::FP_SUB, Funcs::FMT_D),
+        Operands::FloatRegister,
+        Exec::fsub,
+    ),
+    describe(
+        "fmul.d",
+        fp(Funcs::FP_MUL, Funcs::FMT_D),
+        Operands::FloatRegister,
+        Exec::fmul,
+    ),
+    describe(
+        "fdiv.d",
+        fp(Funcs::FP_DIV, Funcs::FMT_D),
+        Operands::FloatRegister,
+        Exec::fdiv,
+    ),
+    describe(
+        "fsqrt.d",
+        fp_unary(Funcs::FP_SQRT, Funcs::FMT_D, 0),
+        Operands::FloatUnary,
+        Exec::fsqrt,
+    ),
+    describe(
+        "fsgnj.d",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_D, Funcs::FSGNJ),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fsgnjn.d",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_D, Funcs::FSGNJN),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fsgnjx.d",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_D, Funcs::FSGNJX),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fmin.d",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_D, Funcs::FMIN),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fmax.d",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_D, Funcs::FMAX),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "feq.d",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_D, Funcs::FEQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "flt.d",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_D, Funcs::FLT),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fle.d",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_D, Funcs::FLE),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fcvt.w.d",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_D, Funcs::FCVT_W),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.wu.d",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_D, Funcs::FCVT_WU),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.l.d",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_D, Funcs::FCVT_L),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.lu.d",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_D, Funcs::FCVT_LU),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.d.w",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_D, Funcs::FCVT_W),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.d.wu",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_D, Funcs::FCVT_WU),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.d.l",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_D, Funcs::FCVT_L),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.d.lu",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_D, Funcs::FCVT_LU),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fmv.x.d",
+        fp_move(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_D, Funcs::FMV_X),
+        Operands::FloatToInteger,
+        Exec::fmv_to_integer,
+    ),
+    describe(
+        "fclass.d",
+        fp_move(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_D, Funcs::FCLASS),
+        Operands::FloatToInteger,
+        Exec::fclass,
+    ),
+    describe(
+        "fmv.d.x",
+        fp_move(Funcs::FP_MOVE_FROM_INTEGER, Funcs::FMT_D, 0),
+        Operands::IntegerToFloat,
+        Exec::fmv_from_integer,
+    ),
+    describe(
+        "fadd.h",
+        fp(Funcs::FP_ADD, Funcs::FMT_H),
+        Operands::FloatRegister,
+        Exec::fadd,
+    ),
+    describe(
+        "fsub.h",
+        fp(Funcs::FP_SUB, Funcs::FMT_H),
+        Operands::FloatRegister,
+        Exec::fsub,
+    ),
+    describe(
+        "fmul.h",
+        fp(Funcs::FP_MUL, Funcs::FMT_H),
+        Operands::FloatRegister,
+        Exec::fmul,
+    ),
+    describe(
+        "fdiv.h",
+        fp(Funcs::FP_DIV, Funcs::FMT_H),
+        Operands::FloatRegister,
+        Exec::fdiv,
+    ),
+    describe(
+        "fsqrt.h",
+        fp_unary(Funcs::FP_SQRT, Funcs::FMT_H, 0),
+        Operands::FloatUnary,
+        Exec::fsqrt,
+    ),
+    describe(
+        "fsgnj.h",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_H, Funcs::FSGNJ),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fsgnjn.h",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_H, Funcs::FSGNJN),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fsgnjx.h",
+        fp_funct3(Funcs::FP_SIGN_INJECTION, Funcs::FMT_H, Funcs::FSGNJX),
+        Operands::FloatRegister,
+        Exec::fsgnj,
+    ),
+    describe(
+        "fmin.h",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_H, Funcs::FMIN),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fmax.h",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_H, Funcs::FMAX),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "feq.h",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_H, Funcs::FEQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "flt.h",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_H, Funcs::FLT),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fle.h",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_H, Funcs::FLE),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fcvt.w.h",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_H, Funcs::FCVT_W),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.wu.h",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_H, Funcs::FCVT_WU),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.l.h",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_H, Funcs::FCVT_L),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.lu.h",
+        fp_unary(Funcs::FP_TO_INTEGER, Funcs::FMT_H, Funcs::FCVT_LU),
+        Operands::FloatToInteger,
+        Exec::fcvt_to_integer,
+    ),
+    describe(
+        "fcvt.h.w",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_H, Funcs::FCVT_W),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.h.wu",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_H, Funcs::FCVT_WU),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.h.l",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_H, Funcs::FCVT_L),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fcvt.h.lu",
+        fp_unary(Funcs::FP_FROM_INTEGER, Funcs::FMT_H, Funcs::FCVT_LU),
+        Operands::IntegerToFloat,
+        Exec::fcvt_from_integer,
+    ),
+    describe(
+        "fmv.x.h",
+        fp_move(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_H, Funcs::FMV_X),
+        Operands::FloatToInteger,
+        Exec::fmv_to_integer,
+    ),
+    describe(
+        "fclass.h",
+        fp_move(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_H, Funcs::FCLASS),
+        Operands::FloatToInteger,
+        Exec::fclass,
+    ),
+    describe(
+        "fmv.h.x",
+        fp_move(Funcs::FP_MOVE_FROM_INTEGER, Funcs::FMT_H, 0),
+        Operands::IntegerToFloat,
+        Exec::fmv_from_integer,
+    ),
+    describe(
+        "fcvt.s.h",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_S, Funcs::FMT_H),
+        Operands::FloatUnary,
+        Exec::fcvt_float,
+    ),
+    describe(
+        "fcvt.h.s",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_H, Funcs::FMT_S),
+        Operands::FloatUnary,
+        Exec::fcvt_float,
+    ),
+    describe(
+        "fcvt.s.d",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_S, Funcs::FMT_D),
+        Operands::FloatUnary,
+        Exec::fcvt_float,
+    ),
+    describe(
+        "fcvt.d.s",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_D, Funcs::FMT_S),
+        Operands::FloatUnary,
+        Exec::fcvt_float,
+    ),
+    describe(
+        "fcvt.d.h",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_D, Funcs::FMT_H),
+        Operands::FloatUnary,
+        Exec::fcvt_float,
+    ),
+    describe(
+        "fcvt.h.d",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_H, Funcs::FMT_D),
+        Operands::FloatUnary,
+        Exec::fcvt_float,
+    ),
+    //Zfa
+    describe(
+        "fli.s",
+        fp_unary_funct3(Funcs::FP_MOVE_FROM_INTEGER, Funcs::FMT_S, Funcs::FLI, 0),
+        Operands::FloatImmediate,
+        Exec::fli,
+    ),
+    describe(
+        "fminm.s",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_S, Funcs::FMINM),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fmaxm.s",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_S, Funcs::FMAXM),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fround.s",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_S, Funcs::FROUND),
+        Operands::FloatUnary,
+        Exec::fround,
+    ),
+    describe(
+        "froundnx.s",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_S, Funcs::FROUNDNX),
+        Operands::FloatUnary,
+        Exec::fround,
+    ),
+    describe(
+        "fleq.s",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_S, Funcs::FLEQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fltq.s",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_S, Funcs::FLTQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fli.d",
+        fp_unary_funct3(Funcs::FP_MOVE_FROM_INTEGER, Funcs::FMT_D, Funcs::FLI, 0),
+        Operands::FloatImmediate,
+        Exec::fli,
+    ),
+    describe(
+        "fminm.d",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_D, Funcs::FMINM),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fmaxm.d",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_D, Funcs::FMAXM),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fround.d",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_D, Funcs::FROUND),
+        Operands::FloatUnary,
+        Exec::fround,
+    ),
+    describe(
+        "froundnx.d",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_D, Funcs::FROUNDNX),
+        Operands::FloatUnary,
+        Exec::fround,
+    ),
+    describe(
+        "fleq.d",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_D, Funcs::FLEQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fltq.d",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_D, Funcs::FLTQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fli.h",
+        fp_unary_funct3(Funcs::FP_MOVE_FROM_INTEGER, Funcs::FMT_H, Funcs::FLI, 0),
+        Operands::FloatImmediate,
+        Exec::fli,
+    ),
+    describe(
+        "fminm.h",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_H, Funcs::FMINM),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fmaxm.h",
+        fp_funct3(Funcs::FP_MIN_MAX, Funcs::FMT_H, Funcs::FMAXM),
+        Operands::FloatRegister,
+        Exec::fmin_max,
+    ),
+    describe(
+        "fround.h",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_H, Funcs::FROUND),
+        Operands::FloatUnary,
+        Exec::fround,
+    ),
+    describe(
+        "froundnx.h",
+        fp_unary(Funcs::FP_CONVERT, Funcs::FMT_H, Funcs::FROUNDNX),
+        Operands::FloatUnary,
+        Exec::fround,
+    ),
+    describe(
+        "fleq.h",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_H, Funcs::FLEQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fltq.h",
+        fp_funct3(Funcs::FP_COMPARE, Funcs::FMT_H, Funcs::FLTQ),
+        Operands::FloatCompare,
+        Exec::fcompare,
+    ),
+    describe(
+        "fcvtmod.w.d",
+        fp_unary_funct3(
+            Funcs::FP_TO_INTEGER,
+            Funcs::FMT_D,
+            Funcs::FCVTMOD_W,
+            Funcs::FCVTMOD_RM,
+        ),
+        Operands::FloatToInteger,
+        Exec::fcvtmod_w_d,
+    ),
+    describe(
+        "fmvh.x.d",
+        fp_unary_funct3(Funcs::FP_MOVE_TO_INTEGER, Funcs::FMT_D, Funcs::FMVH, 0),
+        Operands::FloatToInteger,
+        Exec::fmvh_x_d,
+    ),
+    describe(
+        "fmvp.d.x",
+        fp_funct3(Funcs::FP_MOVE_PAIR_FROM_INTEGER, Funcs::FMT_D, 0),
+        Operands::IntegerPairToFloat,
+        Exec::fmvp_d_x,
+    ),
+    unknown(opcode(Ops::OP_FP), Exec::illegal),
+    //Compressed instructions are not supported
+    unknown(quadrant(0b00), Exec::compressed_not_implemented),
+    unknown(quadrant(0b01), Exec::compressed_not_implemented),
+    unknown(quadrant(0b10), Exec::compressed_not_implemented),
+    unknown(Encoding { mask: 0, bits: 0 }, Exec::not_implemented),
+];
+
+/// Indexes into the instruction table of the entries that can match each
+/// major opcode, in table order
+fn opcode_entries() -> &'static [Vec<u16>] {
+    static ENTRIES: OnceLock<Vec<Vec<u16>>> = OnceLock::new();
+    ENTRIES.get_or_init(|| {
+        (0..=OPCODE_MASK)
+            .map(|opcode| {
+                (0..INSTRUCTIONS.len() as u16)
+                    .filter(|index| {
+                        let encoding = INSTRUCTIONS[*index as usize].encoding;
+                        opcode & encoding.mask & OPCODE_MASK == encoding.bits & OPCODE_MASK
+                    })
+                    .collect()
+            })
+            .collect()
+    })
+}
+
+/// Index in the instruction table of the entry describing an instruction,
+/// every instruction matches at least the last entry
+pub fn lookup(instruction: u32) -> u16 {
+    opcode_entries()[(instruction & OPCODE_MASK) as usize]
+        .iter()
+        .copied()
+        .find(|index| {
+            let encoding = INSTRUCTIONS[*index as usize].encoding;
+            instruction & encoding.mask == encoding.bits
+        })
+        .expect("The instruction table ends with a catch-all entry")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_instruction_decodes_to_its_own_entry() {
+        //The hints refine base instructions, prefetch.i is ori with rd set to
+        //x0, so an instruction may only reach its entry with its free fields set
+        for (index, description) in INSTRUCTIONS.iter().enumerate() {
+            if description.operands == Operands::Unknown {
+                continue;
+            }
+            let Encoding { mask, bits } = description.encoding;
+            let found = [bits, bits | !mask].map(|raw| lookup(raw) as usize);
+            assert!(
+                found.contains(&index),
+                "{} is shadowed by {}",
+                description.mnemonic,
+                INSTRUCTIONS[found[1]].mnemonic
+            );
+        }
+    }
+
+    #[test]
+    fn opcode_buckets_match_a_linear_scan() {
+        //xorshift, the same instructions on every run
+        let mut state = 0x2545_f491_u32;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let linear = INSTRUCTIONS
+                .iter()
+                .position(|description| {
+                    state & description.encoding.mask == description.encoding.bits
+                })
+                .unwrap();
+            assert_eq!(lookup(state) as usize, linear, "{state:#010x}");
+        }
+    }
+}
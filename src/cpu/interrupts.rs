@@ -0,0 +1,609 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aia::{InterruptDomain, InterruptFile},
+    consts::TIMEBASE_FREQUENCY,
+};
+
+use super::{
+    cs_registers::{
+        EnvCfgFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+        SupervisorLevelCSRegisters, UserLevelCSRegisters,
+    },
+    extensions::Extension,
+    privilege::PrivilegeMode,
+    xlen::Xlen,
+    Cpu,
+};
+
+/// Interrupt codes as reported in the mcause/scause registers, each interrupt
+/// uses the bit with the same number in the mip/mie registers
+pub struct InterruptCause;
+impl InterruptCause {
+    pub const SUPERVISOR_SOFTWARE: u64 = 1;
+    pub const VIRTUAL_SUPERVISOR_SOFTWARE: u64 = 2;
+    pub const MACHINE_SOFTWARE: u64 = 3;
+    pub const SUPERVISOR_TIMER: u64 = 5;
+    pub const VIRTUAL_SUPERVISOR_TIMER: u64 = 6;
+    pub const MACHINE_TIMER: u64 = 7;
+    pub const SUPERVISOR_EXTERNAL: u64 = 9;
+    pub const VIRTUAL_SUPERVISOR_EXTERNAL: u64 = 10;
+    pub const MACHINE_EXTERNAL: u64 = 11;
+    pub const SUPERVISOR_GUEST_EXTERNAL: u64 = 12;
+
+    /// Interrupts sorted by decreasing default priority
+    const PRIORITY_ORDER: [u64; 10] = [
+        Self::MACHINE_EXTERNAL,
+        Self::MACHINE_SOFTWARE,
+        Self::MACHINE_TIMER,
+        Self::SUPERVISOR_EXTERNAL,
+        Self::SUPERVISOR_SOFTWARE,
+        Self::SUPERVISOR_TIMER,
+        Self::SUPERVISOR_GUEST_EXTERNAL,
+        Self::VIRTUAL_SUPERVISOR_EXTERNAL,
+        Self::VIRTUAL_SUPERVISOR_SOFTWARE,
+        Self::VIRTUAL_SUPERVISOR_TIMER,
+    ];
+}
+
+/// Bits of the mip and mie registers, sip and sie are restricted views of them
+pub struct InterruptFields;
+impl InterruptFields {
+    pub const SSI: u64 = 1 << InterruptCause::SUPERVISOR_SOFTWARE;
+    pub const MSI: u64 = 1 << InterruptCause::MACHINE_SOFTWARE;
+    pub const STI: u64 = 1 << InterruptCause::SUPERVISOR_TIMER;
+    pub const MTI: u64 = 1 << InterruptCause::MACHINE_TIMER;
+    pub const SEI: u64 = 1 << InterruptCause::SUPERVISOR_EXTERNAL;
+    pub const MEI: u64 = 1 << InterruptCause::MACHINE_EXTERNAL;
+    pub const VSSI: u64 = 1 << InterruptCause::VIRTUAL_SUPERVISOR_SOFTWARE;
+    pub const VSTI: u64 = 1 << InterruptCause::VIRTUAL_SUPERVISOR_TIMER;
+    pub const VSEI: u64 = 1 << InterruptCause::VIRTUAL_SUPERVISOR_EXTERNAL;
+    pub const SGEI: u64 = 1 << InterruptCause::SUPERVISOR_GUEST_EXTERNAL;
+
+    /// VS-level interrupts, set in hvip and delegated to VS-mode through hideleg.
+    /// vsip and vsie show them one bit lower, at the bits of the matching
+    /// supervisor interrupts.
+    pub const VIRTUAL_SUPERVISOR: u64 = Self::VSSI | Self::VSTI | Self::VSEI;
+    /// Interrupts added by the hypervisor extension, always delegated to HS-mode
+    pub const HYPERVISOR: u64 = Self::VIRTUAL_SUPERVISOR | Self::SGEI;
+
+    pub const MIE_WRITE_MASK: u64 =
+        Self::SSI | Self::MSI | Self::STI | Self::MTI | Self::SEI | Self::MEI;
+    /// Only the supervisor interrupts can be delegated
+    pub const MIDELEG_WRITE_MASK: u64 = Self::SSI | Self::STI | Self::SEI;
+    /// Bits of mip writable by software, the SEIP read value is also set while
+    /// the interrupt controllers assert the supervisor external interrupt
+    pub const MIP_WRITE_MASK: u64 = Self::SSI | Self::STI | Self::SEI;
+    pub const SIP_WRITE_MASK: u64 = Self::SSI;
+}
+
+/// Registers selected through miselect/siselect besides the ones of the IMSIC
+/// interrupt files
+struct IndirectRegisters;
+impl IndirectRegisters {
+    /// Major interrupt priorities, read-only zero as only the default priority
+    /// order is supported
+    const IPRIO0: u64 = 0x30;
+    const IPRIO15: u64 = 0x3f;
+    /// Implemented bits of miselect/siselect
+    const SELECT_MASK: u64 = 0xfff;
+}
+
+impl Cpu {
+    /// Bits of mip set by the interrupt signals of the devices, the external
+    /// interrupts of the interrupt controllers and the MSWI software interrupt
+    #[inline(always)]
+    fn external_interrupts(&self) -> u64 {
+        self.system_bus.machine().interrupts(self.hart_id)
+    }
+
+    /// Sstc drives mip.STIP from the comparison of time against stimecmp
+    /// while menvcfg.STCE is set, the bit is read-only in that case
+    fn is_supervisor_timer_compare_enabled(&self) -> bool {
+        self.extensions.contains(Extension::Sstc)
+            && self.cs_registers[MachineLevelCSRegisters::MENVCFG] & EnvCfgFields::STCE != 0
+    }
+
+    /// Bits of mip held by the hart, the software writable bits, the ACLINT
+    /// machine timer and the Sstc timer
+    fn local_interrupts(&self) -> u64 {
+        let time = self.cs_registers[UserLevelCSRegisters::TIME];
+        let mut mip = self.cs_registers[MachineLevelCSRegisters::MIP]
+            | (self.cs_registers[HypervisorLevelCSRegisters::HVIP]
+                & InterruptFields::VIRTUAL_SUPERVISOR);
+        if time >= self.system_bus.machine().mtimecmp(self.hart_id) {
+            mip |= InterruptFields::MTI;
+        }
+        if !self.is_supervisor_timer_compare_enabled() {
+            return mip;
+        }
+        let stimecmp = self.cs_registers[SupervisorLevelCSRegisters::STIMECMP];
+        match time >= stimecmp {
+            true => mip | InterruptFields::STI,
+            false => mip & !InterruptFields::STI,
+        }
+    }
+
+    fn highest_priority_interrupt(interrupts: u64) -> Option<u64> {
+        InterruptCause::PRIORITY_ORDER
+            .into_iter()
+            .find(|cause| (interrupts >> cause) & 1 == 1)
+    }
+
+    /// Encodes the highest priority interrupt of a set as the mtopi/stopi
+    /// registers, IPRIO reads as 1 as every interrupt has the default priority
+    fn topi(interrupts: u64) -> u64 {
+        Self::highest_priority_interrupt(interrupts).map_or(0, |cause| (cause << 16) | 1)
+    }
+
+    /// Reads the interrupt file of the hart for the privilege level of an
+    /// indirect register alias
+    fn read_interrupt_file(
+        &self,
+        domain: InterruptDomain,
+        read: impl Fn(&InterruptFile) -> u64,
+    ) -> u64 {
+        read(
+            self.system_bus
+                .machine()
+                .devices()
+                .imsic
+                .file(self.hart_id, domain),
+        )
+    }
+
+    /// Updates the interrupt file of the hart for the privilege level of an
+    /// indirect register alias
+    fn update_interrupt_file(
+        &self,
+        domain: InterruptDomain,
+        update: impl FnOnce(&mut InterruptFile),
+    ) {
+        self.system_bus
+            .machine()
+            .update_devices(|devices| update(devices.imsic.file_mut(self.hart_id, domain)));
+    }
+
+    fn is_indirect_register_implemented(&self, select: u64) -> bool {
+        match select {
+            //On RV64 each even register holds the priorities of the following odd one
+            IndirectRegisters::IPRIO0..=IndirectRegisters::IPRIO15 => {
+                self.xlen == Xlen::X32 || select.is_multiple_of(2)
+            }
+            _ => InterruptFile::is_register_implemented(select, self.xlen),
+        }
+    }
+
+    /// The AIA CSRs exist when Smaia/Ssaia are enabled, mireg and sireg raise
+    /// an illegal instruction exception if the selected register doesn't exist
+    pub fn is_aia_csr_accessible(&self, addr: usize) -> bool {
+        match addr {
+            MachineLevelCSRegisters::MIREG => {
+                self.extensions.contains(Extension::Smaia)
+                    && self.is_indirect_register_implemented(
+                        self.cs_registers[MachineLevelCSRegisters::MISELECT],
+                    )
+            }
+            MachineLevelCSRegisters::MVIEN
+            | MachineLevelCSRegisters::MVIP
+            | MachineLevelCSRegisters::MIDELEGH
+            | MachineLevelCSRegisters::MIEH
+            | MachineLevelCSRegisters::MVIENH
+            | MachineLevelCSRegisters::MVIPH
+            | MachineLevelCSRegisters::MISELECT
+            | MachineLevelCSRegisters::MIPH
+            | MachineLevelCSRegisters::MTOPEI
+            | MachineLevelCSRegisters::MTOPI => self.extensions.contains(Extension::Smaia),
+            SupervisorLevelCSRegisters::SIREG => {
+                self.extensions.contains(Extension::Ssaia)
+                    && self.is_indirect_register_implemented(
+                        self.cs_registers[SupervisorLevelCSRegisters::SISELECT],
+                    )
+            }
+            SupervisorLevelCSRegisters::SIEH
+            | SupervisorLevelCSRegisters::SISELECT
+            | SupervisorLevelCSRegisters::SIPH
+            | SupervisorLevelCSRegisters::STOPEI
+            | SupervisorLevelCSRegisters::STOPI => self.extensions.contains(Extension::Ssaia),
+            _ => true,
+        }
+    }
+
+    /// Interrupts of the hypervisor extension, they are read-only one in mideleg
+    pub fn hypervisor_interrupts(&self) -> u64 {
+        match self.extensions.contains(Extension::H) {
+            true => InterruptFields::HYPERVISOR,
+            false => 0,
+        }
+    }
+
+    /// Sets the VSSIP bit of hvip, which is aliased in mip, hip and vsip
+    fn store_virtual_software_interrupt(&mut self, value: u64, write_mask: u64) {
+        let write_mask = write_mask & InterruptFields::VSSI;
+        let hvip = &mut self.cs_registers[HypervisorLevelCSRegisters::HVIP];
+        *hvip = (*hvip & !write_mask) | (value & write_mask);
+    }
+
+    pub fn load_interrupt_csr(&self, addr: usize) -> u64 {
+        let mie = self.cs_registers[MachineLevelCSRegisters::MIE];
+        let mideleg = self.cs_registers[MachineLevelCSRegisters::MIDELEG];
+        let hideleg = self.cs_registers[HypervisorLevelCSRegisters::HIDELEG];
+        let mip = self.local_interrupts() | self.external_interrupts();
+        match addr {
+            MachineLevelCSRegisters::MIP => mip,
+            SupervisorLevelCSRegisters::SIP => mip & mideleg & InterruptFields::MIDELEG_WRITE_MASK,
+            HypervisorLevelCSRegisters::HIP => mip & InterruptFields::HYPERVISOR,
+            HypervisorLevelCSRegisters::HIE => mie & InterruptFields::HYPERVISOR,
+            HypervisorLevelCSRegisters::VSIP => {
+                (mip & hideleg & InterruptFields::VIRTUAL_SUPERVISOR) >> 1
+            }
+            HypervisorLevelCSRegisters::VSIE => {
+                (mie & hideleg & InterruptFields::VIRTUAL_SUPERVISOR) >> 1
+            }
+            //mvien is read-only zero, so mvip aliases the software writable bits of mip
+            MachineLevelCSRegisters::MVIP => {
+                self.local_interrupts() & InterruptFields::MIP_WRITE_MASK
+            }
+            MachineLevelCSRegisters::MVIEN => 0,
+            MachineLevelCSRegisters::MIREG => {
+                match self.cs_registers[MachineLevelCSRegisters::MISELECT] {
+                    IndirectRegisters::IPRIO0..=IndirectRegisters::IPRIO15 => 0,
+                    select => self.read_interrupt_file(InterruptDomain::Machine, |file| {
+                        file.read_register(select, self.xlen)
+                    }),
+                }
+            }
+            SupervisorLevelCSRegisters::SIREG => {
+                match self.cs_registers[SupervisorLevelCSRegisters::SISELECT] {
+                    IndirectRegisters::IPRIO0..=IndirectRegisters::IPRIO15 => 0,
+                    select => self.read_interrupt_file(InterruptDomain::Supervisor, |file| {
+                        file.read_register(select, self.xlen)
+                    }),
+                }
+            }
+            MachineLevelCSRegisters::MTOPEI => {
+                self.read_interrupt_file(InterruptDomain::Machine, InterruptFile::topei)
+            }
+            SupervisorLevelCSRegisters::STOPEI => {
+                self.read_interrupt_file(InterruptDomain::Supervisor, InterruptFile::topei)
+            }
+            MachineLevelCSRegisters::MTOPI => Self::topi(mip & mie & !mideleg),
+            SupervisorLevelCSRegisters::STOPI => Self::topi(mip & mie & mideleg & !hideleg),
+            _ => self.cs_registers[addr],
+        }
+    }
+
+    pub fn store_interrupt_csr(&mut self, addr: usize, value: u64) {
+        let xlen = self.xlen;
+        match addr {
+            MachineLevelCSRegisters::MIE => {
+                self.cs_registers[addr] =
+                    value & (InterruptFields::MIE_WRITE_MASK | self.hypervisor_interrupts())
+            }
+            MachineLevelCSRegisters::MIDELEG => {
+                self.cs_registers[addr] =
+                    (value & InterruptFields::MIDELEG_WRITE_MASK) | self.hypervisor_interrupts()
+            }
+            HypervisorLevelCSRegisters::HIDELEG | HypervisorLevelCSRegisters::HVIP => {
+                self.cs_registers[addr] = value & InterruptFields::VIRTUAL_SUPERVISOR
+            }
+            HypervisorLevelCSRegisters::HIE | HypervisorLevelCSRegisters::VSIE => {
+                let (value, write_mask) = match addr {
+                    HypervisorLevelCSRegisters::HIE => (value, InterruptFields::HYPERVISOR),
+                    _ => (
+                        value << 1,
+                        self.cs_registers[HypervisorLevelCSRegisters::HIDELEG]
+                            & InterruptFields::VIRTUAL_SUPERVISOR,
+                    ),
+                };
+                let mie = &mut self.cs_registers[MachineLevelCSRegisters::MIE];
+                *mie = (*mie & !write_mask) | (value & write_mask);
+            }
+            HypervisorLevelCSRegisters::HIP => {
+                self.store_virtual_software_interrupt(value, InterruptFields::VSSI)
+            }
+            HypervisorLevelCSRegisters::VSIP => self.store_virtual_software_interrupt(
+                value << 1,
+                self.cs_registers[HypervisorLevelCSRegisters::HIDELEG],
+            ),
+            //There are no guest external interrupt files, GEILEN is zero
+            HypervisorLevelCSRegisters::HGEIE => (),
+            MachineLevelCSRegisters::MIP
+            | MachineLevelCSRegisters::MVIP
+            | SupervisorLevelCSRegisters::SIP => {
+                let mut write_mask = match addr {
+                    SupervisorLevelCSRegisters::SIP => {
+                        InterruptFields::SIP_WRITE_MASK
+                            & self.cs_registers[MachineLevelCSRegisters::MIDELEG]
+                    }
+                    _ => InterruptFields::MIP_WRITE_MASK,
+                };
+                if self.is_supervisor_timer_compare_enabled() {
+                    write_mask &= !InterruptFields::STI;
+                }
+                if addr == MachineLevelCSRegisters::MIP {
+                    self.store_virtual_software_interrupt(value, self.hypervisor_interrupts());
+                }
+                let mip = &mut self.cs_registers[MachineLevelCSRegisters::MIP];
+                *mip = (*mip & !write_mask) | (value & write_mask);
+            }
+            MachineLevelCSRegisters::MVIEN => (),
+            MachineLevelCSRegisters::MISELECT | SupervisorLevelCSRegisters::SISELECT => {
+                self.cs_registers[addr] = value & IndirectRegisters::SELECT_MASK
+            }
+            MachineLevelCSRegisters::MIREG => {
+                let select = self.cs_registers[MachineLevelCSRegisters::MISELECT];
+                self.update_interrupt_file(InterruptDomain::Machine, |file| {
+                    file.write_register(select, value, xlen)
+                })
+            }
+            SupervisorLevelCSRegisters::SIREG => {
+                let select = self.cs_registers[SupervisorLevelCSRegisters::SISELECT];
+                self.update_interrupt_file(InterruptDomain::Supervisor, |file| {
+                    file.write_register(select, value, xlen)
+                })
+            }
+            MachineLevelCSRegisters::MTOPEI => {
+                self.update_interrupt_file(InterruptDomain::Machine, InterruptFile::claim_top)
+            }
+            SupervisorLevelCSRegisters::STOPEI => {
+                self.update_interrupt_file(InterruptDomain::Supervisor, InterruptFile::claim_top)
+            }
+            _ => self.cs_registers[addr] = value,
+        }
+    }
+
+    /// Earliest deadline of the ACLINT mtimecmp and Sstc stimecmp timers that
+    /// ends a WFI, None when no timer can wake the hart. The largest time is
+    /// never reached.
+    pub fn timer_deadline(&self) -> Option<u64> {
+        let mie = self.cs_registers[MachineLevelCSRegisters::MIE];
+        let machine = (mie & InterruptFields::MTI != 0)
+            .then(|| self.system_bus.machine().mtimecmp(self.hart_id));
+        let supervisor = (mie & InterruptFields::STI != 0
+            && self.is_supervisor_timer_compare_enabled())
+        .then_some(self.cs_registers[SupervisorLevelCSRegisters::STIMECMP]);
+        machine
+            .into_iter()
+            .chain(supervisor)
+            .filter(|deadline| *deadline != u64::MAX)
+            .min()
+    }
+
+    /// Returns true if a WFI has to idle the hart: no interrupt enabled in mie
+    /// is pending, regardless of mstatus and the delegation, and the timers or
+    /// the devices can wake the hart up. The devices only assert interrupts and
+    /// move mtimecmp on stores of the harts, so they can't wake up a lone hart.
+    pub fn must_wait(&self) -> bool {
+        let mie = self.cs_registers[MachineLevelCSRegisters::MIE];
+        let devices = mie
+            & (InterruptFields::MEI
+                | InterruptFields::SEI
+                | InterruptFields::MSI
+                | InterruptFields::MTI)
+            != 0
+            && self.system_bus.machine().hart_count() > 1;
+        self.load_interrupt_csr(MachineLevelCSRegisters::MIP) & mie == 0
+            && (devices || self.timer_deadline().is_some())
+    }
+
+    /// Idles the hart after a WFI until an interrupt enabled in mie is pending.
+    /// The host thread sleeps until the earliest timer deadline, time then
+    /// jumps to the deadline, or until the devices wake it up. Writes to
+    /// mtimecmp wake it up as well to wait for the new deadline. A hart that
+    /// can't be woken up resumes right away, WFI is allowed to act as a NOP.
+    pub fn wait_for_interrupt(&mut self) {
+        if !self.must_wait() {
+            return;
+        }
+        let mie = self.cs_registers[MachineLevelCSRegisters::MIE];
+        let time = self.cs_registers[UserLevelCSRegisters::TIME];
+        let start = Instant::now();
+        //Parking can end spuriously, the devices unpark the thread when they
+        //change the interrupts or the mtimecmp register of the hart
+        let elapsed = loop {
+            let elapsed = elapsed_ticks(start);
+            let ticks = self
+                .timer_deadline()
+                .map_or(u64::MAX, |deadline| deadline.saturating_sub(time));
+            if elapsed >= ticks {
+                break ticks;
+            }
+            let machine = self.system_bus.machine();
+            if machine.is_stopped() || machine.interrupts(self.hart_id) & mie != 0 {
+                break elapsed;
+            }
+            match ticks {
+                u64::MAX => thread::park(),
+                _ => thread::park_timeout(ticks_duration(ticks - elapsed)),
+            }
+        };
+        self.advance_time(time + elapsed);
+    }
+
+    /// Moves time forward to at least `time`, for harts idle in WFI
+    pub fn advance_time(&mut self, time: u64) {
+        let current = &mut self.cs_registers[UserLevelCSRegisters::TIME];
+        *current = (*current).max(time);
+        self.synchronize_time();
+    }
+
+    /// Catches up with the time reached by the other harts
+    pub fn synchronize_time(&mut self) {
+        let time = &mut self.cs_registers[UserLevelCSRegisters::TIME];
+        *time = self.system_bus.machine().synchronize_time(*time);
+    }
+
+    /// Takes the highest priority interrupt that is pending and enabled. An
+    /// interrupt is taken in M-mode if it isn't delegated through mideleg,
+    /// while running in M-mode it is also masked by mstatus.MIE. Delegated
+    /// interrupts are taken in HS-mode, they are never taken while running in
+    /// M-mode and are masked by mstatus.SIE while running in HS-mode. The
+    /// VS-level interrupts delegated through hideleg are only taken in VS and
+    /// VU modes, masked by vsstatus.SIE in VS-mode.
+    pub fn take_pending_interrupt(&mut self) {
+        let mie = self.cs_registers[MachineLevelCSRegisters::MIE];
+        //No interrupt can be taken while all of them are disabled
+        if mie == 0 {
+            return;
+        }
+        let pending = self.load_interrupt_csr(MachineLevelCSRegisters::MIP) & mie;
+        if pending == 0 {
+            return;
+        }
+        let mideleg = self.cs_registers[MachineLevelCSRegisters::MIDELEG];
+        let hideleg = self.cs_registers[HypervisorLevelCSRegisters::HIDELEG];
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let virtual_status = self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS];
+        let (machine_enabled, supervisor_enabled, virtual_enabled) =
+            match (self.privilege_mode, self.virtualization) {
+                (PrivilegeMode::Machine, _) => (status & MStatusFields::MIE != 0, false, false),
+                (PrivilegeMode::Supervisor, false) => {
+                    (true, status & MStatusFields::SIE != 0, false)
+                }
+                (PrivilegeMode::User, false) => (true, true, false),
+                (PrivilegeMode::Supervisor, true) => {
+                    (true, true, virtual_status & MStatusFields::SIE != 0)
+                }
+                (PrivilegeMode::User, true) => (true, true, true),
+            };
+        let enabled = |enabled: bool| if enabled { u64::MAX } else { 0 };
+        //Interrupts handled by M-mode are taken before the ones handled by
+        //HS-mode, and those before the ones handled by VS-mode
+        let machine = pending & !mideleg & enabled(machine_enabled);
+        let supervisor = pending & mideleg & !hideleg & enabled(supervisor_enabled);
+        let virtual_supervisor = pending & mideleg & hideleg & enabled(virtual_enabled);
+        let candidates = [machine, supervisor, virtual_supervisor]
+            .into_iter()
+            .find(|candidates| *candidates != 0)
+            .unwrap_or(0);
+        if let Some(cause) = Self::highest_priority_interrupt(candidates) {
+            self.take_interrupt(cause);
+            self.program_counter = self.effective_address(self.program_counter);
+        }
+    }
+}
+
+/// Host duration of a number of timebase ticks
+pub fn ticks_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks.saturating_mul(1_000_000_000 / TIMEBASE_FREQUENCY as u64))
+}
+
+/// Timebase ticks elapsed on the host since an instant
+pub fn elapsed_ticks(start: Instant) -> u64 {
+    (start.elapsed().as_nanos() / (1_000_000_000 / TIMEBASE_FREQUENCY as u128)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::ACLINT_MTIMER_BASE_ADDR,
+        cpu::{
+            side_effects::OperationSideEffect,
+            test_hart::{self, execute},
+        },
+        error::AppErrors,
+        memory::MemoryOpSize,
+    };
+
+    /// wfi
+    const WFI: u32 = 0x1050_0073;
+
+    fn time(cpu: &Cpu) -> u64 {
+        cpu.cs_registers[UserLevelCSRegisters::TIME]
+    }
+
+    #[test]
+    fn mtimecmp_drives_the_machine_timer_interrupt() {
+        let mut cpu = test_hart::cpu();
+        let mtimecmp = time(&cpu) + 100;
+        cpu.system_bus
+            .store(ACLINT_MTIMER_BASE_ADDR, MemoryOpSize::B64, mtimecmp)
+            .unwrap();
+        let mip = |cpu: &Cpu| cpu.load_interrupt_csr(MachineLevelCSRegisters::MIP);
+        assert_eq!(mip(&cpu) & InterruptFields::MTI, 0);
+        cpu.advance_time(mtimecmp);
+        assert_ne!(mip(&cpu) & InterruptFields::MTI, 0);
+        //MTIP is read-only
+        cpu.store_csr(MachineLevelCSRegisters::MIP, 0);
+        assert_ne!(mip(&cpu) & InterruptFields::MTI, 0);
+    }
+
+    #[test]
+    fn stimecmp_drives_stip_once_enabled() {
+        let mut cpu = test_hart::cpu();
+        cpu.store_csr(SupervisorLevelCSRegisters::STIMECMP, 0);
+        let stip = |cpu: &Cpu| {
+            cpu.load_interrupt_csr(MachineLevelCSRegisters::MIP) & InterruptFields::STI != 0
+        };
+        //Without STCE mip.STIP is written by M-mode
+        assert!(!stip(&cpu));
+        cpu.store_csr(MachineLevelCSRegisters::MIP, InterruptFields::STI);
+        assert!(stip(&cpu));
+        cpu.cs_registers[MachineLevelCSRegisters::MENVCFG] |= EnvCfgFields::STCE;
+        cpu.store_csr(SupervisorLevelCSRegisters::STIMECMP, u64::MAX);
+        assert!(!stip(&cpu));
+        let deadline = time(&cpu) + 10;
+        cpu.store_csr(SupervisorLevelCSRegisters::STIMECMP, deadline);
+        assert!(!stip(&cpu));
+        cpu.advance_time(deadline);
+        assert!(stip(&cpu));
+    }
+
+    #[test]
+    fn timer_deadline_is_the_earliest_enabled_timer() {
+        let mut cpu = test_hart::cpu();
+        let now = time(&cpu);
+        cpu.system_bus
+            .store(ACLINT_MTIMER_BASE_ADDR, MemoryOpSize::B64, now + 200)
+            .unwrap();
+        cpu.cs_registers[MachineLevelCSRegisters::MENVCFG] |= EnvCfgFields::STCE;
+        cpu.store_csr(SupervisorLevelCSRegisters::STIMECMP, now + 100);
+        assert_eq!(cpu.timer_deadline(), None);
+        //A lone hart without an enabled timer can't be woken up
+        assert!(!cpu.must_wait());
+        cpu.cs_registers[MachineLevelCSRegisters::MIE] = InterruptFields::MTI;
+        assert_eq!(cpu.timer_deadline(), Some(now + 200));
+        cpu.cs_registers[MachineLevelCSRegisters::MIE] |= InterruptFields::STI;
+        assert_eq!(cpu.timer_deadline(), Some(now + 100));
+        assert!(cpu.must_wait());
+    }
+
+    #[test]
+    fn wfi_skips_time_to_the_timer_deadline() {
+        let mut cpu = test_hart::cpu();
+        let deadline = time(&cpu) + 50;
+        cpu.system_bus
+            .store(ACLINT_MTIMER_BASE_ADDR, MemoryOpSize::B64, deadline)
+            .unwrap();
+        cpu.cs_registers[MachineLevelCSRegisters::MIE] = InterruptFields::MTI;
+        assert!(matches!(
+            execute(&mut cpu, WFI),
+            Ok(OperationSideEffect::WaitForInterrupt)
+        ));
+        cpu.wait_for_interrupt();
+        assert!(time(&cpu) >= deadline);
+        assert!(!cpu.must_wait());
+    }
+
+    #[test]
+    fn wfi_traps_below_machine_mode() {
+        let mut cpu = test_hart::cpu();
+        cpu.privilege_mode = PrivilegeMode::User;
+        assert!(matches!(
+            execute(&mut cpu, WFI),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        assert!(execute(&mut cpu, WFI).is_ok());
+        cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] |= MStatusFields::TW;
+        assert!(matches!(
+            execute(&mut cpu, WFI),
+            Err(AppErrors::IllegalInstruction { .. })
+        ));
+    }
+}
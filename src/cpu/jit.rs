@@ -0,0 +1,703 @@
+use cranelift_codegen::{
+    ir::{condcodes::IntCC, types, AbiParam, InstBuilder, MemFlags, SigRef, UserFuncName, Value},
+    settings::{self, Configurable},
+    Context,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use crate::error::AppErrors;
+
+use super::{
+    cs_registers::UserLevelCSRegisters,
+    extensions::BaseIsa,
+    instruction_excecutors::DecodedInstruction,
+    instructions::{decoder, implementations::CpuInstructionsOpCodes},
+    pmp::MemoryAccess,
+    side_effects::OperationSideEffect,
+    tlb::{DataTlb, PAGE_SHIFT, TLB_ENTRIES},
+    xlen::Xlen,
+    Cpu,
+};
+use crate::error::AppResult;
+
+/// Executions of a block before its body is compiled
+pub const JIT_THRESHOLD: u32 = 16;
+/// Host code emitted before the module is recreated, the code of the
+/// invalidated blocks is only freed with the module
+pub const JIT_CODE_LIMIT: usize = 64 << 20;
+
+/// Results of the interpreter fallback of the compiled code
+const CONTINUE: i64 = 0;
+/// The instruction raised an exception, left in `Jit::pending_error`
+const FAULTED: i64 = 1;
+/// The instruction wrote a code page, the rest of the block may be stale
+const STOPPED: i64 = 2;
+
+/// Body of a block compiled to host code, returns the number of completed
+/// instructions
+pub type CompiledBlock =
+    unsafe extern "C" fn(cpu: *mut Cpu, registers: *mut u64, tlb: *mut DataTlb, pc: u64) -> u64;
+
+/// Cranelift backend compiling the bodies of hot RV64 blocks. Integer
+/// instructions, loads and stores are compiled, the other instructions and
+/// the TLB misses call back into the interpreter.
+pub struct Jit {
+    module: JITModule,
+    context: Context,
+    builder_context: FunctionBuilderContext,
+    /// Exception raised by an instruction run through the interpreter
+    pending_error: Option<AppErrors>,
+    /// Bytes of host code emitted by the module, live or not
+    pub(super) code_size: usize,
+    pub(super) code_limit: usize,
+}
+
+impl Jit {
+    pub fn new() -> Self {
+        let module = Self::module();
+        Self {
+            context: module.make_context(),
+            module,
+            builder_context: FunctionBuilderContext::new(),
+            pending_error: None,
+            code_size: 0,
+            code_limit: JIT_CODE_LIMIT,
+        }
+    }
+
+    fn module() -> JITModule {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").unwrap();
+        flags.set("is_pic", "false").unwrap();
+        flags.set("opt_level", "speed").unwrap();
+        let isa = cranelift_native::builder()
+            .expect("Host machine is not supported by the JIT")
+            .finish(settings::Flags::new(flags))
+            .unwrap();
+        JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()))
+    }
+
+    /// Whether the module emitted its share of host code and must be recreated
+    pub fn is_full(&self) -> bool {
+        self.code_size >= self.code_limit
+    }
+
+    /// Frees the host code of every compiled block by recreating the module
+    ///
+    /// # Safety
+    /// None of the code compiled so far may run afterwards
+    pub unsafe fn reset(&mut self) {
+        let module = std::mem::replace(&mut self.module, Self::module());
+        module.free_memory();
+        self.context = self.module.make_context();
+        self.code_size = 0;
+    }
+
+    /// Compiles the instructions of a block body, the instructions must outlive
+    /// the compiled code as the interpreter fallback points to them
+    pub fn compile(&mut self, body: &[DecodedInstruction]) -> Option<CompiledBlock> {
+        let pointer = self.module.target_config().pointer_type();
+        let mut signature = self.module.make_signature();
+        signature.params.extend([
+            AbiParam::new(pointer),
+            AbiParam::new(pointer),
+            AbiParam::new(pointer),
+            AbiParam::new(types::I64),
+        ]);
+        signature.returns.push(AbiParam::new(types::I64));
+        let id = self.module.declare_anonymous_function(&signature).ok()?;
+        self.context.func.signature = signature;
+        self.context.func.name = UserFuncName::user(0, id.as_u32());
+
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let mut fallback_signature = self.module.make_signature();
+        fallback_signature.params.extend([
+            AbiParam::new(pointer),
+            AbiParam::new(pointer),
+            AbiParam::new(types::I64),
+        ]);
+        fallback_signature.returns.push(AbiParam::new(types::I64));
+        let fallback_signature = builder.import_signature(fallback_signature);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let [cpu, registers, tlb, pc] = builder.block_params(entry).try_into().unwrap();
+        let mut emitter = Emitter {
+            builder,
+            pointer,
+            fallback_signature,
+            cpu,
+            registers,
+            tlb,
+            pc,
+        };
+        for (index, instruction) in body.iter().enumerate() {
+            emitter.instruction(index, instruction);
+        }
+        let completed = emitter.builder.ins().iconst(types::I64, body.len() as i64);
+        emitter.builder.ins().return_(&[completed]);
+        emitter.builder.seal_all_blocks();
+        emitter.builder.finalize();
+
+        let defined = self.module.define_function(id, &mut self.context);
+        self.code_size += self
+            .context
+            .compiled_code()
+            .map_or(0, |code| code.code_info().total_size as usize);
+        self.module.clear_context(&mut self.context);
+        defined.ok()?;
+        self.module.finalize_definitions().ok()?;
+        let code = self.module.get_finalized_function(id);
+        Some(unsafe { std::mem::transmute::<*const u8, CompiledBlock>(code) })
+    }
+}
+
+/// Translates the instructions of a body into Cranelift IR, registers are
+/// loaded and stored around every instruction as the interpreter fallback can
+/// access any of them
+struct Emitter<'a> {
+    builder: FunctionBuilder<'a>,
+    pointer: types::Type,
+    fallback_signature: SigRef,
+    cpu: Value,
+    registers: Value,
+    tlb: Value,
+    pc: Value,
+}
+
+impl Emitter<'_> {
+    fn read(&mut self, register: u32) -> Value {
+        match register {
+            0 => self.builder.ins().iconst(types::I64, 0),
+            _ => self.builder.ins().load(
+                types::I64,
+                MemFlags::trusted(),
+                self.registers,
+                register as i32 * 8,
+            ),
+        }
+    }
+
+    fn write(&mut self, register: u32, value: Value) {
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            value,
+            self.registers,
+            register as i32 * 8,
+        );
+    }
+
+    fn instruction(&mut self, index: usize, instruction: &DecodedInstruction) {
+        let raw = instruction.raw();
+        let rd = (raw >> 7) & 0x1f;
+        let rs1 = (raw >> 15) & 0x1f;
+        let rs2 = (raw >> 20) & 0x1f;
+        let funct3 = (raw >> 12) & 0x7;
+        let funct7 = raw >> 25;
+        let i_imm = (raw as i32 >> 20) as i64;
+        //Hints and the encodings the decoder rejects keep the interpreter semantics
+        if rd == 0
+            && !matches!(
+                decoder::get_op_code(raw),
+                CpuInstructionsOpCodes::STORE | CpuInstructionsOpCodes::LOAD
+            )
+        {
+            return self.fallback(index, instruction);
+        }
+        let result = match (decoder::get_op_code(raw), funct3, funct7) {
+            (CpuInstructionsOpCodes::INT_REG_IMMEDIATE, _, _) => {
+                let source = self.read(rs1);
+                let ins = self.builder.ins();
+                match (funct3, (i_imm >> 6) & 0x3f) {
+                    (0b000, _) => ins.iadd_imm(source, i_imm),
+                    (0b010, _) => {
+                        let less = ins.icmp_imm(IntCC::SignedLessThan, source, i_imm);
+                        self.builder.ins().uextend(types::I64, less)
+                    }
+                    (0b011, _) => {
+                        let less = ins.icmp_imm(IntCC::UnsignedLessThan, source, i_imm);
+                        self.builder.ins().uextend(types::I64, less)
+                    }
+                    (0b100, _) => ins.bxor_imm(source, i_imm),
+                    (0b110, _) => ins.bor_imm(source, i_imm),
+                    (0b111, _) => ins.band_imm(source, i_imm),
+                    (0b001, _) => ins.ishl_imm(source, i_imm & 0x3f),
+                    (0b101, 0x00) => ins.ushr_imm(source, i_imm & 0x3f),
+                    (0b101, 0x10) => ins.sshr_imm(source, i_imm & 0x3f),
+                    _ => return self.fallback(index, instruction),
+                }
+            }
+            (CpuInstructionsOpCodes::INT_REG_IMMEDIATE_LUI, _, _) => self
+                .builder
+                .ins()
+                .iconst(types::I64, (raw & 0xffff_f000) as i32 as i64),
+            (CpuInstructionsOpCodes::INT_REG_REG_RV32I, _, 0x00 | 0x20) => {
+                let left = self.read(rs1);
+                let right = self.read(rs2);
+                let ins = self.builder.ins();
+                match (funct3, funct7) {
+                    (0b000, 0x00) => ins.iadd(left, right),
+                    (0b000, 0x20) => ins.isub(left, right),
+                    (0b010, 0x00) => {
+                        let less = ins.icmp(IntCC::SignedLessThan, left, right);
+                        self.builder.ins().uextend(types::I64, less)
+                    }
+                    (0b011, 0x00) => {
+                        let less = ins.icmp(IntCC::UnsignedLessThan, left, right);
+                        self.builder.ins().uextend(types::I64, less)
+                    }
+                    (0b100, 0x00) => ins.bxor(left, right),
+                    (0b110, 0x00) => ins.bor(left, right),
+                    (0b111, 0x00) => ins.band(left, right),
+                    //Cranelift masks the shift amount to 6 bits like RV64
+                    (0b001, 0x00) => ins.ishl(left, right),
+                    (0b101, 0x00) => ins.ushr(left, right),
+                    (0b101, 0x20) => ins.sshr(left, right),
+                    _ => return self.fallback(index, instruction),
+                }
+            }
+            (CpuInstructionsOpCodes::INT_REG_IMMEDIATE_RV64I, 0b000, _) => {
+                let source = self.read(rs1);
+                let sum = self.builder.ins().iadd_imm(source, i_imm);
+                self.sign_extend_word(sum)
+            }
+            (CpuInstructionsOpCodes::INT_REG_REG_RV64I, 0b000, 0x00 | 0x20) => {
+                let left = self.read(rs1);
+                let right = self.read(rs2);
+                let result = match funct7 {
+                    0x00 => self.builder.ins().iadd(left, right),
+                    _ => self.builder.ins().isub(left, right),
+                };
+                self.sign_extend_word(result)
+            }
+            (CpuInstructionsOpCodes::LOAD, 0b000..=0b110, _) => {
+                return self.load(index, instruction, rd, rs1, i_imm, funct3);
+            }
+            (CpuInstructionsOpCodes::STORE, 0b000..=0b011, _) => {
+                let s_imm = ((raw & 0xfe00_0000) as i32 >> 20) as i64 | ((raw >> 7) & 0x1f) as i64;
+                return self.store(index, instruction, rs1, rs2, s_imm, funct3);
+            }
+            _ => return self.fallback(index, instruction),
+        };
+        self.write(rd, result);
+    }
+
+    fn sign_extend_word(&mut self, value: Value) -> Value {
+        let word = self.builder.ins().ireduce(types::I32, value);
+        self.builder.ins().sextend(types::I64, word)
+    }
+
+    /// Looks up the TLB, returns the host address of an aligned access to a
+    /// mapped page and jumps to the interpreter fallback otherwise
+    fn host_address(
+        &mut self,
+        index: usize,
+        instruction: &DecodedInstruction,
+        base: u32,
+        offset: i64,
+        size: i64,
+        table: usize,
+    ) -> (Value, cranelift_codegen::ir::Block) {
+        let base = self.read(base);
+        let ins = self.builder.ins();
+        let addr = ins.iadd_imm(base, offset);
+        let page = self.builder.ins().ushr_imm(addr, PAGE_SHIFT as i64);
+        let slot = self.builder.ins().band_imm(page, TLB_ENTRIES as i64 - 1);
+        let slot = self.builder.ins().ishl_imm(slot, 4);
+        let slot = self.builder.ins().iadd(self.tlb, slot);
+        let entry = self.builder.ins().iadd_imm(slot, table as i64);
+        let tag = self
+            .builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), entry, 0);
+        let hit = self.builder.ins().icmp(IntCC::Equal, tag, page);
+        let misaligned = self.builder.ins().band_imm(addr, size - 1);
+        let aligned = self.builder.ins().icmp_imm(IntCC::Equal, misaligned, 0);
+        let fast = self.builder.ins().band(hit, aligned);
+
+        let fast_block = self.builder.create_block();
+        let slow_block = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(fast, fast_block, &[], slow_block, &[]);
+        self.builder.switch_to_block(slow_block);
+        self.fallback(index, instruction);
+        self.builder.ins().jump(done, &[]);
+        self.builder.switch_to_block(fast_block);
+        let addend = self
+            .builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), entry, 8);
+        let host = self.builder.ins().iadd(addr, addend);
+        (host, done)
+    }
+
+    fn load(
+        &mut self,
+        index: usize,
+        instruction: &DecodedInstruction,
+        rd: u32,
+        rs1: u32,
+        offset: i64,
+        funct3: u32,
+    ) {
+        let (size, kind) = match funct3 & 0b11 {
+            0b00 => (1, types::I8),
+            0b01 => (2, types::I16),
+            0b10 => (4, types::I32),
+            _ => (8, types::I64),
+        };
+        let (host, done) = self.host_address(index, instruction, rs1, offset, size, 0);
+        let flags = MemFlags::new().with_notrap().with_aligned();
+        let value = self.builder.ins().load(kind, flags, host, 0);
+        let value = match (funct3, kind) {
+            (_, types::I64) => value,
+            (0b100..=0b110, _) => self.builder.ins().uextend(types::I64, value),
+            _ => self.builder.ins().sextend(types::I64, value),
+        };
+        if rd != 0 {
+            self.write(rd, value);
+        }
+        self.builder.ins().jump(done, &[]);
+        self.builder.switch_to_block(done);
+    }
+
+    fn store(
+        &mut self,
+        index: usize,
+        instruction: &DecodedInstruction,
+        rs1: u32,
+        rs2: u32,
+        offset: i64,
+        funct3: u32,
+    ) {
+        let (size, kind) = match funct3 {
+            0b00 => (1, types::I8),
+            0b01 => (2, types::I16),
+            0b10 => (4, types::I32),
+            _ => (8, types::I64),
+        };
+        let store_table = std::mem::offset_of!(DataTlb, store);
+        let (host, done) = self.host_address(index, instruction, rs1, offset, size, store_table);
+        let value = self.read(rs2);
+        let value = match kind {
+            types::I64 => value,
+            _ => self.builder.ins().ireduce(kind, value),
+        };
+        let flags = MemFlags::new().with_notrap().with_aligned();
+        self.builder.ins().store(flags, value, host, 0);
+        self.builder.ins().jump(done, &[]);
+        self.builder.switch_to_block(done);
+    }
+
+    /// Runs the instruction through the interpreter, returning from the block
+    /// on an exception or on a code page write
+    fn fallback(&mut self, index: usize, instruction: &DecodedInstruction) {
+        let callee = self
+            .builder
+            .ins()
+            .iconst(self.pointer, interpret as *const () as i64);
+        let instruction = self.builder.ins().iconst(
+            self.pointer,
+            instruction as *const DecodedInstruction as i64,
+        );
+        let pc = self.builder.ins().iadd_imm(self.pc, 4 * index as i64);
+        let call = self.builder.ins().call_indirect(
+            self.fallback_signature,
+            callee,
+            &[self.cpu, instruction, pc],
+        );
+        let status = self.builder.inst_results(call)[0];
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(status, exit, &[], next, &[]);
+        self.builder.switch_to_block(exit);
+        //A faulting instruction is not completed, a code write is
+        let completed = self.builder.ins().iadd_imm(status, index as i64 - 1);
+        self.builder.ins().return_(&[completed]);
+        self.builder.switch_to_block(next);
+    }
+}
+
+/// Interpreter fallback called by the compiled code
+extern "C" fn interpret(cpu: *mut Cpu, instruction: *const DecodedInstruction, pc: u64) -> i64 {
+    let cpu = unsafe { &mut *cpu };
+    let instruction = unsafe { &*instruction };
+    cpu.program_counter = pc;
+    let raw = instruction.raw();
+    let access = match decoder::get_op_code(raw) {
+        CpuInstructionsOpCodes::LOAD => Some(((raw as i32 >> 20) as u64, MemoryAccess::Load)),
+        CpuInstructionsOpCodes::STORE => Some((
+            ((raw & 0xfe00_0000) as i32 >> 20) as u64 | ((raw >> 7) & 0x1f) as u64,
+            MemoryAccess::Store,
+        )),
+        _ => None,
+    }
+    .map(|(offset, access)| {
+        let base = cpu.registers[((raw >> 15) & 0x1f) as usize];
+        (base.wrapping_add(offset), access)
+    });
+    match instruction.handler()(cpu, instruction.decoder) {
+        Err(err) => {
+            cpu.jit.as_mut().expect("JIT is enabled").pending_error = Some(err);
+            FAULTED
+        }
+        Ok(_) => {
+            if let Some((addr, access)) = access {
+                cpu.fill_tlb(addr, access);
+            }
+            match cpu.system_bus.has_written_code_pages() {
+                true => STOPPED,
+                false => CONTINUE,
+            }
+        }
+    }
+}
+
+impl Cpu {
+    /// Whether the body of a block can be compiled and run, the compiled code
+    /// assumes RV64 and the I base and doesn't trace
+    #[inline(always)]
+    pub fn can_run_compiled(&self) -> bool {
+        self.jit.is_some()
+            && self.xlen == Xlen::X64
+            && self.config.base == BaseIsa::I
+            && self.tracer.is_none()
+    }
+
+    /// Compiles a block body, the code of every block is freed first once
+    /// the JIT emitted too much code and the blocks run interpreted until
+    /// they are hot again
+    pub fn compile_block(&mut self, body: &[DecodedInstruction]) -> Option<CompiledBlock> {
+        if self.jit.as_ref()?.is_full() {
+            self.blocks.forget_compiled();
+            //Compiled code only runs from the blocks and not while compiling
+            unsafe { self.jit.as_mut()?.reset() };
+        }
+        self.jit.as_mut()?.compile(body)
+    }
+
+    /// Runs a compiled block body and then the last instruction through the
+    /// interpreter
+    pub fn execute_compiled(
+        &mut self,
+        compiled: CompiledBlock,
+        body_len: usize,
+        last: DecodedInstruction,
+    ) -> AppResult<OperationSideEffect> {
+        let start = self.program_counter;
+        let privilege_mode = self.privilege_mode;
+        let tlb: *mut DataTlb = &mut *self.tlb;
+        let registers = self.registers.as_mut_ptr();
+        let completed = unsafe { compiled(self, registers, tlb, start) } as usize;
+        for _ in 0..completed {
+            self.update_counters(true, privilege_mode);
+        }
+        self.program_counter = start + 4 * completed as u64;
+        if let Some(err) = self.jit.as_mut().and_then(|jit| jit.pending_error.take()) {
+            self.update_counters(false, privilege_mode);
+            self.trap_on_exception(err)?;
+            self.program_counter = self.effective_address(self.program_counter);
+            return Ok(OperationSideEffect::None);
+        }
+        if completed < body_len {
+            return Ok(OperationSideEffect::None);
+        }
+        self.execute(last)
+    }
+
+    /// Runs a reference hart until it executed as many instructions as this
+    /// hart. The pending interrupts are only taken first, as this hart takes
+    /// them between blocks, and the reference doesn't wait in WFI but follows
+    /// the time reached by this hart.
+    pub fn run_reference(&self, reference: &mut Cpu) -> AppResult<()> {
+        let gap = self.executed_instructions - reference.executed_instructions;
+        let time = self.cs_registers[UserLevelCSRegisters::TIME];
+        reference.advance_time(time.saturating_sub(gap));
+        reference.take_pending_interrupt();
+        //An instruction fetch that faults is not counted
+        reference.step_instruction()?;
+        for _ in 1..gap {
+            if reference.executed_instructions >= self.executed_instructions {
+                break;
+            }
+            reference.step_instruction()?;
+        }
+        Ok(())
+    }
+
+    /// Compares the architectural state with a hart running the same program
+    /// and the RAM it wrote since the last call, returns the first difference
+    pub fn diverges_from(&self, reference: &mut Cpu) -> Option<String> {
+        let pc = self.program_counter;
+        if pc != reference.program_counter {
+            return Some(format!(
+                "pc {pc:#x}, expected {:#x}",
+                reference.program_counter
+            ));
+        }
+        if (self.privilege_mode, self.virtualization)
+            != (reference.privilege_mode, reference.virtualization)
+        {
+            return Some(format!(
+                "{:?} mode (V={}) at {pc:#x}, expected {:?} (V={})",
+                self.privilege_mode,
+                self.virtualization,
+                reference.privilege_mode,
+                reference.virtualization
+            ));
+        }
+        let written_ram = reference.system_bus.take_written_ram();
+        let registers = (0..self.registers.len())
+            .find(|register| self.registers[*register] != reference.registers[*register])
+            .map(|register| {
+                format!(
+                    "x{register} = {:#x} at {pc:#x}, expected {:#x}",
+                    self.registers[register], reference.registers[register]
+                )
+            });
+        let float_registers = || {
+            (0..self.float_registers.len())
+                .find(|register| {
+                    self.float_registers[*register] != reference.float_registers[*register]
+                })
+                .map(|register| {
+                    format!(
+                        "f{register} = {:#x} at {pc:#x}, expected {:#x}",
+                        self.float_registers[register], reference.float_registers[register]
+                    )
+                })
+        };
+        let cs_registers = || {
+            (0..self.cs_registers.len())
+                .find(|csr| self.cs_registers[*csr] != reference.cs_registers[*csr])
+                .map(|csr| {
+                    format!(
+                        "csr {csr:#x} = {:#x} at {pc:#x}, expected {:#x}",
+                        self.cs_registers[csr], reference.cs_registers[csr]
+                    )
+                })
+        };
+        //Only the bytes written by the reference are compared
+        let memory = &self.system_bus.machine().memory;
+        let expected_memory = &reference.system_bus.machine().memory;
+        let ram = || {
+            written_ram
+                .iter()
+                .flat_map(|(addr, size)| *addr..*addr + size)
+                .find_map(|addr| {
+                    let value = memory.load8(addr).ok()?;
+                    let expected = expected_memory.load8(addr).ok()?;
+                    (value != expected).then(|| {
+                        format!("byte {addr:#x} = {value:#x} at {pc:#x}, expected {expected:#x}")
+                    })
+                })
+        };
+        registers
+            .or_else(float_registers)
+            .or_else(cs_registers)
+            .or_else(ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{basic_blocks::ExecutionEngine, config::CpuConfig, test_hart},
+        memory::MemoryOpSize,
+    };
+
+    const DATA: u64 = DRAM_BASE_ADDR + 0x10_0000;
+    //Stores 1 to 100 to consecutive doublewords
+    const PROGRAM: [u32; 5] = [
+        //addi x5, x5, 1
+        0x0012_8293,
+        //sd x5, 0(x10)
+        0x0055_3023,
+        //addi x10, x10, 8
+        0x0085_0513,
+        //blt x5, x6, -12
+        0xfe62_cae3,
+        //jal x0, 0
+        0x0000_006f,
+    ];
+
+    /// Hart running the program with an engine, on its own machine
+    fn hart(engine: ExecutionEngine) -> Cpu {
+        let mut cpu = test_hart::cpu_with_program(
+            CpuConfig {
+                engine,
+                ..CpuConfig::default()
+            },
+            &PROGRAM,
+        );
+        cpu.registers[6] = 100;
+        cpu.registers[10] = DATA;
+        cpu
+    }
+
+    /// JIT hart and its interpreted reference, run until the first store
+    fn harts() -> (Cpu, Cpu) {
+        let mut cpu = hart(ExecutionEngine::Jit);
+        let mut reference = hart(ExecutionEngine::Interpreter);
+        reference.system_bus.record_writes();
+        cpu.step().unwrap();
+        cpu.run_reference(&mut reference).unwrap();
+        (cpu, reference)
+    }
+
+    #[test]
+    fn the_reference_follows_the_compiled_blocks() {
+        let mut cpu = hart(ExecutionEngine::Jit);
+        let mut reference = hart(ExecutionEngine::Interpreter);
+        reference.system_bus.record_writes();
+        while cpu.program_counter != DRAM_BASE_ADDR + 16 {
+            cpu.step().unwrap();
+            cpu.run_reference(&mut reference).unwrap();
+            assert_eq!(cpu.diverges_from(&mut reference), None);
+        }
+        assert!(cpu.jit.as_ref().unwrap().code_size > 0);
+        assert_eq!(reference.executed_instructions, 400);
+        assert_eq!(reference.registers[5], 100);
+    }
+
+    #[test]
+    fn divergences_are_reported() {
+        let (mut cpu, mut reference) = harts();
+        cpu.system_bus
+            .store(DATA + 4, MemoryOpSize::B8, 0xff)
+            .unwrap();
+        assert_eq!(
+            cpu.diverges_from(&mut reference).unwrap(),
+            "byte 0x80100004 = 0xff at 0x80000000, expected 0x0"
+        );
+        //The written bytes are only compared once
+        assert_eq!(cpu.diverges_from(&mut reference), None);
+
+        let (mut cpu, mut reference) = harts();
+        cpu.float_registers[3] = 1;
+        assert!(cpu
+            .diverges_from(&mut reference)
+            .unwrap()
+            .starts_with("f3 = 0x1"));
+
+        let (cpu, mut reference) = harts();
+        reference.cs_registers[0x340] = 1;
+        assert!(cpu
+            .diverges_from(&mut reference)
+            .unwrap()
+            .starts_with("csr 0x340 = 0x0"));
+
+        let (mut cpu, mut reference) = harts();
+        cpu.virtualization = true;
+        assert!(cpu
+            .diverges_from(&mut reference)
+            .unwrap()
+            .contains("(V=true)"));
+    }
+}
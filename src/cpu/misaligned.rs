@@ -0,0 +1,341 @@
+use crate::{
+    consts::PAGE_SIZE,
+    error::{AppErrors, AppResult},
+    memory::MemoryOpSize,
+};
+
+use super::{extensions::Extension, pmp::MemoryAccess, Cpu};
+
+/// Naturally aligned region inside which misaligned AMOs are atomic (Zama16b)
+const MISALIGNED_ATOMICITY_GRANULE: u64 = 16;
+
+/// How misaligned loads and stores are handled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MisalignedAccessPolicy {
+    /// The access is performed as if it was aligned
+    Emulate,
+    /// Raises an address misaligned exception so the guest can emulate it
+    Trap,
+    /// Raises an access fault exception
+    AccessFault,
+}
+
+impl MisalignedAccessPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "emulate" => Some(MisalignedAccessPolicy::Emulate),
+            "trap" => Some(MisalignedAccessPolicy::Trap),
+            "fault" => Some(MisalignedAccessPolicy::AccessFault),
+            _ => None,
+        }
+    }
+}
+
+impl Cpu {
+    /// Applies the misaligned access policy to a load or store of `size` bytes
+    #[inline(always)]
+    pub fn check_access_alignment(
+        &self,
+        addr: u64,
+        size: u64,
+        access: MemoryAccess,
+    ) -> AppResult<()> {
+        if addr.is_multiple_of(size) {
+            return Ok(());
+        }
+        match (self.config.misaligned_access, access) {
+            (MisalignedAccessPolicy::Emulate, _) => Ok(()),
+            (MisalignedAccessPolicy::AccessFault, _) => Err(access.access_fault(addr)),
+            (MisalignedAccessPolicy::Trap, MemoryAccess::Load) => {
+                Err(AppErrors::LoadAddressMisaligned { addr })
+            }
+            (MisalignedAccessPolicy::Trap, _) => Err(AppErrors::StoreAddressMisaligned { addr }),
+        }
+    }
+
+    /// Translates a load or store of `size` bytes, returns its physical address
+    /// and the one of the next page when it crosses a page boundary. An emulated
+    /// misaligned access is split at the boundary as the two pages may map to
+    /// unrelated physical pages, each part is translated and checked on its own
+    /// so a fault on the second page is reported at its virtual address.
+    #[inline(always)]
+    pub fn translate_data_access(
+        &mut self,
+        addr: u64,
+        size: u64,
+        access: MemoryAccess,
+    ) -> AppResult<(u64, Option<u64>)> {
+        let first = PAGE_SIZE - (addr & (PAGE_SIZE - 1));
+        if first >= size {
+            return Ok((self.translate_address(addr, size, access)?, None));
+        }
+        let physical = self.translate_address(addr, first, access)?;
+        let next_page = self.translate_address(addr.wrapping_add(first), size - first, access)?;
+        Ok((physical, Some(next_page)))
+    }
+
+    /// Loads `size` bytes translated by translate_data_access
+    #[inline(always)]
+    pub fn load_physical(
+        &mut self,
+        physical: u64,
+        next_page: Option<u64>,
+        size: u64,
+    ) -> AppResult<u64> {
+        let Some(next_page) = next_page else {
+            return match size {
+                1 => self.system_bus.load8(physical).map(u64::from),
+                2 => self.system_bus.load16(physical).map(u64::from),
+                4 => self.system_bus.load32(physical).map(u64::from),
+                _ => self.system_bus.load64(physical),
+            };
+        };
+        let first = PAGE_SIZE - (physical & (PAGE_SIZE - 1));
+        (0..size).try_fold(0, |value, byte| {
+            let addr = match byte < first {
+                true => physical + byte,
+                false => next_page + byte - first,
+            };
+            Ok(value | (self.system_bus.load8(addr)? as u64) << (8 * byte))
+        })
+    }
+
+    /// Stores the `size` lower bytes of a value translated by
+    /// translate_data_access
+    #[inline(always)]
+    pub fn store_physical(
+        &mut self,
+        physical: u64,
+        next_page: Option<u64>,
+        size: MemoryOpSize,
+        value: u64,
+    ) -> AppResult<()> {
+        let Some(next_page) = next_page else {
+            return self.system_bus.store(physical, size, value);
+        };
+        let first = PAGE_SIZE - (physical & (PAGE_SIZE - 1));
+        for byte in 0..size.bytes() {
+            let addr = match byte < first {
+                true => physical + byte,
+                false => next_page + byte - first,
+            };
+            self.system_bus
+                .store(addr, MemoryOpSize::B8, value >> (8 * byte))?;
+        }
+        Ok(())
+    }
+
+    /// AMOs are never split, with Zama16b they can be misaligned as long as they
+    /// don't cross a 16-byte boundary. Other misaligned AMOs raise an address
+    /// misaligned exception, or an access fault under the access fault policy.
+    #[inline(always)]
+    pub fn check_atomic_alignment(&self, addr: u64, size: u64) -> AppResult<()> {
+        let granule = addr / MISALIGNED_ATOMICITY_GRANULE;
+        let last_granule = addr.wrapping_add(size - 1) / MISALIGNED_ATOMICITY_GRANULE;
+        if addr.is_multiple_of(size)
+            || (self.extensions.contains(Extension::Zama16b) && granule == last_granule)
+        {
+            return Ok(());
+        }
+        match self.config.misaligned_access {
+            MisalignedAccessPolicy::AccessFault => Err(AppErrors::StoreAccessFault { addr }),
+            _ => Err(AppErrors::StoreAddressMisaligned { addr }),
+        }
+    }
+
+    /// Without the C extension jump and taken branch targets must be 4-byte
+    /// aligned, the exception is raised by the jump so rd is left unchanged
+    #[inline(always)]
+    pub fn check_jump_target(&self, target: u64) -> AppResult<()> {
+        match target.is_multiple_of(4) {
+            true => Ok(()),
+            false => Err(AppErrors::InstructionAddressMisaligned { addr: target }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{
+            config::CpuConfig,
+            extensions::IsaProfile,
+            mmu::{PteFields, SatpFields},
+            privilege::PrivilegeMode,
+            test_hart::{self, execute},
+        },
+    };
+
+    /// lw x5, 1(x10)
+    const LW: u32 = 0x0015_2283;
+    /// sw x11, 1(x10)
+    const SW: u32 = 0x00b5_20a3;
+    /// amoadd.w x5, x11, (x10)
+    const AMOADD_W: u32 = 0x00b5_22af;
+
+    /// M-mode hart with a misaligned access policy, x10 points to the RAM
+    fn hart(misaligned_access: MisalignedAccessPolicy) -> Cpu {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            misaligned_access,
+            ..CpuConfig::default()
+        });
+        cpu.registers[10] = DRAM_BASE_ADDR;
+        cpu
+    }
+
+    #[test]
+    fn misaligned_accesses_follow_the_policy() {
+        let mut cpu = hart(MisalignedAccessPolicy::Emulate);
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B64, 0x8877_6655_4433_2211)
+            .unwrap();
+        execute(&mut cpu, LW).unwrap();
+        assert_eq!(cpu.registers[5], 0x5544_3322);
+        cpu.registers[11] = 0xaabb_ccdd;
+        execute(&mut cpu, SW).unwrap();
+        assert_eq!(
+            cpu.system_bus.load64(DRAM_BASE_ADDR).unwrap(),
+            0x8877_66aa_bbcc_dd11
+        );
+
+        let mut cpu = hart(MisalignedAccessPolicy::Trap);
+        assert!(matches!(
+            execute(&mut cpu, LW),
+            Err(AppErrors::LoadAddressMisaligned { addr }) if addr == DRAM_BASE_ADDR + 1
+        ));
+        assert!(matches!(
+            execute(&mut cpu, SW),
+            Err(AppErrors::StoreAddressMisaligned { addr }) if addr == DRAM_BASE_ADDR + 1
+        ));
+
+        let mut cpu = hart(MisalignedAccessPolicy::AccessFault);
+        assert!(matches!(
+            execute(&mut cpu, LW),
+            Err(AppErrors::LoadAccessFault { addr }) if addr == DRAM_BASE_ADDR + 1
+        ));
+        assert!(matches!(
+            execute(&mut cpu, SW),
+            Err(AppErrors::StoreAccessFault { addr }) if addr == DRAM_BASE_ADDR + 1
+        ));
+    }
+
+    #[test]
+    fn accesses_crossing_pages_are_split() {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            pmp_entries: 0,
+            ..CpuConfig::default()
+        });
+        //Sv39 maps 0x40001000 and 0x40002000 to pages 16 KiB apart, 0x40003000
+        //is not mapped
+        let (root, level1, level0) = (
+            DRAM_BASE_ADDR + 0x10000,
+            DRAM_BASE_ADDR + 0x11000,
+            DRAM_BASE_ADDR + 0x12000,
+        );
+        let (low, high) = (DRAM_BASE_ADDR + 0x5000, DRAM_BASE_ADDR + 0x9000);
+        let pte =
+            |physical: u64, flags: u64| ((physical / PAGE_SIZE) << PteFields::PPN_SHIFT) | flags;
+        let leaf = PteFields::V | PteFields::R | PteFields::W | PteFields::A | PteFields::D;
+        for (addr, value) in [
+            (root + 8, pte(level1, PteFields::V)),
+            (level1, pte(level0, PteFields::V)),
+            (level0 + 8, pte(low, leaf)),
+            (level0 + 16, pte(high, leaf)),
+        ] {
+            cpu.system_bus
+                .store(addr, MemoryOpSize::B64, value)
+                .unwrap();
+        }
+        cpu.store_satp((SatpFields::SV39 << SatpFields::MODE_SHIFT) | (root / PAGE_SIZE));
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu.system_bus
+            .store(low + 0xffc, MemoryOpSize::B32, 0x4433_2211)
+            .unwrap();
+        cpu.system_bus
+            .store(high, MemoryOpSize::B32, 0x8877_6655)
+            .unwrap();
+
+        assert_eq!(
+            cpu.load_data(0x4000_1ffc, 8).unwrap(),
+            0x8877_6655_4433_2211
+        );
+        assert_eq!(cpu.load_data(0x4000_1fff, 2).unwrap(), 0x5544);
+        cpu.store_data(0x4000_1ffe, MemoryOpSize::B32, 0xddcc_bbaa)
+            .unwrap();
+        assert_eq!(cpu.system_bus.load32(low + 0xffc).unwrap(), 0xbbaa_2211);
+        assert_eq!(cpu.system_bus.load32(high).unwrap(), 0x8877_ddcc);
+        //The page fault is raised at the start of the unmapped page, before
+        //the first page is written
+        assert!(matches!(
+            cpu.load_data(0x4000_2ffe, 4),
+            Err(AppErrors::LoadPageFault { addr: 0x4000_3000 })
+        ));
+        assert!(matches!(
+            cpu.store_data(0x4000_2ffe, MemoryOpSize::B32, 0),
+            Err(AppErrors::StorePageFault { addr: 0x4000_3000 })
+        ));
+        cpu.system_bus
+            .store(high + 0xffe, MemoryOpSize::B16, 0xffff)
+            .unwrap();
+        assert!(cpu.store_data(0x4000_2ffe, MemoryOpSize::B32, 0).is_err());
+        assert_eq!(cpu.system_bus.load16(high + 0xffe).unwrap(), 0xffff);
+    }
+
+    #[test]
+    fn zama16b_allows_amos_inside_a_16_byte_granule() {
+        let mut cpu = hart(MisalignedAccessPolicy::Trap);
+        cpu.system_bus
+            .store(DRAM_BASE_ADDR + 4, MemoryOpSize::B32, 0x0001_0000)
+            .unwrap();
+        cpu.registers[10] = DRAM_BASE_ADDR + 2;
+        cpu.registers[11] = 1;
+        execute(&mut cpu, AMOADD_W).unwrap();
+        assert_eq!(cpu.registers[5], 0x0000_0000);
+        assert_eq!(cpu.system_bus.load32(DRAM_BASE_ADDR + 2).unwrap(), 1);
+        assert_eq!(
+            cpu.system_bus.load32(DRAM_BASE_ADDR + 4).unwrap(),
+            0x0001_0000
+        );
+        //Crossing the granule is never allowed, even when loads are emulated
+        cpu.registers[10] = DRAM_BASE_ADDR + 14;
+        assert!(matches!(
+            execute(&mut cpu, AMOADD_W),
+            Err(AppErrors::StoreAddressMisaligned { .. })
+        ));
+        let cpu = hart(MisalignedAccessPolicy::Emulate);
+        assert!(cpu.check_atomic_alignment(DRAM_BASE_ADDR + 14, 4).is_err());
+        let cpu = hart(MisalignedAccessPolicy::AccessFault);
+        assert!(matches!(
+            cpu.check_atomic_alignment(DRAM_BASE_ADDR + 14, 4),
+            Err(AppErrors::StoreAccessFault { .. })
+        ));
+    }
+
+    #[test]
+    fn misaligned_amos_trap_without_zama16b() {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            profile: IsaProfile::Rva20,
+            ..CpuConfig::default()
+        });
+        assert!(cpu.check_atomic_alignment(DRAM_BASE_ADDR + 8, 8).is_ok());
+        cpu.registers[10] = DRAM_BASE_ADDR + 2;
+        assert!(matches!(
+            execute(&mut cpu, AMOADD_W),
+            Err(AppErrors::StoreAddressMisaligned { .. })
+        ));
+    }
+
+    #[test]
+    fn jumps_to_misaligned_targets_leave_rd_unchanged() {
+        let mut cpu = hart(MisalignedAccessPolicy::Emulate);
+        cpu.registers[1] = 7;
+        //jalr x1, 2(x10)
+        assert!(matches!(
+            execute(&mut cpu, 0x0025_00e7),
+            Err(AppErrors::InstructionAddressMisaligned { addr }) if addr == DRAM_BASE_ADDR + 2
+        ));
+        assert_eq!(cpu.registers[1], 7);
+    }
+}
@@ -0,0 +1,765 @@
+use crate::{
+    consts::PAGE_SIZE,
+    error::{AppErrors, AppResult},
+};
+
+use super::{
+    cs_registers::{
+        EnvCfgFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+        SupervisorLevelCSRegisters,
+    },
+    extensions::Extension,
+    hypervisor::HypervisorAccess,
+    pmp::MemoryAccess,
+    privilege::PrivilegeMode,
+    xlen::Xlen,
+    Cpu,
+};
+
+/// Bit fields of the satp register, RV32 uses a narrower layout
+pub struct SatpFields;
+impl SatpFields {
+    pub const MODE_SHIFT: u64 = 60;
+    pub const ASID_SHIFT: u64 = 44;
+    pub const PPN: u64 = (1 << Self::ASID_SHIFT) - 1;
+
+    pub const MODE_32: u64 = 1 << 31;
+    pub const ASID_32_SHIFT: u64 = 22;
+    pub const ASID_32: u64 = 0x1ff << Self::ASID_32_SHIFT;
+    pub const PPN_32: u64 = (1 << Self::ASID_32_SHIFT) - 1;
+
+    /// Translation modes held in MODE on RV64
+    pub const BARE: u64 = 0;
+    pub const SV39: u64 = 8;
+    pub const SV48: u64 = 9;
+    pub const SV57: u64 = 10;
+}
+
+/// Bit fields of the hgatp register, MODE and PPN have the same layout as in
+/// satp and Sv32x4/Sv39x4/Sv48x4/Sv57x4 use the encodings of the matching satp
+/// modes
+pub struct HgatpFields;
+impl HgatpFields {
+    pub const VMID_SHIFT: u64 = 44;
+    pub const VMID: u64 = 0x3fff << Self::VMID_SHIFT;
+    pub const VMID_32_SHIFT: u64 = 22;
+    pub const VMID_32: u64 = 0x7f << Self::VMID_32_SHIFT;
+    /// The root page table is 16 KiB aligned
+    pub const ROOT_ALIGNMENT: u64 = 0b11;
+}
+
+/// Bit fields of the page table entries
+pub struct PteFields;
+impl PteFields {
+    pub const V: u64 = 1 << 0;
+    pub const R: u64 = 1 << 1;
+    pub const W: u64 = 1 << 2;
+    pub const X: u64 = 1 << 3;
+    pub const U: u64 = 1 << 4;
+    pub const A: u64 = 1 << 6;
+    pub const D: u64 = 1 << 7;
+    pub const PPN_SHIFT: u64 = 10;
+    /// Bits reserved for future standard use in the RV64 entries
+    pub const RESERVED: u64 = 0x7f << 54;
+    /// Svpbmt page-based memory type, 2 bit field
+    pub const PBMT_SHIFT: u64 = 61;
+    pub const PBMT: u64 = 0b11 << Self::PBMT_SHIFT;
+    /// PBMT value reserved for future standard use
+    pub const PBMT_RESERVED: u64 = 0b11;
+    /// Svnapot naturally aligned power-of-two translation contiguity
+    pub const N: u64 = 1 << 63;
+    /// Low PPN bits of a NAPOT entry encoding a 64 KiB range, the only size
+    /// defined by Svnapot
+    pub const NAPOT_64K: u64 = 0b1000;
+    pub const NAPOT_64K_MASK: u64 = 0b1111;
+}
+
+/// Address translation scheme selected by satp.MODE
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TranslationMode {
+    Bare,
+    Sv32,
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl TranslationMode {
+    /// Decodes the MODE field for the supervisor XLEN, returns None for the
+    /// reserved encodings
+    fn from_satp(satp: u64, xlen: Xlen) -> Option<Self> {
+        match xlen {
+            Xlen::X32 if satp & SatpFields::MODE_32 != 0 => Some(TranslationMode::Sv32),
+            Xlen::X32 => Some(TranslationMode::Bare),
+            Xlen::X64 => match satp >> SatpFields::MODE_SHIFT {
+                SatpFields::BARE => Some(TranslationMode::Bare),
+                SatpFields::SV39 => Some(TranslationMode::Sv39),
+                SatpFields::SV48 => Some(TranslationMode::Sv48),
+                SatpFields::SV57 => Some(TranslationMode::Sv57),
+                _ => None,
+            },
+        }
+    }
+
+    fn levels(&self) -> u64 {
+        match self {
+            TranslationMode::Bare => 0,
+            TranslationMode::Sv32 => 2,
+            TranslationMode::Sv39 => 3,
+            TranslationMode::Sv48 => 4,
+            TranslationMode::Sv57 => 5,
+        }
+    }
+
+    /// Width of each virtual page number field
+    fn vpn_bits(&self) -> u64 {
+        match self {
+            TranslationMode::Sv32 => 10,
+            _ => 9,
+        }
+    }
+
+    fn pte_size(&self) -> u64 {
+        match self {
+            TranslationMode::Sv32 => 4,
+            _ => 8,
+        }
+    }
+
+    fn ppn_mask(&self) -> u64 {
+        match self {
+            TranslationMode::Sv32 => SatpFields::PPN_32,
+            _ => SatpFields::PPN,
+        }
+    }
+}
+
+/// Page tables walked by one stage of the address translation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    /// Single-stage translation of HS-mode and U-mode through satp
+    Supervisor,
+    /// VS-stage translation of the guest virtual addresses through vsatp
+    VirtualSupervisor,
+    /// G-stage translation of the guest physical addresses through hgatp.
+    /// `virtual_addr` is the guest virtual address reported by the faults, the
+    /// implicit accesses of the VS-stage walk are checked as loads.
+    Guest { virtual_addr: u64, implicit: bool },
+}
+
+impl MemoryAccess {
+    #[inline(always)]
+    fn page_fault(&self, addr: u64) -> AppErrors {
+        match self {
+            MemoryAccess::Fetch => AppErrors::InstructionPageFault { addr },
+            MemoryAccess::Load => AppErrors::LoadPageFault { addr },
+            MemoryAccess::Store => AppErrors::StorePageFault { addr },
+        }
+    }
+
+    #[inline(always)]
+    fn guest_page_fault(&self, addr: u64, guest_physical: u64) -> AppErrors {
+        match self {
+            MemoryAccess::Fetch => AppErrors::InstructionGuestPageFault {
+                addr,
+                guest_physical,
+            },
+            MemoryAccess::Load => AppErrors::LoadGuestPageFault {
+                addr,
+                guest_physical,
+            },
+            MemoryAccess::Store => AppErrors::StoreGuestPageFault {
+                addr,
+                guest_physical,
+            },
+        }
+    }
+}
+
+impl Cpu {
+    /// Translates a virtual address accessed by `size` bytes and checks the
+    /// physical access against the PMP entries, returns the physical address.
+    /// Accesses crossing a page boundary must map to contiguous physical pages,
+    /// the loads and stores split them with translate_data_access.
+    /// Load and store address triggers are matched first as they have priority
+    /// over the translation faults.
+    #[inline(always)]
+    pub fn translate_address(
+        &mut self,
+        addr: u64,
+        size: u64,
+        access: MemoryAccess,
+    ) -> AppResult<u64> {
+        if access != MemoryAccess::Fetch {
+            self.check_access_triggers(access, addr, size, None)?;
+        }
+        let physical = self.walk_page_table(addr, access)?;
+        let last = addr.wrapping_add(size - 1);
+        if (addr ^ last) & !(PAGE_SIZE - 1) != 0
+            && self.walk_page_table(last, access)? != physical.wrapping_add(size - 1)
+        {
+            return Err(access.access_fault(addr));
+        }
+        self.check_pmp(physical, size, access)?;
+        Ok(physical)
+    }
+
+    /// Mode used by a stage to translate the accesses of the current privilege
+    /// level, M-mode accesses are never translated
+    fn translation_mode(&self, stage: Stage, access: MemoryAccess) -> TranslationMode {
+        let (atp, xlen) = match stage {
+            Stage::Supervisor if self.access_privilege(access) == PrivilegeMode::Machine => {
+                return TranslationMode::Bare
+            }
+            Stage::Supervisor => (
+                SupervisorLevelCSRegisters::SATP,
+                self.xlen_of(PrivilegeMode::Supervisor),
+            ),
+            Stage::VirtualSupervisor => (
+                HypervisorLevelCSRegisters::VSATP,
+                self.virtual_xlen_of(PrivilegeMode::Supervisor),
+            ),
+            Stage::Guest { .. } => (
+                HypervisorLevelCSRegisters::HGATP,
+                self.xlen_of(PrivilegeMode::Supervisor),
+            ),
+        };
+        TranslationMode::from_satp(self.cs_registers[atp], xlen).unwrap_or(TranslationMode::Bare)
+    }
+
+    /// Memory types are only honoured when Svpbmt is enabled through
+    /// menvcfg.PBMTE, and also through henvcfg.PBMTE for the VS-stage
+    fn is_pbmt_enabled(&self, stage: Stage) -> bool {
+        let henvcfg = match stage {
+            Stage::VirtualSupervisor => self.cs_registers[HypervisorLevelCSRegisters::HENVCFG],
+            _ => u64::MAX,
+        };
+        self.extensions.contains(Extension::Svpbmt)
+            && self.cs_registers[MachineLevelCSRegisters::MENVCFG] & henvcfg & EnvCfgFields::PBMTE
+                != 0
+    }
+
+    /// Checks the reserved encodings of the Svnapot and Svpbmt fields of an entry,
+    /// non-leaf entries must have both fields cleared
+    fn is_pte_encoding_valid(&self, pte: u64, leaf: bool, stage: Stage) -> bool {
+        let pbmt = (pte & PteFields::PBMT) >> PteFields::PBMT_SHIFT;
+        let pbmt_valid = match (leaf, self.is_pbmt_enabled(stage)) {
+            (false, _) => pbmt == 0,
+            (true, true) => pbmt != PteFields::PBMT_RESERVED,
+            //Non-zero memory types are ignored unless strict checking is requested
+            (true, false) => pbmt == 0 || !self.config.strict_pbmt,
+        };
+        let napot_valid =
+            pte & PteFields::N == 0 || (leaf && self.extensions.contains(Extension::Svnapot));
+        pbmt_valid && napot_valid
+    }
+
+    /// Returns the physical address of a virtual address. The virtualized
+    /// accesses go through the VS-stage and then the G-stage translation.
+    fn walk_page_table(&mut self, addr: u64, access: MemoryAccess) -> AppResult<u64> {
+        if !self.is_access_virtualized(access) {
+            return self.walk_stage(addr, access, Stage::Supervisor);
+        }
+        let guest_physical = self.walk_stage(addr, access, Stage::VirtualSupervisor)?;
+        self.walk_stage(
+            guest_physical,
+            access,
+            Stage::Guest {
+                virtual_addr: addr,
+                implicit: false,
+            },
+        )
+    }
+
+    /// Walks the page tables of a translation stage. As specified by Svade the
+    /// A and D bits are never updated by the walk, accesses to pages without
+    /// them raise a page fault. The faults of the G-stage are guest page faults,
+    /// including the ones of the implicit accesses to the VS-stage page tables
+    /// which are reported with the type of the original access.
+    fn walk_stage(&mut self, addr: u64, access: MemoryAccess, stage: Stage) -> AppResult<u64> {
+        let mode = self.translation_mode(stage, access);
+        if mode == TranslationMode::Bare {
+            return Ok(addr);
+        }
+        let (reported_addr, page_fault) = match stage {
+            Stage::Guest { virtual_addr, .. } => {
+                (virtual_addr, access.guest_page_fault(virtual_addr, addr))
+            }
+            _ => (addr, access.page_fault(addr)),
+        };
+        let vpn_bits = mode.vpn_bits();
+        let va_bits = 12 + mode.levels() * vpn_bits;
+        //The G-stage root table is 4 times larger and translates 2 more bits
+        let (root_vpn_bits, root_alignment) = match stage {
+            Stage::Guest { .. } => (vpn_bits + 2, HgatpFields::ROOT_ALIGNMENT),
+            _ => (vpn_bits, 0),
+        };
+        match stage {
+            //The guest physical addresses are zero-extended
+            Stage::Guest { .. } if addr >> (va_bits + 2) != 0 => return Err(page_fault),
+            Stage::Guest { .. } => {}
+            //The unused upper bits of the virtual address must equal its top bit
+            _ if mode != TranslationMode::Sv32 => {
+                let upper = (addr as i64) >> (va_bits - 1);
+                if upper != 0 && upper != -1 {
+                    return Err(page_fault);
+                }
+            }
+            _ => {}
+        }
+
+        let privilege = self.access_privilege(access);
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let virtual_status = self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS];
+        let (atp, sum, mxr) = match stage {
+            Stage::Supervisor => (
+                SupervisorLevelCSRegisters::SATP,
+                status & MStatusFields::SUM != 0,
+                status & MStatusFields::MXR != 0,
+            ),
+            Stage::VirtualSupervisor => (
+                HypervisorLevelCSRegisters::VSATP,
+                virtual_status & MStatusFields::SUM != 0,
+                (status | virtual_status) & MStatusFields::MXR != 0,
+            ),
+            Stage::Guest { .. } => (
+                HypervisorLevelCSRegisters::HGATP,
+                false,
+                status & MStatusFields::MXR != 0,
+            ),
+        };
+        //The implicit accesses to the VS-stage page tables are reads, HLVX
+        //reads executable pages instead of readable ones
+        let (checked_access, execute_only) = match stage {
+            Stage::Guest { implicit: true, .. } => (MemoryAccess::Load, false),
+            _ => (
+                access,
+                self.hypervisor_access == Some(HypervisorAccess::Execute),
+            ),
+        };
+        let mut table = (self.cs_registers[atp] & mode.ppn_mask() & !root_alignment) * PAGE_SIZE;
+        for level in (0..mode.levels()).rev() {
+            let level_bits = match level == mode.levels() - 1 {
+                true => root_vpn_bits,
+                false => vpn_bits,
+            };
+            let vpn = (addr >> (12 + level * vpn_bits)) & ((1 << level_bits) - 1);
+            let pte_addr = table + vpn * mode.pte_size();
+            //The VS-stage page tables are at guest physical addresses
+            let pte_addr = match stage {
+                Stage::VirtualSupervisor => self.walk_stage(
+                    pte_addr,
+                    access,
+                    Stage::Guest {
+                        virtual_addr: addr,
+                        implicit: true,
+                    },
+                )?,
+                _ => pte_addr,
+            };
+            //Page table accesses are implicit S-mode loads, faults are reported
+            //as access faults of the original access
+            if self
+                .check_pmp_as(
+                    pte_addr,
+                    mode.pte_size(),
+                    MemoryAccess::Load,
+                    PrivilegeMode::Supervisor,
+                )
+                .is_err()
+                || self.system_bus.is_mmio(pte_addr)
+            {
+                return Err(access.access_fault(reported_addr));
+            }
+            let pte = match mode {
+                TranslationMode::Sv32 => self.system_bus.load32(pte_addr).map(|pte| pte as u64),
+                _ => self.system_bus.load64(pte_addr),
+            }
+            .map_err(|_| access.access_fault(reported_addr))?;
+
+            let leaf = pte & (PteFields::R | PteFields::X) != 0;
+            if pte & PteFields::V == 0
+                || (pte & PteFields::R == 0 && pte & PteFields::W != 0)
+                || pte & PteFields::RESERVED != 0
+                || !self.is_pte_encoding_valid(pte, leaf, stage)
+            {
+                return Err(page_fault);
+            }
+            let ppn = (pte >> PteFields::PPN_SHIFT) & SatpFields::PPN;
+            if !leaf {
+                //The D, A and U bits are reserved in pointers to the next level
+                if level == 0 || pte & (PteFields::D | PteFields::A | PteFields::U) != 0 {
+                    return Err(page_fault);
+                }
+                table = ppn * PAGE_SIZE;
+                continue;
+            }
+
+            let user_page = pte & PteFields::U != 0;
+            let privilege_allowed = match (stage, privilege) {
+                //Every G-stage access is a user-level access
+                (Stage::Guest { .. }, _) | (_, PrivilegeMode::User) => user_page,
+                _ => !user_page || (access != MemoryAccess::Fetch && sum),
+            };
+            let access_allowed = match checked_access {
+                MemoryAccess::Fetch => pte & PteFields::X != 0,
+                MemoryAccess::Load if execute_only => pte & PteFields::X != 0,
+                MemoryAccess::Load => pte & PteFields::R != 0 || (mxr && pte & PteFields::X != 0),
+                MemoryAccess::Store => pte & PteFields::W != 0,
+            };
+            let superpage_mask = (1 << (level * vpn_bits)) - 1;
+            if !privilege_allowed
+                || !access_allowed
+                || ppn & superpage_mask != 0
+                || pte & PteFields::A == 0
+                || (checked_access == MemoryAccess::Store && pte & PteFields::D == 0)
+            {
+                return Err(page_fault);
+            }
+            let ppn = match pte & PteFields::N {
+                0 => ppn,
+                //A NAPOT entry maps 16 pages, the low PPN bits come from the VPN
+                _ if level == 0 && ppn & PteFields::NAPOT_64K_MASK == PteFields::NAPOT_64K => {
+                    (ppn & !PteFields::NAPOT_64K_MASK) | (vpn & PteFields::NAPOT_64K_MASK)
+                }
+                _ => return Err(page_fault),
+            };
+            let offset_mask = (PAGE_SIZE << (level * vpn_bits)) - 1;
+            return Ok((ppn * PAGE_SIZE) | (addr & offset_mask));
+        }
+        Err(page_fault)
+    }
+
+    /// Writes satp, the write is ignored if it selects an unsupported mode
+    pub fn store_satp(&mut self, value: u64) {
+        let xlen = self.xlen_of(PrivilegeMode::Supervisor);
+        if TranslationMode::from_satp(value, xlen).is_none() {
+            return;
+        }
+        self.cs_registers[SupervisorLevelCSRegisters::SATP] = match xlen {
+            Xlen::X32 => value & (SatpFields::MODE_32 | SatpFields::ASID_32 | SatpFields::PPN_32),
+            Xlen::X64 => value,
+        };
+    }
+
+    /// Writes vsatp, which has the layout of satp for the VS-mode XLEN
+    pub fn store_vsatp(&mut self, value: u64) {
+        let xlen = self.virtual_xlen_of(PrivilegeMode::Supervisor);
+        if TranslationMode::from_satp(value, xlen).is_none() {
+            return;
+        }
+        self.cs_registers[HypervisorLevelCSRegisters::VSATP] = match xlen {
+            Xlen::X32 => value & (SatpFields::MODE_32 | SatpFields::ASID_32 | SatpFields::PPN_32),
+            Xlen::X64 => value,
+        };
+    }
+
+    /// Writes hgatp, the write is ignored if it selects an unsupported mode.
+    /// The two low bits of the PPN are read-only zero as the root table is
+    /// 16 KiB aligned.
+    pub fn store_hgatp(&mut self, value: u64) {
+        let xlen = self.xlen_of(PrivilegeMode::Supervisor);
+        if TranslationMode::from_satp(value, xlen).is_none() {
+            return;
+        }
+        let write_mask = match xlen {
+            Xlen::X32 => SatpFields::MODE_32 | HgatpFields::VMID_32 | SatpFields::PPN_32,
+            Xlen::X64 => (0xf << SatpFields::MODE_SHIFT) | HgatpFields::VMID | SatpFields::PPN,
+        };
+        self.cs_registers[HypervisorLevelCSRegisters::HGATP] =
+            value & write_mask & !HgatpFields::ROOT_ALIGNMENT;
+    }
+
+    /// Name of the widest supported translation mode as advertised in the
+    /// mmu-type device tree property
+    pub fn mmu_type(xlen: Xlen) -> &'static str {
+        match xlen {
+            Xlen::X32 => "riscv,sv32",
+            Xlen::X64 => "riscv,sv57",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::DRAM_BASE_ADDR,
+        cpu::{config::CpuConfig, test_hart},
+        memory::MemoryOpSize,
+    };
+
+    const ROOT: u64 = DRAM_BASE_ADDR + 0x10000;
+    const LEVEL1: u64 = DRAM_BASE_ADDR + 0x11000;
+    const LEVEL0: u64 = DRAM_BASE_ADDR + 0x12000;
+    const LEAF: u64 = PteFields::V | PteFields::R | PteFields::W | PteFields::X;
+    const ACCESSED: u64 = PteFields::A | PteFields::D;
+
+    /// S-mode hart with Sv39 enabled and no PMP, the root table maps the 1 GiB
+    /// at 0x40000000 through LEVEL1 and its first 2 MiB through LEVEL0
+    fn cpu() -> Cpu {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            pmp_entries: 0,
+            ..CpuConfig::default()
+        });
+        cpu.store_satp((SatpFields::SV39 << SatpFields::MODE_SHIFT) | (ROOT / PAGE_SIZE));
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        write_pte(&mut cpu, ROOT + 8, pointer(LEVEL1));
+        write_pte(&mut cpu, LEVEL1, pointer(LEVEL0));
+        cpu
+    }
+
+    fn pointer(table: u64) -> u64 {
+        ((table / PAGE_SIZE) << PteFields::PPN_SHIFT) | PteFields::V
+    }
+
+    fn leaf(physical: u64, flags: u64) -> u64 {
+        ((physical / PAGE_SIZE) << PteFields::PPN_SHIFT) | flags
+    }
+
+    fn write_pte(cpu: &mut Cpu, addr: u64, pte: u64) {
+        cpu.system_bus.store(addr, MemoryOpSize::B64, pte).unwrap();
+    }
+
+    #[test]
+    fn translates_pages() {
+        let mut cpu = cpu();
+        write_pte(
+            &mut cpu,
+            LEVEL0 + 8,
+            leaf(DRAM_BASE_ADDR + 0x5000, LEAF | ACCESSED),
+        );
+        assert_eq!(
+            cpu.translate_address(0x4000_1abc, 4, MemoryAccess::Load)
+                .unwrap(),
+            DRAM_BASE_ADDR + 0x5abc
+        );
+        assert!(matches!(
+            cpu.translate_address(0x4000_2000, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { addr: 0x4000_2000 })
+        ));
+    }
+
+    #[test]
+    fn missing_accessed_and_dirty_bits_fault() {
+        let mut cpu = cpu();
+        write_pte(&mut cpu, LEVEL0, leaf(DRAM_BASE_ADDR + 0x5000, LEAF));
+        write_pte(
+            &mut cpu,
+            LEVEL0 + 8,
+            leaf(DRAM_BASE_ADDR + 0x6000, LEAF | PteFields::A),
+        );
+        assert!(matches!(
+            cpu.translate_address(0x4000_0000, 4, MemoryAccess::Fetch),
+            Err(AppErrors::InstructionPageFault { .. })
+        ));
+        assert!(cpu
+            .translate_address(0x4000_1000, 4, MemoryAccess::Load)
+            .is_ok());
+        assert!(matches!(
+            cpu.translate_address(0x4000_1000, 4, MemoryAccess::Store),
+            Err(AppErrors::StorePageFault { .. })
+        ));
+    }
+
+    #[test]
+    fn superpages_must_be_aligned() {
+        let mut cpu = cpu();
+        //2 MiB megapages at 0x40200000 and 0x40400000
+        write_pte(
+            &mut cpu,
+            LEVEL1 + 8,
+            leaf(DRAM_BASE_ADDR + 0x20_0000, LEAF | ACCESSED),
+        );
+        write_pte(
+            &mut cpu,
+            LEVEL1 + 16,
+            leaf(DRAM_BASE_ADDR + 0x20_1000, LEAF | ACCESSED),
+        );
+        assert_eq!(
+            cpu.translate_address(0x4021_2345, 4, MemoryAccess::Store)
+                .unwrap(),
+            DRAM_BASE_ADDR + 0x21_2345
+        );
+        assert!(matches!(
+            cpu.translate_address(0x4040_0000, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { .. })
+        ));
+        //1 GiB gigapage at 0x80000000 whose PPN is only 2 MiB aligned
+        write_pte(
+            &mut cpu,
+            ROOT + 16,
+            leaf(DRAM_BASE_ADDR + 0x20_0000, LEAF | ACCESSED),
+        );
+        assert!(matches!(
+            cpu.translate_address(0x8000_0000, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { .. })
+        ));
+    }
+
+    #[test]
+    fn napot_entries_map_64k() {
+        let mut cpu = cpu();
+        let napot = |physical: u64| {
+            leaf(physical, LEAF | ACCESSED | PteFields::N)
+                | (PteFields::NAPOT_64K << PteFields::PPN_SHIFT)
+        };
+        //Every entry of the 64 KiB range holds the same NAPOT entry
+        for index in 16..32 {
+            write_pte(
+                &mut cpu,
+                LEVEL0 + index * 8,
+                napot(DRAM_BASE_ADDR + 0x30000),
+            );
+        }
+        assert_eq!(
+            cpu.translate_address(0x4001_3abc, 4, MemoryAccess::Load)
+                .unwrap(),
+            DRAM_BASE_ADDR + 0x33abc
+        );
+        //64 KiB is the only NAPOT size
+        write_pte(
+            &mut cpu,
+            LEVEL0 + 32 * 8,
+            leaf(DRAM_BASE_ADDR + 0x40000, LEAF | ACCESSED | PteFields::N)
+                | (0b0100 << PteFields::PPN_SHIFT),
+        );
+        assert!(matches!(
+            cpu.translate_address(0x4002_0000, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { .. })
+        ));
+        //Only leaf entries of the last level can be NAPOT
+        write_pte(&mut cpu, LEVEL1 + 8, napot(DRAM_BASE_ADDR + 0x20_0000));
+        assert!(matches!(
+            cpu.translate_address(0x4020_0000, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { .. })
+        ));
+    }
+
+    /// Guest physical addresses of the VS-stage page tables, the G-stage maps
+    /// the first GiB of guest physical memory to the RAM at DRAM_BASE_ADDR
+    const GUEST_ROOT: u64 = 0x30000;
+    const GUEST_LEVEL1: u64 = 0x31000;
+    const GUEST_LEVEL0: u64 = 0x32000;
+    const G_STAGE_ROOT: u64 = DRAM_BASE_ADDR + 0x40000;
+
+    /// VS-mode hart with Sv39 VS-stage and Sv39x4 G-stage translations, the
+    /// VS-stage maps the 2 MiB at 0x40000000 through GUEST_LEVEL0
+    fn guest_cpu() -> Cpu {
+        let mut cpu = cpu();
+        cpu.store_hgatp((SatpFields::SV39 << SatpFields::MODE_SHIFT) | (G_STAGE_ROOT / PAGE_SIZE));
+        cpu.store_vsatp((SatpFields::SV39 << SatpFields::MODE_SHIFT) | (GUEST_ROOT / PAGE_SIZE));
+        write_pte(
+            &mut cpu,
+            G_STAGE_ROOT,
+            leaf(DRAM_BASE_ADDR, LEAF | ACCESSED | PteFields::U),
+        );
+        write_pte(
+            &mut cpu,
+            DRAM_BASE_ADDR + GUEST_ROOT + 8,
+            pointer(GUEST_LEVEL1),
+        );
+        write_pte(
+            &mut cpu,
+            DRAM_BASE_ADDR + GUEST_LEVEL1,
+            pointer(GUEST_LEVEL0),
+        );
+        cpu.set_virtualization(true);
+        cpu
+    }
+
+    #[test]
+    fn composes_the_vs_and_g_stages() {
+        let mut cpu = guest_cpu();
+        write_pte(
+            &mut cpu,
+            DRAM_BASE_ADDR + GUEST_LEVEL0 + 8,
+            leaf(0x5000, LEAF | ACCESSED),
+        );
+        assert_eq!(
+            cpu.translate_address(0x4000_1abc, 4, MemoryAccess::Load)
+                .unwrap(),
+            DRAM_BASE_ADDR + 0x5abc
+        );
+        //satp is not used while V=1
+        cpu.set_virtualization(false);
+        assert!(matches!(
+            cpu.translate_address(0x4000_1abc, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { .. })
+        ));
+    }
+
+    #[test]
+    fn g_stage_faults_are_guest_page_faults() {
+        let mut cpu = guest_cpu();
+        //The guest physical page is above the GiB mapped by the G-stage
+        write_pte(
+            &mut cpu,
+            DRAM_BASE_ADDR + GUEST_LEVEL0 + 2 * 8,
+            leaf(0x4000_0000, LEAF | ACCESSED),
+        );
+        assert!(matches!(
+            cpu.translate_address(0x4000_2010, 4, MemoryAccess::Store),
+            Err(AppErrors::StoreGuestPageFault {
+                addr: 0x4000_2010,
+                guest_physical: 0x4000_0010,
+            })
+        ));
+        //G-stage leaves must be user pages, the first G-stage access reads the
+        //VS-stage root table
+        write_pte(
+            &mut cpu,
+            DRAM_BASE_ADDR + GUEST_LEVEL0 + 8,
+            leaf(0x5000, LEAF | ACCESSED),
+        );
+        write_pte(
+            &mut cpu,
+            G_STAGE_ROOT,
+            leaf(DRAM_BASE_ADDR, LEAF | ACCESSED),
+        );
+        assert!(matches!(
+            cpu.translate_address(0x4000_1000, 4, MemoryAccess::Fetch),
+            Err(AppErrors::InstructionGuestPageFault {
+                addr: 0x4000_1000,
+                guest_physical: 0x30008,
+            })
+        ));
+    }
+
+    #[test]
+    fn vs_stage_page_table_accesses_go_through_the_g_stage() {
+        let mut cpu = guest_cpu();
+        //The VS-stage root table is at a guest physical address the G-stage
+        //doesn't map, the fault has the type of the original access
+        cpu.store_vsatp((SatpFields::SV39 << SatpFields::MODE_SHIFT) | (0x4000_0000 / PAGE_SIZE));
+        assert!(matches!(
+            cpu.translate_address(0x4000_1000, 4, MemoryAccess::Store),
+            Err(AppErrors::StoreGuestPageFault {
+                addr: 0x4000_1000,
+                guest_physical: 0x4000_0008,
+            })
+        ));
+        //VS-stage faults are regular page faults
+        let mut cpu = guest_cpu();
+        assert!(matches!(
+            cpu.translate_address(0x4000_1000, 4, MemoryAccess::Load),
+            Err(AppErrors::LoadPageFault { addr: 0x4000_1000 })
+        ));
+    }
+
+    #[test]
+    fn hgatp_root_table_is_16k_aligned() {
+        let mut cpu = cpu();
+        cpu.store_hgatp((SatpFields::SV48 << SatpFields::MODE_SHIFT) | 0x80007);
+        assert_eq!(
+            cpu.cs_registers[HypervisorLevelCSRegisters::HGATP],
+            (SatpFields::SV48 << SatpFields::MODE_SHIFT) | 0x80004
+        );
+        //Unsupported modes are ignored
+        cpu.store_hgatp(5 << SatpFields::MODE_SHIFT);
+        assert_eq!(
+            cpu.cs_registers[HypervisorLevelCSRegisters::HGATP] >> SatpFields::MODE_SHIFT,
+            SatpFields::SV48
+        );
+    }
+}
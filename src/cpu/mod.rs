@@ -1,51 +1,188 @@
+use std::sync::Arc;
+
 use crate::{
-    consts::DRAM_BASE_ADDR,
+    cache_model::OperationCounter,
+    consts::{BOOT_STACK_SIZE, DRAM_BASE_ADDR},
     error::{AppErrors, AppResult},
+    machine::Machine,
+    memory::MemoryOpSize,
     system_bus::SystemBus,
+    trace::Tracer,
 };
 
-use self::{instructions::decoder::InstructionSize, side_effects::OperationSideEffect};
+use self::{
+    basic_blocks::BlockCache,
+    config::CpuConfig,
+    cs_registers::{
+        HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+    },
+    extensions::{Extension, Extensions},
+    hypervisor::HypervisorAccess,
+    instruction_cache::InstructionCache,
+    instruction_excecutors::DecodedInstruction,
+    instructions::decoder::InstructionSize,
+    pmp::{MemoryAccess, Pmp},
+    privilege::PrivilegeMode,
+    side_effects::OperationSideEffect,
+    tlb::DataTlb,
+    triggers::Triggers,
+    xlen::Xlen,
+};
 
+pub mod basic_blocks;
+pub mod config;
 mod cs_registers;
-mod instruction_excecutors;
+pub mod extensions;
+mod hypervisor;
+mod instruction_cache;
+pub mod instruction_excecutors;
 pub mod instructions;
+pub mod interrupts;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod misaligned;
+mod mmu;
+mod pmp;
+pub mod privilege;
 pub mod side_effects;
+mod softfloat;
+#[cfg(test)]
+pub(crate) mod test_hart;
+mod tlb;
+mod trap;
+mod triggers;
+pub mod xlen;
 
 const CPU_REG_COUNT: usize = 32;
 
 pub struct Cpu {
     registers: [u64; CPU_REG_COUNT],
+    /// F registers, NaN-boxed to FLEN
+    float_registers: [u64; CPU_REG_COUNT],
     program_counter: u64,
     pub system_bus: SystemBus,
     cs_registers: [u64; 4096],
+    privilege_mode: PrivilegeMode,
+    /// Virtualization mode of the hypervisor extension, set in VS and VU modes
+    virtualization: bool,
+    /// Set while a hypervisor load or store accesses the memory of the guest
+    hypervisor_access: Option<HypervisorAccess>,
+    /// Address reserved by the last LR instruction, its width and the value it
+    /// loaded, SC only succeeds if the memory still holds that value
+    reservation: Option<(u64, MemoryOpSize, u64)>,
+    extensions: Extensions,
+    /// XLEN of the current privilege mode
+    xlen: Xlen,
+    pmp: Pmp,
+    triggers: Triggers,
+    instruction_cache: InstructionCache,
+    blocks: BlockCache,
+    /// Host addresses of the system memory pages accessed by loads and stores,
+    /// flushed by system instructions and traps
+    tlb: Box<DataTlb>,
+    #[cfg(feature = "jit")]
+    jit: Option<Box<jit::Jit>>,
+    /// Observers of the executed instructions, None while tracing is off
+    tracer: Option<Box<Tracer>>,
+    /// Instructions executed so far, including the ones that raised an exception
+    executed_instructions: u64,
+    /// Index of the hart in the interrupt controllers
+    hart_id: usize,
+    config: CpuConfig,
 }
 
 impl Cpu {
-    pub fn new(memory_size: u64, init_code: Vec<u8>) -> Self {
+    pub fn new(machine: Arc<Machine>, hart_id: usize, config: CpuConfig) -> Self {
+        let device_tree_addr = machine.device_tree_addr();
         let mut cpu = Self {
             registers: [0_u64; 32],
+            float_registers: [0_u64; 32],
             program_counter: DRAM_BASE_ADDR,
-            system_bus: SystemBus::new(memory_size, init_code),
+            system_bus: SystemBus::new(machine, hart_id),
             cs_registers: [0_u64; 4096],
+            privilege_mode: PrivilegeMode::Machine,
+            virtualization: false,
+            hypervisor_access: None,
+            reservation: None,
+            extensions: config.profile.extensions(),
+            xlen: config.xlen,
+            pmp: Pmp::new(config.pmp_entries, config.pmp_granularity),
+            triggers: Triggers::new(config.xlen),
+            instruction_cache: InstructionCache::new(),
+            blocks: BlockCache::new(),
+            tlb: Box::new(DataTlb::new()),
+            #[cfg(feature = "jit")]
+            jit: (config.engine == basic_blocks::ExecutionEngine::Jit)
+                .then(|| Box::new(jit::Jit::new())),
+            tracer: None,
+            executed_instructions: 0,
+            hart_id,
+            config,
         };
-        cpu.registers[0x02] = DRAM_BASE_ADDR + memory_size - 1;
+        cpu.cs_registers[MachineLevelCSRegisters::MISA] =
+            cpu.extensions.misa(cpu.config.xlen, cpu.config.base);
+        if cpu.config.cache_statistics {
+            let hart = (cpu.config.harts > 1).then_some(hart_id);
+            cpu.system_bus
+                .attach_cache_model(Box::new(OperationCounter::new(hart)));
+        }
+        //S and U modes start with the machine XLEN, the XL fields don't exist on RV32
+        if cpu.config.xlen == Xlen::X64 {
+            cpu.cs_registers[MachineLevelCSRegisters::MSTATUS] =
+                (2 << MStatusFields::UXL_SHIFT) | (2 << MStatusFields::SXL_SHIFT);
+            cpu.cs_registers[HypervisorLevelCSRegisters::HSTATUS] = 2 << HStatusFields::VSXL_SHIFT;
+            cpu.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] = 2 << MStatusFields::UXL_SHIFT;
+        }
+        //The VS-level and guest external interrupts are always delegated to HS-mode
+        cpu.cs_registers[MachineLevelCSRegisters::MIDELEG] = cpu.hypervisor_interrupts();
+        //Every hart starts at the boot image, with its own stack below the device tree
+        let stack = (device_tree_addr & !0xf) - hart_id as u64 * BOOT_STACK_SIZE;
+        // Boot protocol expected by Linux: a0 holds the hart id and a1 the device tree address
+        cpu.write_reg(0x02, stack).unwrap();
+        cpu.write_reg(0x0a, hart_id as u64).unwrap();
+        cpu.write_reg(0x0b, device_tree_addr).unwrap();
         cpu
     }
 
-    pub fn fetch_next_instruction(&mut self) -> AppResult<u32> {
-        self.system_bus.load32(self.program_counter)
+    /// Fetches the instruction at the program counter, instructions are decoded
+    /// once and then served from the instruction cache until their page is written
+    pub fn fetch_next_instruction(&mut self) -> AppResult<DecodedInstruction> {
+        let addr = self.translate_address(self.program_counter, 4, MemoryAccess::Fetch)?;
+        //Device regions are not executable
+        if self.system_bus.is_mmio(addr) {
+            return Err(AppErrors::InstructionAccessFault {
+                addr: self.program_counter,
+            });
+        }
+        self.invalidate_written_code();
+        let page = self.system_bus.page_index(addr);
+        if let Some(instruction) = page.and_then(|page| self.instruction_cache.get(page, addr)) {
+            return Ok(instruction);
+        }
+        let raw_instruction = self.system_bus.load32(addr)?;
+        let instruction = self.decode(raw_instruction);
+        if page.is_some_and(|page| self.instruction_cache.insert(page, addr, instruction)) {
+            self.mark_code_page(addr);
+        }
+        Ok(instruction)
     }
-    #[allow(dead_code)]
+    /// Reads an operand register, the value is sign-extended from the current
+    /// XLEN so instructions running with a narrower XLEN ignore the upper bits
+    /// left by a wider mode
     #[inline(always)]
-    pub fn read_reg(&mut self, register: usize) -> AppResult<u64> {
-        if register > CPU_REG_COUNT {
-            return Err(AppErrors::OutOfBoundRegister);
+    pub fn read_reg(&self, register: usize) -> u64 {
+        match self.xlen {
+            Xlen::X32 => self.registers[register] as i32 as i64 as u64,
+            Xlen::X64 => self.registers[register],
         }
-        Ok(self.registers[register])
     }
     #[inline(always)]
     pub fn write_reg(&mut self, register: usize, value: u64) -> AppResult<OperationSideEffect> {
-        self.registers[register] = value;
+        //Results are sign-extended from the current XLEN
+        self.registers[register] = match self.xlen {
+            Xlen::X32 => value as i32 as i64 as u64,
+            Xlen::X64 => value,
+        };
         self.registers[0] = 0;
         Ok(OperationSideEffect::None)
     }
@@ -59,6 +196,30 @@ impl Cpu {
         // self.program_counter += DEFAULT_INSTRUCTION_SIZE_BYTES as u64;
     }
 
+    /// Number of integer registers of the base ISA
+    #[inline(always)]
+    pub fn register_count(&self) -> u8 {
+        self.config.base.register_count()
+    }
+
+    /// Returns an illegal instruction error if the extension is not enabled
+    #[inline(always)]
+    pub fn require_extension(&self, extension: Extension, instruction: u32) -> AppResult<()> {
+        match self.extensions.contains(extension) {
+            true => Ok(()),
+            false => Err(AppErrors::IllegalInstruction { instruction }),
+        }
+    }
+
+    /// The word instructions of RV64 are illegal while XLEN is 32
+    #[inline(always)]
+    pub fn require_xlen64(&self, instruction: u32) -> AppResult<()> {
+        match self.xlen {
+            Xlen::X64 => Ok(()),
+            Xlen::X32 => Err(AppErrors::IllegalInstruction { instruction }),
+        }
+    }
+
     #[inline(always)]
     pub fn get_program_counter(&mut self) -> u64 {
         self.program_counter
@@ -67,4 +228,38 @@ impl Cpu {
     pub fn get_registers(&mut self) -> [u64; 32] {
         self.registers
     }
+
+    pub fn attach_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Stops tracing, returns once the observers processed every event
+    pub fn detach_tracer(&mut self) {
+        self.tracer = None;
+    }
+
+    /// Moves the tracer to another hart sharing the host thread, so the harts
+    /// record their instructions into a single trace
+    pub fn hand_over_tracer(&mut self, next: &mut Cpu) {
+        if self.tracer.is_some() {
+            next.tracer = self.tracer.take();
+        }
+    }
+
+    pub fn executed_instructions(&self) -> u64 {
+        self.executed_instructions
+    }
+
+    /// Records an instruction about to be executed
+    #[inline(always)]
+    fn trace(&mut self, instruction: u32) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(
+                self.hart_id,
+                self.program_counter,
+                instruction,
+                self.privilege_mode,
+            );
+        }
+    }
 }
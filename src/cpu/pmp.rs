@@ -0,0 +1,692 @@
+use crate::error::{AppErrors, AppResult};
+
+use super::{
+    cs_registers::{MStatusFields, MachineLevelCSRegisters},
+    extensions::Extension,
+    privilege::PrivilegeMode,
+    xlen::Xlen,
+    Cpu,
+};
+
+/// Maximum number of PMP entries, the pmpcfg/pmpaddr CSRs of the entries that
+/// are not implemented are read-only zero
+pub const PMP_MAX_ENTRIES: usize = 64;
+
+/// Bit fields of the 8 bit configuration of each PMP entry
+pub struct PmpConfigFields;
+impl PmpConfigFields {
+    pub const R: u8 = 1 << 0;
+    pub const W: u8 = 1 << 1;
+    pub const X: u8 = 1 << 2;
+    pub const A_SHIFT: u8 = 3;
+    pub const A: u8 = 0b11 << Self::A_SHIFT;
+    pub const L: u8 = 1 << 7;
+
+    /// Address matching modes held in A
+    pub const OFF: u8 = 0b00;
+    pub const TOR: u8 = 0b01;
+    pub const NA4: u8 = 0b10;
+    pub const NAPOT: u8 = 0b11;
+
+    pub const WRITE_MASK: u8 = Self::R | Self::W | Self::X | Self::A | Self::L;
+}
+
+/// Bit fields of the Smepmp mseccfg register
+pub struct MSecCfgFields;
+impl MSecCfgFields {
+    /// Machine mode lockdown
+    pub const MML: u64 = 1 << 0;
+    /// Machine mode whitelist policy
+    pub const MMWP: u64 = 1 << 1;
+    /// Rule locking bypass
+    pub const RLB: u64 = 1 << 2;
+}
+
+/// Kind of memory access checked against the PMP entries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl MemoryAccess {
+    #[inline(always)]
+    pub fn access_fault(&self, addr: u64) -> AppErrors {
+        match self {
+            MemoryAccess::Fetch => AppErrors::InstructionAccessFault { addr },
+            MemoryAccess::Load => AppErrors::LoadAccessFault { addr },
+            MemoryAccess::Store => AppErrors::StoreAccessFault { addr },
+        }
+    }
+}
+
+/// Physical memory protection unit state
+pub struct Pmp {
+    config: [u8; PMP_MAX_ENTRIES],
+    address: [u64; PMP_MAX_ENTRIES],
+    /// Number of implemented entries
+    entries: usize,
+    /// Granularity G, regions are at least 2^(G+2) bytes
+    granularity: u32,
+    /// One past the highest entry with an address matching mode other than OFF
+    active_entries: usize,
+    mseccfg: u64,
+}
+
+impl Pmp {
+    pub fn new(entries: usize, granularity: u32) -> Self {
+        Self {
+            config: [0; PMP_MAX_ENTRIES],
+            address: [0; PMP_MAX_ENTRIES],
+            entries,
+            granularity,
+            active_entries: 0,
+            mseccfg: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn matching_mode(&self, entry: usize) -> u8 {
+        (self.config[entry] & PmpConfigFields::A) >> PmpConfigFields::A_SHIFT
+    }
+
+    #[inline(always)]
+    fn is_locked(&self, entry: usize) -> bool {
+        self.config[entry] & PmpConfigFields::L != 0 && self.mseccfg & MSecCfgFields::RLB == 0
+    }
+
+    /// Value of pmpaddr as read back, the bits below the granularity read as
+    /// ones for NAPOT and as zeros for OFF and TOR
+    fn read_address(&self, entry: usize) -> u64 {
+        let address = self.address[entry];
+        match (self.granularity, self.matching_mode(entry)) {
+            (0, _) => address,
+            (granularity, PmpConfigFields::NAPOT) => address | ((1 << (granularity - 1)) - 1),
+            (granularity, _) => address & !((1 << granularity) - 1),
+        }
+    }
+
+    fn write_address(&mut self, entry: usize, value: u64, xlen: Xlen) {
+        //Writes are ignored when the entry is locked, or the next entry is a locked
+        //TOR entry using this address as its lower bound
+        let next_is_locked_tor = entry + 1 < self.entries
+            && self.is_locked(entry + 1)
+            && self.matching_mode(entry + 1) == PmpConfigFields::TOR;
+        if self.is_locked(entry) || next_is_locked_tor {
+            return;
+        }
+        //pmpaddr holds bits [55:2] of a 56-bit physical address on RV64 and bits
+        //[33:2] of a 34-bit physical address on RV32
+        self.address[entry] = match xlen {
+            Xlen::X32 => value & 0xffff_ffff,
+            Xlen::X64 => value & ((1 << 54) - 1),
+        };
+    }
+
+    fn write_config(&mut self, entry: usize, value: u8) {
+        if self.is_locked(entry) {
+            return;
+        }
+        let mut value = value & PmpConfigFields::WRITE_MASK;
+        let mml = self.mseccfg & MSecCfgFields::MML != 0;
+        let rlb = self.mseccfg & MSecCfgFields::RLB != 0;
+        let permissions = value & (PmpConfigFields::R | PmpConfigFields::W | PmpConfigFields::X);
+        //R=0 W=1 is reserved unless MML gives it the meaning of a shared region
+        if !mml && permissions & (PmpConfigFields::R | PmpConfigFields::W) == PmpConfigFields::W {
+            return;
+        }
+        //With MML set, new executable M-mode rules can only be added with RLB set
+        if mml && !rlb && value & PmpConfigFields::L != 0 {
+            let r = PmpConfigFields::R;
+            let w = PmpConfigFields::W;
+            let x = PmpConfigFields::X;
+            if [x, w, w | x, r | x].contains(&permissions) {
+                return;
+            }
+        }
+        //NA4 can't be selected when the granularity is bigger than 4 bytes
+        if self.granularity >= 1
+            && (value & PmpConfigFields::A) >> PmpConfigFields::A_SHIFT == PmpConfigFields::NA4
+        {
+            value &= !PmpConfigFields::A;
+        }
+        self.config[entry] = value;
+        self.active_entries = (0..self.entries)
+            .rev()
+            .find(|entry| self.matching_mode(*entry) != PmpConfigFields::OFF)
+            .map_or(0, |entry| entry + 1);
+    }
+
+    fn write_mseccfg(&mut self, value: u64) {
+        //MML and MMWP are sticky until reset
+        let sticky = MSecCfgFields::MML | MSecCfgFields::MMWP;
+        let mut mseccfg = self.mseccfg | (value & sticky);
+        //RLB can't be set again once cleared while there are locked entries
+        let any_locked = self.config[..self.entries]
+            .iter()
+            .any(|config| config & PmpConfigFields::L != 0);
+        if self.mseccfg & MSecCfgFields::RLB != 0 || !any_locked {
+            mseccfg = (mseccfg & !MSecCfgFields::RLB) | (value & MSecCfgFields::RLB);
+        }
+        self.mseccfg = mseccfg;
+    }
+
+    /// Address range [start, end) matched by an entry, None if the entry is OFF
+    fn range(&self, entry: usize) -> Option<(u64, u64)> {
+        let address = self.read_address(entry);
+        match self.matching_mode(entry) {
+            PmpConfigFields::TOR => {
+                let start = match entry {
+                    0 => 0,
+                    _ => self.read_address(entry - 1) << 2,
+                };
+                Some((start, address << 2))
+            }
+            PmpConfigFields::NA4 => Some((address << 2, (address << 2) + 4)),
+            PmpConfigFields::NAPOT => {
+                let ones = address.trailing_ones();
+                let start = (address >> (ones + 1)) << (ones + 3);
+                Some((start, start.saturating_add(1 << (ones + 3))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks the permissions granted by a matching entry
+    fn is_allowed(&self, entry: usize, privilege: PrivilegeMode, access: MemoryAccess) -> bool {
+        let config = self.config[entry];
+        let locked = config & PmpConfigFields::L != 0;
+        let machine = privilege == PrivilegeMode::Machine;
+        let permission = match access {
+            MemoryAccess::Fetch => PmpConfigFields::X,
+            MemoryAccess::Load => PmpConfigFields::R,
+            MemoryAccess::Store => PmpConfigFields::W,
+        };
+        if self.mseccfg & MSecCfgFields::MML == 0 {
+            //Unlocked entries don't apply to M-mode
+            return (machine && !locked) || config & permission != 0;
+        }
+
+        //Smepmp truth table, locked rules are M-mode only and unlocked rules S/U-mode
+        //only, except for the shared regions encoded with R=0 W=1
+        let r = PmpConfigFields::R;
+        let w = PmpConfigFields::W;
+        let x = PmpConfigFields::X;
+        let permissions = config & (r | w | x);
+        let granted = match (locked, permissions) {
+            (false, p) if p == w => match machine {
+                true => r | w,
+                false => r,
+            },
+            (false, p) if p == w | x => r | w,
+            (false, p) => match machine {
+                true => 0,
+                false => p,
+            },
+            (true, p) if p == w => x,
+            (true, p) if p == w | x => match machine {
+                true => r | x,
+                false => x,
+            },
+            (true, p) if p == r | w | x => r,
+            (true, p) => match machine {
+                true => p,
+                false => 0,
+            },
+        };
+        granted & permission != 0
+    }
+}
+
+impl Cpu {
+    /// Privilege used for loads and stores, mstatus.MPRV makes M-mode accesses
+    /// use the privilege held in mstatus.MPP and the hypervisor loads and
+    /// stores use the one selected by hstatus.SPVP
+    #[inline(always)]
+    pub fn data_access_privilege(&self) -> PrivilegeMode {
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        if self.hypervisor_access.is_some() {
+            return self.hypervisor_access_privilege();
+        }
+        match self.privilege_mode {
+            PrivilegeMode::Machine if status & MStatusFields::MPRV != 0 => {
+                PrivilegeMode::from_bits((status & MStatusFields::MPP) >> MStatusFields::MPP_SHIFT)
+            }
+            privilege_mode => privilege_mode,
+        }
+    }
+
+    /// Privilege of a kind of access, instruction fetches are not affected by MPRV
+    #[inline(always)]
+    pub fn access_privilege(&self, access: MemoryAccess) -> PrivilegeMode {
+        match access {
+            MemoryAccess::Fetch => self.privilege_mode,
+            _ => self.data_access_privilege(),
+        }
+    }
+
+    /// Checks a physical memory access of `size` bytes against the PMP entries,
+    /// returns the access fault matching the kind of access if it is denied
+    #[inline(always)]
+    pub fn check_pmp(&self, addr: u64, size: u64, access: MemoryAccess) -> AppResult<()> {
+        self.check_pmp_as(addr, size, access, self.access_privilege(access))
+    }
+
+    /// Same as check_pmp for an access made with an explicit privilege, such as
+    /// the implicit S-mode accesses of the page table walks
+    #[inline(always)]
+    pub fn check_pmp_as(
+        &self,
+        addr: u64,
+        size: u64,
+        access: MemoryAccess,
+        privilege: PrivilegeMode,
+    ) -> AppResult<()> {
+        let pmp = &self.pmp;
+        if pmp.active_entries == 0 && privilege == PrivilegeMode::Machine && pmp.mseccfg == 0 {
+            return Ok(());
+        }
+
+        let end = addr.saturating_add(size);
+        for entry in 0..pmp.active_entries {
+            let Some((start, entry_end)) = pmp.range(entry) else {
+                continue;
+            };
+            if addr >= entry_end || end <= start {
+                continue;
+            }
+            //The lowest numbered matching entry decides, it must cover every byte
+            let covered = addr >= start && end <= entry_end;
+            return match covered && pmp.is_allowed(entry, privilege, access) {
+                true => Ok(()),
+                false => Err(access.access_fault(addr)),
+            };
+        }
+
+        let allowed = match privilege {
+            PrivilegeMode::Machine => {
+                let mml = pmp.mseccfg & MSecCfgFields::MML != 0;
+                pmp.mseccfg & MSecCfgFields::MMWP == 0 && !(mml && access == MemoryAccess::Fetch)
+            }
+            //S and U accesses fail when no entry matches and PMP is implemented
+            _ => pmp.entries == 0,
+        };
+        match allowed {
+            true => Ok(()),
+            false => Err(access.access_fault(addr)),
+        }
+    }
+
+    /// Reads the pmpcfg, pmpaddr and mseccfg CSRs
+    pub fn load_pmp_csr(&self, addr: usize) -> u64 {
+        match addr {
+            MachineLevelCSRegisters::PMPCFG0..=MachineLevelCSRegisters::PMPCFG15 => {
+                let first = (addr - MachineLevelCSRegisters::PMPCFG0) * 4;
+                let count = self.config.xlen.bits() as usize / 8;
+                (first..first + count)
+                    .filter(|entry| *entry < self.pmp.entries)
+                    .fold(0, |value, entry| {
+                        value | (self.pmp.config[entry] as u64) << ((entry - first) * 8)
+                    })
+            }
+            MachineLevelCSRegisters::PMPADDR0..=MachineLevelCSRegisters::PMPADDR63 => {
+                let entry = addr - MachineLevelCSRegisters::PMPADDR0;
+                match entry < self.pmp.entries {
+                    true => self.pmp.read_address(entry),
+                    false => 0,
+                }
+            }
+            MachineLevelCSRegisters::MSECCFG => self.pmp.mseccfg,
+            _ => 0,
+        }
+    }
+
+    /// Writes the pmpcfg, pmpaddr and mseccfg CSRs
+    pub fn store_pmp_csr(&mut self, addr: usize, value: u64) {
+        match addr {
+            MachineLevelCSRegisters::PMPCFG0..=MachineLevelCSRegisters::PMPCFG15 => {
+                let first = (addr - MachineLevelCSRegisters::PMPCFG0) * 4;
+                let count = self.config.xlen.bits() as usize / 8;
+                for entry in first..(first + count).min(self.pmp.entries) {
+                    self.pmp
+                        .write_config(entry, (value >> ((entry - first) * 8)) as u8);
+                }
+            }
+            MachineLevelCSRegisters::PMPADDR0..=MachineLevelCSRegisters::PMPADDR63 => {
+                let entry = addr - MachineLevelCSRegisters::PMPADDR0;
+                if entry < self.pmp.entries {
+                    self.pmp.write_address(entry, value, self.config.xlen);
+                }
+            }
+            MachineLevelCSRegisters::MSECCFG if self.extensions.contains(Extension::Smepmp) => {
+                self.pmp.write_mseccfg(value)
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{consts::DRAM_BASE_ADDR, cpu::test_hart::cpu};
+
+    /// pmpaddr value of a NAPOT region of `size` bytes at `base`
+    fn napot(base: u64, size: u64) -> u64 {
+        (base >> 2) | ((size >> 3) - 1)
+    }
+
+    fn set_entry(cpu: &mut Cpu, entry: usize, address: u64, config: u8) {
+        cpu.store_pmp_csr(MachineLevelCSRegisters::PMPADDR0 + entry, address);
+        let shift = entry * 8;
+        let pmpcfg = cpu.load_pmp_csr(MachineLevelCSRegisters::PMPCFG0);
+        let pmpcfg = (pmpcfg & !(0xff << shift)) | (config as u64) << shift;
+        cpu.store_pmp_csr(MachineLevelCSRegisters::PMPCFG0, pmpcfg);
+    }
+
+    fn allowed(cpu: &Cpu, addr: u64, access: MemoryAccess, privilege: PrivilegeMode) -> bool {
+        cpu.check_pmp_as(addr, 4, access, privilege).is_ok()
+    }
+
+    const R: u8 = PmpConfigFields::R;
+    const W: u8 = PmpConfigFields::W;
+    const X: u8 = PmpConfigFields::X;
+    const L: u8 = PmpConfigFields::L;
+    const TOR: u8 = PmpConfigFields::TOR << PmpConfigFields::A_SHIFT;
+    const NA4: u8 = PmpConfigFields::NA4 << PmpConfigFields::A_SHIFT;
+    const NAPOT: u8 = PmpConfigFields::NAPOT << PmpConfigFields::A_SHIFT;
+
+    #[test]
+    fn lowest_numbered_matching_entry_decides() {
+        let mut cpu = cpu();
+        set_entry(&mut cpu, 0, napot(DRAM_BASE_ADDR, 0x1000), NAPOT);
+        set_entry(
+            &mut cpu,
+            1,
+            napot(DRAM_BASE_ADDR, 0x10000),
+            NAPOT | R | W | X,
+        );
+        let user = PrivilegeMode::User;
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x800,
+            MemoryAccess::Load,
+            user
+        ));
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x1000,
+            MemoryAccess::Load,
+            user
+        ));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x10000,
+            MemoryAccess::Load,
+            user
+        ));
+        //The first entry doesn't cover every byte of the access
+        assert!(cpu
+            .check_pmp_as(DRAM_BASE_ADDR + 0xffc, 8, MemoryAccess::Load, user)
+            .is_err());
+    }
+
+    #[test]
+    fn tor_and_na4_ranges() {
+        let mut cpu = cpu();
+        set_entry(&mut cpu, 0, DRAM_BASE_ADDR >> 2, 0);
+        set_entry(&mut cpu, 1, (DRAM_BASE_ADDR + 0x100) >> 2, TOR | R);
+        set_entry(&mut cpu, 2, (DRAM_BASE_ADDR + 0x100) >> 2, NA4 | R | W);
+        let user = PrivilegeMode::User;
+        assert!(!allowed(&cpu, DRAM_BASE_ADDR - 4, MemoryAccess::Load, user));
+        assert!(allowed(&cpu, DRAM_BASE_ADDR, MemoryAccess::Load, user));
+        assert!(!allowed(&cpu, DRAM_BASE_ADDR, MemoryAccess::Store, user));
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x100,
+            MemoryAccess::Store,
+            user
+        ));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x104,
+            MemoryAccess::Load,
+            user
+        ));
+    }
+
+    #[test]
+    fn unlocked_entries_only_apply_below_machine_mode() {
+        let mut cpu = cpu();
+        set_entry(&mut cpu, 0, napot(DRAM_BASE_ADDR, 0x1000), NAPOT);
+        set_entry(
+            &mut cpu,
+            1,
+            napot(DRAM_BASE_ADDR + 0x1000, 0x1000),
+            NAPOT | L | R,
+        );
+        let machine = PrivilegeMode::Machine;
+        assert!(allowed(&cpu, DRAM_BASE_ADDR, MemoryAccess::Store, machine));
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x1000,
+            MemoryAccess::Load,
+            machine
+        ));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x1000,
+            MemoryAccess::Store,
+            machine
+        ));
+        //Without a matching entry M-mode is allowed and S-mode denied
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x2000,
+            MemoryAccess::Fetch,
+            machine
+        ));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x2000,
+            MemoryAccess::Fetch,
+            PrivilegeMode::Supervisor
+        ));
+    }
+
+    #[test]
+    fn locked_entries_ignore_writes() {
+        let mut cpu = cpu();
+        set_entry(&mut cpu, 0, DRAM_BASE_ADDR >> 2, 0);
+        set_entry(&mut cpu, 1, (DRAM_BASE_ADDR + 0x1000) >> 2, TOR | L | R);
+        set_entry(&mut cpu, 1, (DRAM_BASE_ADDR + 0x2000) >> 2, TOR | R | W | X);
+        //The lower bound of a locked TOR entry can't be moved either
+        set_entry(&mut cpu, 0, (DRAM_BASE_ADDR - 0x1000) >> 2, 0);
+        assert_eq!(
+            cpu.load_pmp_csr(MachineLevelCSRegisters::PMPADDR0 + 1),
+            (DRAM_BASE_ADDR + 0x1000) >> 2
+        );
+        assert_eq!(
+            cpu.load_pmp_csr(MachineLevelCSRegisters::PMPADDR0),
+            DRAM_BASE_ADDR >> 2
+        );
+        assert_eq!(
+            cpu.load_pmp_csr(MachineLevelCSRegisters::PMPCFG0) >> 8 & 0xff,
+            (TOR | L | R) as u64
+        );
+    }
+
+    #[test]
+    fn rule_locking_bypass_allows_locked_entries_to_be_edited() {
+        let mut cpu = cpu();
+        cpu.store_pmp_csr(MachineLevelCSRegisters::MSECCFG, MSecCfgFields::RLB);
+        set_entry(&mut cpu, 0, napot(DRAM_BASE_ADDR, 0x1000), NAPOT | L | R);
+        set_entry(
+            &mut cpu,
+            0,
+            napot(DRAM_BASE_ADDR, 0x1000),
+            NAPOT | L | R | W,
+        );
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR,
+            MemoryAccess::Store,
+            PrivilegeMode::Machine
+        ));
+        //RLB can't be set again once cleared while an entry is locked
+        cpu.store_pmp_csr(MachineLevelCSRegisters::MSECCFG, 0);
+        cpu.store_pmp_csr(MachineLevelCSRegisters::MSECCFG, MSecCfgFields::RLB);
+        assert_eq!(cpu.load_pmp_csr(MachineLevelCSRegisters::MSECCFG), 0);
+    }
+
+    #[test]
+    fn machine_mode_lockdown_separates_the_rules() {
+        let mut cpu = cpu();
+        set_entry(
+            &mut cpu,
+            0,
+            napot(DRAM_BASE_ADDR, 0x1000),
+            NAPOT | L | R | X,
+        );
+        set_entry(
+            &mut cpu,
+            1,
+            napot(DRAM_BASE_ADDR + 0x1000, 0x1000),
+            NAPOT | R | W,
+        );
+        set_entry(
+            &mut cpu,
+            3,
+            napot(DRAM_BASE_ADDR + 0x3000, 0x1000),
+            NAPOT | L | R | W | X,
+        );
+        //R=0 W=1 is reserved until MML is set
+        set_entry(
+            &mut cpu,
+            2,
+            napot(DRAM_BASE_ADDR + 0x2000, 0x1000),
+            NAPOT | W,
+        );
+        assert_eq!(
+            cpu.load_pmp_csr(MachineLevelCSRegisters::PMPCFG0) >> 16 & 0xff,
+            0
+        );
+        cpu.store_pmp_csr(MachineLevelCSRegisters::MSECCFG, MSecCfgFields::MML);
+        set_entry(
+            &mut cpu,
+            2,
+            napot(DRAM_BASE_ADDR + 0x2000, 0x1000),
+            NAPOT | W,
+        );
+        let (machine, user) = (PrivilegeMode::Machine, PrivilegeMode::User);
+        //Locked rules are M-mode only
+        assert!(allowed(&cpu, DRAM_BASE_ADDR, MemoryAccess::Fetch, machine));
+        assert!(!allowed(&cpu, DRAM_BASE_ADDR, MemoryAccess::Fetch, user));
+        //Unlocked rules are S/U-mode only
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x1000,
+            MemoryAccess::Store,
+            user
+        ));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x1000,
+            MemoryAccess::Load,
+            machine
+        ));
+        //Shared data region, read/write for M-mode and read-only for S/U-mode
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x2000,
+            MemoryAccess::Store,
+            machine
+        ));
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x2000,
+            MemoryAccess::Load,
+            user
+        ));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x2000,
+            MemoryAccess::Store,
+            user
+        ));
+        //Locked RWX is a read-only region shared by every mode
+        for privilege in [machine, user] {
+            assert!(allowed(
+                &cpu,
+                DRAM_BASE_ADDR + 0x3000,
+                MemoryAccess::Load,
+                privilege
+            ));
+            assert!(!allowed(
+                &cpu,
+                DRAM_BASE_ADDR + 0x3000,
+                MemoryAccess::Store,
+                privilege
+            ));
+            assert!(!allowed(
+                &cpu,
+                DRAM_BASE_ADDR + 0x3000,
+                MemoryAccess::Fetch,
+                privilege
+            ));
+        }
+        //M-mode can't execute outside of the rules
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x4000,
+            MemoryAccess::Fetch,
+            machine
+        ));
+        assert!(allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x4000,
+            MemoryAccess::Load,
+            machine
+        ));
+        //New executable M-mode rules can't be added without RLB
+        set_entry(
+            &mut cpu,
+            4,
+            napot(DRAM_BASE_ADDR + 0x4000, 0x1000),
+            NAPOT | L | X,
+        );
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x4000,
+            MemoryAccess::Fetch,
+            machine
+        ));
+        //MML can't be cleared
+        cpu.store_pmp_csr(MachineLevelCSRegisters::MSECCFG, 0);
+        assert_eq!(
+            cpu.load_pmp_csr(MachineLevelCSRegisters::MSECCFG),
+            MSecCfgFields::MML
+        );
+    }
+
+    #[test]
+    fn machine_mode_whitelist_denies_unmatched_accesses() {
+        let mut cpu = cpu();
+        set_entry(
+            &mut cpu,
+            0,
+            napot(DRAM_BASE_ADDR, 0x1000),
+            NAPOT | L | R | W | X,
+        );
+        cpu.store_pmp_csr(MachineLevelCSRegisters::MSECCFG, MSecCfgFields::MMWP);
+        let machine = PrivilegeMode::Machine;
+        assert!(allowed(&cpu, DRAM_BASE_ADDR, MemoryAccess::Store, machine));
+        assert!(!allowed(
+            &cpu,
+            DRAM_BASE_ADDR + 0x1000,
+            MemoryAccess::Load,
+            machine
+        ));
+    }
+}
@@ -0,0 +1,27 @@
+/// Privilege levels as encoded in the mstatus.MPP field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrivilegeMode {
+    User = 0b00,
+    Supervisor = 0b01,
+    Machine = 0b11,
+}
+
+impl PrivilegeMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "m" => Some(PrivilegeMode::Machine),
+            "s" => Some(PrivilegeMode::Supervisor),
+            "u" => Some(PrivilegeMode::User),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0b00 => PrivilegeMode::User,
+            0b01 => PrivilegeMode::Supervisor,
+            _ => PrivilegeMode::Machine,
+        }
+    }
+}
@@ -1,6 +1,11 @@
 pub enum OperationSideEffect {
     None,
     SkipPCIncrease,
-    TriggerSyscall,
-    TriggerBreakpoint,
+    /// The hart is idle until an interrupt becomes pending
+    WaitForInterrupt,
+    /// The hart is idle until its reservation set is written or an interrupt
+    /// becomes pending, for a short time only when bounded
+    WaitForStore {
+        bounded: bool,
+    },
 }
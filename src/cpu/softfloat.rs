@@ -0,0 +1,1095 @@
+use std::cmp::Ordering;
+
+/// Rounding modes of the rm instruction field and the frm CSR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even
+    NearestEven,
+    TowardZero,
+    /// Round towards negative infinity
+    Down,
+    /// Round towards positive infinity
+    Up,
+    /// Round to nearest, ties to max magnitude
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    /// rm field value selecting the rounding mode held in frm
+    pub const DYNAMIC: u64 = 0b111;
+
+    /// Decodes a rm field or frm value, the other encodings are reserved
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0b000 => Some(RoundingMode::NearestEven),
+            0b001 => Some(RoundingMode::TowardZero),
+            0b010 => Some(RoundingMode::Down),
+            0b011 => Some(RoundingMode::Up),
+            0b100 => Some(RoundingMode::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// Bits of the accrued exception flags held in fflags
+pub struct ExceptionFlags;
+impl ExceptionFlags {
+    /// Inexact
+    pub const NX: u64 = 1 << 0;
+    /// Underflow
+    pub const UF: u64 = 1 << 1;
+    /// Overflow
+    pub const OF: u64 = 1 << 2;
+    /// Divide by zero
+    pub const DZ: u64 = 1 << 3;
+    /// Invalid operation
+    pub const NV: u64 = 1 << 4;
+
+    pub const ALL: u64 = Self::NX | Self::UF | Self::OF | Self::DZ | Self::NV;
+}
+
+/// IEEE 754 binary interchange format, values are held in the low bits of a u64
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloatFormat {
+    exponent_bits: u32,
+    fraction_bits: u32,
+}
+
+impl FloatFormat {
+    pub const HALF: Self = Self {
+        exponent_bits: 5,
+        fraction_bits: 10,
+    };
+    pub const SINGLE: Self = Self {
+        exponent_bits: 8,
+        fraction_bits: 23,
+    };
+    pub const DOUBLE: Self = Self {
+        exponent_bits: 11,
+        fraction_bits: 52,
+    };
+
+    pub fn bits(&self) -> u32 {
+        1 + self.exponent_bits + self.fraction_bits
+    }
+
+    /// Mask of the bits of a value
+    pub fn mask(&self) -> u64 {
+        u64::MAX >> (64 - self.bits())
+    }
+
+    pub fn sign(&self) -> u64 {
+        1 << (self.bits() - 1)
+    }
+
+    /// The only NaN produced by the arithmetic, payloads are not propagated
+    pub fn canonical_nan(&self) -> u64 {
+        self.infinity(false) | 1 << (self.fraction_bits - 1)
+    }
+
+    /// Smallest positive normal value
+    pub fn min_normal(&self) -> u64 {
+        1 << self.fraction_bits
+    }
+
+    fn bias(&self) -> i32 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    /// Exponent of the smallest normal value
+    fn min_exponent(&self) -> i32 {
+        1 - self.bias()
+    }
+
+    fn max_exponent_field(&self) -> u64 {
+        (1 << self.exponent_bits) - 1
+    }
+
+    fn fraction_mask(&self) -> u64 {
+        (1 << self.fraction_bits) - 1
+    }
+
+    fn zero(&self, sign: bool) -> u64 {
+        match sign {
+            true => self.sign(),
+            false => 0,
+        }
+    }
+
+    fn infinity(&self, sign: bool) -> u64 {
+        self.zero(sign) | self.max_exponent_field() << self.fraction_bits
+    }
+
+    fn max_finite(&self, sign: bool) -> u64 {
+        self.infinity(sign) - 1
+    }
+
+    fn unpack(&self, bits: u64) -> Unpacked {
+        let sign = bits & self.sign() != 0;
+        let exponent_field = (bits >> self.fraction_bits) & self.max_exponent_field();
+        let fraction = bits & self.fraction_mask();
+        let value = match exponent_field {
+            0 if fraction == 0 => Value::Zero,
+            0 => Value::Finite {
+                exponent: self.min_exponent() - self.fraction_bits as i32,
+                significand: fraction as u128,
+            },
+            _ if exponent_field == self.max_exponent_field() => match fraction {
+                0 => Value::Infinity,
+                _ => Value::NaN {
+                    signaling: fraction >> (self.fraction_bits - 1) == 0,
+                },
+            },
+            _ => Value::Finite {
+                exponent: exponent_field as i32 - self.bias() - self.fraction_bits as i32,
+                significand: (fraction | 1 << self.fraction_bits) as u128,
+            },
+        };
+        Unpacked { sign, value }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Value {
+    NaN {
+        signaling: bool,
+    },
+    Infinity,
+    Zero,
+    /// significand * 2^exponent
+    Finite {
+        exponent: i32,
+        significand: u128,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct Unpacked {
+    sign: bool,
+    value: Value,
+}
+
+impl Unpacked {
+    fn is_nan(&self) -> bool {
+        matches!(self.value, Value::NaN { .. })
+    }
+
+    fn is_signaling(&self) -> bool {
+        matches!(self.value, Value::NaN { signaling: true })
+    }
+}
+
+/// Bit position the significands are aligned to before an addition, leaving
+/// room for the carry
+const SUM_ALIGNMENT: u32 = 125;
+
+/// Arithmetic on the values of a format with a rounding mode, the exception
+/// flags raised by the operations accrue in `flags`
+pub struct SoftFloat {
+    format: FloatFormat,
+    rounding: RoundingMode,
+    pub flags: u64,
+}
+
+impl SoftFloat {
+    pub fn new(format: FloatFormat, rounding: RoundingMode) -> Self {
+        Self {
+            format,
+            rounding,
+            flags: 0,
+        }
+    }
+
+    pub fn add(&mut self, a: u64, b: u64) -> u64 {
+        let (a, b) = (self.format.unpack(a), self.format.unpack(b));
+        self.sum(a, b)
+    }
+
+    pub fn sub(&mut self, a: u64, b: u64) -> u64 {
+        let (a, mut b) = (self.format.unpack(a), self.format.unpack(b));
+        b.sign = !b.sign;
+        self.sum(a, b)
+    }
+
+    pub fn mul(&mut self, a: u64, b: u64) -> u64 {
+        let (a, b) = (self.format.unpack(a), self.format.unpack(b));
+        match self.product(a, b) {
+            Some(product) => self.pack(product),
+            None => self.invalid(),
+        }
+    }
+
+    /// a * b + c with a single rounding, the negated variants flip the sign
+    /// of the operands
+    pub fn fused_multiply_add(&mut self, a: u64, b: u64, c: u64) -> u64 {
+        let (a, b, c) = (
+            self.format.unpack(a),
+            self.format.unpack(b),
+            self.format.unpack(c),
+        );
+        //Infinity times zero is invalid even when the addend is a quiet NaN
+        if c.is_signaling() {
+            self.flags |= ExceptionFlags::NV;
+        }
+        match self.product(a, b) {
+            Some(product) if c.is_nan() => self.propagate_nan(&[product, c]),
+            Some(product) => self.sum(product, c),
+            None => self.invalid(),
+        }
+    }
+
+    pub fn div(&mut self, a: u64, b: u64) -> u64 {
+        let (a, b) = (self.format.unpack(a), self.format.unpack(b));
+        if a.is_nan() || b.is_nan() {
+            return self.propagate_nan(&[a, b]);
+        }
+        let sign = a.sign ^ b.sign;
+        match (a.value, b.value) {
+            (Value::Infinity, Value::Infinity) | (Value::Zero, Value::Zero) => self.invalid(),
+            (Value::Infinity, _) => self.format.infinity(sign),
+            (_, Value::Infinity) | (Value::Zero, _) => self.format.zero(sign),
+            (_, Value::Zero) => {
+                self.flags |= ExceptionFlags::DZ;
+                self.format.infinity(sign)
+            }
+            (
+                Value::Finite {
+                    exponent: dividend_exponent,
+                    significand: dividend,
+                },
+                Value::Finite {
+                    exponent: divisor_exponent,
+                    significand: divisor,
+                },
+            ) => {
+                //The quotient keeps over 60 bits, enough for any format up to
+                //double precision and its rounding bits
+                let (dividend, dividend_exponent) = normalize(dividend, dividend_exponent, 125);
+                let (divisor, divisor_exponent) = normalize(divisor, divisor_exponent, 62);
+                let quotient = dividend / divisor;
+                let sticky = (dividend % divisor != 0) as u128;
+                self.round(
+                    sign,
+                    dividend_exponent - divisor_exponent,
+                    quotient | sticky,
+                )
+            }
+            _ => unreachable!("NaNs are handled first"),
+        }
+    }
+
+    pub fn sqrt(&mut self, a: u64) -> u64 {
+        let a = self.format.unpack(a);
+        match a.value {
+            Value::NaN { .. } => self.propagate_nan(&[a]),
+            Value::Zero => self.format.zero(a.sign),
+            _ if a.sign => self.invalid(),
+            Value::Infinity => self.format.infinity(false),
+            Value::Finite {
+                exponent,
+                significand,
+            } => {
+                let (mut significand, mut exponent) = normalize(significand, exponent, 124);
+                if exponent.rem_euclid(2) == 1 {
+                    significand <<= 1;
+                    exponent -= 1;
+                }
+                let (root, remainder) = integer_sqrt(significand);
+                self.round(false, exponent / 2, root | (remainder != 0) as u128)
+            }
+        }
+    }
+
+    /// IEEE 754-2019 minimumNumber, a NaN operand is ignored and -0 is less than +0
+    pub fn min(&mut self, a: u64, b: u64) -> u64 {
+        self.min_max(a, b, Ordering::Less)
+    }
+
+    /// IEEE 754-2019 maximumNumber, a NaN operand is ignored and +0 is greater than -0
+    pub fn max(&mut self, a: u64, b: u64) -> u64 {
+        self.min_max(a, b, Ordering::Greater)
+    }
+
+    /// IEEE 754-2019 minimum, a NaN operand gives the canonical NaN
+    pub fn minimum(&mut self, a: u64, b: u64) -> u64 {
+        self.min_max_propagating(a, b, Ordering::Less)
+    }
+
+    /// IEEE 754-2019 maximum, a NaN operand gives the canonical NaN
+    pub fn maximum(&mut self, a: u64, b: u64) -> u64 {
+        self.min_max_propagating(a, b, Ordering::Greater)
+    }
+
+    /// Quiet comparison, only signaling NaNs are invalid
+    pub fn eq(&mut self, a: u64, b: u64) -> bool {
+        self.compare(a, b, false) == Some(Ordering::Equal)
+    }
+
+    /// Quiet comparison, only signaling NaNs are invalid
+    pub fn lt_quiet(&mut self, a: u64, b: u64) -> bool {
+        self.compare(a, b, false) == Some(Ordering::Less)
+    }
+
+    /// Quiet comparison, only signaling NaNs are invalid
+    pub fn le_quiet(&mut self, a: u64, b: u64) -> bool {
+        matches!(
+            self.compare(a, b, false),
+            Some(Ordering::Less | Ordering::Equal)
+        )
+    }
+
+    /// Signaling comparison, any NaN is invalid
+    pub fn lt(&mut self, a: u64, b: u64) -> bool {
+        self.compare(a, b, true) == Some(Ordering::Less)
+    }
+
+    /// Signaling comparison, any NaN is invalid
+    pub fn le(&mut self, a: u64, b: u64) -> bool {
+        matches!(
+            self.compare(a, b, true),
+            Some(Ordering::Less | Ordering::Equal)
+        )
+    }
+
+    /// One-hot class of a value as reported by the fclass instructions
+    pub fn classify(&self, a: u64) -> u64 {
+        let unpacked = self.format.unpack(a);
+        let normal = a & (self.format.max_exponent_field() << self.format.fraction_bits) != 0;
+        let class = match (unpacked.value, unpacked.sign) {
+            (Value::Infinity, true) => 0,
+            (Value::Finite { .. }, true) if normal => 1,
+            (Value::Finite { .. }, true) => 2,
+            (Value::Zero, true) => 3,
+            (Value::Zero, false) => 4,
+            (Value::Finite { .. }, false) if !normal => 5,
+            (Value::Finite { .. }, false) => 6,
+            (Value::Infinity, false) => 7,
+            (Value::NaN { signaling: true }, _) => 8,
+            (Value::NaN { signaling: false }, _) => 9,
+        };
+        1 << class
+    }
+
+    /// Rounds a value to an integer of `bits` bits, out of range values and
+    /// NaNs are invalid and saturate, NaNs to the largest integer. The result
+    /// is sign-extended from `bits`.
+    pub fn round_to_integer(&mut self, a: u64, signed: bool, bits: u32) -> u64 {
+        let a = self.format.unpack(a);
+        let max = match signed {
+            true => (1_u128 << (bits - 1)) - 1,
+            false => (1_u128 << bits) - 1,
+        };
+        //Magnitude of the most negative integer
+        let min = match signed {
+            true => 1_u128 << (bits - 1),
+            false => 0,
+        };
+        let (magnitude, inexact) = match a.value {
+            Value::NaN { .. } => (u128::MAX, false),
+            Value::Infinity => (u128::MAX, false),
+            Value::Zero => (0, false),
+            Value::Finite {
+                exponent,
+                significand,
+            } => match exponent {
+                0..=64 => (significand << exponent, false),
+                65.. => (u128::MAX, false),
+                _ => self.shift_round(a.sign, significand, -exponent),
+            },
+        };
+        let negative = a.sign && !a.is_nan();
+        let result = match negative {
+            true if magnitude <= min => (magnitude as u64).wrapping_neg(),
+            false if magnitude <= max => magnitude as u64,
+            _ => {
+                self.flags |= ExceptionFlags::NV;
+                let saturated = match negative {
+                    true => (min as u64).wrapping_neg(),
+                    false => max as u64,
+                };
+                return sign_extend(saturated, bits);
+            }
+        };
+        if inexact {
+            self.flags |= ExceptionFlags::NX;
+        }
+        sign_extend(result, bits)
+    }
+
+    /// Rounds a value to an integral value of the same format, raising inexact
+    /// only when `exact` is set. Zeros keep the sign of the value.
+    pub fn round_to_integral(&mut self, a: u64, exact: bool) -> u64 {
+        let unpacked = self.format.unpack(a);
+        match unpacked.value {
+            Value::NaN { .. } => self.propagate_nan(&[unpacked]),
+            Value::Finite {
+                exponent,
+                significand,
+            } if exponent < 0 => {
+                let (integer, inexact) = self.shift_round(unpacked.sign, significand, -exponent);
+                if inexact && exact {
+                    self.flags |= ExceptionFlags::NX;
+                }
+                match integer {
+                    0 => self.format.zero(unpacked.sign),
+                    _ => self.round(unpacked.sign, 0, integer),
+                }
+            }
+            _ => a,
+        }
+    }
+
+    /// Truncates a value to an integer and returns its low 32 bits sign-extended.
+    /// NaNs and infinities give 0, they and the values out of the range of a
+    /// signed word are invalid.
+    pub fn modular_to_word(&mut self, a: u64) -> u64 {
+        let a = self.format.unpack(a);
+        let (magnitude, inexact, out_of_range) = match a.value {
+            Value::NaN { .. } | Value::Infinity => (0, false, true),
+            Value::Zero => (0, false, false),
+            Value::Finite {
+                exponent,
+                significand,
+            } => match exponent {
+                32.. => (0, false, true),
+                0..=31 => (significand << exponent, false, false),
+                -127..=-1 => (
+                    significand >> -exponent,
+                    significand & ((1 << -exponent) - 1) != 0,
+                    false,
+                ),
+                _ => (0, true, false),
+            },
+        };
+        let out_of_range = out_of_range || magnitude > (1 << 31) - 1 + a.sign as u128;
+        if out_of_range {
+            self.flags |= ExceptionFlags::NV;
+        } else if inexact {
+            self.flags |= ExceptionFlags::NX;
+        }
+        let integer = match a.sign {
+            true => (magnitude as u64).wrapping_neg(),
+            false => magnitude as u64,
+        };
+        sign_extend(integer, 32)
+    }
+
+    /// Rounds an integer given as its sign and magnitude
+    pub fn round_from_integer(&mut self, negative: bool, magnitude: u64) -> u64 {
+        match magnitude {
+            0 => self.format.zero(false),
+            _ => self.round(negative, 0, magnitude as u128),
+        }
+    }
+
+    /// Converts a value of another format, NaNs become the canonical NaN
+    pub fn convert(&mut self, a: u64, from: FloatFormat) -> u64 {
+        let a = from.unpack(a);
+        match a.value {
+            Value::NaN { .. } => self.propagate_nan(&[a]),
+            _ => self.pack(a),
+        }
+    }
+
+    /// Operands of an addition, including the product of a fused multiply-add
+    fn sum(&mut self, a: Unpacked, b: Unpacked) -> u64 {
+        if a.is_nan() || b.is_nan() {
+            return self.propagate_nan(&[a, b]);
+        }
+        match (a.value, b.value) {
+            (Value::Infinity, Value::Infinity) if a.sign != b.sign => self.invalid(),
+            (Value::Infinity, _) => self.format.infinity(a.sign),
+            (_, Value::Infinity) => self.format.infinity(b.sign),
+            (Value::Zero, Value::Zero) => self.format.zero(match a.sign == b.sign {
+                true => a.sign,
+                false => self.rounding == RoundingMode::Down,
+            }),
+            (Value::Zero, _) => self.pack(b),
+            (_, Value::Zero) => self.pack(a),
+            (
+                Value::Finite {
+                    exponent: a_exponent,
+                    significand: a_significand,
+                },
+                Value::Finite {
+                    exponent: b_exponent,
+                    significand: b_significand,
+                },
+            ) => {
+                let a_normalized = normalize(a_significand, a_exponent, SUM_ALIGNMENT);
+                let b_normalized = normalize(b_significand, b_exponent, SUM_ALIGNMENT);
+                //The operand with the smaller exponent is aligned to the other
+                //one, the bits shifted out are kept as a sticky bit
+                let ((large_sign, (large, exponent)), (small_sign, (small, small_exponent))) =
+                    match a_normalized.1 >= b_normalized.1 {
+                        true => ((a.sign, a_normalized), (b.sign, b_normalized)),
+                        false => ((b.sign, b_normalized), (a.sign, a_normalized)),
+                    };
+                let small = shift_right_sticky(small, (exponent - small_exponent) as u32);
+                let (sign, significand) = match (large_sign == small_sign, large >= small) {
+                    (true, _) => (large_sign, large + small),
+                    (false, true) => (large_sign, large - small),
+                    (false, false) => (small_sign, small - large),
+                };
+                match significand {
+                    0 => self.format.zero(self.rounding == RoundingMode::Down),
+                    _ => self.round(sign, exponent, significand),
+                }
+            }
+            _ => unreachable!("NaNs are handled first"),
+        }
+    }
+
+    /// Exact product, None if it is invalid
+    fn product(&mut self, a: Unpacked, b: Unpacked) -> Option<Unpacked> {
+        let sign = a.sign ^ b.sign;
+        let value = match (a.value, b.value) {
+            (Value::Infinity, Value::Zero) | (Value::Zero, Value::Infinity) => return None,
+            (Value::NaN { .. }, _) | (_, Value::NaN { .. }) => {
+                if a.is_signaling() || b.is_signaling() {
+                    self.flags |= ExceptionFlags::NV;
+                }
+                Value::NaN { signaling: false }
+            }
+            (Value::Infinity, _) | (_, Value::Infinity) => Value::Infinity,
+            (Value::Zero, _) | (_, Value::Zero) => Value::Zero,
+            (
+                Value::Finite {
+                    exponent: a_exponent,
+                    significand: a_significand,
+                },
+                Value::Finite {
+                    exponent: b_exponent,
+                    significand: b_significand,
+                },
+            ) => Value::Finite {
+                exponent: a_exponent + b_exponent,
+                significand: a_significand * b_significand,
+            },
+        };
+        Some(Unpacked { sign, value })
+    }
+
+    fn min_max(&mut self, a: u64, b: u64, pick: Ordering) -> u64 {
+        let (a_unpacked, b_unpacked) = (self.format.unpack(a), self.format.unpack(b));
+        if a_unpacked.is_signaling() || b_unpacked.is_signaling() {
+            self.flags |= ExceptionFlags::NV;
+        }
+        match (a_unpacked.is_nan(), b_unpacked.is_nan()) {
+            (true, true) => self.format.canonical_nan(),
+            (true, false) => b,
+            (false, true) => a,
+            (false, false) => match self.total_order(a).cmp(&self.total_order(b)) == pick {
+                true => a,
+                false => b,
+            },
+        }
+    }
+
+    fn min_max_propagating(&mut self, a: u64, b: u64, pick: Ordering) -> u64 {
+        let (a_unpacked, b_unpacked) = (self.format.unpack(a), self.format.unpack(b));
+        match a_unpacked.is_nan() || b_unpacked.is_nan() {
+            true => self.propagate_nan(&[a_unpacked, b_unpacked]),
+            false => self.min_max(a, b, pick),
+        }
+    }
+
+    fn compare(&mut self, a: u64, b: u64, signaling: bool) -> Option<Ordering> {
+        let (a_unpacked, b_unpacked) = (self.format.unpack(a), self.format.unpack(b));
+        if a_unpacked.is_nan() || b_unpacked.is_nan() {
+            if signaling || a_unpacked.is_signaling() || b_unpacked.is_signaling() {
+                self.flags |= ExceptionFlags::NV;
+            }
+            return None;
+        }
+        match (a_unpacked.value, b_unpacked.value) {
+            (Value::Zero, Value::Zero) => Some(Ordering::Equal),
+            _ => Some(self.total_order(a).cmp(&self.total_order(b))),
+        }
+    }
+
+    /// Key ordering the non-NaN values, with -0 below +0
+    fn total_order(&self, a: u64) -> i128 {
+        let magnitude = (a & !self.format.sign() & self.format.mask()) as i128;
+        match a & self.format.sign() != 0 {
+            true => -magnitude - 1,
+            false => magnitude,
+        }
+    }
+
+    fn invalid(&mut self) -> u64 {
+        self.flags |= ExceptionFlags::NV;
+        self.format.canonical_nan()
+    }
+
+    /// NaN operands produce the canonical NaN, signaling ones are invalid
+    fn propagate_nan(&mut self, operands: &[Unpacked]) -> u64 {
+        if operands.iter().any(Unpacked::is_signaling) {
+            self.flags |= ExceptionFlags::NV;
+        }
+        self.format.canonical_nan()
+    }
+
+    /// Packs a value that is not a NaN, rounding it if needed
+    fn pack(&mut self, value: Unpacked) -> u64 {
+        match value.value {
+            Value::NaN { .. } => self.format.canonical_nan(),
+            Value::Infinity => self.format.infinity(value.sign),
+            Value::Zero => self.format.zero(value.sign),
+            Value::Finite {
+                exponent,
+                significand,
+            } => self.round(value.sign, exponent, significand),
+        }
+    }
+
+    /// Rounds significand * 2^exponent to the format. An inexact significand
+    /// must hold at least two bits below the rounding position, its lowest bit
+    /// being set if any lower bit was discarded.
+    fn round(&mut self, sign: bool, exponent: i32, significand: u128) -> u64 {
+        let format = self.format;
+        let fraction_bits = format.fraction_bits as i32;
+        let min_exponent = format.min_exponent();
+        //Exponent of the leading bit, and of the last bit kept by the format
+        let magnitude = exponent + 127 - significand.leading_zeros() as i32;
+        let mut last_bit = magnitude.max(min_exponent) - fraction_bits;
+        let (mut rounded, inexact) = self.shift_round(sign, significand, last_bit - exponent);
+        if rounded >> (fraction_bits + 1) != 0 {
+            rounded >>= 1;
+            last_bit += 1;
+        }
+        let exponent_field = match rounded >> fraction_bits {
+            0 => 0,
+            _ => (last_bit + fraction_bits + format.bias()) as u64,
+        };
+        if exponent_field >= format.max_exponent_field() {
+            self.flags |= ExceptionFlags::OF | ExceptionFlags::NX;
+            return match (self.rounding, sign) {
+                (RoundingMode::TowardZero, _)
+                | (RoundingMode::Down, false)
+                | (RoundingMode::Up, true) => format.max_finite(sign),
+                _ => format.infinity(sign),
+            };
+        }
+        if inexact {
+            self.flags |= ExceptionFlags::NX;
+            //Tininess is detected after rounding, as if the exponent range was
+            //unbounded
+            let tiny = magnitude < min_exponent - 1
+                || (magnitude == min_exponent - 1 && {
+                    let unbounded_last_bit = magnitude - fraction_bits;
+                    let (unbounded, _) =
+                        self.shift_round(sign, significand, unbounded_last_bit - exponent);
+                    unbounded >> (fraction_bits + 1) == 0
+                });
+            if tiny {
+                self.flags |= ExceptionFlags::UF;
+            }
+        }
+        format.zero(sign)
+            | exponent_field << format.fraction_bits
+            | rounded as u64 & format.fraction_mask()
+    }
+
+    /// Drops the `shift` low bits of a significand, rounding the result with
+    /// the rounding mode. Returns whether a set bit was dropped.
+    fn shift_round(&self, sign: bool, significand: u128, shift: i32) -> (u128, bool) {
+        if shift <= 0 {
+            return (significand << -shift, false);
+        }
+        let (kept, dropped) = match shift {
+            1..=127 => (
+                significand >> shift,
+                (significand & ((1 << shift) - 1)).cmp(&(1 << (shift - 1))),
+            ),
+            128 => (0, significand.cmp(&(1 << 127))),
+            _ => (0, Ordering::Less),
+        };
+        let inexact = match shift {
+            1..=127 => significand & ((1 << shift) - 1) != 0,
+            _ => significand != 0,
+        };
+        let increment = match self.rounding {
+            RoundingMode::NearestEven => {
+                dropped == Ordering::Greater || (dropped == Ordering::Equal && kept & 1 == 1)
+            }
+            RoundingMode::NearestMaxMagnitude => dropped != Ordering::Less,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => inexact && sign,
+            RoundingMode::Up => inexact && !sign,
+        };
+        (kept + increment as u128, inexact)
+    }
+}
+
+/// Shifts a non-zero significand so its leading bit is at `position`
+fn normalize(significand: u128, exponent: i32, position: u32) -> (u128, i32) {
+    let shift = position as i32 - (127 - significand.leading_zeros() as i32);
+    match shift >= 0 {
+        true => (significand << shift, exponent - shift),
+        false => (
+            shift_right_sticky(significand, -shift as u32),
+            exponent - shift,
+        ),
+    }
+}
+
+/// Shifts right, setting the lowest bit if a set bit was shifted out
+fn shift_right_sticky(value: u128, shift: u32) -> u128 {
+    match shift {
+        0 => value,
+        1..=127 => value >> shift | (value & ((1 << shift) - 1) != 0) as u128,
+        _ => (value != 0) as u128,
+    }
+}
+
+/// Square root rounded down and the remainder
+fn integer_sqrt(value: u128) -> (u128, u128) {
+    let mut remainder = value;
+    let mut root = 0;
+    let mut bit = 1 << ((127 - value.leading_zeros()) & !1);
+    while bit != 0 {
+        if remainder >= root + bit {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, remainder)
+}
+
+fn sign_extend(value: u64, bits: u32) -> u64 {
+    ((value << (64 - bits)) as i64 >> (64 - bits)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Operands covering every class of single precision value
+    fn operands() -> impl Iterator<Item = u32> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..ITERATIONS).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            match state % 8 {
+                //Values close to each other, to exercise cancellation
+                0 => 0x3f80_0000 ^ (state >> 40) as u32 & 0xff,
+                1 => (state >> 32) as u32 & 0x807f_ffff,
+                2 => (state >> 32) as u32 | 0x7f80_0000,
+                _ => (state >> 32) as u32,
+            }
+        })
+    }
+
+    const ITERATIONS: usize = 2_000_000;
+
+    fn same(result: u64, expected: f32) -> bool {
+        match expected.is_nan() {
+            true => result == FloatFormat::SINGLE.canonical_nan(),
+            false => result == expected.to_bits() as u64,
+        }
+    }
+
+    #[test]
+    fn single_precision_matches_host() {
+        let mut previous = 0x4049_0fdb;
+        for a in operands() {
+            let b = previous;
+            previous = a;
+            let c = a.rotate_left(7);
+            let (x, y, z) = (f32::from_bits(a), f32::from_bits(b), f32::from_bits(c));
+            let mut arithmetic = SoftFloat::new(FloatFormat::SINGLE, RoundingMode::NearestEven);
+            let (a, b, c) = (a as u64, b as u64, c as u64);
+            assert!(same(arithmetic.add(a, b), x + y), "{a:#x} + {b:#x}");
+            assert!(same(arithmetic.sub(a, b), x - y), "{a:#x} - {b:#x}");
+            assert!(same(arithmetic.mul(a, b), x * y), "{a:#x} * {b:#x}");
+            assert!(same(arithmetic.div(a, b), x / y), "{a:#x} / {b:#x}");
+            assert!(same(arithmetic.sqrt(a), x.sqrt()), "sqrt {a:#x}");
+            assert!(
+                same(arithmetic.fused_multiply_add(a, b, c), x.mul_add(y, z)),
+                "{a:#x} * {b:#x} + {c:#x}"
+            );
+            assert_eq!(arithmetic.lt(a, b), x < y, "{a:#x} < {b:#x}");
+            assert_eq!(arithmetic.le(a, b), x <= y, "{a:#x} <= {b:#x}");
+            assert_eq!(arithmetic.eq(a, b), x == y, "{a:#x} == {b:#x}");
+            assert!(same(
+                arithmetic.round_from_integer((a as i32) < 0, (a as i32).unsigned_abs() as u64),
+                a as i32 as f32
+            ));
+            let mut truncating = SoftFloat::new(FloatFormat::SINGLE, RoundingMode::TowardZero);
+            let x = match x.is_nan() {
+                true => f32::INFINITY,
+                false => x,
+            };
+            assert_eq!(
+                truncating.round_to_integer(a, true, 32),
+                x as i32 as i64 as u64,
+                "{a:#x} as i32"
+            );
+            assert_eq!(
+                truncating.round_to_integer(a, true, 64),
+                x as i64 as u64,
+                "{a:#x} as i64"
+            );
+            assert_eq!(
+                truncating.round_to_integer(a, false, 64),
+                x as u64,
+                "{a:#x} as u64"
+            );
+            let mut narrowing = SoftFloat::new(FloatFormat::SINGLE, RoundingMode::NearestEven);
+            let x = f32::from_bits(a as u32);
+            let wide = (x as f64 * 3.0).to_bits();
+            assert!(
+                same(
+                    narrowing.convert(wide, FloatFormat::DOUBLE),
+                    (x as f64 * 3.0) as f32
+                ),
+                "{wide:#x} as f32"
+            );
+        }
+    }
+
+    #[test]
+    fn double_precision_matches_host() {
+        let mut previous = 0x4009_21fb_5444_2d18;
+        for operands in operands().collect::<Vec<_>>().chunks_exact(2) {
+            //Spread the single precision classes over the double precision range
+            let a = (operands[0] as u64) << 32 | operands[1] as u64;
+            let a = match operands[1] % 4 {
+                0 => a & 0x800f_ffff_ffff_ffff | 0x3ff0_0000_0000_0000,
+                _ => a,
+            };
+            let b = previous;
+            previous = a;
+            let c = a.rotate_left(11);
+            let (x, y, z) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+            let same = |result: u64, expected: f64| match expected.is_nan() {
+                true => result == FloatFormat::DOUBLE.canonical_nan(),
+                false => result == expected.to_bits(),
+            };
+            let mut arithmetic = SoftFloat::new(FloatFormat::DOUBLE, RoundingMode::NearestEven);
+            assert!(same(arithmetic.add(a, b), x + y), "{a:#x} + {b:#x}");
+            assert!(same(arithmetic.mul(a, b), x * y), "{a:#x} * {b:#x}");
+            assert!(same(arithmetic.div(a, b), x / y), "{a:#x} / {b:#x}");
+            assert!(same(arithmetic.sqrt(a), x.sqrt()), "sqrt {a:#x}");
+            assert!(
+                same(arithmetic.fused_multiply_add(a, b, c), x.mul_add(y, z)),
+                "{a:#x} * {b:#x} + {c:#x}"
+            );
+            let mut truncating = SoftFloat::new(FloatFormat::DOUBLE, RoundingMode::TowardZero);
+            let x = match x.is_nan() {
+                true => f64::INFINITY,
+                false => x,
+            };
+            assert_eq!(
+                truncating.round_to_integer(a, true, 64),
+                x as i64 as u64,
+                "{a:#x} as i64"
+            );
+        }
+    }
+
+    /// Rounds an exact value to single precision with a rounding mode
+    fn directed(exact: f64, rounding: RoundingMode) -> f32 {
+        let nearest = exact as f32;
+        if nearest as f64 == exact || exact.is_nan() {
+            return nearest;
+        }
+        let (below, above) = match (nearest as f64) < exact {
+            true => (nearest, nearest.next_up()),
+            false => (nearest.next_down(), nearest),
+        };
+        let tie = exact - below as f64 == above as f64 - exact;
+        match rounding {
+            RoundingMode::NearestEven => nearest,
+            RoundingMode::NearestMaxMagnitude if tie && exact > 0.0 => above,
+            RoundingMode::NearestMaxMagnitude if tie => below,
+            RoundingMode::NearestMaxMagnitude => nearest,
+            RoundingMode::TowardZero if exact > 0.0 => below,
+            RoundingMode::TowardZero => above,
+            RoundingMode::Down => below,
+            RoundingMode::Up => above,
+        }
+    }
+
+    #[test]
+    fn directed_rounding_of_exact_results() {
+        let modes = [
+            RoundingMode::NearestEven,
+            RoundingMode::TowardZero,
+            RoundingMode::Down,
+            RoundingMode::Up,
+            RoundingMode::NearestMaxMagnitude,
+        ];
+        let mut previous = 0x4049_0fdb;
+        for (a, rounding) in operands().zip(modes.into_iter().cycle()) {
+            let b = previous;
+            previous = a;
+            let (x, y) = (f32::from_bits(a) as f64, f32::from_bits(b) as f64);
+            let mut arithmetic = SoftFloat::new(FloatFormat::SINGLE, rounding);
+            let (a, b) = (a as u64, b as u64);
+            //Products of single precision values are exact in double precision
+            let product = x * y;
+            if product.is_finite() {
+                assert!(
+                    same(arithmetic.mul(a, b), directed(product, rounding)),
+                    "{a:#x} * {b:#x} {rounding:?}"
+                );
+                assert!(
+                    same(
+                        arithmetic.convert(product.to_bits(), FloatFormat::DOUBLE),
+                        directed(product, rounding)
+                    ),
+                    "{product} {rounding:?}"
+                );
+            }
+            let rounded = match rounding {
+                RoundingMode::NearestEven => x.round_ties_even(),
+                RoundingMode::TowardZero => x.trunc(),
+                RoundingMode::Down => x.floor(),
+                RoundingMode::Up => x.ceil(),
+                RoundingMode::NearestMaxMagnitude => x.round(),
+            };
+            let rounded = match x.is_nan() {
+                true => f64::INFINITY,
+                false => rounded,
+            };
+            assert_eq!(
+                arithmetic.round_to_integer(a, true, 64),
+                rounded as i64 as u64,
+                "{a:#x} as i64 {rounding:?}"
+            );
+            assert_eq!(
+                arithmetic.round_to_integer(a, false, 32),
+                rounded as u32 as i32 as i64 as u64,
+                "{a:#x} as u32 {rounding:?}"
+            );
+            let integer = (a << 32 | b) as i64;
+            assert!(
+                same(
+                    arithmetic.round_from_integer(integer < 0, integer.unsigned_abs()),
+                    directed(integer as f64, rounding)
+                ) || integer.unsigned_abs() > 1 << 53,
+                "{integer} {rounding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn half_precision_matches_double_precision() {
+        let modes = [
+            RoundingMode::NearestEven,
+            RoundingMode::TowardZero,
+            RoundingMode::Down,
+            RoundingMode::Up,
+            RoundingMode::NearestMaxMagnitude,
+        ];
+        let mut widening = SoftFloat::new(FloatFormat::DOUBLE, RoundingMode::NearestEven);
+        for (operands, rounding) in operands().zip(modes.into_iter().cycle()) {
+            let (a, b) = ((operands & 0xffff) as u64, (operands >> 16) as u64);
+            let x = f64::from_bits(widening.convert(a, FloatFormat::HALF));
+            let y = f64::from_bits(widening.convert(b, FloatFormat::HALF));
+            let mut arithmetic = SoftFloat::new(FloatFormat::HALF, rounding);
+            let expected = |value: f64| {
+                SoftFloat::new(FloatFormat::HALF, rounding)
+                    .convert(value.to_bits(), FloatFormat::DOUBLE)
+            };
+            //Sums and products of half precision values are exact in double precision
+            let sum = match x + y == 0.0 && x.is_sign_negative() != y.is_sign_negative() {
+                //The host computes with round to nearest, giving +0
+                true if rounding == RoundingMode::Down => -0.0,
+                _ => x + y,
+            };
+            assert_eq!(arithmetic.add(a, b), expected(sum), "{a:#x} + {b:#x}");
+            assert_eq!(arithmetic.mul(a, b), expected(x * y), "{a:#x} * {b:#x}");
+            //Rounding twice to nearest is innocuous for quotients and roots
+            if rounding == RoundingMode::NearestEven {
+                assert_eq!(arithmetic.div(a, b), expected(x / y), "{a:#x} / {b:#x}");
+                assert_eq!(arithmetic.sqrt(a), expected(x.sqrt()), "sqrt {a:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn tininess_is_detected_after_rounding() {
+        let mut arithmetic = SoftFloat::new(FloatFormat::SINGLE, RoundingMode::NearestEven);
+        //(1 - 2^-25) * 2^-126 rounds to the smallest normal with an unbounded exponent
+        let below_normal = (1.0 - 2_f64.powi(-25)) * 2_f64.powi(-126);
+        assert_eq!(
+            arithmetic.convert(below_normal.to_bits(), FloatFormat::DOUBLE),
+            0x0080_0000
+        );
+        assert_eq!(arithmetic.flags, ExceptionFlags::NX);
+        //(1 - 2^-24) * 2^-126 is tiny, only the bounded rounding reaches 2^-126
+        arithmetic.flags = 0;
+        assert_eq!(arithmetic.mul(0x3f7f_ffff, 0x0080_0000), 0x0080_0000);
+        assert_eq!(arithmetic.flags, ExceptionFlags::NX | ExceptionFlags::UF);
+        //Exact subnormal results don't underflow
+        arithmetic.flags = 0;
+        assert_eq!(arithmetic.mul(0x3f00_0000, 0x0080_0000), 0x0040_0000);
+        assert_eq!(arithmetic.flags, 0);
+    }
+
+    #[test]
+    fn exception_flags() {
+        let half = |rounding| SoftFloat::new(FloatFormat::HALF, rounding);
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        //65504 + 16 rounds to infinity, towards zero it saturates
+        assert_eq!(arithmetic.add(0x7bff, 0x4c00), 0x7c00);
+        assert_eq!(arithmetic.flags, ExceptionFlags::OF | ExceptionFlags::NX);
+        assert_eq!(half(RoundingMode::TowardZero).add(0x7bff, 0x4c00), 0x7bff);
+        assert_eq!(half(RoundingMode::Down).add(0xfbff, 0xcc00), 0xfc00);
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        assert_eq!(arithmetic.div(0x3c00, 0x8000), 0xfc00);
+        assert_eq!(arithmetic.flags, ExceptionFlags::DZ);
+        //Signaling NaNs and infinity times zero are invalid, even in a fused
+        //multiply-add with a quiet NaN addend
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        assert_eq!(
+            arithmetic.fused_multiply_add(0x7c00, 0x0000, 0x7e00),
+            0x7e00
+        );
+        assert_eq!(arithmetic.flags, ExceptionFlags::NV);
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        assert_eq!(arithmetic.add(0x7e00, 0x3c00), 0x7e00);
+        assert!(!arithmetic.eq(0x7e00, 0x7e00));
+        assert_eq!(arithmetic.flags, 0);
+        assert!(!arithmetic.le(0x7e00, 0x3c00));
+        assert_eq!(arithmetic.flags, ExceptionFlags::NV);
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        assert_eq!(arithmetic.min(0x7d00, 0x3c00), 0x3c00);
+        assert_eq!(arithmetic.flags, ExceptionFlags::NV);
+        assert_eq!(arithmetic.max(0x8000, 0x0000), 0x0000);
+        assert_eq!(arithmetic.min(0x8000, 0x0000), 0x8000);
+        //Exact cancellation gives -0 only when rounding down
+        assert_eq!(half(RoundingMode::NearestEven).sub(0x3c00, 0x3c00), 0x0000);
+        assert_eq!(half(RoundingMode::Down).sub(0x3c00, 0x3c00), 0x8000);
+        //Out of range conversions saturate
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        assert_eq!(arithmetic.round_to_integer(0xbc00, false, 32), 0);
+        assert_eq!(arithmetic.flags, ExceptionFlags::NV);
+        let mut arithmetic = half(RoundingMode::NearestEven);
+        assert_eq!(arithmetic.round_to_integer(0xb400, false, 32), 0);
+        assert_eq!(arithmetic.flags, ExceptionFlags::NX);
+        assert_eq!(arithmetic.round_to_integer(0x7e00, false, 32), u64::MAX);
+        assert_eq!(
+            arithmetic.round_to_integer(0xfc00, true, 32),
+            i32::MIN as i64 as u64
+        );
+    }
+
+    #[test]
+    fn classify() {
+        let arithmetic = SoftFloat::new(FloatFormat::HALF, RoundingMode::NearestEven);
+        let classes = [
+            0xfc00, 0xbc00, 0x8001, 0x8000, 0x0000, 0x0001, 0x3c00, 0x7c00, 0x7d00, 0x7e00,
+        ];
+        for (class, value) in classes.into_iter().enumerate() {
+            assert_eq!(arithmetic.classify(value), 1 << class, "{value:#x}");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use crate::{consts::DRAM_BASE_ADDR, error::AppResult, machine::Machine, memory::MemoryOpSize};
+
+use super::{config::CpuConfig, side_effects::OperationSideEffect, Cpu};
+
+/// Machine with the default memory layout, no boot image and the harts of
+/// the configuration
+pub fn machine(config: &CpuConfig) -> Arc<Machine> {
+    Arc::new(Machine::new(config, &[], config.harts).unwrap())
+}
+
+/// First hart of a machine with the default memory layout and no boot image
+pub fn cpu_with(config: CpuConfig) -> Cpu {
+    Cpu::new(machine(&config), 0, config)
+}
+
+/// First hart of a machine with a program at the start of the RAM, where the
+/// hart starts
+pub fn cpu_with_program(config: CpuConfig, program: &[u32]) -> Cpu {
+    let mut cpu = cpu_with(config);
+    for (index, instruction) in program.iter().enumerate() {
+        cpu.system_bus
+            .store(
+                DRAM_BASE_ADDR + 4 * index as u64,
+                MemoryOpSize::B32,
+                *instruction as u64,
+            )
+            .unwrap();
+    }
+    cpu.program_counter = DRAM_BASE_ADDR;
+    cpu
+}
+
+/// Hart with the default configuration
+pub fn cpu() -> Cpu {
+    cpu_with(CpuConfig::default())
+}
+
+/// Runs the handler of a raw instruction, errors are returned instead of
+/// trapping and the program counter is left unchanged
+pub fn execute(cpu: &mut Cpu, raw_instruction: u32) -> AppResult<OperationSideEffect> {
+    let instruction = cpu.decode(raw_instruction);
+    instruction.handler()(cpu, instruction.decoder)
+}
@@ -0,0 +1,219 @@
+use crate::{
+    consts::PAGE_SIZE,
+    memory::{read_host, write_host},
+};
+
+use super::{pmp::MemoryAccess, Cpu};
+
+pub const TLB_ENTRIES: usize = 256;
+pub const PAGE_SHIFT: u32 = PAGE_SIZE.trailing_zeros();
+
+/// Virtual page mapped to its RAM page, `addend` is added to the
+/// virtual address to get the host address
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TlbEntry {
+    pub page: u64,
+    pub addend: u64,
+}
+
+impl TlbEntry {
+    const INVALID: TlbEntry = TlbEntry {
+        page: u64::MAX,
+        addend: 0,
+    };
+}
+
+/// Direct mapped TLB of the pages of RAM the hart loads from and
+/// stores to, resolving a virtual address straight to a host address. Pages
+/// holding decoded instructions or granules waited on by WRS are never mapped
+/// for stores so their writes are still tracked by the system bus. The compiled blocks look it up inline.
+#[repr(C)]
+pub struct DataTlb {
+    pub load: [TlbEntry; TLB_ENTRIES],
+    pub store: [TlbEntry; TLB_ENTRIES],
+}
+
+impl DataTlb {
+    pub fn new() -> Self {
+        Self {
+            load: [TlbEntry::INVALID; TLB_ENTRIES],
+            store: [TlbEntry::INVALID; TLB_ENTRIES],
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.load = [TlbEntry::INVALID; TLB_ENTRIES];
+        self.store = [TlbEntry::INVALID; TLB_ENTRIES];
+    }
+
+    /// Drops the store mappings, on a page becoming a code page
+    pub fn flush_stores(&mut self) {
+        self.store = [TlbEntry::INVALID; TLB_ENTRIES];
+    }
+
+    /// Host address of an access of `size` bytes that doesn't leave a mapped page
+    #[inline(always)]
+    fn host_address(table: &[TlbEntry; TLB_ENTRIES], addr: u64, size: u64) -> Option<*mut u8> {
+        let page = addr >> PAGE_SHIFT;
+        let entry = table[page as usize % TLB_ENTRIES];
+        match entry.page == page && (addr & (PAGE_SIZE - 1)) + size <= PAGE_SIZE {
+            true => Some(addr.wrapping_add(entry.addend) as *mut u8),
+            false => None,
+        }
+    }
+}
+
+impl Cpu {
+    /// Loads `size` bytes from a mapped page, None on a miss or while triggers
+    /// are armed as they must see every access
+    #[inline(always)]
+    pub fn tlb_load(&self, addr: u64, size: u64) -> Option<u64> {
+        if self.triggers_armed() {
+            return None;
+        }
+        let host = DataTlb::host_address(&self.tlb.load, addr, size)?;
+        //The host address points to system memory owned by the machine
+        Some(unsafe { read_host(host, size) })
+    }
+
+    /// Stores the `size` lower bytes of the value to a mapped page, returns
+    /// false on a miss or while triggers are armed
+    #[inline(always)]
+    pub fn tlb_store(&mut self, addr: u64, size: u64, value: u64) -> bool {
+        if self.triggers_armed() {
+            return false;
+        }
+        let Some(host) = DataTlb::host_address(&self.tlb.store, addr, size) else {
+            return false;
+        };
+        unsafe { write_host(host, size, value) };
+        true
+    }
+
+    /// Maps the page of an access that was just performed, as long as the
+    /// whole page is RAM allowed by PMP and, for stores, its stores aren't
+    /// tracked
+    pub fn fill_tlb(&mut self, addr: u64, access: MemoryAccess) {
+        if self.triggers_armed() {
+            return;
+        }
+        let page_addr = addr & !(PAGE_SIZE - 1);
+        let Ok(physical) = self.translate_address(page_addr, 1, access) else {
+            return;
+        };
+        if self.check_pmp(physical, PAGE_SIZE, access).is_err()
+            || (access == MemoryAccess::Store && self.system_bus.tracks_stores(physical))
+        {
+            return;
+        }
+        let Some(host) = self.system_bus.host_page(physical) else {
+            return;
+        };
+        let page = addr >> PAGE_SHIFT;
+        let entry = TlbEntry {
+            page,
+            addend: (host as u64).wrapping_sub(page_addr),
+        };
+        let table = match access {
+            MemoryAccess::Store => &mut self.tlb.store,
+            _ => &mut self.tlb.load,
+        };
+        table[page as usize % TLB_ENTRIES] = entry;
+    }
+
+    /// Marks the page of an address as holding decoded instructions, its
+    /// stores go back through the system bus. The other harts drop their store
+    /// mappings before their next block.
+    pub fn mark_code_page(&mut self, addr: u64) {
+        if !self.system_bus.is_code_page(addr) {
+            self.system_bus.mark_code_page(addr);
+            self.tlb.flush_stores();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consts::{ACLINT_MTIMER_BASE_ADDR, DRAM_BASE_ADDR},
+        cpu::test_hart::{self, cpu},
+        memory::MemoryOpSize,
+    };
+
+    const DATA: u64 = DRAM_BASE_ADDR + 0x10_0000;
+
+    /// Whether a page is mapped for loads and for stores
+    fn mapped(cpu: &Cpu, addr: u64) -> (bool, bool) {
+        let page = addr >> PAGE_SHIFT;
+        let index = page as usize % TLB_ENTRIES;
+        (
+            cpu.tlb.load[index].page == page,
+            cpu.tlb.store[index].page == page,
+        )
+    }
+
+    #[test]
+    fn ram_accesses_go_through_the_host_page() {
+        let mut cpu = cpu();
+        assert_eq!(mapped(&cpu, DATA), (false, false));
+        cpu.store_data(DATA, MemoryOpSize::B64, 0x1122_3344_5566_7788)
+            .unwrap();
+        assert_eq!(cpu.load_data(DATA + 4, 4).unwrap(), 0x1122_3344);
+        assert_eq!(mapped(&cpu, DATA), (true, true));
+        //Both tables resolve to the RAM seen by the bus
+        assert!(cpu.tlb_store(DATA + 0x10, 2, 0xabcd));
+        assert_eq!(cpu.system_bus.load16(DATA + 0x10).unwrap(), 0xabcd);
+        cpu.system_bus
+            .store(DATA + 0x20, MemoryOpSize::B32, 0xdead_beef)
+            .unwrap();
+        assert_eq!(cpu.tlb_load(DATA + 0x21, 2), Some(0xadbe));
+        //Misaligned accesses are native as long as they stay in the page
+        assert_eq!(cpu.tlb_load(DATA + 0x1f, 8), Some(0x00de_adbe_ef00));
+        assert_eq!(cpu.tlb_load(DATA + PAGE_SIZE - 4, 8), None);
+        assert_eq!(cpu.tlb_load(DATA + PAGE_SIZE, 4), None);
+    }
+
+    #[test]
+    fn devices_are_never_mapped() {
+        let mut cpu = cpu();
+        //mtime
+        let mtime = ACLINT_MTIMER_BASE_ADDR + 0x7ff8;
+        cpu.load_data(mtime, 8).unwrap();
+        cpu.store_data(mtime, MemoryOpSize::B64, 0).unwrap();
+        assert_eq!(mapped(&cpu, mtime), (false, false));
+        assert_eq!(cpu.tlb_load(mtime, 8), None);
+    }
+
+    #[test]
+    fn code_pages_are_not_mapped_for_stores() {
+        let mut cpu = cpu();
+        cpu.store_data(DATA, MemoryOpSize::B32, 0).unwrap();
+        assert_eq!(mapped(&cpu, DATA), (false, true));
+        cpu.mark_code_page(DATA);
+        assert_eq!(mapped(&cpu, DATA), (false, false));
+        cpu.load_data(DATA, 4).unwrap();
+        assert_eq!(mapped(&cpu, DATA), (true, false));
+        //The store is seen by the bus, the page is no longer a code page
+        //until its instructions are decoded again
+        cpu.store_data(DATA, MemoryOpSize::B32, 0).unwrap();
+        assert!(cpu.system_bus.has_written_code_pages());
+        assert!(!cpu.system_bus.is_code_page(DATA));
+        assert_eq!(mapped(&cpu, DATA), (true, true));
+    }
+
+    #[test]
+    fn fences_and_traps_flush_the_tlb() {
+        let mut cpu = cpu();
+        cpu.load_data(DATA, 8).unwrap();
+        cpu.store_data(DATA, MemoryOpSize::B64, 0).unwrap();
+        //sfence.vma x0, x0
+        test_hart::execute(&mut cpu, 0x1200_0073).unwrap();
+        assert_eq!(mapped(&cpu, DATA), (false, false));
+        cpu.load_data(DATA, 8).unwrap();
+        //Illegal instruction
+        cpu.take_trap(2, 0, 0);
+        assert_eq!(mapped(&cpu, DATA), (false, false));
+    }
+}
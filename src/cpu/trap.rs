@@ -0,0 +1,317 @@
+use crate::error::{AppErrors, AppResult};
+
+use super::{
+    cs_registers::{
+        HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+        SupervisorLevelCSRegisters,
+    },
+    interrupts::InterruptFields,
+    pmp::MemoryAccess,
+    privilege::PrivilegeMode,
+    Cpu,
+};
+
+/// Synchronous exception codes as reported in the mcause/scause registers
+pub struct ExceptionCause;
+impl ExceptionCause {
+    pub const INSTRUCTION_ADDRESS_MISALIGNED: u64 = 0;
+    pub const INSTRUCTION_ACCESS_FAULT: u64 = 1;
+    pub const ILLEGAL_INSTRUCTION: u64 = 2;
+    pub const BREAKPOINT: u64 = 3;
+    pub const LOAD_ADDRESS_MISALIGNED: u64 = 4;
+    pub const LOAD_ACCESS_FAULT: u64 = 5;
+    pub const STORE_ADDRESS_MISALIGNED: u64 = 6;
+    pub const STORE_ACCESS_FAULT: u64 = 7;
+    pub const ENV_CALL_FROM_U: u64 = 8;
+    pub const ENV_CALL_FROM_S: u64 = 9;
+    pub const ENV_CALL_FROM_VS: u64 = 10;
+    pub const ENV_CALL_FROM_M: u64 = 11;
+    pub const INSTRUCTION_PAGE_FAULT: u64 = 12;
+    pub const LOAD_PAGE_FAULT: u64 = 13;
+    pub const STORE_PAGE_FAULT: u64 = 15;
+    pub const INSTRUCTION_GUEST_PAGE_FAULT: u64 = 20;
+    pub const LOAD_GUEST_PAGE_FAULT: u64 = 21;
+    pub const VIRTUAL_INSTRUCTION: u64 = 22;
+    pub const STORE_GUEST_PAGE_FAULT: u64 = 23;
+}
+
+/// Privilege mode handling a trap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TrapHandler {
+    Machine,
+    Supervisor,
+    /// VS-mode, for the traps of VS and VU modes delegated through
+    /// hedeleg/hideleg
+    VirtualSupervisor,
+}
+
+impl AppErrors {
+    /// Returns the exception code and trap value for the errors that are
+    /// architectural exceptions that have to be handled by the guest
+    pub fn as_exception(&self) -> Option<(u64, u64)> {
+        match self {
+            //Encodings of instructions that are not implemented are illegal
+            //for the guest, which can emulate them in the trap handler
+            AppErrors::IllegalInstruction { instruction }
+            | AppErrors::InstructionNotImplemented { instruction } => {
+                Some((ExceptionCause::ILLEGAL_INSTRUCTION, *instruction as u64))
+            }
+            AppErrors::VirtualInstruction { instruction } => {
+                Some((ExceptionCause::VIRTUAL_INSTRUCTION, *instruction as u64))
+            }
+            AppErrors::EnvironmentCall {
+                privilege,
+                virtualized,
+            } => Some((
+                match (privilege, virtualized) {
+                    (PrivilegeMode::User, _) => ExceptionCause::ENV_CALL_FROM_U,
+                    (PrivilegeMode::Supervisor, false) => ExceptionCause::ENV_CALL_FROM_S,
+                    (PrivilegeMode::Supervisor, true) => ExceptionCause::ENV_CALL_FROM_VS,
+                    (PrivilegeMode::Machine, _) => ExceptionCause::ENV_CALL_FROM_M,
+                },
+                0,
+            )),
+            AppErrors::Breakpoint { addr } => Some((ExceptionCause::BREAKPOINT, *addr)),
+            AppErrors::InstructionAddressMisaligned { addr } => {
+                Some((ExceptionCause::INSTRUCTION_ADDRESS_MISALIGNED, *addr))
+            }
+            AppErrors::LoadAddressMisaligned { addr } => {
+                Some((ExceptionCause::LOAD_ADDRESS_MISALIGNED, *addr))
+            }
+            AppErrors::StoreAddressMisaligned { addr } => {
+                Some((ExceptionCause::STORE_ADDRESS_MISALIGNED, *addr))
+            }
+            AppErrors::InstructionAccessFault { addr } => {
+                Some((ExceptionCause::INSTRUCTION_ACCESS_FAULT, *addr))
+            }
+            AppErrors::LoadAccessFault { addr } => Some((ExceptionCause::LOAD_ACCESS_FAULT, *addr)),
+            AppErrors::StoreAccessFault { addr } => {
+                Some((ExceptionCause::STORE_ACCESS_FAULT, *addr))
+            }
+            AppErrors::InstructionPageFault { addr } => {
+                Some((ExceptionCause::INSTRUCTION_PAGE_FAULT, *addr))
+            }
+            AppErrors::LoadPageFault { addr } => Some((ExceptionCause::LOAD_PAGE_FAULT, *addr)),
+            AppErrors::StorePageFault { addr } => Some((ExceptionCause::STORE_PAGE_FAULT, *addr)),
+            AppErrors::InstructionGuestPageFault { addr, .. } => {
+                Some((ExceptionCause::INSTRUCTION_GUEST_PAGE_FAULT, *addr))
+            }
+            AppErrors::LoadGuestPageFault { addr, .. } => {
+                Some((ExceptionCause::LOAD_GUEST_PAGE_FAULT, *addr))
+            }
+            AppErrors::StoreGuestPageFault { addr, .. } => {
+                Some((ExceptionCause::STORE_GUEST_PAGE_FAULT, *addr))
+            }
+            _ => None,
+        }
+    }
+
+    /// Guest physical address that caused a guest page fault, reported in
+    /// htval and mtval2, zero for the other exceptions
+    pub fn guest_physical_address(&self) -> u64 {
+        match self {
+            AppErrors::InstructionGuestPageFault { guest_physical, .. }
+            | AppErrors::LoadGuestPageFault { guest_physical, .. }
+            | AppErrors::StoreGuestPageFault { guest_physical, .. } => *guest_physical,
+            _ => 0,
+        }
+    }
+}
+
+impl Cpu {
+    /// Takes a trap if the error is an architectural exception, other errors
+    /// are returned as they can't be handled by the guest
+    pub fn trap_on_exception(&mut self, err: AppErrors) -> AppResult<()> {
+        match err.as_exception() {
+            Some((cause, trap_value)) => {
+                self.take_trap(cause, trap_value, err.guest_physical_address());
+                self.program_counter = self.effective_address(self.program_counter);
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    /// Transfers control to the trap handler of the privilege mode that handles
+    /// the exception, exceptions raised below M-mode go to HS-mode when
+    /// delegated through medeleg, and those raised in VS and VU modes go to
+    /// VS-mode when also delegated through hedeleg. `guest_physical` is the
+    /// faulting address of the guest page faults.
+    pub fn take_trap(&mut self, cause: u64, trap_value: u64, guest_physical: u64) {
+        self.enter_trap(cause, trap_value, guest_physical, false);
+    }
+
+    /// Same as take_trap for interrupts, which are delegated through mideleg
+    /// and hideleg
+    pub fn take_interrupt(&mut self, cause: u64) {
+        self.enter_trap(cause, 0, 0, true);
+    }
+
+    fn enter_trap(&mut self, code: u64, trap_value: u64, guest_physical: u64, interrupt: bool) {
+        self.match_trap_triggers(code, interrupt);
+        self.tlb.flush();
+        let guest_virtual_address = !interrupt && self.is_guest_virtual_trap_value(code);
+        self.hypervisor_access = None;
+        let (delegation, virtual_delegation) = match interrupt {
+            true => (
+                MachineLevelCSRegisters::MIDELEG,
+                HypervisorLevelCSRegisters::HIDELEG,
+            ),
+            false => (
+                MachineLevelCSRegisters::MEDELEG,
+                HypervisorLevelCSRegisters::HEDELEG,
+            ),
+        };
+        let delegated = |register: usize| (self.cs_registers[register] >> code) & 1 == 1;
+        let handler = match self.privilege_mode {
+            PrivilegeMode::Machine => TrapHandler::Machine,
+            _ if !delegated(delegation) => TrapHandler::Machine,
+            _ if self.virtualization && delegated(virtual_delegation) => {
+                TrapHandler::VirtualSupervisor
+            }
+            _ => TrapHandler::Supervisor,
+        };
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let handler_xlen = match handler {
+            TrapHandler::Machine => self.xlen_of(PrivilegeMode::Machine),
+            TrapHandler::Supervisor => self.xlen_of(PrivilegeMode::Supervisor),
+            TrapHandler::VirtualSupervisor => self.virtual_xlen_of(PrivilegeMode::Supervisor),
+        };
+        //VS-mode sees the VS-level interrupts as the supervisor ones
+        let code = match handler {
+            TrapHandler::VirtualSupervisor
+                if interrupt && (1 << code) & InterruptFields::VIRTUAL_SUPERVISOR != 0 =>
+            {
+                code - 1
+            }
+            _ => code,
+        };
+        //The interrupt flag is the most significant bit of the cause register
+        let cause = code | (interrupt as u64) << (handler_xlen.bits() - 1);
+
+        if handler == TrapHandler::VirtualSupervisor {
+            self.cs_registers[HypervisorLevelCSRegisters::VSEPC] = self.program_counter;
+            self.cs_registers[HypervisorLevelCSRegisters::VSCAUSE] = cause;
+            self.cs_registers[HypervisorLevelCSRegisters::VSTVAL] = trap_value;
+
+            let virtual_status = self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS];
+            let mut new_status =
+                virtual_status & !(MStatusFields::SPIE | MStatusFields::SIE | MStatusFields::SPP);
+            if virtual_status & MStatusFields::SIE != 0 {
+                new_status |= MStatusFields::SPIE;
+            }
+            if self.privilege_mode == PrivilegeMode::Supervisor {
+                new_status |= MStatusFields::SPP;
+            }
+            self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] = new_status;
+            self.program_counter = Self::trap_vector(
+                self.cs_registers[HypervisorLevelCSRegisters::VSTVEC],
+                code,
+                interrupt,
+            );
+            self.privilege_mode = PrivilegeMode::Supervisor;
+        } else if handler == TrapHandler::Supervisor {
+            self.cs_registers[SupervisorLevelCSRegisters::SEPC] = self.program_counter;
+            self.cs_registers[SupervisorLevelCSRegisters::SCAUSE] = cause;
+            self.cs_registers[SupervisorLevelCSRegisters::STVAL] = trap_value;
+
+            let mut new_status =
+                status & !(MStatusFields::SPIE | MStatusFields::SIE | MStatusFields::SPP);
+            if status & MStatusFields::SIE != 0 {
+                new_status |= MStatusFields::SPIE;
+            }
+            if self.privilege_mode == PrivilegeMode::Supervisor {
+                new_status |= MStatusFields::SPP;
+            }
+            self.cs_registers[MachineLevelCSRegisters::MSTATUS] = new_status;
+
+            self.cs_registers[HypervisorLevelCSRegisters::HTVAL] = guest_physical >> 2;
+            self.cs_registers[HypervisorLevelCSRegisters::HTINST] = 0;
+            let hstatus = &mut self.cs_registers[HypervisorLevelCSRegisters::HSTATUS];
+            *hstatus &= !(HStatusFields::GVA | HStatusFields::SPV);
+            if guest_virtual_address {
+                *hstatus |= HStatusFields::GVA;
+            }
+            //SPVP is only updated by the traps taken from VS and VU modes
+            if self.virtualization {
+                *hstatus &= !HStatusFields::SPVP;
+                *hstatus |= HStatusFields::SPV;
+                if self.privilege_mode == PrivilegeMode::Supervisor {
+                    *hstatus |= HStatusFields::SPVP;
+                }
+            }
+            self.set_virtualization(false);
+            self.program_counter = Self::trap_vector(
+                self.cs_registers[SupervisorLevelCSRegisters::STVEC],
+                code,
+                interrupt,
+            );
+            self.privilege_mode = PrivilegeMode::Supervisor;
+        } else {
+            self.cs_registers[MachineLevelCSRegisters::MEPC] = self.program_counter;
+            self.cs_registers[MachineLevelCSRegisters::MCAUSE] = cause;
+            self.cs_registers[MachineLevelCSRegisters::MTVAL] = trap_value;
+
+            let mut new_status =
+                status & !(MStatusFields::MPIE | MStatusFields::MIE | MStatusFields::MPP);
+            if status & MStatusFields::MIE != 0 {
+                new_status |= MStatusFields::MPIE;
+            }
+            new_status |= (self.privilege_mode as u64) << MStatusFields::MPP_SHIFT;
+            new_status &= !(MStatusFields::MPV | MStatusFields::GVA);
+            if self.virtualization {
+                new_status |= MStatusFields::MPV;
+            }
+            if guest_virtual_address {
+                new_status |= MStatusFields::GVA;
+            }
+            self.cs_registers[MachineLevelCSRegisters::MSTATUS] = new_status;
+            self.cs_registers[MachineLevelCSRegisters::MTVAL2] = guest_physical >> 2;
+            self.cs_registers[MachineLevelCSRegisters::MTINST] = 0;
+            self.set_virtualization(false);
+            self.triggers.enter_machine_trap();
+            self.program_counter = Self::trap_vector(
+                self.cs_registers[MachineLevelCSRegisters::MTVEC],
+                code,
+                interrupt,
+            );
+            self.privilege_mode = PrivilegeMode::Machine;
+        }
+        self.update_xlen();
+    }
+
+    /// Whether the trap value of an exception is a guest virtual address: the
+    /// address of a virtualized instruction fetch or data access
+    fn is_guest_virtual_trap_value(&self, code: u64) -> bool {
+        match code {
+            ExceptionCause::INSTRUCTION_ADDRESS_MISALIGNED
+            | ExceptionCause::INSTRUCTION_ACCESS_FAULT
+            | ExceptionCause::BREAKPOINT
+            | ExceptionCause::INSTRUCTION_PAGE_FAULT
+            | ExceptionCause::INSTRUCTION_GUEST_PAGE_FAULT => {
+                self.is_access_virtualized(MemoryAccess::Fetch)
+            }
+            ExceptionCause::LOAD_ADDRESS_MISALIGNED
+            | ExceptionCause::LOAD_ACCESS_FAULT
+            | ExceptionCause::STORE_ADDRESS_MISALIGNED
+            | ExceptionCause::STORE_ACCESS_FAULT
+            | ExceptionCause::LOAD_PAGE_FAULT
+            | ExceptionCause::STORE_PAGE_FAULT
+            | ExceptionCause::LOAD_GUEST_PAGE_FAULT
+            | ExceptionCause::STORE_GUEST_PAGE_FAULT => {
+                self.is_access_virtualized(MemoryAccess::Load)
+            }
+            _ => false,
+        }
+    }
+
+    /// Handler address held in mtvec/stvec/vstvec, in vectored mode interrupts jump
+    /// to BASE + 4 * cause while exceptions use BASE
+    fn trap_vector(tvec: u64, code: u64, interrupt: bool) -> u64 {
+        let base = tvec & !0b11;
+        match tvec & 0b11 {
+            0b01 if interrupt => base + 4 * code,
+            _ => base,
+        }
+    }
+}
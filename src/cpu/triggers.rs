@@ -0,0 +1,595 @@
+use crate::error::{AppErrors, AppResult};
+
+use super::{
+    cs_registers::MachineLevelCSRegisters, extensions::Extension, pmp::MemoryAccess,
+    privilege::PrivilegeMode, xlen::Xlen, Cpu,
+};
+
+/// Number of implemented triggers, selected through tselect
+pub const TRIGGER_COUNT: usize = 4;
+
+/// Trigger types held in the type field in the upper bits of tdata1
+pub struct TriggerType;
+impl TriggerType {
+    pub const ICOUNT: u64 = 3;
+    pub const ITRIGGER: u64 = 4;
+    pub const ETRIGGER: u64 = 5;
+    pub const MCONTROL6: u64 = 6;
+    /// The trigger exists but is disabled, the reset type of every trigger
+    pub const DISABLED: u64 = 15;
+
+    /// Types reported by tinfo
+    const SUPPORTED: u64 = (1 << Self::ICOUNT)
+        | (1 << Self::ITRIGGER)
+        | (1 << Self::ETRIGGER)
+        | (1 << Self::MCONTROL6)
+        | (1 << Self::DISABLED);
+}
+
+/// Bit fields of tdata1 for the mcontrol6 address/data match triggers
+pub struct MControl6Fields;
+impl MControl6Fields {
+    pub const LOAD: u64 = 1 << 0;
+    pub const STORE: u64 = 1 << 1;
+    pub const EXECUTE: u64 = 1 << 2;
+    pub const U: u64 = 1 << 3;
+    pub const S: u64 = 1 << 4;
+    pub const M: u64 = 1 << 6;
+    pub const MATCH_SHIFT: u64 = 7;
+    pub const MATCH: u64 = 0xf << Self::MATCH_SHIFT;
+    /// The trigger only fires if the following one also matches
+    pub const CHAIN: u64 = 1 << 11;
+    pub const ACTION_SHIFT: u64 = 12;
+    pub const ACTION: u64 = 0xf << Self::ACTION_SHIFT;
+    /// Compare the accessed data or the instruction opcode instead of the address
+    pub const SELECT: u64 = 1 << 21;
+    pub const HIT0: u64 = 1 << 22;
+
+    /// Match conditions, the NOT bit negates the other ones
+    pub const MATCH_EQUAL: u64 = 0;
+    pub const MATCH_NAPOT: u64 = 1;
+    pub const MATCH_GE: u64 = 2;
+    pub const MATCH_LT: u64 = 3;
+    pub const MATCH_MASK_LOW: u64 = 4;
+    pub const MATCH_MASK_HIGH: u64 = 5;
+    pub const MATCH_NOT: u64 = 8;
+
+    /// The size field is read-only zero, triggers match accesses of any size
+    pub const WRITE_MASK: u64 = Self::LOAD
+        | Self::STORE
+        | Self::EXECUTE
+        | Self::U
+        | Self::S
+        | Self::M
+        | Self::MATCH
+        | Self::CHAIN
+        | Self::ACTION
+        | Self::SELECT
+        | Self::HIT0;
+}
+
+/// Bit fields of tdata1 shared by the icount, itrigger and etrigger types
+pub struct CountTrapFields;
+impl CountTrapFields {
+    pub const ACTION: u64 = 0x3f;
+    pub const U: u64 = 1 << 6;
+    pub const S: u64 = 1 << 7;
+    pub const M: u64 = 1 << 9;
+    /// icount only, set when count reaches zero until the trigger fires
+    pub const PENDING: u64 = 1 << 8;
+    pub const COUNT_SHIFT: u64 = 10;
+    pub const COUNT: u64 = 0x3fff << Self::COUNT_SHIFT;
+    pub const ICOUNT_HIT: u64 = 1 << 24;
+
+    pub const WRITE_MASK: u64 = Self::ACTION | Self::U | Self::S | Self::M;
+    pub const ICOUNT_WRITE_MASK: u64 =
+        Self::WRITE_MASK | Self::PENDING | Self::COUNT | Self::ICOUNT_HIT;
+
+    fn mode_bit(privilege_mode: PrivilegeMode) -> u64 {
+        match privilege_mode {
+            PrivilegeMode::User => Self::U,
+            PrivilegeMode::Supervisor => Self::S,
+            PrivilegeMode::Machine => Self::M,
+        }
+    }
+}
+
+/// Bit fields of the tcontrol register
+pub struct TControlFields;
+impl TControlFields {
+    /// M-mode trigger enable, breakpoint triggers don't fire in M-mode while clear
+    pub const MTE: u64 = 1 << 3;
+    /// Previous value of MTE, saved on traps into M-mode
+    pub const MPTE: u64 = 1 << 7;
+}
+
+/// Version of the Sdtrig specification reported by tinfo
+const TINFO_VERSION: u64 = 1 << 24;
+
+/// State of the Sdtrig trigger module
+pub struct Triggers {
+    tselect: usize,
+    tdata1: [u64; TRIGGER_COUNT],
+    tdata2: [u64; TRIGGER_COUNT],
+    tcontrol: u64,
+    /// Position of the type field, the top 4 bits of the machine XLEN
+    type_shift: u64,
+    /// Set while any trigger is enabled so the hot paths can skip matching
+    armed: bool,
+    /// itrigger/etrigger that matched the last trap, it fires before the
+    /// first instruction of the handler
+    trap_trigger: Option<usize>,
+}
+
+impl Triggers {
+    pub fn new(xlen: Xlen) -> Self {
+        let type_shift = xlen.bits() as u64 - 4;
+        Self {
+            tselect: 0,
+            tdata1: [TriggerType::DISABLED << type_shift; TRIGGER_COUNT],
+            tdata2: [0; TRIGGER_COUNT],
+            tcontrol: 0,
+            type_shift,
+            armed: false,
+            trap_trigger: None,
+        }
+    }
+
+    #[inline(always)]
+    fn kind(&self, index: usize) -> u64 {
+        self.tdata1[index] >> self.type_shift
+    }
+
+    /// hit bit of the itrigger and etrigger types, placed at XLEN-6
+    fn trap_hit(&self) -> u64 {
+        1 << (self.type_shift - 2)
+    }
+
+    /// Legalizes a tdata1 write, unsupported types disable the trigger and
+    /// unsupported match values fall back to zero. The action field is WARL,
+    /// without a debug module only the breakpoint exception action (0) exists.
+    fn legalize_tdata1(&self, value: u64) -> u64 {
+        let kind = value >> self.type_shift;
+        let fields = match kind {
+            TriggerType::MCONTROL6 => {
+                let mut fields = value & MControl6Fields::WRITE_MASK;
+                let condition = (fields & MControl6Fields::MATCH) >> MControl6Fields::MATCH_SHIFT;
+                if !matches!(condition, 0..=5 | 8 | 9 | 12 | 13) {
+                    fields &= !MControl6Fields::MATCH;
+                }
+                fields & !MControl6Fields::ACTION
+            }
+            TriggerType::ICOUNT | TriggerType::ITRIGGER | TriggerType::ETRIGGER => {
+                let fields = match kind {
+                    TriggerType::ICOUNT => value & CountTrapFields::ICOUNT_WRITE_MASK,
+                    _ => value & (CountTrapFields::WRITE_MASK | self.trap_hit()),
+                };
+                fields & !CountTrapFields::ACTION
+            }
+            _ => return TriggerType::DISABLED << self.type_shift,
+        };
+        (kind << self.type_shift) | fields
+    }
+
+    /// Saves and clears tcontrol.MTE when a trap is taken into M-mode
+    pub fn enter_machine_trap(&mut self) {
+        let mte = self.tcontrol & TControlFields::MTE != 0;
+        self.tcontrol = match mte {
+            true => TControlFields::MPTE,
+            false => 0,
+        };
+    }
+
+    /// Restores tcontrol.MTE from MPTE on mret
+    pub fn return_from_machine_trap(&mut self) {
+        if self.tcontrol & TControlFields::MPTE != 0 {
+            self.tcontrol |= TControlFields::MTE;
+        } else {
+            self.tcontrol &= !TControlFields::MTE;
+        }
+    }
+}
+
+/// Compares a value against tdata2 with one of the mcontrol6 match conditions,
+/// the NOT bit is handled by the caller
+fn value_matches(condition: u64, tdata2: u64, value: u64, xlen: Xlen) -> bool {
+    let half = xlen.bits() as u64 / 2;
+    let low_mask = (1 << half) - 1;
+    match condition & !MControl6Fields::MATCH_NOT {
+        MControl6Fields::MATCH_EQUAL => value == tdata2,
+        //The bits up to the least significant zero of tdata2 are ignored
+        MControl6Fields::MATCH_NAPOT => {
+            let ignored = tdata2.trailing_ones() + 1;
+            ignored >= 64 || value >> ignored == tdata2 >> ignored
+        }
+        MControl6Fields::MATCH_GE => value >= tdata2,
+        MControl6Fields::MATCH_LT => value < tdata2,
+        MControl6Fields::MATCH_MASK_LOW => value & low_mask & (tdata2 >> half) == tdata2 & low_mask,
+        MControl6Fields::MATCH_MASK_HIGH => {
+            (value >> half) & low_mask & (tdata2 >> half) == tdata2 & low_mask
+        }
+        _ => false,
+    }
+}
+
+impl Cpu {
+    /// The trigger CSRs exist when Sdtrig is enabled
+    pub fn is_trigger_csr_accessible(&self, addr: usize) -> bool {
+        match addr {
+            MachineLevelCSRegisters::TSELECT..=MachineLevelCSRegisters::TCONTROL => {
+                self.extensions.contains(Extension::Sdtrig)
+            }
+            _ => true,
+        }
+    }
+
+    pub fn load_trigger_csr(&self, addr: usize) -> u64 {
+        let triggers = &self.triggers;
+        match addr {
+            MachineLevelCSRegisters::TSELECT => triggers.tselect as u64,
+            MachineLevelCSRegisters::TDATA1 => triggers.tdata1[triggers.tselect],
+            MachineLevelCSRegisters::TDATA2 => triggers.tdata2[triggers.tselect],
+            MachineLevelCSRegisters::TINFO => TINFO_VERSION | TriggerType::SUPPORTED,
+            MachineLevelCSRegisters::TCONTROL => triggers.tcontrol,
+            //None of the tdata3 context matching fields are implemented
+            _ => 0,
+        }
+    }
+
+    pub fn store_trigger_csr(&mut self, addr: usize, value: u64) {
+        let triggers = &mut self.triggers;
+        match addr {
+            //Selecting a trigger that doesn't exist keeps the current one, so
+            //debuggers can find the number of triggers by reading it back
+            MachineLevelCSRegisters::TSELECT if (value as usize) < TRIGGER_COUNT => {
+                triggers.tselect = value as usize
+            }
+            MachineLevelCSRegisters::TDATA1 => {
+                triggers.tdata1[triggers.tselect] = triggers.legalize_tdata1(value);
+                triggers.armed =
+                    (0..TRIGGER_COUNT).any(|index| triggers.kind(index) != TriggerType::DISABLED);
+            }
+            MachineLevelCSRegisters::TDATA2 => triggers.tdata2[triggers.tselect] = value,
+            MachineLevelCSRegisters::TCONTROL => {
+                triggers.tcontrol = value & (TControlFields::MTE | TControlFields::MPTE)
+            }
+            _ => (),
+        }
+    }
+
+    /// Checks the privilege mode bits of a trigger against the current privilege,
+    /// as every trigger raises a breakpoint exception they also require
+    /// tcontrol.MTE in M-mode
+    fn is_trigger_enabled(&self, tdata1: u64, mode_bits: [u64; 3]) -> bool {
+        let [user, supervisor, machine] = mode_bits;
+        match self.privilege_mode {
+            PrivilegeMode::User => tdata1 & user != 0,
+            PrivilegeMode::Supervisor => tdata1 & supervisor != 0,
+            PrivilegeMode::Machine => {
+                tdata1 & machine != 0 && self.triggers.tcontrol & TControlFields::MTE != 0
+            }
+        }
+    }
+
+    fn fire_trigger(trap_value: u64) -> AppResult<()> {
+        Err(AppErrors::Breakpoint { addr: trap_value })
+    }
+
+    fn mcontrol6_matches(
+        &self,
+        index: usize,
+        access: MemoryAccess,
+        addr: u64,
+        size: u64,
+        data: Option<u64>,
+    ) -> bool {
+        let tdata1 = self.triggers.tdata1[index];
+        let access_bit = match access {
+            MemoryAccess::Fetch => MControl6Fields::EXECUTE,
+            MemoryAccess::Load => MControl6Fields::LOAD,
+            MemoryAccess::Store => MControl6Fields::STORE,
+        };
+        let mode_bits = [MControl6Fields::U, MControl6Fields::S, MControl6Fields::M];
+        if tdata1 & access_bit == 0 || !self.is_trigger_enabled(tdata1, mode_bits) {
+            return false;
+        }
+        let condition = (tdata1 & MControl6Fields::MATCH) >> MControl6Fields::MATCH_SHIFT;
+        let tdata2 = self.triggers.tdata2[index];
+        let matched = match (tdata1 & MControl6Fields::SELECT != 0, data) {
+            //Address triggers match if any of the accessed bytes matches
+            (false, _) => (addr..addr.wrapping_add(size))
+                .any(|byte| value_matches(condition, tdata2, byte, self.xlen)),
+            (true, Some(data)) => value_matches(condition, tdata2, data, self.xlen),
+            //The data is not known yet
+            (true, None) => return false,
+        };
+        matched != (condition & MControl6Fields::MATCH_NOT != 0)
+    }
+
+    /// Matches an access against the mcontrol6 triggers, a chain of triggers
+    /// fires with the action of its last trigger when all of them match.
+    /// `data` holds the accessed value or the opcode of executed instructions,
+    /// it is None while the address is checked before the access.
+    pub fn check_access_triggers(
+        &mut self,
+        access: MemoryAccess,
+        addr: u64,
+        size: u64,
+        data: Option<u64>,
+    ) -> AppResult<()> {
+        if !self.triggers.armed {
+            return Ok(());
+        }
+        let mut chain_start = 0;
+        let mut chain_matched = true;
+        for index in 0..TRIGGER_COUNT {
+            if self.triggers.kind(index) != TriggerType::MCONTROL6 {
+                chain_start = index + 1;
+                chain_matched = true;
+                continue;
+            }
+            chain_matched =
+                chain_matched && self.mcontrol6_matches(index, access, addr, size, data);
+            let tdata1 = self.triggers.tdata1[index];
+            if tdata1 & MControl6Fields::CHAIN != 0 {
+                continue;
+            }
+            if chain_matched {
+                for member in chain_start..=index {
+                    self.triggers.tdata1[member] |= MControl6Fields::HIT0;
+                }
+                return Self::fire_trigger(addr);
+            }
+            chain_start = index + 1;
+            chain_matched = true;
+        }
+        Ok(())
+    }
+
+    /// Set while any trigger is enabled, every instruction has to be matched
+    #[inline(always)]
+    pub fn triggers_armed(&self) -> bool {
+        self.triggers.armed
+    }
+
+    /// Runs before each instruction, fires the pending triggers and matches
+    /// the execute triggers against the pc and the opcode
+    #[inline(always)]
+    pub fn check_instruction_triggers(&mut self, instruction: u32) -> AppResult<()> {
+        if !self.triggers.armed {
+            return Ok(());
+        }
+        self.fire_pending_triggers()?;
+        let pc = self.program_counter;
+        self.check_access_triggers(MemoryAccess::Fetch, pc, 4, Some(instruction as u64))
+    }
+
+    /// Fires the itrigger/etrigger matched by the last trap and the icount
+    /// triggers that reached zero
+    fn fire_pending_triggers(&mut self) -> AppResult<()> {
+        if !self.triggers.armed {
+            return Ok(());
+        }
+        if let Some(index) = self.triggers.trap_trigger.take() {
+            let masked = self.privilege_mode == PrivilegeMode::Machine
+                && self.triggers.tcontrol & TControlFields::MTE == 0;
+            if !masked {
+                self.triggers.tdata1[index] |= self.triggers.trap_hit();
+                return Self::fire_trigger(0);
+            }
+        }
+        let mode_bits = [CountTrapFields::U, CountTrapFields::S, CountTrapFields::M];
+        for index in 0..TRIGGER_COUNT {
+            let tdata1 = self.triggers.tdata1[index];
+            if self.triggers.kind(index) == TriggerType::ICOUNT
+                && tdata1 & CountTrapFields::PENDING != 0
+                && self.is_trigger_enabled(tdata1, mode_bits)
+            {
+                self.triggers.tdata1[index] =
+                    (tdata1 & !CountTrapFields::PENDING) | CountTrapFields::ICOUNT_HIT;
+                return Self::fire_trigger(0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decrements the icount triggers enabled in the privilege mode an
+    /// instruction completed in, the trigger becomes pending when count reaches zero
+    pub fn count_instruction_triggers(&mut self, privilege_mode: PrivilegeMode) {
+        if !self.triggers.armed {
+            return;
+        }
+        let mode_bit = CountTrapFields::mode_bit(privilege_mode);
+        for index in 0..TRIGGER_COUNT {
+            let tdata1 = self.triggers.tdata1[index];
+            if self.triggers.kind(index) != TriggerType::ICOUNT || tdata1 & mode_bit == 0 {
+                continue;
+            }
+            self.triggers.tdata1[index] =
+                match (tdata1 & CountTrapFields::COUNT) >> CountTrapFields::COUNT_SHIFT {
+                    0 => tdata1,
+                    1 => (tdata1 & !CountTrapFields::COUNT) | CountTrapFields::PENDING,
+                    _ => tdata1 - (1 << CountTrapFields::COUNT_SHIFT),
+                };
+        }
+    }
+
+    /// Matches a trap taken from the current privilege mode against the
+    /// itrigger (interrupts) and etrigger (exceptions) triggers, tdata2 holds
+    /// the mask of the cause codes to match
+    pub fn match_trap_triggers(&mut self, code: u64, interrupt: bool) {
+        if !self.triggers.armed {
+            return;
+        }
+        let kind = match interrupt {
+            true => TriggerType::ITRIGGER,
+            false => TriggerType::ETRIGGER,
+        };
+        let mode_bit = CountTrapFields::mode_bit(self.privilege_mode);
+        self.triggers.trap_trigger = (0..TRIGGER_COUNT).find(|index| {
+            self.triggers.kind(*index) == kind
+                && self.triggers.tdata1[*index] & mode_bit != 0
+                && (self.triggers.tdata2[*index] >> code) & 1 == 1
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{test_hart, trap::ExceptionCause};
+
+    const TYPE_SHIFT: u64 = 60;
+
+    /// Programs trigger `index` through tselect/tdata1/tdata2
+    fn set_trigger(cpu: &mut Cpu, index: u64, tdata1: u64, tdata2: u64) {
+        cpu.store_trigger_csr(MachineLevelCSRegisters::TSELECT, index);
+        cpu.store_trigger_csr(MachineLevelCSRegisters::TDATA1, tdata1);
+        cpu.store_trigger_csr(MachineLevelCSRegisters::TDATA2, tdata2);
+    }
+
+    fn mcontrol6(fields: u64, condition: u64) -> u64 {
+        (TriggerType::MCONTROL6 << TYPE_SHIFT)
+            | MControl6Fields::S
+            | (condition << MControl6Fields::MATCH_SHIFT)
+            | fields
+    }
+
+    fn tdata1(cpu: &mut Cpu, index: u64) -> u64 {
+        cpu.store_trigger_csr(MachineLevelCSRegisters::TSELECT, index);
+        cpu.load_trigger_csr(MachineLevelCSRegisters::TDATA1)
+    }
+
+    fn supervisor_cpu() -> Cpu {
+        let mut cpu = test_hart::cpu();
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu
+    }
+
+    #[test]
+    fn tdata1_writes_are_legalized() {
+        let mut cpu = supervisor_cpu();
+        //Type 2 (mcontrol) is not supported
+        set_trigger(&mut cpu, 0, 2 << TYPE_SHIFT, 0);
+        assert_eq!(tdata1(&mut cpu, 0), TriggerType::DISABLED << TYPE_SHIFT);
+        assert!(!cpu.triggers_armed());
+        //The action field only holds the breakpoint exception action
+        set_trigger(
+            &mut cpu,
+            0,
+            mcontrol6(
+                MControl6Fields::LOAD | (1 << MControl6Fields::ACTION_SHIFT),
+                0,
+            ),
+            0,
+        );
+        assert_eq!(tdata1(&mut cpu, 0), mcontrol6(MControl6Fields::LOAD, 0));
+        assert!(cpu.triggers_armed());
+        //Selecting a trigger past the last one keeps the selection
+        cpu.store_trigger_csr(MachineLevelCSRegisters::TSELECT, TRIGGER_COUNT as u64);
+        assert_eq!(cpu.load_trigger_csr(MachineLevelCSRegisters::TSELECT), 0);
+    }
+
+    #[test]
+    fn chained_triggers_match_an_address_range() {
+        let mut cpu = supervisor_cpu();
+        let chained = MControl6Fields::STORE | MControl6Fields::CHAIN;
+        set_trigger(
+            &mut cpu,
+            0,
+            mcontrol6(chained, MControl6Fields::MATCH_GE),
+            0x1000,
+        );
+        set_trigger(
+            &mut cpu,
+            1,
+            mcontrol6(MControl6Fields::STORE, MControl6Fields::MATCH_LT),
+            0x1010,
+        );
+        assert!(cpu
+            .check_access_triggers(MemoryAccess::Store, 0x1010, 8, None)
+            .is_ok());
+        assert!(cpu
+            .check_access_triggers(MemoryAccess::Load, 0x1008, 8, None)
+            .is_ok());
+        //Any accessed byte matches
+        assert!(matches!(
+            cpu.check_access_triggers(MemoryAccess::Store, 0xffc, 8, None),
+            Err(AppErrors::Breakpoint { addr: 0xffc })
+        ));
+        assert_ne!(tdata1(&mut cpu, 0) & MControl6Fields::HIT0, 0);
+        assert_ne!(tdata1(&mut cpu, 1) & MControl6Fields::HIT0, 0);
+    }
+
+    #[test]
+    fn data_triggers_match_the_accessed_value() {
+        let mut cpu = supervisor_cpu();
+        let fields = MControl6Fields::LOAD | MControl6Fields::SELECT;
+        //Mask match on the low half, bits 0-7 of the value have to be 0x42
+        set_trigger(
+            &mut cpu,
+            0,
+            mcontrol6(fields, MControl6Fields::MATCH_MASK_LOW),
+            (0xff << 32) | 0x42,
+        );
+        assert!(cpu
+            .check_access_triggers(MemoryAccess::Load, 0x2000, 4, None)
+            .is_ok());
+        assert!(cpu
+            .check_access_triggers(MemoryAccess::Load, 0x2000, 4, Some(0x143))
+            .is_ok());
+        assert!(cpu
+            .check_access_triggers(MemoryAccess::Load, 0x2000, 4, Some(0x142))
+            .is_err());
+    }
+
+    #[test]
+    fn machine_mode_triggers_require_mte() {
+        let mut cpu = test_hart::cpu();
+        let fields = MControl6Fields::EXECUTE | MControl6Fields::M;
+        let pc = cpu.program_counter;
+        set_trigger(&mut cpu, 0, mcontrol6(fields, 0), pc);
+        assert!(cpu.check_instruction_triggers(0x13).is_ok());
+        cpu.store_trigger_csr(MachineLevelCSRegisters::TCONTROL, TControlFields::MTE);
+        assert!(cpu.check_instruction_triggers(0x13).is_err());
+        //A trap into M-mode saves MTE in MPTE and mret restores it
+        cpu.triggers.enter_machine_trap();
+        assert_eq!(
+            cpu.load_trigger_csr(MachineLevelCSRegisters::TCONTROL),
+            TControlFields::MPTE
+        );
+        assert!(cpu.check_instruction_triggers(0x13).is_ok());
+        cpu.triggers.return_from_machine_trap();
+        assert!(cpu.check_instruction_triggers(0x13).is_err());
+    }
+
+    #[test]
+    fn icount_fires_after_count_instructions() {
+        let mut cpu = supervisor_cpu();
+        let icount = (TriggerType::ICOUNT << TYPE_SHIFT)
+            | CountTrapFields::S
+            | (2 << CountTrapFields::COUNT_SHIFT);
+        set_trigger(&mut cpu, 0, icount, 0);
+        cpu.count_instruction_triggers(PrivilegeMode::Supervisor);
+        //Instructions of other modes are not counted
+        cpu.count_instruction_triggers(PrivilegeMode::User);
+        assert!(cpu.check_instruction_triggers(0x13).is_ok());
+        cpu.count_instruction_triggers(PrivilegeMode::Supervisor);
+        assert!(cpu.check_instruction_triggers(0x13).is_err());
+        assert_ne!(tdata1(&mut cpu, 0) & CountTrapFields::ICOUNT_HIT, 0);
+        assert!(cpu.check_instruction_triggers(0x13).is_ok());
+    }
+
+    #[test]
+    fn etrigger_fires_before_the_handler() {
+        let mut cpu = supervisor_cpu();
+        let etrigger = (TriggerType::ETRIGGER << TYPE_SHIFT) | CountTrapFields::S;
+        set_trigger(&mut cpu, 2, etrigger, 1 << ExceptionCause::BREAKPOINT);
+        cpu.match_trap_triggers(ExceptionCause::ILLEGAL_INSTRUCTION, false);
+        assert!(cpu.check_instruction_triggers(0x13).is_ok());
+        cpu.match_trap_triggers(ExceptionCause::BREAKPOINT, false);
+        assert!(cpu.check_instruction_triggers(0x13).is_err());
+        let hit = cpu.triggers.trap_hit();
+        assert_ne!(tdata1(&mut cpu, 2) & hit, 0);
+    }
+}
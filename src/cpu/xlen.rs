@@ -0,0 +1,208 @@
+use crate::error::{AppErrors, AppResult};
+
+use super::{
+    cs_registers::{
+        HStatusFields, HypervisorLevelCSRegisters, MStatusFields, MachineLevelCSRegisters,
+    },
+    privilege::PrivilegeMode,
+    Cpu,
+};
+
+/// Width of the integer registers, the discriminant is the encoding used by
+/// the misa.MXL and mstatus.SXL/UXL fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Xlen {
+    X32 = 1,
+    X64 = 2,
+}
+
+impl Xlen {
+    /// Decodes a MXL/SXL/UXL field, returns None for the reserved encodings
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            1 => Some(Xlen::X32),
+            2 => Some(Xlen::X64),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "32" => Some(Xlen::X32),
+            "64" => Some(Xlen::X64),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            Xlen::X32 => 32,
+            Xlen::X64 => 64,
+        }
+    }
+}
+
+impl Cpu {
+    /// XLEN of a privilege mode, M-mode runs with the machine XLEN while S and U
+    /// modes take it from mstatus.SXL and mstatus.UXL
+    pub fn xlen_of(&self, privilege_mode: PrivilegeMode) -> Xlen {
+        let status = self.cs_registers[MachineLevelCSRegisters::MSTATUS];
+        let field = match privilege_mode {
+            PrivilegeMode::Machine => return self.config.xlen,
+            PrivilegeMode::Supervisor => status >> MStatusFields::SXL_SHIFT,
+            PrivilegeMode::User => status >> MStatusFields::UXL_SHIFT,
+        };
+        //The fields are read-only zero when the machine XLEN is 32
+        Xlen::from_bits(field & 0b11).unwrap_or(self.config.xlen)
+    }
+
+    /// XLEN of a virtual privilege mode, VS-mode takes it from hstatus.VSXL
+    /// and VU-mode from vsstatus.UXL. A virtual mode is never wider than the
+    /// supervisor XLEN.
+    pub fn virtual_xlen_of(&self, privilege_mode: PrivilegeMode) -> Xlen {
+        let field = match privilege_mode {
+            PrivilegeMode::User => {
+                self.cs_registers[HypervisorLevelCSRegisters::VSSTATUS] >> MStatusFields::UXL_SHIFT
+            }
+            _ => {
+                self.cs_registers[HypervisorLevelCSRegisters::HSTATUS] >> HStatusFields::VSXL_SHIFT
+            }
+        };
+        let supervisor = self.xlen_of(PrivilegeMode::Supervisor);
+        match Xlen::from_bits(field & 0b11) {
+            Some(Xlen::X64) if supervisor == Xlen::X64 => Xlen::X64,
+            Some(_) => Xlen::X32,
+            None => supervisor,
+        }
+    }
+
+    /// Recomputes the XLEN of the current privilege mode, has to be called
+    /// whenever the privilege mode, the virtualization mode or the XL fields
+    /// change
+    pub fn update_xlen(&mut self) {
+        let xlen = match self.virtualization {
+            true => self.virtual_xlen_of(self.privilege_mode),
+            false => self.xlen_of(self.privilege_mode),
+        };
+        //The registers keep their upper bits, the executors read their
+        //operands through read_reg which ignores them at a narrower XLEN
+        self.xlen = xlen;
+    }
+
+    /// Truncates an address to the current XLEN
+    #[inline(always)]
+    pub fn effective_address(&self, addr: u64) -> u64 {
+        match self.xlen {
+            Xlen::X32 => addr & 0xffff_ffff,
+            Xlen::X64 => addr,
+        }
+    }
+
+    /// Zero-extends a register value from the current XLEN
+    #[inline(always)]
+    pub fn zero_extend_xlen(&self, value: u64) -> u64 {
+        self.effective_address(value)
+    }
+
+    /// Mask of the shift amount held in a register for the current XLEN
+    #[inline(always)]
+    pub fn shift_amount_mask(&self) -> u64 {
+        self.xlen.bits() as u64 - 1
+    }
+
+    /// Decodes the shamt field of the immediate shifts, shamt[5] is reserved
+    /// when XLEN is 32
+    #[inline(always)]
+    pub fn immediate_shift_amount(&self, imm: u64, instruction: u32) -> AppResult<u32> {
+        if self.xlen == Xlen::X32 && imm & 0x20 != 0 {
+            return Err(AppErrors::IllegalInstruction { instruction });
+        }
+        Ok((imm & 0x3f) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{
+        config::CpuConfig,
+        cs_registers::SupervisorLevelCSRegisters,
+        test_hart::{self, execute},
+    };
+
+    /// addi x6, x5, 1
+    const ADDI: u32 = 0x0012_8313;
+    /// slt x7, x5, x0
+    const SLT: u32 = 0x0002_a3b3;
+    /// sra x7, x5, x6
+    const SRA: u32 = 0x4062_d3b3;
+
+    #[test]
+    fn rv32_results_are_sign_extended() {
+        let mut cpu = test_hart::cpu_with(CpuConfig {
+            xlen: Xlen::X32,
+            ..CpuConfig::default()
+        });
+        assert_eq!(cpu.xlen, Xlen::X32);
+        cpu.registers[5] = 0x7fff_ffff;
+        execute(&mut cpu, ADDI).unwrap();
+        assert_eq!(cpu.registers[6], 0xffff_ffff_8000_0000);
+        execute(&mut cpu, SLT).unwrap();
+        assert_eq!(cpu.registers[7], 0);
+        //The SXL and UXL fields are read-only
+        cpu.store_csr(
+            MachineLevelCSRegisters::MSTATUS,
+            2 << MStatusFields::SXL_SHIFT,
+        );
+        assert_eq!(cpu.xlen_of(PrivilegeMode::Supervisor), Xlen::X32);
+    }
+
+    #[test]
+    fn narrower_modes_ignore_the_upper_register_bits() {
+        let mut cpu = test_hart::cpu();
+        let status = cpu.load_csr(MachineLevelCSRegisters::MSTATUS);
+        cpu.store_csr(
+            MachineLevelCSRegisters::MSTATUS,
+            (status & !MStatusFields::SXL) | (1 << MStatusFields::SXL_SHIFT),
+        );
+        cpu.registers[5] = 0x1_8000_0000;
+        cpu.privilege_mode = PrivilegeMode::Supervisor;
+        cpu.update_xlen();
+        assert_eq!(cpu.xlen, Xlen::X32);
+        //x5 reads as -2^31 at XLEN 32
+        execute(&mut cpu, ADDI).unwrap();
+        assert_eq!(cpu.registers[6], 0xffff_ffff_8000_0001);
+        execute(&mut cpu, SLT).unwrap();
+        assert_eq!(cpu.registers[7], 1);
+        cpu.registers[6] = 4;
+        execute(&mut cpu, SRA).unwrap();
+        assert_eq!(cpu.registers[7], 0xffff_ffff_f800_0000);
+        //The register keeps its upper bits for M-mode
+        cpu.privilege_mode = PrivilegeMode::Machine;
+        cpu.update_xlen();
+        assert_eq!(cpu.registers[5], 0x1_8000_0000);
+        execute(&mut cpu, SLT).unwrap();
+        assert_eq!(cpu.registers[7], 0);
+    }
+
+    #[test]
+    fn uxl_is_written_through_sstatus() {
+        let mut cpu = test_hart::cpu();
+        cpu.privilege_mode = PrivilegeMode::User;
+        cpu.update_xlen();
+        assert_eq!(cpu.xlen, Xlen::X64);
+        let status = cpu.load_csr(SupervisorLevelCSRegisters::SSTATUS);
+        cpu.store_csr(
+            SupervisorLevelCSRegisters::SSTATUS,
+            (status & !MStatusFields::UXL) | (1 << MStatusFields::UXL_SHIFT),
+        );
+        assert_eq!(cpu.xlen, Xlen::X32);
+        assert_eq!(cpu.xlen_of(PrivilegeMode::Supervisor), Xlen::X64);
+        //The reserved encoding leaves the field unchanged
+        cpu.store_csr(
+            SupervisorLevelCSRegisters::SSTATUS,
+            status | MStatusFields::UXL,
+        );
+        assert_eq!(cpu.xlen_of(PrivilegeMode::User), Xlen::X32);
+    }
+}
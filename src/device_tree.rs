@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use crate::{
+    aclint::MTIME_OFFSET,
+    aia::{APLIC_NUM_SOURCES, IMSIC_NUM_IDS},
+    consts::{
+        ACLINT_MSWI_BASE_ADDR, ACLINT_MSWI_SIZE, ACLINT_MTIMER_BASE_ADDR, ACLINT_MTIMER_SIZE,
+        APLIC_M_BASE_ADDR, APLIC_SIZE, APLIC_S_BASE_ADDR, FINISHER_BASE_ADDR, FINISHER_SIZE,
+        IMSIC_M_BASE_ADDR, IMSIC_S_BASE_ADDR, PAGE_SIZE, TIMEBASE_FREQUENCY,
+    },
+    finisher::FinisherCommand,
+};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMPATIBLE_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_MEMORY_RESERVATION_MAP_SIZE: usize = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// Phandles of the interrupt controllers referenced by other nodes, the ones
+/// of the hart interrupt controllers follow the one of hart 0
+const CPU_INTC_PHANDLE: u32 = 0x100;
+const IMSIC_M_PHANDLE: u32 = 2;
+const IMSIC_S_PHANDLE: u32 = 3;
+const APLIC_M_PHANDLE: u32 = 4;
+const APLIC_S_PHANDLE: u32 = 5;
+const FINISHER_PHANDLE: u32 = 6;
+
+/// Local interrupt numbers in the hart interrupt controller
+const MACHINE_SOFTWARE_INTERRUPT: u32 = 3;
+const MACHINE_TIMER_INTERRUPT: u32 = 7;
+const MACHINE_EXTERNAL_INTERRUPT: u32 = 11;
+const SUPERVISOR_EXTERNAL_INTERRUPT: u32 = 9;
+
+/// Builds a flattened device tree blob (dtb) as described in the devicetree
+/// specification, nodes are written in order between begin_node and end_node calls
+#[derive(Default)]
+pub struct DeviceTreeBuilder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, u32>,
+}
+
+impl DeviceTreeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align_structure();
+    }
+
+    pub fn end_node(&mut self) {
+        self.push_u32(FDT_END_NODE);
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let name_offset = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_offset);
+        self.structure.extend_from_slice(value);
+        self.align_structure();
+    }
+
+    pub fn property_null(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value);
+    }
+
+    pub fn property_string(&mut self, name: &str, value: &str) {
+        self.property_strings(name, &[value]);
+    }
+
+    pub fn property_strings(&mut self, name: &str, values: &[&str]) {
+        let mut value = Vec::new();
+        for string in values {
+            value.extend_from_slice(string.as_bytes());
+            value.push(0);
+        }
+        self.property(name, &value);
+    }
+
+    /// Serializes the tree, the structure block must be balanced at this point
+    pub fn finish(mut self) -> Vec<u8> {
+        self.push_u32(FDT_END);
+
+        let structure_offset = FDT_HEADER_SIZE + FDT_MEMORY_RESERVATION_MAP_SIZE;
+        let strings_offset = structure_offset + self.structure.len();
+        let total_size = strings_offset + self.strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            total_size as u32,
+            structure_offset as u32,
+            strings_offset as u32,
+            FDT_HEADER_SIZE as u32,
+            FDT_VERSION,
+            FDT_LAST_COMPATIBLE_VERSION,
+            0, //Boot cpu id
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+
+        let mut blob = Vec::with_capacity(total_size);
+        blob.extend(header.iter().flat_map(|field| field.to_be_bytes()));
+        //Empty memory reservation map, only holds the terminating entry
+        blob.extend_from_slice(&[0; FDT_MEMORY_RESERVATION_MAP_SIZE]);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        if let Some(offset) = self.string_offsets.get(name) {
+            return *offset;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.insert(name.to_string(), offset);
+        offset
+    }
+
+    #[inline(always)]
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend_from_slice(&value.to_be_bytes());
+    }
+
+    #[inline(always)]
+    fn align_structure(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+}
+
+/// Hardware description passed to the guest in the device tree
+pub struct MachineDescription<'a> {
+    pub isa: &'a str,
+    /// Widest supported address translation mode, such as riscv,sv39
+    pub mmu_type: &'a str,
+    /// Base and size of the RAM regions
+    pub memory: &'a [(u64, u64)],
+    pub cache_block_size: u64,
+    /// Adds the IMSIC and APLIC interrupt controllers (Smaia/Ssaia)
+    pub aia: bool,
+    pub harts: usize,
+}
+
+/// Generates the device tree blob describing the emulated machine
+pub fn generate(machine: &MachineDescription) -> Vec<u8> {
+    let mut tree = DeviceTreeBuilder::new();
+
+    tree.begin_node("");
+    tree.property_u32("#address-cells", 2);
+    tree.property_u32("#size-cells", 2);
+    tree.property_string("compatible", "riscvemulator");
+    tree.property_string("model", "riscvemulator");
+
+    tree.begin_node("chosen");
+    tree.end_node();
+
+    tree.begin_node("cpus");
+    tree.property_u32("#address-cells", 1);
+    tree.property_u32("#size-cells", 0);
+    tree.property_u32("timebase-frequency", TIMEBASE_FREQUENCY);
+
+    for hart in 0..machine.harts {
+        tree.begin_node(&format!("cpu@{hart}"));
+        tree.property_string("device_type", "cpu");
+        tree.property_u32("reg", hart as u32);
+        tree.property_string("status", "okay");
+        tree.property_string("compatible", "riscv");
+        tree.property_string("riscv,isa", machine.isa);
+        tree.property_string("mmu-type", machine.mmu_type);
+        tree.property_u32("riscv,cbom-block-size", machine.cache_block_size as u32);
+        tree.property_u32("riscv,cboz-block-size", machine.cache_block_size as u32);
+        tree.property_u32("riscv,cbop-block-size", machine.cache_block_size as u32);
+
+        tree.begin_node("interrupt-controller");
+        tree.property_u32("#interrupt-cells", 1);
+        tree.property_null("interrupt-controller");
+        tree.property_string("compatible", "riscv,cpu-intc");
+        tree.property_u32("phandle", CPU_INTC_PHANDLE + hart as u32);
+        tree.end_node();
+
+        tree.end_node(); // cpu
+    }
+    tree.end_node(); // cpus
+
+    for (base, size) in machine.memory {
+        tree.begin_node(&format!("memory@{base:x}"));
+        tree.property_string("device_type", "memory");
+        tree.property_cells("reg", &reg_cells(*base, *size));
+        tree.end_node();
+    }
+
+    tree.begin_node("soc");
+    tree.property_u32("#address-cells", 2);
+    tree.property_u32("#size-cells", 2);
+    tree.property_string("compatible", "simple-bus");
+    tree.property_null("ranges");
+
+    tree.begin_node(&format!("mswi@{ACLINT_MSWI_BASE_ADDR:x}"));
+    tree.property_string("compatible", "riscv,aclint-mswi");
+    tree.property_cells("reg", &reg_cells(ACLINT_MSWI_BASE_ADDR, ACLINT_MSWI_SIZE));
+    tree.property_cells(
+        "interrupts-extended",
+        &hart_interrupts(machine.harts, MACHINE_SOFTWARE_INTERRUPT),
+    );
+    tree.property_null("interrupt-controller");
+    tree.property_u32("#interrupt-cells", 0);
+    tree.end_node();
+
+    //The mtime register comes first, followed by the mtimecmp registers
+    let mtime = ACLINT_MTIMER_BASE_ADDR + MTIME_OFFSET;
+    tree.begin_node(&format!("mtimer@{mtime:x}"));
+    tree.property_string("compatible", "riscv,aclint-mtimer");
+    tree.property_cells(
+        "reg",
+        &[
+            reg_cells(mtime, ACLINT_MTIMER_SIZE - MTIME_OFFSET),
+            reg_cells(ACLINT_MTIMER_BASE_ADDR, MTIME_OFFSET),
+        ]
+        .concat(),
+    );
+    tree.property_cells(
+        "interrupts-extended",
+        &hart_interrupts(machine.harts, MACHINE_TIMER_INTERRUPT),
+    );
+    tree.end_node();
+
+    tree.begin_node(&format!("test@{FINISHER_BASE_ADDR:x}"));
+    tree.property_strings("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+    tree.property_cells("reg", &reg_cells(FINISHER_BASE_ADDR, FINISHER_SIZE));
+    tree.property_u32("phandle", FINISHER_PHANDLE);
+    tree.end_node();
+
+    if machine.aia {
+        add_imsic(
+            &mut tree,
+            IMSIC_M_BASE_ADDR,
+            IMSIC_M_PHANDLE,
+            &hart_interrupts(machine.harts, MACHINE_EXTERNAL_INTERRUPT),
+        );
+        add_imsic(
+            &mut tree,
+            IMSIC_S_BASE_ADDR,
+            IMSIC_S_PHANDLE,
+            &hart_interrupts(machine.harts, SUPERVISOR_EXTERNAL_INTERRUPT),
+        );
+        add_aplic(
+            &mut tree,
+            APLIC_M_BASE_ADDR,
+            APLIC_M_PHANDLE,
+            IMSIC_M_PHANDLE,
+        );
+        tree.property_cells("riscv,children", &[APLIC_S_PHANDLE]);
+        tree.property_cells(
+            "riscv,delegation",
+            &[APLIC_S_PHANDLE, 1, APLIC_NUM_SOURCES as u32],
+        );
+        tree.end_node();
+        add_aplic(
+            &mut tree,
+            APLIC_S_BASE_ADDR,
+            APLIC_S_PHANDLE,
+            IMSIC_S_PHANDLE,
+        );
+        tree.end_node();
+    }
+    tree.end_node(); // soc
+
+    tree.begin_node("poweroff");
+    tree.property_string("compatible", "syscon-poweroff");
+    tree.property_u32("regmap", FINISHER_PHANDLE);
+    tree.property_u32("offset", 0);
+    tree.property_u32("value", FinisherCommand::PASS);
+    tree.end_node();
+
+    tree.end_node(); // root
+    tree.finish()
+}
+
+/// Cells of a reg property with two address and two size cells
+fn reg_cells(addr: u64, size: u64) -> [u32; 4] {
+    [
+        (addr >> 32) as u32,
+        addr as u32,
+        (size >> 32) as u32,
+        size as u32,
+    ]
+}
+
+/// Cells of an interrupts-extended property wiring a device to the same local
+/// interrupt of every hart
+fn hart_interrupts(harts: usize, interrupt: u32) -> Vec<u32> {
+    (0..harts as u32)
+        .flat_map(|hart| [CPU_INTC_PHANDLE + hart, interrupt])
+        .collect()
+}
+
+/// Adds the interrupt files of a privilege level, one page per hart wired to
+/// the external interrupt of the hart at that level
+fn add_imsic(tree: &mut DeviceTreeBuilder, base: u64, phandle: u32, interrupts: &[u32]) {
+    let harts = interrupts.len() as u64 / 2;
+    tree.begin_node(&format!("interrupt-controller@{base:x}"));
+    tree.property_string("compatible", "riscv,imsics");
+    tree.property_cells("reg", &reg_cells(base, harts * PAGE_SIZE));
+    tree.property_cells("interrupts-extended", interrupts);
+    tree.property_null("interrupt-controller");
+    tree.property_u32("#interrupt-cells", 0);
+    tree.property_null("msi-controller");
+    tree.property_u32("#msi-cells", 0);
+    tree.property_u32("riscv,num-ids", IMSIC_NUM_IDS as u32);
+    tree.property_u32("phandle", phandle);
+    tree.end_node();
+}
+
+/// Begins the node of an APLIC domain that forwards its interrupts as MSIs,
+/// the caller closes it after adding the properties specific to the domain
+fn add_aplic(tree: &mut DeviceTreeBuilder, base: u64, phandle: u32, msi_parent: u32) {
+    tree.begin_node(&format!("interrupt-controller@{base:x}"));
+    tree.property_string("compatible", "riscv,aplic");
+    tree.property_cells("reg", &reg_cells(base, APLIC_SIZE));
+    tree.property_null("interrupt-controller");
+    tree.property_u32("#interrupt-cells", 2);
+    tree.property_u32("#address-cells", 0);
+    tree.property_cells("msi-parent", &[msi_parent]);
+    tree.property_u32("riscv,num-sources", APLIC_NUM_SOURCES as u32);
+    tree.property_u32("phandle", phandle);
+}
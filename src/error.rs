@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::cpu::privilege::PrivilegeMode;
+
 #[allow(dead_code)]
 #[derive(Error, Debug)]
 pub enum AppErrors {
@@ -11,10 +13,47 @@ pub enum AppErrors {
     AddressNotFound,
     #[error("Cannot access memmory thats out of bounds")]
     OutOfBoundsPointer,
+    #[error("Invalid memory region at {base:#x}: {reason}")]
+    InvalidMemoryRegion { base: u64, reason: &'static str },
+    #[error("Cannot map the memory region at {base:#x}: {source}")]
+    MemoryMapFailed { base: u64, source: std::io::Error },
     #[error("Instruction is not supported")]
     InstructionNotImplemented { instruction: u32 },
-    #[error("Instruction function is not supported yet")]
-    FuctionNotImplemented(u8, Option<u8>),
+    #[error("Illegal instruction")]
+    IllegalInstruction { instruction: u32 },
+    #[error("Instruction address misaligned")]
+    InstructionAddressMisaligned { addr: u64 },
+    #[error("Load address misaligned")]
+    LoadAddressMisaligned { addr: u64 },
+    #[error("Store/AMO address misaligned")]
+    StoreAddressMisaligned { addr: u64 },
+    #[error("Instruction access fault")]
+    InstructionAccessFault { addr: u64 },
+    #[error("Load access fault")]
+    LoadAccessFault { addr: u64 },
+    #[error("Store/AMO access fault")]
+    StoreAccessFault { addr: u64 },
+    #[error("Instruction page fault")]
+    InstructionPageFault { addr: u64 },
+    #[error("Load page fault")]
+    LoadPageFault { addr: u64 },
+    #[error("Store/AMO page fault")]
+    StorePageFault { addr: u64 },
+    #[error("Instruction guest-page fault")]
+    InstructionGuestPageFault { addr: u64, guest_physical: u64 },
+    #[error("Load guest-page fault")]
+    LoadGuestPageFault { addr: u64, guest_physical: u64 },
+    #[error("Store/AMO guest-page fault")]
+    StoreGuestPageFault { addr: u64, guest_physical: u64 },
+    #[error("Virtual instruction")]
+    VirtualInstruction { instruction: u32 },
+    #[error("Environment call from {privilege:?} mode")]
+    EnvironmentCall {
+        privilege: PrivilegeMode,
+        virtualized: bool,
+    },
+    #[error("Breakpoint")]
+    Breakpoint { addr: u64 },
     #[error("Instruction size is not supported")]
     InstructionSizeNotSupported,
     #[error("unknown error ocurred")]
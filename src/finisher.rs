@@ -0,0 +1,20 @@
+//! SiFive test finisher, a syscon register the guest writes to power the
+//! machine off with an exit status
+
+/// Commands held in the low 16 bits of a write to the finisher register
+pub struct FinisherCommand;
+impl FinisherCommand {
+    /// The upper 16 bits hold the exit status
+    pub const FAIL: u32 = 0x3333;
+    pub const PASS: u32 = 0x5555;
+}
+
+/// Exit status requested by a write to the finisher register, the other
+/// values (such as the reset command) are ignored
+pub fn exit_status(value: u32) -> Option<u32> {
+    match value & 0xffff {
+        FinisherCommand::PASS => Some(0),
+        FinisherCommand::FAIL => Some(value >> 16),
+        _ => None,
+    }
+}
@@ -0,0 +1,13 @@
+pub mod aclint;
+pub mod aia;
+pub mod cache_model;
+pub mod consts;
+pub mod cpu;
+pub mod device_tree;
+pub mod error;
+pub mod finisher;
+pub mod machine;
+pub mod memory;
+pub mod scheduler;
+pub mod system_bus;
+pub mod trace;
@@ -0,0 +1,521 @@
+use std::{
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU64, Ordering},
+        Mutex, MutexGuard, OnceLock,
+    },
+    thread::{self, Thread},
+};
+
+use crate::{
+    aclint::{self, Mswi, Mtimer, MTIME_OFFSET},
+    aia::{Aplic, Imsic, InterruptDomain, Msi},
+    consts::{
+        ACLINT_MSWI_BASE_ADDR, ACLINT_MSWI_SIZE, ACLINT_MTIMER_BASE_ADDR, ACLINT_MTIMER_SIZE,
+        APLIC_M_BASE_ADDR, APLIC_SIZE, APLIC_S_BASE_ADDR, FINISHER_BASE_ADDR, FINISHER_SIZE,
+        IMSIC_M_BASE_ADDR, IMSIC_S_BASE_ADDR, PAGE_SIZE, RESERVATION_GRANULE,
+    },
+    cpu::{config::CpuConfig, extensions::Extension, interrupts::InterruptFields, Cpu},
+    device_tree::{self, MachineDescription},
+    error::{AppErrors, AppResult},
+    finisher,
+    memory::{MemoryOpSize, SystemMemory},
+};
+
+/// Memory mapped devices
+#[derive(Clone, Copy)]
+enum MmioDevice {
+    Aplic(InterruptDomain),
+    Imsic,
+    Mswi,
+    Mtimer,
+    Finisher,
+}
+
+/// Devices shared by the harts, behind a single lock as the APLIC writes its
+/// MSIs to the IMSIC
+pub struct Devices {
+    pub aplic: Aplic,
+    pub imsic: Imsic,
+    pub mswi: Mswi,
+}
+
+impl Devices {
+    /// Bits of mip asserted by the devices for a hart
+    fn interrupts(&self, hart: usize) -> u64 {
+        let pending = |domain| {
+            self.imsic.interrupt_pending(hart, domain) || self.aplic.interrupt_pending(hart, domain)
+        };
+        let mut interrupts = 0;
+        if pending(InterruptDomain::Machine) {
+            interrupts |= InterruptFields::MEI;
+        }
+        if pending(InterruptDomain::Supervisor) {
+            interrupts |= InterruptFields::SEI;
+        }
+        if self.mswi.interrupt_pending(hart) {
+            interrupts |= InterruptFields::MSI;
+        }
+        interrupts
+    }
+}
+
+/// Waiting granule of the harts that are not waiting in WRS
+const NO_GRANULE: u64 = u64::MAX;
+
+/// Interrupt signals of a hart, read by the hart without taking the device lock
+struct HartSignals {
+    /// Bits of mip asserted by the devices
+    interrupts: AtomicU64,
+    /// Host thread running the hart, woken up when its interrupts change
+    thread: Mutex<Option<Thread>>,
+    /// Code pages written by any hart since the hart last synchronized its
+    /// decoded instructions, `code_written` is set while it isn't empty
+    written_code_pages: Mutex<Vec<usize>>,
+    code_written: AtomicBool,
+    /// Reservation granule the hart waits on in WRS, NO_GRANULE otherwise
+    waiting_granule: AtomicU64,
+}
+
+/// Machine shared by the harts: system memory, devices and time. Each hart
+/// accesses it through its own system bus.
+pub struct Machine {
+    pub memory: SystemMemory,
+    devices: Mutex<Devices>,
+    /// Compared against the time of each hart without taking the device lock
+    mtimer: Mtimer,
+    harts: Vec<HartSignals>,
+    /// Latest time reached by the harts, each hart counts time on its own and
+    /// catches up with the others periodically
+    time: AtomicU64,
+    /// Set once a hart stops, the other harts stop with it
+    stopped: AtomicBool,
+    /// Exit status written to the finisher, the first write wins
+    exit_status: OnceLock<u32>,
+    /// RAM pages some hart decoded instructions from, one bit per page
+    code_pages: Vec<AtomicU64>,
+    /// RAM pages holding a granule some hart waited on in WRS, the bits are
+    /// never cleared
+    watched_pages: Vec<AtomicU64>,
+    /// Incremented each time the stores to a page start going through the
+    /// system bus, the harts drop their store TLB mappings when it changes
+    store_generation: AtomicU64,
+    device_tree_addr: u64,
+}
+
+impl Machine {
+    pub fn new(config: &CpuConfig, init_code: &[u8], hart_count: usize) -> AppResult<Self> {
+        let memory = SystemMemory::new(&config.memory, init_code)?;
+        let mut machine = Self {
+            code_pages: (0..memory.page_count().div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            watched_pages: (0..memory.page_count().div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            store_generation: AtomicU64::new(0),
+            memory,
+            devices: Mutex::new(Devices {
+                aplic: Aplic::new(hart_count),
+                imsic: Imsic::new(hart_count),
+                mswi: Mswi::new(hart_count),
+            }),
+            mtimer: Mtimer::new(hart_count),
+            harts: (0..hart_count)
+                .map(|_| HartSignals {
+                    interrupts: AtomicU64::new(0),
+                    thread: Mutex::new(None),
+                    written_code_pages: Mutex::new(Vec::new()),
+                    code_written: AtomicBool::new(false),
+                    waiting_granule: AtomicU64::new(NO_GRANULE),
+                })
+                .collect(),
+            time: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+            exit_status: OnceLock::new(),
+            device_tree_addr: 0,
+        };
+        //RAM would shadow the devices
+        if let Some(region) = config.memory.iter().find(|region| {
+            machine
+                .device_windows()
+                .iter()
+                .any(|(base, size)| region.base < base + size && *base < region.base + region.size)
+        }) {
+            return Err(AppErrors::InvalidMemoryRegion {
+                base: region.base,
+                reason: "the region overlaps a device",
+            });
+        }
+        machine.device_tree_addr = machine.load_device_tree(config);
+        Ok(machine)
+    }
+
+    /// Places the device tree blob at the end of the boot region and returns
+    /// its address
+    fn load_device_tree(&self, config: &CpuConfig) -> u64 {
+        let extensions = config.profile.extensions();
+        let memory = self.memory.layout();
+        let (boot_base, boot_size) = memory[0];
+        let blob = device_tree::generate(&MachineDescription {
+            isa: &extensions.isa_string(config.xlen, config.base),
+            mmu_type: Cpu::mmu_type(config.xlen),
+            memory: &memory,
+            cache_block_size: config.cache_block_size,
+            aia: extensions.contains(Extension::Smaia),
+            harts: self.hart_count(),
+        });
+        let addr = (boot_base + boot_size - blob.len() as u64) & !0x7;
+        for (offset, byte) in blob.iter().enumerate() {
+            self.memory
+                .store(addr + offset as u64, MemoryOpSize::B8, *byte as u64)
+                .expect("Device tree does not fit in memory");
+        }
+        addr
+    }
+
+    pub fn device_tree_addr(&self) -> u64 {
+        self.device_tree_addr
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
+
+    /// Registers the calling thread as the one running a hart
+    pub fn attach_thread(&self, hart: usize) {
+        *self.harts[hart].thread.lock().unwrap() = Some(thread::current());
+    }
+
+    /// Bits of mip asserted by the devices for a hart
+    #[inline(always)]
+    pub fn interrupts(&self, hart: usize) -> u64 {
+        self.harts[hart].interrupts.load(Ordering::Acquire)
+    }
+
+    /// Gives access to the devices, the interrupt signals of the harts are
+    /// updated afterwards and the harts whose signals changed are woken up
+    pub fn update_devices<T>(&self, update: impl FnOnce(&mut Devices) -> T) -> T {
+        let mut devices = self.devices.lock().unwrap();
+        let result = update(&mut devices);
+        self.signal_interrupts(&devices);
+        result
+    }
+
+    /// Read-only access to the devices
+    pub fn devices(&self) -> MutexGuard<'_, Devices> {
+        self.devices.lock().unwrap()
+    }
+
+    fn signal_interrupts(&self, devices: &Devices) {
+        for (hart, signals) in self.harts.iter().enumerate() {
+            let interrupts = devices.interrupts(hart);
+            if signals.interrupts.swap(interrupts, Ordering::AcqRel) != interrupts {
+                Self::wake(signals);
+            }
+        }
+    }
+
+    fn wake(signals: &HartSignals) {
+        if let Some(thread) = signals.thread.lock().unwrap().as_ref() {
+            thread.unpark();
+        }
+    }
+
+    /// Stops every hart, the ones idle in WFI are woken up
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.harts.iter().for_each(Self::wake);
+    }
+
+    #[inline(always)]
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Exit status of the guest once it powered the machine off through the
+    /// finisher, None if the machine stopped for another reason or still runs
+    pub fn exit_status(&self) -> Option<u32> {
+        self.exit_status.get().copied()
+    }
+
+    /// Marks a RAM page as holding decoded instructions, the stores to it have
+    /// to go through the system bus of the harts
+    pub fn mark_code_page(&self, page: usize) {
+        let bit = 1 << (page % 64);
+        if self.code_pages[page / 64].fetch_or(bit, Ordering::AcqRel) & bit == 0 {
+            self.store_generation.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_code_page(&self, page: usize) -> bool {
+        self.code_pages[page / 64].load(Ordering::Acquire) & (1 << (page % 64)) != 0
+    }
+
+    /// Whether the stores to a RAM page have to go through the system bus of
+    /// the harts as they are tracked
+    #[inline(always)]
+    pub fn tracks_stores(&self, page: usize) -> bool {
+        let bit = 1 << (page % 64);
+        (self.code_pages[page / 64].load(Ordering::Acquire)
+            | self.watched_pages[page / 64].load(Ordering::Acquire))
+            & bit
+            != 0
+    }
+
+    #[inline(always)]
+    pub fn store_generation(&self) -> u64 {
+        self.store_generation.load(Ordering::Acquire)
+    }
+
+    /// Registers a hart waiting in WRS for a store to a reservation granule,
+    /// the stores to its page go through the system bus from then on
+    pub fn watch_granule(&self, hart: usize, granule: u64) {
+        let Some(page) = self.memory.page_index(granule) else {
+            return;
+        };
+        let bit = 1 << (page % 64);
+        if self.watched_pages[page / 64].fetch_or(bit, Ordering::AcqRel) & bit == 0 {
+            self.store_generation.fetch_add(1, Ordering::AcqRel);
+        }
+        self.harts[hart]
+            .waiting_granule
+            .store(granule, Ordering::SeqCst);
+        //Orders the registration before the check of the reserved value
+        fence(Ordering::SeqCst);
+    }
+
+    pub fn unwatch_granule(&self, hart: usize) {
+        self.harts[hart]
+            .waiting_granule
+            .store(NO_GRANULE, Ordering::SeqCst);
+    }
+
+    /// Wakes up the harts waiting on a granule written by a store
+    #[inline(always)]
+    pub fn notify_store(&self, page: usize, addr: u64, size: u64) {
+        //Orders the store before the check of the waiting harts
+        fence(Ordering::SeqCst);
+        if self.watched_pages[page / 64].load(Ordering::Relaxed) & (1 << (page % 64)) == 0 {
+            return;
+        }
+        for signals in &self.harts {
+            let granule = signals.waiting_granule.load(Ordering::SeqCst);
+            if granule != NO_GRANULE
+                && addr < granule + RESERVATION_GRANULE
+                && granule < addr + size
+            {
+                Self::wake(signals);
+            }
+        }
+    }
+
+    /// Notifies every hart of a write to a code page, the page is no longer a
+    /// code page until a hart decodes instructions from it again
+    #[inline(always)]
+    pub fn track_code_write(&self, page: usize) {
+        let bit = 1 << (page % 64);
+        if !self.is_code_page(page)
+            || self.code_pages[page / 64].fetch_and(!bit, Ordering::AcqRel) & bit == 0
+        {
+            return;
+        }
+        for signals in &self.harts {
+            signals.written_code_pages.lock().unwrap().push(page);
+            signals.code_written.store(true, Ordering::Release);
+        }
+    }
+
+    /// Whether code pages were written since the hart last took them
+    #[inline(always)]
+    pub fn has_written_code_pages(&self, hart: usize) -> bool {
+        self.harts[hart].code_written.load(Ordering::Acquire)
+    }
+
+    /// Returns the code pages written since the last call of the hart
+    pub fn take_written_code_pages(&self, hart: usize) -> Option<Vec<usize>> {
+        let signals = &self.harts[hart];
+        if !signals.code_written.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut pages = signals.written_code_pages.lock().unwrap();
+        signals.code_written.store(false, Ordering::Release);
+        Some(std::mem::take(&mut *pages))
+    }
+
+    /// Value of the ACLINT mtimecmp register of a hart
+    #[inline(always)]
+    pub fn mtimecmp(&self, hart: usize) -> u64 {
+        self.mtimer.mtimecmp(hart)
+    }
+
+    /// Publishes the time reached by a hart and returns the latest time of the machine
+    #[inline(always)]
+    pub fn synchronize_time(&self, time: u64) -> u64 {
+        self.time.fetch_max(time, Ordering::Relaxed).max(time)
+    }
+
+    /// Device mapped at an address and the offset of the address in its region
+    fn device_at(&self, addr: u64) -> Option<(MmioDevice, u64)> {
+        let imsic_size = self.hart_count() as u64 * PAGE_SIZE;
+        if (APLIC_M_BASE_ADDR..APLIC_M_BASE_ADDR + APLIC_SIZE).contains(&addr) {
+            Some((
+                MmioDevice::Aplic(InterruptDomain::Machine),
+                addr - APLIC_M_BASE_ADDR,
+            ))
+        } else if (APLIC_S_BASE_ADDR..APLIC_S_BASE_ADDR + APLIC_SIZE).contains(&addr) {
+            Some((
+                MmioDevice::Aplic(InterruptDomain::Supervisor),
+                addr - APLIC_S_BASE_ADDR,
+            ))
+        } else if (IMSIC_M_BASE_ADDR..IMSIC_M_BASE_ADDR + imsic_size).contains(&addr)
+            || (IMSIC_S_BASE_ADDR..IMSIC_S_BASE_ADDR + imsic_size).contains(&addr)
+        {
+            Some((MmioDevice::Imsic, addr))
+        } else if (ACLINT_MSWI_BASE_ADDR..ACLINT_MSWI_BASE_ADDR + ACLINT_MSWI_SIZE).contains(&addr)
+        {
+            Some((MmioDevice::Mswi, addr - ACLINT_MSWI_BASE_ADDR))
+        } else if (ACLINT_MTIMER_BASE_ADDR..ACLINT_MTIMER_BASE_ADDR + ACLINT_MTIMER_SIZE)
+            .contains(&addr)
+        {
+            Some((MmioDevice::Mtimer, addr - ACLINT_MTIMER_BASE_ADDR))
+        } else if (FINISHER_BASE_ADDR..FINISHER_BASE_ADDR + FINISHER_SIZE).contains(&addr) {
+            Some((MmioDevice::Finisher, addr - FINISHER_BASE_ADDR))
+        } else {
+            None
+        }
+    }
+
+    /// Base and size of the address ranges decoded by the devices
+    fn device_windows(&self) -> [(u64, u64); 7] {
+        let imsic_size = self.hart_count() as u64 * PAGE_SIZE;
+        [
+            (APLIC_M_BASE_ADDR, APLIC_SIZE),
+            (APLIC_S_BASE_ADDR, APLIC_SIZE),
+            (IMSIC_M_BASE_ADDR, imsic_size),
+            (IMSIC_S_BASE_ADDR, imsic_size),
+            (ACLINT_MSWI_BASE_ADDR, ACLINT_MSWI_SIZE),
+            (ACLINT_MTIMER_BASE_ADDR, ACLINT_MTIMER_SIZE),
+            (FINISHER_BASE_ADDR, FINISHER_SIZE),
+        ]
+    }
+
+    /// Returns true if the address belongs to a memory mapped device
+    #[inline(always)]
+    pub fn is_mmio(&self, addr: u64) -> bool {
+        self.device_at(addr).is_some()
+    }
+
+    /// Whether a device supports an access, the device registers are 32 bits
+    /// wide and only support aligned word accesses except the 64-bit MTIMER
+    /// registers that can also be accessed as a whole
+    fn is_mmio_access_supported(device: MmioDevice, addr: u64, size: &MemoryOpSize) -> bool {
+        let supported = match device {
+            MmioDevice::Mtimer => matches!(size, MemoryOpSize::B32 | MemoryOpSize::B64),
+            _ => matches!(size, MemoryOpSize::B32),
+        };
+        supported && addr.is_multiple_of(size.bytes())
+    }
+
+    pub fn load_mmio(&self, addr: u64, size: MemoryOpSize) -> AppResult<u64> {
+        let Some((device, offset)) = self.device_at(addr) else {
+            return Err(AppErrors::AddressNotFound);
+        };
+        if !Self::is_mmio_access_supported(device, addr, &size) {
+            return Err(AppErrors::LoadAccessFault { addr });
+        }
+        if let MmioDevice::Mtimer = device {
+            let mtime = self.time.load(Ordering::Relaxed);
+            return Ok(self.mtimer.load(offset, size, mtime));
+        }
+        //Reading the claim registers of the APLIC changes its state
+        let value = self.update_devices(|devices| match device {
+            MmioDevice::Aplic(domain) => devices.aplic.load(domain, offset),
+            MmioDevice::Imsic => devices.imsic.load(offset),
+            MmioDevice::Mswi => devices.mswi.load(offset),
+            MmioDevice::Mtimer | MmioDevice::Finisher => 0,
+        });
+        Ok(value as u64)
+    }
+
+    pub fn store_mmio(&self, addr: u64, size: MemoryOpSize, value: u64) -> AppResult<()> {
+        let Some((device, offset)) = self.device_at(addr) else {
+            return Err(AppErrors::AddressNotFound);
+        };
+        if !Self::is_mmio_access_supported(device, addr, &size) {
+            return Err(AppErrors::StoreAccessFault { addr });
+        }
+        if let MmioDevice::Mtimer = device {
+            self.store_mtimer(offset, size, value);
+            return Ok(());
+        }
+        //Powering off needs no device state, the harts stop before their next instruction
+        if let MmioDevice::Finisher = device {
+            if let Some(status) = finisher::exit_status(value as u32).filter(|_| offset == 0) {
+                let _ = self.exit_status.set(status);
+                self.stop();
+            }
+            return Ok(());
+        }
+        self.update_devices(|devices| {
+            let msis = Self::store_device(devices, device, offset, value as u32);
+            self.send_msis(devices, msis);
+        });
+        Ok(())
+    }
+
+    /// Writing mtimecmp wakes its hart up so it waits for the new deadline.
+    /// Time can't go backwards, writing mtime only moves the time of the
+    /// machine forward and the harts catch up with it.
+    fn store_mtimer(&self, offset: u64, size: MemoryOpSize, value: u64) {
+        if offset & !0x7 == MTIME_OFFSET {
+            let mtime = self.time.load(Ordering::Relaxed);
+            self.time.fetch_max(
+                aclint::write_register(mtime, offset, size, value),
+                Ordering::Relaxed,
+            );
+            self.harts.iter().for_each(Self::wake);
+        } else if let Some(hart) = self.mtimer.store_mtimecmp(offset, size, value) {
+            Self::wake(&self.harts[hart]);
+        }
+    }
+
+    fn store_device(
+        devices: &mut Devices,
+        device: MmioDevice,
+        offset: u64,
+        value: u32,
+    ) -> Vec<Msi> {
+        match device {
+            MmioDevice::Aplic(domain) => return devices.aplic.store(domain, offset, value),
+            MmioDevice::Imsic => devices.imsic.store(offset, value),
+            MmioDevice::Mswi => devices.mswi.store(offset, value),
+            //MSIs can't power the machine off or reach the timer
+            MmioDevice::Mtimer | MmioDevice::Finisher => (),
+        }
+        Vec::new()
+    }
+
+    /// MSIs are regular writes, the ones to addresses that can't be written are dropped
+    fn send_msis(&self, devices: &mut Devices, mut msis: Vec<Msi>) {
+        let mut sent = 0;
+        while let Some(msi) = msis.get(sent).copied() {
+            sent += 1;
+            if self.memory.contains(msi.addr) {
+                let _ = self
+                    .memory
+                    .store(msi.addr, MemoryOpSize::B32, msi.data as u64);
+            } else if let Some((device, offset)) = self.device_at(msi.addr) {
+                msis.extend(Self::store_device(devices, device, offset, msi.data));
+            }
+        }
+    }
+
+    /// Drives a wired interrupt input of the APLIC
+    pub fn set_interrupt_level(&self, source: usize, level: bool) {
+        self.update_devices(|devices| {
+            let msis = devices.aplic.set_input(source, level);
+            self.send_msis(devices, msis);
+        });
+    }
+}
@@ -1,91 +1,267 @@
-use std::{env, fs::File, io::Read, time::Instant};
+use std::{env, fs::File, io::Read, path::PathBuf, process, sync::Arc, thread, time::Instant};
 
-use consts::DRAM_SIZE;
-use cpu::Cpu;
+use riscvemulator::{
+    consts::{BYTES_IN_MEGABYTE, PAGE_SIZE},
+    cpu::{
+        basic_blocks::ExecutionEngine,
+        config::CpuConfig,
+        extensions::{BaseIsa, IsaProfile},
+        misaligned::MisalignedAccessPolicy,
+        privilege::PrivilegeMode,
+        xlen::Xlen,
+    },
+    machine::Machine,
+    memory::RamRegion,
+    scheduler::{self, Schedule, SchedulerConfig},
+    trace::{dump_registers, OpcodeClass, TraceConfig},
+};
 
-#[cfg(feature = "debug")]
-use std::{thread, time::Duration};
+const USAGE: &str = "Usage: emulator [--cache-block-size <bytes>] [--cache-stats] \
+    [--profile <rv64iafd|rva20|rva22|rva23|max>] [--xlen <32|64>] [--base <i|e>] \
+    [--pmp-entries <0|16|64>] [--pmp-granularity <bytes>] [--strict-pbmt] \
+    [--misaligned <emulate|trap|fault>] [--mvendorid <id>] [--marchid <id>] [--mimpid <id>] \
+    [--mconfigptr <addr>] [--engine <interpreter|blocks|jit>] [--lockstep] [--trace] [--trace-histogram] \
+    [--trace-pc <start>:<end>] [--trace-privilege <m|s|u>] \
+    [--trace-class <alu|load|store|branch|jump|atomic|fence|system|other>] \
+    [--trace-sample <n>] [--dump-registers] [--memory-size <bytes>] [--memory-file <path>] \
+    [--memory-file-overwrite] [--memory-region <base>:<size>[:<path>]]... [--harts <n>] \
+    [--schedule <threads|round-robin|random>] [--quantum <n>] [--seed <n>] <filename>";
 
-use crate::cpu::side_effects::OperationSideEffect;
-#[cfg(feature = "debug")]
-use crate::debug::{init_debug_print_thread_channel, DebugMessages};
+/// Parses a decimal or 0x prefixed hexadecimal number
+fn parse_number(value: String) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
 
-mod consts;
-mod cpu;
-#[cfg(feature = "debug")]
-mod debug;
-mod error;
-mod memory;
-mod system_bus;
+/// Parses a number of bytes, optionally followed by a K, M or G suffix
+fn parse_size(value: String) -> Option<u64> {
+    let (number, unit) = match value.char_indices().last()? {
+        (index, 'K') => (&value[..index], 1024),
+        (index, 'M') => (&value[..index], BYTES_IN_MEGABYTE),
+        (index, 'G') => (&value[..index], 1024 * BYTES_IN_MEGABYTE),
+        _ => (value.as_str(), 1),
+    };
+    parse_number(number.to_string())?.checked_mul(unit)
+}
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+fn parse_args() -> (CpuConfig, SchedulerConfig, TraceConfig, String) {
+    let mut config = CpuConfig::default();
+    let mut scheduler = SchedulerConfig::default();
+    let mut trace = TraceConfig::default();
+    let mut file_name = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cache-block-size" => {
+                let size: u64 = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .expect(USAGE);
+                if !size.is_power_of_two() || !(8..=PAGE_SIZE).contains(&size) {
+                    panic!("Cache block size must be a power of two between 8 and {PAGE_SIZE}");
+                }
+                config.cache_block_size = size;
+            }
+            "--cache-stats" => config.cache_statistics = true,
+            "--profile" => {
+                config.profile = args
+                    .next()
+                    .and_then(|name| IsaProfile::from_name(&name))
+                    .expect(USAGE);
+            }
+            "--xlen" => {
+                config.xlen = args
+                    .next()
+                    .and_then(|xlen| Xlen::from_name(&xlen))
+                    .expect(USAGE);
+            }
+            "--base" => {
+                config.base = args
+                    .next()
+                    .and_then(|base| BaseIsa::from_name(&base))
+                    .expect(USAGE);
+            }
+            "--pmp-entries" => {
+                config.pmp_entries = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|entries| [0, 16, 64].contains(entries))
+                    .expect(USAGE);
+            }
+            "--pmp-granularity" => {
+                let size: u64 = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .expect(USAGE);
+                if !size.is_power_of_two() || size < 4 {
+                    panic!("PMP granularity must be a power of two of at least 4 bytes");
+                }
+                config.pmp_granularity = size.trailing_zeros() - 2;
+            }
+            "--strict-pbmt" => config.strict_pbmt = true,
+            "--misaligned" => {
+                config.misaligned_access = args
+                    .next()
+                    .and_then(|policy| MisalignedAccessPolicy::from_name(&policy))
+                    .expect(USAGE);
+            }
+            "--mvendorid" => {
+                config.machine_ids.vendor = args.next().and_then(parse_number).expect(USAGE)
+            }
+            "--marchid" => {
+                config.machine_ids.architecture = args.next().and_then(parse_number).expect(USAGE)
+            }
+            "--mimpid" => {
+                config.machine_ids.implementation = args.next().and_then(parse_number).expect(USAGE)
+            }
+            "--mconfigptr" => {
+                config.machine_ids.config_pointer = args.next().and_then(parse_number).expect(USAGE)
+            }
+            "--engine" => {
+                config.engine = args
+                    .next()
+                    .and_then(|engine| ExecutionEngine::from_name(&engine))
+                    .expect(USAGE);
+            }
+            #[cfg(feature = "jit")]
+            "--lockstep" => config.lockstep = true,
+            "--trace" => trace.print = true,
+            "--trace-histogram" => trace.histogram = true,
+            "--trace-pc" => {
+                let range = args.next().expect(USAGE);
+                let (start, end) = range.split_once(':').expect(USAGE);
+                trace.filter.pc_range = Some(
+                    parse_number(start.to_string()).expect(USAGE)
+                        ..=parse_number(end.to_string()).expect(USAGE),
+                );
+            }
+            "--trace-privilege" => {
+                trace.filter.privilege_mode = args
+                    .next()
+                    .and_then(|mode| PrivilegeMode::from_name(&mode))
+                    .map(Some)
+                    .expect(USAGE);
+            }
+            "--trace-class" => {
+                trace.filter.opcode_class = args
+                    .next()
+                    .and_then(|class| OpcodeClass::from_name(&class))
+                    .map(Some)
+                    .expect(USAGE);
+            }
+            "--trace-sample" => {
+                trace.sample_interval = args.next().and_then(parse_number).expect(USAGE)
+            }
+            "--dump-registers" => trace.dump_registers = true,
+            //The first region is the one holding the boot image
+            "--memory-size" => {
+                config.memory[0].size = args.next().and_then(parse_size).expect(USAGE)
+            }
+            "--memory-file" => config.memory[0].file = Some(args.next().expect(USAGE).into()),
+            "--memory-file-overwrite" => config.memory[0].overwrite = true,
+            "--memory-region" => {
+                let region = args.next().expect(USAGE);
+                let mut fields = region.splitn(3, ':');
+                config.memory.push(RamRegion {
+                    base: fields
+                        .next()
+                        .and_then(|base| parse_number(base.to_string()))
+                        .expect(USAGE),
+                    size: fields
+                        .next()
+                        .and_then(|size| parse_size(size.to_string()))
+                        .expect(USAGE),
+                    file: fields.next().map(PathBuf::from),
+                    snapshot: false,
+                    overwrite: false,
+                });
+            }
+            "--harts" => {
+                config.harts = args
+                    .next()
+                    .and_then(parse_number)
+                    .filter(|harts| (1..=64).contains(harts))
+                    .expect(USAGE) as usize
+            }
+            "--schedule" => {
+                scheduler.schedule = args
+                    .next()
+                    .and_then(|schedule| Schedule::from_name(&schedule))
+                    .expect(USAGE);
+            }
+            "--quantum" => {
+                scheduler.quantum = args
+                    .next()
+                    .and_then(parse_number)
+                    .filter(|quantum| *quantum > 0)
+                    .expect(USAGE)
+            }
+            "--seed" => scheduler.seed = args.next().and_then(parse_number).expect(USAGE),
+            _ if file_name.is_none() && !arg.starts_with("--") => file_name = Some(arg),
+            _ => panic!("{USAGE}"),
+        }
+    }
+    (config, scheduler, trace, file_name.expect(USAGE))
+}
 
-    if args.len() != 2 {
-        panic!("Usage: emulator <filename>");
+fn main() {
+    let (config, scheduler, mut trace, file_name) = parse_args();
+    let missing = config.profile.unimplemented_extensions();
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: profile {} runs without its unimplemented extensions: {}",
+            config.profile.name(),
+            missing.join(", ")
+        );
     }
-    let mut file = File::open(&args[1]).unwrap();
+    let mut file = File::open(file_name).unwrap();
     let mut code = Vec::new();
     file.read_to_end(&mut code).unwrap();
 
-    #[cfg(feature = "debug")]
-    let (debug_thread_handle, debug_tx) = init_debug_print_thread_channel();
-
-    let mut cpu = Cpu::new(DRAM_SIZE, code);
+    #[cfg(feature = "jit")]
+    if config.lockstep && (config.harts > 1 || scheduler.schedule != Schedule::Threads) {
+        panic!("Lockstep runs a single hart on its own thread");
+    }
+    trace.harts = config.harts;
+    let machine =
+        Arc::new(Machine::new(&config, &code, config.harts).unwrap_or_else(|err| panic!("{err}")));
 
     let now = Instant::now();
-    loop {
-        #[cfg(feature = "debug")]
-        let debug_cycle_start = now.elapsed().as_nanos();
-
-        let fetched_instruction = match cpu.fetch_next_instruction() {
-            Ok(inst) => inst,
-            Err(err) => {
-                let program_counter = cpu.get_program_counter();
-                eprintln!("{program_counter:0x}: {err}");
-                break;
-            }
-        };
-
-        // #[cfg(feature = "debug")]
-        let fetched_pc = cpu.get_program_counter();
-        let execution_result = cpu.execute(fetched_instruction);
-
-        #[cfg(feature = "debug")]
-        {
-            debug_tx
-                .send(DebugMessages::InstructionExecution {
-                    time_elapsed_ns: now.elapsed().as_nanos() - debug_cycle_start,
-                    pc: fetched_pc,
-                    instruction: fetched_instruction,
-                    registers: cpu.get_registers(),
+    let registers: Vec<Vec<u64>> = match scheduler.schedule {
+        Schedule::Threads => thread::scope(|scope| {
+            let harts: Vec<_> = (0..config.harts)
+                .map(|hart_id| {
+                    let (machine, config, trace, code) = (&machine, config.clone(), &trace, &code);
+                    thread::Builder::new()
+                        .name(format!("hart{hart_id}"))
+                        .spawn_scoped(scope, move || {
+                            scheduler::run_hart(machine, hart_id, config, trace, code)
+                        })
+                        .expect("Cannot spawn the hart thread")
                 })
-                .unwrap();
-        }
-
-        match execution_result {
-            Ok(OperationSideEffect::None) => (),
-            Err(err) => {
-                eprintln!("{fetched_pc:0x}: {fetched_instruction:0x} {err}");
-                break;
-            }
-            _ => (),
-        };
-    }
+                .collect();
+            harts
+                .into_iter()
+                .map(|hart| hart.join().expect("Hart thread panicked"))
+                .collect()
+        }),
+        _ => scheduler::run_interleaved(&machine, config.clone(), &scheduler, &trace),
+    };
 
     let run_time = now.elapsed();
-    #[cfg(feature = "debug")]
-    {
-        debug_tx
-            .send(DebugMessages::DumpRegisters(cpu.get_registers()))
-            .unwrap();
-        debug_tx.send(DebugMessages::Terminate).unwrap();
-        loop {
-            if debug_thread_handle.is_finished() {
-                break;
-            };
-            thread::sleep(Duration::from_millis(100));
+    if trace.dump_registers {
+        for (hart_id, registers) in registers.iter().enumerate() {
+            if config.harts > 1 {
+                println!("hart {hart_id}:");
+            }
+            dump_registers(registers);
         }
     }
     println!("Total Execution time: {:.2?}", run_time);
+    //The exit status written to the finisher becomes the one of the emulator
+    if let Some(status) = machine.exit_status() {
+        process::exit(status as i32);
+    }
 }
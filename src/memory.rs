@@ -1,10 +1,235 @@
-use std::cmp::Ordering;
+use std::{
+    fs::{File, OpenOptions},
+    io::Read,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Mutex,
+    },
+};
 
-use crate::error::{AppErrors, AppResult};
+use memmap2::{MmapMut, MmapOptions};
 
+use crate::{
+    consts::{DRAM_BASE_ADDR, PAGE_SIZE},
+    error::{AppErrors, AppResult},
+};
+
+/// RAM region of the physical address space
+#[derive(Clone, Debug)]
+pub struct RamRegion {
+    pub base: u64,
+    pub size: u64,
+    /// Host file mapped as the content of the region, the guest writes are
+    /// written back to it. Anonymous memory is used otherwise.
+    pub file: Option<PathBuf>,
+    /// Copies the content of the file into anonymous memory instead of
+    /// mapping it, the guest writes don't reach the file
+    pub snapshot: bool,
+    /// Allows the boot image and the device tree to be written over the data
+    /// of an existing file mapped as the boot region
+    pub overwrite: bool,
+}
+
+/// Host mapping of a RAM region, host memory is only committed for the pages
+/// the guest touches
+struct MappedRegion {
+    base: u64,
+    size: u64,
+    /// Index of the first page of the region, pages are numbered across the
+    /// regions in the order they are mapped
+    first_page: usize,
+    /// Start of the mapping, the harts access it concurrently so it is only
+    /// accessed atomically
+    host: *mut u8,
+    mapping: MmapMut,
+    /// Mapped from a file that already held data, the guest writes reach it
+    holds_file_data: bool,
+}
+
+impl MappedRegion {
+    fn new(region: &RamRegion, first_page: usize) -> AppResult<Self> {
+        let map_failed = |source| AppErrors::MemoryMapFailed {
+            base: region.base,
+            source,
+        };
+        let anonymous = || {
+            MmapOptions::new()
+                .len(region.size as usize)
+                .no_reserve_swap()
+                .map_anon()
+                .map_err(map_failed)
+        };
+        let mut holds_file_data = false;
+        let mut mapping = match &region.file {
+            Some(path) if region.snapshot => {
+                let mut mapping = anonymous()?;
+                let file = File::open(path).map_err(map_failed)?;
+                //Files larger than the region are truncated
+                let mut content = file.take(region.size);
+                let mut filled = 0;
+                while filled < mapping.len() {
+                    match content.read(&mut mapping[filled..]).map_err(map_failed)? {
+                        0 => break,
+                        read => filled += read,
+                    }
+                }
+                mapping
+            }
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .map_err(map_failed)?;
+                let len = file.metadata().map_err(map_failed)?.len();
+                holds_file_data = len > 0;
+                if len < region.size {
+                    file.set_len(region.size).map_err(map_failed)?;
+                }
+                //The file must not be resized by another process while it is mapped
+                unsafe {
+                    MmapOptions::new()
+                        .len(region.size as usize)
+                        .map_mut(&file)
+                        .map_err(map_failed)?
+                }
+            }
+            None => anonymous()?,
+        };
+        Ok(Self {
+            base: region.base,
+            size: region.size,
+            first_page,
+            host: mapping.as_mut_ptr(),
+            mapping,
+            holds_file_data,
+        })
+    }
+}
+
+/// Guest RAM made of disjoint regions, the region at the start of the system
+/// memory holds the boot image. It is shared by the harts, aligned accesses are
+/// single atomic accesses so a hart never observes a torn value.
 pub struct SystemMemory {
-    pub data: Vec<u8>,
+    /// The boot region comes first as it serves most accesses
+    regions: Vec<MappedRegion>,
+    page_count: usize,
+    /// Serializes the 16-byte compare and swaps and the atomic operations
+    /// crossing two words on hosts without a 16-byte compare and swap, they are
+    /// then not atomic with respect to the plain stores of other harts
+    serialized_atomics: Mutex<()>,
+}
+
+//The mappings live as long as the system memory and are only accessed atomically
+unsafe impl Send for SystemMemory {}
+unsafe impl Sync for SystemMemory {}
+
+/// Reads `size` little endian bytes at a host address of guest memory, as a
+/// single access if it is aligned and byte by byte otherwise
+///
+/// # Safety
+/// The bytes must be inside a mapped region
+#[inline(always)]
+pub unsafe fn read_host(host: *const u8, size: u64) -> u64 {
+    let host = host as *mut u8;
+    if !(host as u64).is_multiple_of(size) {
+        return (0..size).fold(0, |value, byte| {
+            value
+                | (AtomicU8::from_ptr(host.add(byte as usize)).load(Ordering::Relaxed) as u64)
+                    << (8 * byte)
+        });
+    }
+    match size {
+        1 => AtomicU8::from_ptr(host).load(Ordering::Relaxed) as u64,
+        2 => u16::from_le(AtomicU16::from_ptr(host as *mut u16).load(Ordering::Relaxed)) as u64,
+        4 => u32::from_le(AtomicU32::from_ptr(host as *mut u32).load(Ordering::Relaxed)) as u64,
+        _ => u64::from_le(AtomicU64::from_ptr(host as *mut u64).load(Ordering::Relaxed)),
+    }
+}
+
+/// Writes the `size` lower bytes of the value at a host address of guest
+/// memory in little endian order, as a single access if it is aligned
+///
+/// # Safety
+/// The bytes must be inside a mapped region
+#[inline(always)]
+pub unsafe fn write_host(host: *mut u8, size: u64, value: u64) {
+    if !(host as u64).is_multiple_of(size) {
+        for byte in 0..size {
+            AtomicU8::from_ptr(host.add(byte as usize))
+                .store((value >> (8 * byte)) as u8, Ordering::Relaxed);
+        }
+        return;
+    }
+    match size {
+        1 => AtomicU8::from_ptr(host).store(value as u8, Ordering::Relaxed),
+        2 => AtomicU16::from_ptr(host as *mut u16).store((value as u16).to_le(), Ordering::Relaxed),
+        4 => AtomicU32::from_ptr(host as *mut u32).store((value as u32).to_le(), Ordering::Relaxed),
+        _ => AtomicU64::from_ptr(host as *mut u64).store(value.to_le(), Ordering::Relaxed),
+    }
+}
+
+/// Whether the host has a 16-byte compare and swap, the atomic operations
+/// crossing two 64-bit words need it to be atomic with respect to the others
+#[inline(always)]
+fn has_compare_exchange_128() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        std::arch::is_x86_feature_detected!("cmpxchg16b")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+/// Atomically replaces the 16 little endian bytes at an aligned host address
+/// by the result of the operation on their value, nothing is stored when it
+/// returns None. Returns the replaced value.
+///
+/// # Safety
+/// The bytes must be inside a mapped region and the host must have a 16-byte
+/// compare and swap
+#[cfg(target_arch = "x86_64")]
+unsafe fn fetch_update_128(host: *mut u8, mut operation: impl FnMut(u128) -> Option<u128>) -> u128 {
+    /// LLVM reserves rbx, the low half of the new value is swapped into it
+    unsafe fn compare_exchange(host: *mut u128, current: u128, new: u128) -> u128 {
+        let (low, high): (u64, u64);
+        std::arch::asm!(
+            "xchg {new_low}, rbx",
+            "lock cmpxchg16b xmmword ptr [{host}]",
+            "mov rbx, {new_low}",
+            host = in(reg) host,
+            new_low = inout(reg) new as u64 => _,
+            in("rcx") (new >> 64) as u64,
+            inout("rax") current as u64 => low,
+            inout("rdx") (current >> 64) as u64 => high,
+            options(nostack),
+        );
+        low as u128 | (high as u128) << 64
+    }
+    let host = host as *mut u128;
+    //Exchanging zero for zero is an atomic load
+    let mut value = compare_exchange(host, 0, 0);
+    loop {
+        let Some(new) = operation(u128::from_le(value)) else {
+            return u128::from_le(value);
+        };
+        match compare_exchange(host, value, new.to_le()) {
+            previous if previous == value => return u128::from_le(value),
+            previous => value = previous,
+        }
+    }
 }
+
+#[cfg(not(target_arch = "x86_64"))]
+unsafe fn fetch_update_128(_: *mut u8, _: impl FnMut(u128) -> Option<u128>) -> u128 {
+    unreachable!("The host has no 16-byte compare and swap")
+}
+
 #[derive(Clone, Debug)]
 pub enum MemoryOpSize {
     B8,
@@ -13,138 +238,508 @@ pub enum MemoryOpSize {
     B64,
 }
 
-impl SystemMemory {
-    pub fn new(size_bytes: u64, initial_progam_code: Vec<u8>) -> Self {
-        let mut data = vec![0; size_bytes as usize];
-        data.splice(
-            ..initial_progam_code.len(),
-            initial_progam_code.iter().cloned(),
-        );
-        Self { data }
+impl MemoryOpSize {
+    #[inline(always)]
+    pub fn bytes(&self) -> u64 {
+        match self {
+            MemoryOpSize::B8 => 1,
+            MemoryOpSize::B16 => 2,
+            MemoryOpSize::B32 => 4,
+            MemoryOpSize::B64 => 8,
+        }
     }
 
+    /// Sign extends the lower bytes of the value that fit in this size
     #[inline(always)]
-    pub fn load(&self, addr: u64, size: MemoryOpSize) -> AppResult<u64> {
-        match self.validate_mem_address(addr, size.clone()) {
-            Ok(_) => match size {
-                MemoryOpSize::B8 => self.load8(addr).map(|r| r as u64),
-                MemoryOpSize::B16 => self.load16(addr).map(|r| r as u64),
-                MemoryOpSize::B32 => self.load32(addr).map(|r| r as u64),
-                MemoryOpSize::B64 => self.load64(addr),
-            },
-            Err(err) => Err(err),
+    pub fn sign_extend(&self, value: u64) -> u64 {
+        match self {
+            MemoryOpSize::B8 => value as i8 as i64 as u64,
+            MemoryOpSize::B16 => value as i16 as i64 as u64,
+            MemoryOpSize::B32 => value as i32 as i64 as u64,
+            MemoryOpSize::B64 => value,
         }
     }
 
+    /// Zero extends the lower bytes of the value that fit in this size
     #[inline(always)]
-    pub fn store(&mut self, addr: u64, size: MemoryOpSize, value: u64) -> AppResult<()> {
-        self.validate_mem_address(addr, size.clone())
-            .map(|_| match size {
-                MemoryOpSize::B8 => self.store8(addr, value),
-                MemoryOpSize::B16 => self.store16(addr, value),
-                MemoryOpSize::B32 => self.store32(addr, value),
-                MemoryOpSize::B64 => self.store64(addr, value),
-            })
+    pub fn zero_extend(&self, value: u64) -> u64 {
+        match self {
+            MemoryOpSize::B8 => value as u8 as u64,
+            MemoryOpSize::B16 => value as u16 as u64,
+            MemoryOpSize::B32 => value as u32 as u64,
+            MemoryOpSize::B64 => value,
+        }
+    }
+}
+
+impl SystemMemory {
+    pub fn new(regions: &[RamRegion], initial_progam_code: &[u8]) -> AppResult<Self> {
+        Self::validate(regions)?;
+        let mut ordered: Vec<&RamRegion> = regions.iter().collect();
+        ordered.sort_by_key(|region| (region.base != DRAM_BASE_ADDR, region.base));
+        let mut mapped = Vec::with_capacity(ordered.len());
+        let mut page_count = 0;
+        for region in &ordered {
+            mapped.push(MappedRegion::new(region, page_count)?);
+            page_count += (region.size / PAGE_SIZE) as usize;
+        }
+        //Loading the boot image would silently clobber the data of the file
+        if mapped[0].holds_file_data && !ordered[0].overwrite {
+            return Err(AppErrors::InvalidMemoryRegion {
+                base: DRAM_BASE_ADDR,
+                reason:
+                    "the file of the region already holds data, the boot image would overwrite it",
+            });
+        }
+        let boot = &mut mapped[0].mapping;
+        if initial_progam_code.len() > boot.len() {
+            return Err(AppErrors::InvalidMemoryRegion {
+                base: DRAM_BASE_ADDR,
+                reason: "the program does not fit in the region",
+            });
+        }
+        boot[..initial_progam_code.len()].copy_from_slice(initial_progam_code);
+        Ok(Self {
+            regions: mapped,
+            page_count,
+            serialized_atomics: Mutex::new(()),
+        })
     }
 
-    fn validate_mem_address(&self, addr: u64, access_size: MemoryOpSize) -> AppResult<()> {
-        let address_offset = match access_size {
-            MemoryOpSize::B8 => 0,
-            MemoryOpSize::B16 => 1,
-            MemoryOpSize::B32 => 2,
-            MemoryOpSize::B64 => 3,
+    /// Regions must be made of whole pages, must not overlap and one of them
+    /// must start at the system memory base address
+    fn validate(regions: &[RamRegion]) -> AppResult<()> {
+        let invalid = |region: &RamRegion, reason| {
+            Err(AppErrors::InvalidMemoryRegion {
+                base: region.base,
+                reason,
+            })
         };
-        match ((addr + address_offset) as usize).cmp(&self.data.len()) {
-            Ordering::Less => Ok(()),
-            _ => Err(AppErrors::OutOfBoundsPointer),
+        for (index, region) in regions.iter().enumerate() {
+            if region.size == 0
+                || !region.base.is_multiple_of(PAGE_SIZE)
+                || !region.size.is_multiple_of(PAGE_SIZE)
+            {
+                return invalid(region, "the base and size must be page aligned");
+            }
+            if region.base.checked_add(region.size).is_none() {
+                return invalid(region, "the region exceeds the physical address space");
+            }
+            if regions[..index].iter().any(|other| {
+                region.base < other.base + other.size && other.base < region.base + region.size
+            }) {
+                return invalid(region, "the region overlaps another region");
+            }
+        }
+        match regions.iter().any(|region| region.base == DRAM_BASE_ADDR) {
+            true => Ok(()),
+            false => Err(AppErrors::InvalidMemoryRegion {
+                base: DRAM_BASE_ADDR,
+                reason: "no region starts at the boot address",
+            }),
         }
     }
 
     #[inline(always)]
-    pub fn load8(&self, addr: u64) -> AppResult<u8> {
-        match self.validate_mem_address(addr, MemoryOpSize::B8) {
-            Ok(()) => {
-                let index = addr as usize;
-                Ok(self.data[index] as u8)
-            }
-            Err(err) => Err(err),
+    fn region(&self, addr: u64) -> Option<&MappedRegion> {
+        self.regions
+            .iter()
+            .find(|region| addr.wrapping_sub(region.base) < region.size)
+    }
+
+    /// Returns true if the address is in a RAM region
+    #[inline(always)]
+    pub fn contains(&self, addr: u64) -> bool {
+        self.region(addr).is_some()
+    }
+
+    /// Number of pages of all the regions
+    pub fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    /// Index of the page of an address among the pages of all the regions
+    #[inline(always)]
+    pub fn page_index(&self, addr: u64) -> Option<usize> {
+        self.region(addr)
+            .map(|region| region.first_page + ((addr - region.base) / PAGE_SIZE) as usize)
+    }
+
+    /// Base and size of the regions, the boot region first
+    pub fn layout(&self) -> Vec<(u64, u64)> {
+        self.regions
+            .iter()
+            .map(|region| (region.base, region.size))
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn store(&self, addr: u64, size: MemoryOpSize, value: u64) -> AppResult<()> {
+        match size {
+            MemoryOpSize::B8 => self.write::<1>(addr, value),
+            MemoryOpSize::B16 => self.write::<2>(addr, value),
+            MemoryOpSize::B32 => self.write::<4>(addr, value),
+            MemoryOpSize::B64 => self.write::<8>(addr, value),
         }
     }
 
+    /// Host address of an access of N bytes, accesses can't span two regions
+    #[inline(always)]
+    fn host<const N: usize>(&self, addr: u64) -> AppResult<*mut u8> {
+        self.regions
+            .iter()
+            .find(|region| addr.wrapping_sub(region.base) < region.size)
+            .filter(|region| addr - region.base + N as u64 <= region.size)
+            //The offset is inside the mapping
+            .map(|region| unsafe { region.host.add((addr - region.base) as usize) })
+            .ok_or(AppErrors::OutOfBoundsPointer)
+    }
+
+    /// Reads N bytes in little endian order, a single bounds check for the whole access
+    #[inline(always)]
+    fn read<const N: usize>(&self, addr: u64) -> AppResult<u64> {
+        let host = self.host::<N>(addr)?;
+        Ok(unsafe { read_host(host, N as u64) })
+    }
+
+    /// Writes the N lower bytes of the value in little endian order
+    #[inline(always)]
+    fn write<const N: usize>(&self, addr: u64, value: u64) -> AppResult<()> {
+        let host = self.host::<N>(addr)?;
+        unsafe { write_host(host, N as u64, value) };
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn load8(&self, addr: u64) -> AppResult<u8> {
+        self.read::<1>(addr).map(|value| value as u8)
+    }
+
     #[inline(always)]
     pub fn load16(&self, addr: u64) -> AppResult<u16> {
-        match self.validate_mem_address(addr, MemoryOpSize::B16) {
-            Ok(()) => {
-                let index = addr as usize;
-                Ok((self.data[index] as u16) | ((self.data[index + 1] as u16) << 8))
-            }
-            Err(err) => Err(err),
-        }
+        self.read::<2>(addr).map(|value| value as u16)
     }
 
     #[inline(always)]
     pub fn load32(&self, addr: u64) -> AppResult<u32> {
-        match self.validate_mem_address(addr, MemoryOpSize::B32) {
-            Ok(()) => {
-                let index = addr as usize;
-                Ok((self.data[index] as u32)
-                    | ((self.data[index + 1] as u32) << 8)
-                    | ((self.data[index + 2] as u32) << 16)
-                    | ((self.data[index + 3] as u32) << 24))
-            }
-            Err(err) => Err(err),
-        }
+        self.read::<4>(addr).map(|value| value as u32)
     }
 
     #[inline(always)]
     pub fn load64(&self, addr: u64) -> AppResult<u64> {
-        match self.validate_mem_address(addr, MemoryOpSize::B64) {
-            Ok(()) => {
-                let index = addr as usize;
-                Ok((self.data[index] as u64)
-                    | ((self.data[index + 1] as u64) << 8)
-                    | ((self.data[index + 2] as u64) << 16)
-                    | ((self.data[index + 3] as u64) << 24)
-                    | ((self.data[index + 4] as u64) << 32)
-                    | ((self.data[index + 5] as u64) << 40)
-                    | ((self.data[index + 6] as u64) << 48)
-                    | ((self.data[index + 7] as u64) << 56))
+        self.read::<8>(addr)
+    }
+
+    /// Atomically replaces the `size` bytes at an address by the result of the
+    /// operation on their value, nothing is stored when it returns None. Returns
+    /// the replaced value, the operation runs again if another hart wrote the
+    /// bytes in the meantime. Accesses inside an aligned word are native atomic
+    /// updates on every host, only the ones crossing two words may be serialized.
+    pub fn fetch_update(
+        &self,
+        addr: u64,
+        size: MemoryOpSize,
+        mut operation: impl FnMut(u64) -> Option<u64>,
+    ) -> AppResult<u64> {
+        let bytes = size.bytes();
+        let host = match size {
+            MemoryOpSize::B8 => self.host::<1>(addr)?,
+            MemoryOpSize::B16 => self.host::<2>(addr)?,
+            MemoryOpSize::B32 => self.host::<4>(addr)?,
+            MemoryOpSize::B64 => self.host::<8>(addr)?,
+        };
+        let offset = host as u64 % 8;
+        let (Ok(value) | Err(value)) = match (offset, bytes) {
+            (0, 8) => unsafe { AtomicU64::from_ptr(host as *mut u64) }.fetch_update(
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                |value| operation(u64::from_le(value)).map(u64::to_le),
+            ),
+            //Narrower accesses update the aligned word holding them
+            (offset, _) if offset % 4 + bytes <= 4 => {
+                let shift = 8 * (offset % 4);
+                let mask = (size.zero_extend(u64::MAX) as u32) << shift;
+                let word =
+                    unsafe { AtomicU32::from_ptr(host.sub(offset as usize % 4) as *mut u32) };
+                word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |word| {
+                    let word = u32::from_le(word);
+                    operation(((word & mask) >> shift) as u64)
+                        .map(|value| ((word & !mask) | (((value as u32) << shift) & mask)).to_le())
+                })
+                .map(|word| ((u32::from_le(word) & mask) >> shift) as u64)
+                .map_err(|word| ((u32::from_le(word) & mask) >> shift) as u64)
+            }
+            (offset, _) if offset + bytes <= 8 => {
+                let shift = 8 * offset;
+                let mask = size.zero_extend(u64::MAX) << shift;
+                let word = unsafe { AtomicU64::from_ptr(host.sub(offset as usize) as *mut u64) };
+                word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |word| {
+                    let word = u64::from_le(word);
+                    operation((word & mask) >> shift)
+                        .map(|value| ((word & !mask) | ((value << shift) & mask)).to_le())
+                })
+                .map(|word| (u64::from_le(word) & mask) >> shift)
+                .map_err(|word| (u64::from_le(word) & mask) >> shift)
+            }
+            //Accesses crossing two words stay in an aligned 16-byte block (Zama16b)
+            _ if has_compare_exchange_128() => {
+                let offset = host as u64 % 16;
+                let shift = 8 * offset;
+                let mask = (size.zero_extend(u64::MAX) as u128) << shift;
+                let block = unsafe {
+                    fetch_update_128(host.sub(offset as usize), |block| {
+                        operation(((block & mask) >> shift) as u64)
+                            .map(|value| (block & !mask) | (((value as u128) << shift) & mask))
+                    })
+                };
+                Ok(((block & mask) >> shift) as u64)
+            }
+            _ => {
+                let _guard = self.serialized_atomics.lock().unwrap();
+                let value = unsafe { read_host(host, bytes) };
+                if let Some(result) = operation(value) {
+                    unsafe { write_host(host, bytes, result) };
+                }
+                Ok(value)
+            }
+        };
+        Ok(value)
+    }
+
+    /// Atomically stores `new` in the 16 bytes at an address if they hold
+    /// `expected`, returns the previous value
+    pub fn compare_exchange_128(&self, addr: u64, expected: u128, new: u128) -> AppResult<u128> {
+        let host = self.host::<16>(addr)?;
+        if has_compare_exchange_128() {
+            return Ok(unsafe {
+                fetch_update_128(host, |value| (value == expected).then_some(new))
+            });
+        }
+        let _guard = self.serialized_atomics.lock().unwrap();
+        let read = |offset| unsafe { read_host(host.add(offset), 8) } as u128;
+        let value = read(0) | read(8) << 64;
+        if value == expected {
+            unsafe {
+                write_host(host, 8, new as u64);
+                write_host(host.add(8), 8, (new >> 64) as u64);
             }
-            Err(err) => Err(err),
         }
+        Ok(value)
     }
-    #[inline(always)]
-    fn store8(&mut self, addr: u64, value: u64) {
-        let index = addr as usize;
-        self.data[index] = (value & 0xff) as u8;
+
+    /// Host address of the page of an address, the mappings are never moved
+    pub fn host_page(&self, addr: u64) -> Option<*mut u8> {
+        let region = self.region(addr)?;
+        let offset = ((addr - region.base) & !(PAGE_SIZE - 1)) as usize;
+        //The page is inside the mapping
+        Some(unsafe { region.host.add(offset) })
     }
+}
 
-    #[inline(always)]
-    fn store16(&mut self, addr: u64, value: u64) {
-        let index = addr as usize;
-        self.data[index] = (value & 0xff) as u8;
-        self.data[index + 1] = ((value >> 8) & 0xff) as u8;
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::consts::BYTES_IN_MEGABYTE;
+
+    const HIGH_BASE: u64 = 0x1_0000_0000;
+
+    fn region(base: u64, size: u64) -> RamRegion {
+        RamRegion {
+            base,
+            size,
+            file: None,
+            snapshot: false,
+            overwrite: false,
+        }
     }
 
-    #[inline(always)]
-    fn store32(&mut self, addr: u64, value: u64) {
-        let index = addr as usize;
-        self.data[index] = (value & 0xff) as u8;
-        self.data[index + 1] = ((value >> 8) & 0xff) as u8;
-        self.data[index + 2] = ((value >> 16) & 0xff) as u8;
-        self.data[index + 3] = ((value >> 24) & 0xff) as u8;
+    /// Path of a scratch file of the test, removed when dropped
+    struct ScratchFile(PathBuf);
+
+    impl ScratchFile {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("riscvemulator-{}-{name}", std::process::id()));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
     }
 
-    #[inline(always)]
-    fn store64(&mut self, addr: u64, value: u64) {
-        let index = addr as usize;
-        self.data[index] = (value & 0xff) as u8;
-        self.data[index + 1] = ((value >> 8) & 0xff) as u8;
-        self.data[index + 2] = ((value >> 16) & 0xff) as u8;
-        self.data[index + 3] = ((value >> 24) & 0xff) as u8;
-        self.data[index + 4] = ((value >> 32) & 0xff) as u8;
-        self.data[index + 5] = ((value >> 40) & 0xff) as u8;
-        self.data[index + 6] = ((value >> 48) & 0xff) as u8;
-        self.data[index + 7] = ((value >> 56) & 0xff) as u8;
+    impl Drop for ScratchFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn disjoint_regions_are_numbered_from_the_boot_region() {
+        //4 GiB are only committed as the guest touches them
+        let memory = SystemMemory::new(
+            &[
+                region(HIGH_BASE, 4096 * BYTES_IN_MEGABYTE),
+                region(DRAM_BASE_ADDR, 16 * PAGE_SIZE),
+            ],
+            &[0x13, 0, 0, 0],
+        )
+        .unwrap();
+        assert_eq!(
+            memory.layout(),
+            [
+                (DRAM_BASE_ADDR, 16 * PAGE_SIZE),
+                (HIGH_BASE, 4096 * BYTES_IN_MEGABYTE)
+            ]
+        );
+        assert_eq!(memory.page_count(), 16 + (1 << 20));
+        assert_eq!(memory.load32(DRAM_BASE_ADDR).unwrap(), 0x13);
+        assert_eq!(memory.page_index(DRAM_BASE_ADDR + PAGE_SIZE), Some(1));
+        assert_eq!(memory.page_index(HIGH_BASE + PAGE_SIZE), Some(17));
+        assert!(!memory.contains(DRAM_BASE_ADDR + 16 * PAGE_SIZE));
+        let last = HIGH_BASE + 4096 * BYTES_IN_MEGABYTE - 8;
+        memory.store(last, MemoryOpSize::B64, u64::MAX).unwrap();
+        assert_eq!(memory.load64(last).unwrap(), u64::MAX);
+        //Accesses can't leave a region
+        assert!(matches!(
+            memory.load64(DRAM_BASE_ADDR + 16 * PAGE_SIZE - 4),
+            Err(AppErrors::OutOfBoundsPointer)
+        ));
+    }
+
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        let rejected = |regions: &[RamRegion], program: &[u8]| {
+            matches!(
+                SystemMemory::new(regions, program),
+                Err(AppErrors::InvalidMemoryRegion { .. })
+            )
+        };
+        let boot = region(DRAM_BASE_ADDR, PAGE_SIZE);
+        assert!(rejected(&[region(DRAM_BASE_ADDR, PAGE_SIZE + 1)], &[]));
+        assert!(rejected(&[region(HIGH_BASE, PAGE_SIZE)], &[]));
+        assert!(rejected(
+            &[
+                boot.clone(),
+                region(DRAM_BASE_ADDR - PAGE_SIZE, 2 * PAGE_SIZE)
+            ],
+            &[]
+        ));
+        assert!(rejected(
+            &[
+                boot.clone(),
+                region(u64::MAX - PAGE_SIZE + 1, 2 * PAGE_SIZE)
+            ],
+            &[]
+        ));
+        assert!(rejected(&[boot], &[0; PAGE_SIZE as usize + 1]));
+    }
+
+    #[test]
+    fn file_backed_regions_keep_the_guest_writes() {
+        let file = ScratchFile::new("file-backed");
+        let backed = RamRegion {
+            file: Some(file.0.clone()),
+            ..region(HIGH_BASE, 2 * PAGE_SIZE)
+        };
+        let regions = [region(DRAM_BASE_ADDR, PAGE_SIZE), backed.clone()];
+        let memory = SystemMemory::new(&regions, &[]).unwrap();
+        memory
+            .store(HIGH_BASE + PAGE_SIZE, MemoryOpSize::B32, 0x1234_5678)
+            .unwrap();
+        drop(memory);
+        let content = fs::read(&file.0).unwrap();
+        assert_eq!(content.len(), 2 * PAGE_SIZE as usize);
+        assert_eq!(
+            content[PAGE_SIZE as usize..][..4],
+            0x1234_5678_u32.to_le_bytes()
+        );
+        //Mapped again the region starts from the file data
+        let memory = SystemMemory::new(&regions, &[]).unwrap();
+        assert_eq!(memory.load32(HIGH_BASE + PAGE_SIZE).unwrap(), 0x1234_5678);
+    }
+
+    #[test]
+    fn snapshots_leave_the_file_unchanged() {
+        let file = ScratchFile::new("snapshot");
+        fs::write(&file.0, [0xaa; 16]).unwrap();
+        let snapshot = RamRegion {
+            file: Some(file.0.clone()),
+            snapshot: true,
+            ..region(DRAM_BASE_ADDR, PAGE_SIZE)
+        };
+        let memory = SystemMemory::new(&[snapshot], &[]).unwrap();
+        assert_eq!(
+            memory.load64(DRAM_BASE_ADDR + 8).unwrap(),
+            0xaaaa_aaaa_aaaa_aaaa
+        );
+        assert_eq!(memory.load64(DRAM_BASE_ADDR + 16).unwrap(), 0);
+        memory.store(DRAM_BASE_ADDR, MemoryOpSize::B8, 0).unwrap();
+        drop(memory);
+        assert_eq!(fs::read(&file.0).unwrap(), [0xaa; 16]);
+    }
+
+    #[test]
+    fn boot_files_holding_data_need_overwrite() {
+        let file = ScratchFile::new("boot");
+        fs::write(&file.0, [0xaa; 16]).unwrap();
+        let boot = RamRegion {
+            file: Some(file.0.clone()),
+            ..region(DRAM_BASE_ADDR, PAGE_SIZE)
+        };
+        assert!(matches!(
+            SystemMemory::new(std::slice::from_ref(&boot), &[0x13]),
+            Err(AppErrors::InvalidMemoryRegion { .. })
+        ));
+        let overwrite = RamRegion {
+            overwrite: true,
+            ..boot
+        };
+        let memory = SystemMemory::new(&[overwrite], &[0x13]).unwrap();
+        assert_eq!(memory.load16(DRAM_BASE_ADDR).unwrap(), 0xaa13);
+    }
+
+    #[test]
+    fn atomic_updates_leave_the_neighbouring_bytes() {
+        let memory = SystemMemory::new(&[region(DRAM_BASE_ADDR, PAGE_SIZE)], &[]).unwrap();
+        memory
+            .store(DRAM_BASE_ADDR, MemoryOpSize::B64, 0x8877_6655_4433_2211)
+            .unwrap();
+        memory
+            .store(DRAM_BASE_ADDR + 8, MemoryOpSize::B64, 0xffee_ddcc_bbaa_0099)
+            .unwrap();
+        let add = |addr, size: MemoryOpSize| {
+            let bytes = size.bytes();
+            memory
+                .fetch_update(DRAM_BASE_ADDR + addr, size, |value| {
+                    Some(value.wrapping_add(1) & (u64::MAX >> (64 - 8 * bytes)))
+                })
+                .unwrap()
+        };
+        assert_eq!(add(1, MemoryOpSize::B8), 0x22);
+        assert_eq!(add(6, MemoryOpSize::B16), 0x8877);
+        assert_eq!(add(4, MemoryOpSize::B32), 0x8878_6655);
+        assert_eq!(
+            memory.load64(DRAM_BASE_ADDR).unwrap(),
+            0x8878_6656_4433_2311
+        );
+        //Misaligned updates crossing two words
+        assert_eq!(add(6, MemoryOpSize::B32), 0x0099_8878);
+        assert_eq!(add(4, MemoryOpSize::B64), 0xbbaa_0099_8879_6656);
+        assert_eq!(
+            memory.load64(DRAM_BASE_ADDR).unwrap(),
+            0x8879_6657_4433_2311
+        );
+        assert_eq!(
+            memory.load64(DRAM_BASE_ADDR + 8).unwrap(),
+            0xffee_ddcc_bbaa_0099
+        );
+        //Nothing is stored when the operation gives up
+        let loaded = memory
+            .fetch_update(DRAM_BASE_ADDR + 2, MemoryOpSize::B16, |_| None)
+            .unwrap();
+        assert_eq!(loaded, 0x4433);
+        assert_eq!(
+            memory.load64(DRAM_BASE_ADDR).unwrap(),
+            0x8879_6657_4433_2311
+        );
     }
 }
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+#[cfg(feature = "jit")]
+use crate::cpu::basic_blocks::ExecutionEngine;
+use crate::{
+    cpu::{config::CpuConfig, side_effects::OperationSideEffect, Cpu},
+    machine::Machine,
+    trace::TraceConfig,
+};
+
+/// Instructions a hart runs per turn when the harts share a host thread
+pub const DEFAULT_QUANTUM: u64 = 1000;
+
+/// How the harts are mapped onto host threads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Each hart runs on its own host thread
+    Threads,
+    /// The harts take turns of a quantum of instructions on a single thread
+    RoundRobin,
+    /// Each turn goes to a random runnable hart and lasts a random number of
+    /// instructions up to the quantum, drawn from a seeded generator
+    Random,
+}
+
+impl Schedule {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "threads" => Some(Schedule::Threads),
+            "round-robin" => Some(Schedule::RoundRobin),
+            "random" => Some(Schedule::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    pub schedule: Schedule,
+    /// Instructions per turn, the block engines end a turn on a block boundary
+    pub quantum: u64,
+    /// Seed of the random schedule, the same seed replays the same interleaving
+    pub seed: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            schedule: Schedule::Threads,
+            quantum: DEFAULT_QUANTUM,
+            seed: 0,
+        }
+    }
+}
+
+/// SplitMix64 generator, small and stable across platforms and releases
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform enough value in 0..bound for picking harts and turn lengths
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// Prefix naming the hart in the error messages when there are several
+fn hart_prefix(machine: &Machine, hart_id: usize) -> String {
+    match machine.hart_count() {
+        1 => String::new(),
+        _ => format!("hart {hart_id}: "),
+    }
+}
+
+fn registers(cpu: &mut Cpu) -> Vec<u64> {
+    cpu.get_registers()[..cpu.register_count() as usize].to_vec()
+}
+
+/// Runs a hart on the calling thread until it stops or another hart stops,
+/// the other harts are stopped with it. Returns the registers of the hart.
+pub fn run_hart(
+    machine: &Arc<Machine>,
+    hart_id: usize,
+    config: CpuConfig,
+    trace: &TraceConfig,
+    code: &[u8],
+) -> Vec<u64> {
+    machine.attach_thread(hart_id);
+    //The reference hart interprets the same program one instruction at a time
+    #[cfg(feature = "jit")]
+    let mut reference = config.lockstep.then(|| {
+        let mut reference_config = config.clone();
+        reference_config.engine = ExecutionEngine::Interpreter;
+        //Only the checked hart writes to the memory files
+        reference_config
+            .memory
+            .iter_mut()
+            .for_each(|region| region.snapshot = true);
+        let machine =
+            Machine::new(&reference_config, code, 1).unwrap_or_else(|err| panic!("{err}"));
+        let mut reference = Cpu::new(Arc::new(machine), hart_id, reference_config);
+        reference.system_bus.record_writes();
+        reference
+    });
+    #[cfg(not(feature = "jit"))]
+    let _ = code;
+    let mut cpu = Cpu::new(Arc::clone(machine), hart_id, config);
+    if let Some(tracer) = trace.tracer() {
+        cpu.attach_tracer(tracer);
+    }
+    let hart = hart_prefix(machine, hart_id);
+
+    while !machine.is_stopped() {
+        let result = cpu.step();
+        #[cfg(feature = "jit")]
+        if let (Some(reference), Ok(_)) = (reference.as_mut(), &result) {
+            let divergence = match cpu.run_reference(reference) {
+                Ok(()) => cpu.diverges_from(reference),
+                Err(err) => Some(format!(
+                    "{err} at {:#x} in the reference hart",
+                    reference.get_program_counter()
+                )),
+            };
+            if let Some(divergence) = divergence {
+                eprintln!("{hart}Lockstep divergence: {divergence}");
+                break;
+            }
+        }
+        match result {
+            Ok(OperationSideEffect::WaitForInterrupt) => cpu.wait_for_interrupt(),
+            Ok(OperationSideEffect::WaitForStore { bounded }) => cpu.wait_for_store(bounded),
+            Ok(_) => (),
+            Err(err) => {
+                eprintln!("{hart}{:0x}: {err}", cpu.get_program_counter());
+                break;
+            }
+        }
+    }
+
+    machine.stop();
+    //Waits for the observers to print the whole trace
+    cpu.detach_tracer();
+    registers(&mut cpu)
+}
+
+/// Moves the tracer between two harts of the slice
+fn hand_over_tracer(cpus: &mut [Cpu], from: usize, to: usize) {
+    if from == to {
+        return;
+    }
+    let (low, high) = cpus.split_at_mut(from.max(to));
+    match from < to {
+        true => low[from].hand_over_tracer(&mut high[0]),
+        false => high[0].hand_over_tracer(&mut low[to]),
+    }
+}
+
+/// Interleaves every hart of the machine on the calling thread. Nothing
+/// depends on the host: WFI doesn't sleep, WRS ends the turn, time jumps to
+/// the next timer deadline once every hart waits, so a schedule and a seed
+/// always replay the same run. Returns the registers of the harts.
+pub fn run_interleaved(
+    machine: &Arc<Machine>,
+    config: CpuConfig,
+    scheduler: &SchedulerConfig,
+    trace: &TraceConfig,
+) -> Vec<Vec<u64>> {
+    let mut cpus: Vec<Cpu> = (0..machine.hart_count())
+        .map(|hart_id| Cpu::new(Arc::clone(machine), hart_id, config.clone()))
+        .collect();
+    //A single tracer follows the running hart, so the trace is in execution order
+    if let Some(tracer) = trace.tracer() {
+        cpus[0].attach_tracer(tracer);
+    }
+    let mut traced = 0;
+    let mut waiting = vec![false; cpus.len()];
+    let mut random = SplitMix64(scheduler.seed);
+    let mut current = cpus.len() - 1;
+
+    while !machine.is_stopped() {
+        //Stores of the other harts may have raised the interrupts of a waiting hart
+        for (cpu, waiting) in cpus.iter().zip(waiting.iter_mut()) {
+            *waiting &= cpu.must_wait();
+        }
+        if waiting.iter().all(|waiting| *waiting) {
+            let deadline = cpus.iter().filter_map(Cpu::timer_deadline).min();
+            if let Some(deadline) = deadline {
+                cpus.iter_mut().for_each(|cpu| cpu.advance_time(deadline));
+            }
+            for (cpu, waiting) in cpus.iter().zip(waiting.iter_mut()) {
+                *waiting = cpu.must_wait();
+            }
+            //Nothing can wake the harts up, WFI acts as a NOP
+            if waiting.iter().all(|waiting| *waiting) {
+                waiting.fill(false);
+            }
+        }
+
+        let runnable: Vec<usize> = (0..cpus.len()).filter(|hart| !waiting[*hart]).collect();
+        let turn = match scheduler.schedule {
+            Schedule::Random => {
+                current = runnable[random.below(runnable.len() as u64) as usize];
+                1 + random.below(scheduler.quantum)
+            }
+            _ => {
+                current = runnable
+                    .iter()
+                    .copied()
+                    .find(|hart| *hart > current)
+                    .unwrap_or(runnable[0]);
+                scheduler.quantum
+            }
+        };
+        hand_over_tracer(&mut cpus, traced, current);
+        traced = current;
+
+        let cpu = &mut cpus[current];
+        let mut executed = 0;
+        //A hart powering the machine off ends the turn
+        while executed < turn && !machine.is_stopped() {
+            let start = cpu.executed_instructions();
+            let result = cpu.step();
+            //Taking an interrupt executes no instruction, a turn always ends
+            executed += (cpu.executed_instructions() - start).max(1);
+            match result {
+                Ok(OperationSideEffect::WaitForInterrupt) if cpu.must_wait() => {
+                    waiting[current] = true;
+                    break;
+                }
+                //The other harts run until the next turn of the hart
+                Ok(OperationSideEffect::WaitForStore { .. }) => break,
+                Ok(_) => (),
+                Err(err) => {
+                    eprintln!(
+                        "{}{:0x}: {err}",
+                        hart_prefix(machine, current),
+                        cpu.get_program_counter()
+                    );
+                    machine.stop();
+                    break;
+                }
+            }
+        }
+    }
+
+    //Waits for the observers to print the whole trace
+    cpus[traced].detach_tracer();
+    cpus.iter_mut().map(registers).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::DRAM_BASE_ADDR;
+
+    /// Each hart appends its hart id to a shared log 100 times, the first
+    /// one done powers the machine off
+    const RACE: [u32; 17] = [
+        0x4010_0593, //addi a1, x0, 0x401
+        0x0155_9593, //slli a1, a1, 21
+        0xf140_2573, //csrr a0, mhartid
+        0x0010_0293, //addi t0, x0, 1
+        0x0640_0e93, //addi t4, x0, 100
+        0x0000_0e13, //addi t3, x0, 0
+        0x0055_b32f, //amoadd.d t1, t0, (a1)
+        0x0033_1313, //slli t1, t1, 3
+        0x0065_83b3, //add t2, a1, t1
+        0x00a3_b423, //sd a0, 8(t2)
+        0x001e_0e13, //addi t3, t3, 1
+        0xffde_46e3, //blt t3, t4, -20
+        0x0010_0f37, //lui t5, 0x100
+        0x0000_5fb7, //lui t6, 0x5
+        0x555f_8f93, //addi t6, t6, 0x555
+        0x01ff_2023, //sw t6, 0(t5)
+        0x0000_006f, //jal x0, 0
+    ];
+    const LOG: u64 = DRAM_BASE_ADDR + 0x20_0000;
+
+    /// Runs the race on interleaved harts, returns the log of hart ids and
+    /// the registers of the harts
+    fn race(harts: usize, scheduler: SchedulerConfig) -> (Vec<u64>, Vec<Vec<u64>>) {
+        let config = CpuConfig {
+            harts,
+            ..CpuConfig::default()
+        };
+        let code: Vec<u8> = RACE.iter().flat_map(|raw| raw.to_le_bytes()).collect();
+        let machine = Arc::new(Machine::new(&config, &code, harts).unwrap());
+        let registers = run_interleaved(&machine, config, &scheduler, &TraceConfig::default());
+        let entries = machine.memory.load64(LOG).unwrap();
+        let log = (0..entries)
+            .map(|entry| machine.memory.load64(LOG + 8 + 8 * entry).unwrap())
+            .collect();
+        (log, registers)
+    }
+
+    #[test]
+    fn round_robin_turns_last_a_quantum() {
+        //After the first turn, each turn runs one iteration of the loop
+        let (log, registers) = race(
+            3,
+            SchedulerConfig {
+                schedule: Schedule::RoundRobin,
+                quantum: 6,
+                seed: 0,
+            },
+        );
+        assert_eq!(log.len(), 3 * 100);
+        assert!(log
+            .iter()
+            .enumerate()
+            .all(|(entry, hart)| *hart == entry as u64 % 3));
+        //t3 counts the iterations of each hart
+        let iterations: Vec<u64> = registers.iter().map(|registers| registers[28]).collect();
+        assert_eq!(iterations, [100, 100, 100]);
+    }
+
+    #[test]
+    fn random_schedules_replay_with_the_same_seed() {
+        let scheduler = |seed| SchedulerConfig {
+            schedule: Schedule::Random,
+            quantum: 16,
+            seed,
+        };
+        let first = race(4, scheduler(7));
+        assert_eq!(race(4, scheduler(7)), first);
+        assert_ne!(race(4, scheduler(8)).0, first.0);
+        //Every hart got turns
+        assert!((0..4).all(|hart| first.0.contains(&hart)));
+    }
+}
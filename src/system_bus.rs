@@ -1,69 +1,248 @@
-use std::cmp::Ordering;
+use std::sync::Arc;
 
 use crate::{
-    consts::DRAM_BASE_ADDR,
+    cache_model::{CacheBlockOperation, CacheModel},
     error::{AppErrors, AppResult},
-    memory::{MemoryOpSize, SystemMemory},
+    machine::Machine,
+    memory::MemoryOpSize,
 };
 
 pub type BusOpSize = MemoryOpSize;
 
+/// View of the shared machine from a hart. The writes of every hart to the
+/// pages any hart decoded instructions from are reported to all the harts.
 pub struct SystemBus {
-    system_memory: SystemMemory,
+    machine: Arc<Machine>,
+    hart: usize,
+    cache_model: Option<Box<dyn CacheModel>>,
+    /// Store generation of the machine when the hart last dropped its store
+    /// TLB mappings
+    store_generation: u64,
+    /// RAM ranges written by the hart as address and size, only recorded
+    /// for the reference hart of the lockstep mode
+    written_ram: Option<Vec<(u64, u64)>>,
 }
 
 impl SystemBus {
-    pub fn new(memory_size: u64, init_code: Vec<u8>) -> Self {
+    pub fn new(machine: Arc<Machine>, hart: usize) -> Self {
         Self {
-            system_memory: SystemMemory::new(memory_size, init_code),
+            machine,
+            hart,
+            cache_model: None,
+            store_generation: 0,
+            written_ram: None,
         }
     }
 
-    #[allow(dead_code)]
-    pub fn load(&self, addr: u64, size: BusOpSize) -> AppResult<u64> {
-        match addr.cmp(&DRAM_BASE_ADDR) {
-            Ordering::Less => Err(AppErrors::AddressNotFound),
-            _ => self.system_memory.load(addr - DRAM_BASE_ADDR, size),
+    #[inline(always)]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Attaches a cache model that will observe every cache block operation
+    pub fn attach_cache_model(&mut self, cache_model: Box<dyn CacheModel>) {
+        self.cache_model = Some(cache_model);
+    }
+
+    #[inline(always)]
+    pub fn notify_cache_block_operation(&mut self, operation: CacheBlockOperation, addr: u64) {
+        if let Some(cache_model) = self.cache_model.as_mut() {
+            cache_model.observe(operation, addr);
         }
     }
 
     #[inline(always)]
-    pub fn load8(&self, addr: u64) -> AppResult<u8> {
-        match addr.cmp(&DRAM_BASE_ADDR) {
-            Ordering::Less => Err(AppErrors::AddressNotFound),
-            _ => self.system_memory.load8(addr - DRAM_BASE_ADDR),
+    pub fn load8(&mut self, addr: u64) -> AppResult<u8> {
+        match self.machine.memory.contains(addr) {
+            false => self
+                .machine
+                .load_mmio(addr, BusOpSize::B8)
+                .map(|value| value as u8),
+            true => self.machine.memory.load8(addr),
         }
     }
 
     #[inline(always)]
-    pub fn load16(&self, addr: u64) -> AppResult<u16> {
-        match addr.cmp(&DRAM_BASE_ADDR) {
-            Ordering::Less => Err(AppErrors::AddressNotFound),
-            _ => self.system_memory.load16(addr - DRAM_BASE_ADDR),
+    pub fn load16(&mut self, addr: u64) -> AppResult<u16> {
+        match self.machine.memory.contains(addr) {
+            false => self
+                .machine
+                .load_mmio(addr, BusOpSize::B16)
+                .map(|value| value as u16),
+            true => self.machine.memory.load16(addr),
         }
     }
 
     #[inline(always)]
-    pub fn load32(&self, addr: u64) -> AppResult<u32> {
-        match addr.cmp(&DRAM_BASE_ADDR) {
-            Ordering::Less => Err(AppErrors::AddressNotFound),
-            _ => self.system_memory.load32(addr - DRAM_BASE_ADDR),
+    pub fn load32(&mut self, addr: u64) -> AppResult<u32> {
+        match self.machine.memory.contains(addr) {
+            false => self
+                .machine
+                .load_mmio(addr, BusOpSize::B32)
+                .map(|value| value as u32),
+            true => self.machine.memory.load32(addr),
         }
     }
 
     #[inline(always)]
-    pub fn load64(&self, addr: u64) -> AppResult<u64> {
-        match addr.cmp(&DRAM_BASE_ADDR) {
-            Ordering::Less => Err(AppErrors::AddressNotFound),
-            _ => self.system_memory.load64(addr - DRAM_BASE_ADDR),
+    pub fn load64(&mut self, addr: u64) -> AppResult<u64> {
+        match self.machine.memory.contains(addr) {
+            false => self.machine.load_mmio(addr, BusOpSize::B64),
+            true => self.machine.memory.load64(addr),
         }
     }
 
     #[inline(always)]
     pub fn store(&mut self, addr: u64, size: BusOpSize, value: u64) -> AppResult<()> {
-        match addr.cmp(&DRAM_BASE_ADDR) {
-            Ordering::Less => Err(AppErrors::AddressNotFound),
-            _ => self.system_memory.store(addr - DRAM_BASE_ADDR, size, value),
+        match self.machine.memory.contains(addr) {
+            false => self.machine.store_mmio(addr, size, value),
+            true => {
+                self.machine.memory.store(addr, size.clone(), value)?;
+                self.track_write(addr, size.bytes());
+                Ok(())
+            }
+        }
+    }
+
+    /// Atomic read-modify-write of AMOs and SC, returns the replaced value. RAM
+    /// is updated atomically with respect to the other harts, device registers
+    /// are read and written back.
+    pub fn fetch_update(
+        &mut self,
+        addr: u64,
+        size: BusOpSize,
+        mut operation: impl FnMut(u64) -> Option<u64>,
+    ) -> AppResult<u64> {
+        if !self.machine.memory.contains(addr) {
+            let value = self.machine.load_mmio(addr, size.clone())?;
+            if let Some(result) = operation(value) {
+                self.machine.store_mmio(addr, size, result)?;
+            }
+            return Ok(value);
+        }
+        let value = self
+            .machine
+            .memory
+            .fetch_update(addr, size.clone(), &mut operation)?;
+        self.track_write(addr, size.bytes());
+        Ok(value)
+    }
+
+    /// Compare and swap of 16 bytes of RAM for amocas.q, the device registers
+    /// are too narrow for it
+    pub fn compare_exchange_128(
+        &mut self,
+        addr: u64,
+        expected: u128,
+        new: u128,
+    ) -> AppResult<u128> {
+        if self.machine.is_mmio(addr) {
+            return Err(AppErrors::StoreAccessFault { addr });
         }
+        let value = self
+            .machine
+            .memory
+            .compare_exchange_128(addr, expected, new)?;
+        self.track_write(addr, 16);
+        Ok(value)
+    }
+
+    /// Index of the RAM page of an address, pages are numbered across the RAM
+    /// regions. None if the address is not in RAM.
+    #[inline(always)]
+    pub fn page_index(&self, addr: u64) -> Option<usize> {
+        self.machine.memory.page_index(addr)
+    }
+
+    /// Marks the RAM page of an address as holding cached instructions
+    pub fn mark_code_page(&mut self, addr: u64) {
+        if let Some(page) = self.page_index(addr) {
+            self.machine.mark_code_page(page);
+        }
+    }
+
+    /// Returns the code pages written by any hart since the last call, the
+    /// pages are no longer tracked until they are marked again
+    #[inline(always)]
+    pub fn take_written_code_pages(&mut self) -> Option<Vec<usize>> {
+        self.machine.take_written_code_pages(self.hart)
+    }
+
+    /// Whether the RAM page of an address holds cached instructions
+    pub fn is_code_page(&self, addr: u64) -> bool {
+        self.page_index(addr)
+            .is_some_and(|page| self.machine.is_code_page(page))
+    }
+
+    /// Whether the stores to the RAM page of an address have to go through
+    /// the bus, as the page holds cached instructions or a watched granule or
+    /// the writes are recorded
+    pub fn tracks_stores(&self, addr: u64) -> bool {
+        self.written_ram.is_some()
+            || self
+                .page_index(addr)
+                .is_some_and(|page| self.machine.tracks_stores(page))
+    }
+
+    /// Records the RAM writes of the hart from now on, the stores no longer
+    /// go through the TLB
+    #[cfg(feature = "jit")]
+    pub fn record_writes(&mut self) {
+        self.written_ram = Some(Vec::new());
+    }
+
+    /// Returns the RAM ranges written since the last call as address and size
+    #[cfg(feature = "jit")]
+    pub fn take_written_ram(&mut self) -> Vec<(u64, u64)> {
+        self.written_ram
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Whether the stores to more pages are tracked since the last call, the
+    /// store TLB may map them
+    #[inline(always)]
+    pub fn take_new_tracked_pages(&mut self) -> bool {
+        let generation = self.machine.store_generation();
+        let changed = generation != self.store_generation;
+        self.store_generation = generation;
+        changed
+    }
+
+    /// Host address of the RAM page of a physical address, None if the page is
+    /// not RAM. The pages are never moved so the address stays valid as long
+    /// as the bus.
+    pub fn host_page(&mut self, addr: u64) -> Option<*mut u8> {
+        self.machine.memory.host_page(addr)
+    }
+
+    #[inline(always)]
+    pub fn has_written_code_pages(&self) -> bool {
+        self.machine.has_written_code_pages(self.hart)
+    }
+
+    /// Reports a RAM write to the harts caching instructions from its pages
+    /// or waiting on its granule
+    #[inline(always)]
+    fn track_write(&mut self, addr: u64, size: u64) {
+        //The write succeeded so both ends are in the same region
+        let (Some(first), Some(last)) = (self.page_index(addr), self.page_index(addr + size - 1))
+        else {
+            return;
+        };
+        if let Some(written_ram) = self.written_ram.as_mut() {
+            written_ram.push((addr, size));
+        }
+        for page in first..=last {
+            self.machine.track_code_write(page);
+            self.machine.notify_store(page, addr, size);
+        }
+    }
+
+    /// Returns true if the address belongs to a memory mapped device
+    #[inline(always)]
+    pub fn is_mmio(&self, addr: u64) -> bool {
+        self.machine.is_mmio(addr)
     }
 }
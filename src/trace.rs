@@ -0,0 +1,556 @@
+use std::{
+    io::{self, Write},
+    mem,
+    ops::RangeInclusive,
+    sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use crate::cpu::{
+    instructions::{decoder, disassembler::Disassembly, implementations::CpuInstructionsOpCodes},
+    privilege::PrivilegeMode,
+};
+
+/// Events handed to the observers at once
+const BATCH_SIZE: usize = 4096;
+/// Batches in flight between the hart and the observers, the hart waits for a
+/// free batch when the observers fall behind
+const BATCH_COUNT: usize = 4;
+
+/// Instruction executed by the hart, the register file is not copied
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEvent {
+    /// Index of the instruction among all the executed ones, sampled and
+    /// filtered out instructions included
+    pub sequence: u64,
+    pub hart: usize,
+    pub pc: u64,
+    pub instruction: u32,
+    pub privilege_mode: PrivilegeMode,
+}
+
+/// Consumer of the trace, runs on the tracing thread
+pub trait TraceObserver: Send {
+    /// Receives the recorded events in execution order
+    fn observe(&mut self, events: &[TraceEvent]);
+    /// Called after the last batch
+    fn finish(&mut self) {}
+}
+
+/// Group of opcodes an event filter can select
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpcodeClass {
+    Alu,
+    Load,
+    Store,
+    Branch,
+    Jump,
+    Atomic,
+    Fence,
+    System,
+    Other,
+}
+
+impl OpcodeClass {
+    const ALL: [OpcodeClass; 9] = [
+        OpcodeClass::Alu,
+        OpcodeClass::Load,
+        OpcodeClass::Store,
+        OpcodeClass::Branch,
+        OpcodeClass::Jump,
+        OpcodeClass::Atomic,
+        OpcodeClass::Fence,
+        OpcodeClass::System,
+        OpcodeClass::Other,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|class| class.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpcodeClass::Alu => "alu",
+            OpcodeClass::Load => "load",
+            OpcodeClass::Store => "store",
+            OpcodeClass::Branch => "branch",
+            OpcodeClass::Jump => "jump",
+            OpcodeClass::Atomic => "atomic",
+            OpcodeClass::Fence => "fence",
+            OpcodeClass::System => "system",
+            OpcodeClass::Other => "other",
+        }
+    }
+
+    #[inline(always)]
+    pub fn of(instruction: u32) -> Self {
+        match decoder::get_op_code(instruction) {
+            CpuInstructionsOpCodes::INT_REG_IMMEDIATE
+            | CpuInstructionsOpCodes::INT_REG_IMMEDIATE_RV64I
+            | CpuInstructionsOpCodes::INT_REG_IMMEDIATE_LUI
+            | CpuInstructionsOpCodes::INT_REG_IMMEDIATE_AUIPC
+            | CpuInstructionsOpCodes::INT_REG_REG_RV32I
+            | CpuInstructionsOpCodes::INT_REG_REG_RV64I => OpcodeClass::Alu,
+            CpuInstructionsOpCodes::LOAD | CpuInstructionsOpCodes::LOAD_FP => OpcodeClass::Load,
+            CpuInstructionsOpCodes::STORE | CpuInstructionsOpCodes::STORE_FP => OpcodeClass::Store,
+            CpuInstructionsOpCodes::CONDITIONAL_BRANCHES => OpcodeClass::Branch,
+            CpuInstructionsOpCodes::CONTROL_JAL | CpuInstructionsOpCodes::CONTROL_JALR => {
+                OpcodeClass::Jump
+            }
+            CpuInstructionsOpCodes::ATOMIC => OpcodeClass::Atomic,
+            CpuInstructionsOpCodes::MEM_ORDERING => OpcodeClass::Fence,
+            CpuInstructionsOpCodes::SYSCALLS_CSR => OpcodeClass::System,
+            _ => OpcodeClass::Other,
+        }
+    }
+}
+
+/// Selects the instructions that are recorded, every set condition must match
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    pub pc_range: Option<RangeInclusive<u64>>,
+    pub privilege_mode: Option<PrivilegeMode>,
+    pub opcode_class: Option<OpcodeClass>,
+}
+
+impl TraceFilter {
+    #[inline(always)]
+    fn matches(&self, pc: u64, instruction: u32, privilege_mode: PrivilegeMode) -> bool {
+        self.pc_range
+            .as_ref()
+            .is_none_or(|range| range.contains(&pc))
+            && self
+                .privilege_mode
+                .is_none_or(|mode| mode == privilege_mode)
+            && self
+                .opcode_class
+                .is_none_or(|class| class == OpcodeClass::of(instruction))
+    }
+}
+
+/// Tracing options selected on the command line
+#[derive(Clone, Debug, Default)]
+pub struct TraceConfig {
+    /// Prints every recorded instruction
+    pub print: bool,
+    /// Prints the number of recorded instructions of each opcode class
+    pub histogram: bool,
+    pub filter: TraceFilter,
+    /// Records one out of this many matching instructions, 0 and 1 record all of them
+    pub sample_interval: u64,
+    /// Prints the register file when the emulator stops
+    pub dump_registers: bool,
+    /// Harts of the machine, the output names the hart of each event when
+    /// there are several
+    pub harts: usize,
+}
+
+impl TraceConfig {
+    /// Returns a tracer feeding the selected observers, None when no observer
+    /// is selected so the hart doesn't trace at all
+    pub fn tracer(&self) -> Option<Tracer> {
+        let mut observers: Vec<Box<dyn TraceObserver>> = Vec::new();
+        if self.print {
+            observers.push(Box::new(PrintObserver::new(self.harts > 1)));
+        }
+        if self.histogram {
+            observers.push(Box::new(ClassHistogram::new(self.harts > 1)));
+        }
+        match observers.is_empty() {
+            true => None,
+            false => Some(Tracer::new(
+                self.filter.clone(),
+                self.sample_interval,
+                observers,
+            )),
+        }
+    }
+}
+
+/// Records the executed instructions into batches that are handed to the
+/// observers on a separate thread
+pub struct Tracer {
+    filter: TraceFilter,
+    sample_interval: u64,
+    /// Matching instructions since the last recorded one
+    skipped: u64,
+    sequence: u64,
+    batch: Vec<TraceEvent>,
+    full_batches: Option<SyncSender<Vec<TraceEvent>>>,
+    free_batches: Receiver<Vec<TraceEvent>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Tracer {
+    pub fn new(
+        filter: TraceFilter,
+        sample_interval: u64,
+        mut observers: Vec<Box<dyn TraceObserver>>,
+    ) -> Self {
+        let (full_tx, full_rx) = sync_channel::<Vec<TraceEvent>>(BATCH_COUNT);
+        let (free_tx, free_rx): (Sender<Vec<TraceEvent>>, _) = channel();
+        for _ in 1..BATCH_COUNT {
+            free_tx.send(Vec::with_capacity(BATCH_SIZE)).unwrap();
+        }
+        let worker = thread::spawn(move || {
+            for mut batch in full_rx {
+                observers
+                    .iter_mut()
+                    .for_each(|observer| observer.observe(&batch));
+                batch.clear();
+                //The hart is gone once it stops taking batches back
+                let _ = free_tx.send(batch);
+            }
+            observers.iter_mut().for_each(|observer| observer.finish());
+        });
+        Self {
+            filter,
+            sample_interval: sample_interval.max(1),
+            skipped: 0,
+            sequence: 0,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            full_batches: Some(full_tx),
+            free_batches: free_rx,
+            worker: Some(worker),
+        }
+    }
+
+    #[inline(always)]
+    pub fn record(
+        &mut self,
+        hart: usize,
+        pc: u64,
+        instruction: u32,
+        privilege_mode: PrivilegeMode,
+    ) {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        if !self.filter.matches(pc, instruction, privilege_mode) {
+            return;
+        }
+        if self.skipped != 0 {
+            self.skipped = (self.skipped + 1) % self.sample_interval;
+            return;
+        }
+        self.skipped = 1 % self.sample_interval;
+        self.batch.push(TraceEvent {
+            sequence,
+            hart,
+            pc,
+            instruction,
+            privilege_mode,
+        });
+        if self.batch.len() == BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let free = self
+            .free_batches
+            .recv()
+            .unwrap_or_else(|_| Vec::with_capacity(BATCH_SIZE));
+        let full = mem::replace(&mut self.batch, free);
+        if let Some(full_batches) = self.full_batches.as_ref() {
+            full_batches.send(full).expect("Tracing thread stopped");
+        }
+    }
+}
+
+impl Drop for Tracer {
+    /// Hands the last events to the observers and waits for them to finish
+    fn drop(&mut self) {
+        if let Some(full_batches) = self.full_batches.take() {
+            let _ = full_batches.send(mem::take(&mut self.batch));
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Prints one line per recorded instruction
+pub struct PrintObserver {
+    /// Lines of the batch, written to stdout at once so the lines of harts
+    /// tracing on other threads never splice into them
+    lines: Vec<u8>,
+    /// Prints the hart of each instruction
+    show_hart: bool,
+}
+
+impl PrintObserver {
+    pub fn new(show_hart: bool) -> Self {
+        Self {
+            lines: Vec::new(),
+            show_hart,
+        }
+    }
+
+    /// Formats the lines of a batch
+    fn format(&mut self, events: &[TraceEvent]) {
+        self.lines.clear();
+        for event in events {
+            let mode = match event.privilege_mode {
+                PrivilegeMode::Machine => 'M',
+                PrivilegeMode::Supervisor => 'S',
+                PrivilegeMode::User => 'U',
+            };
+            if self.show_hart {
+                let _ = write!(self.lines, "h{:<3} ", event.hart);
+            }
+            let _ = writeln!(
+                self.lines,
+                "{:>10} {mode} {:0x}: {:08x} {}",
+                event.sequence,
+                event.pc,
+                event.instruction,
+                Disassembly {
+                    pc: event.pc,
+                    instruction: event.instruction,
+                }
+            );
+        }
+    }
+}
+
+impl TraceObserver for PrintObserver {
+    fn observe(&mut self, events: &[TraceEvent]) {
+        self.format(events);
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(&self.lines);
+        let _ = stdout.flush();
+    }
+}
+
+/// Counts the recorded instructions of each opcode class, per hart
+pub struct ClassHistogram {
+    counts: Vec<[u64; OpcodeClass::ALL.len()]>,
+    /// Prints a heading before the counts of each hart
+    show_hart: bool,
+}
+
+impl ClassHistogram {
+    pub fn new(show_hart: bool) -> Self {
+        Self {
+            counts: vec![[0; OpcodeClass::ALL.len()]],
+            show_hart,
+        }
+    }
+}
+
+impl TraceObserver for ClassHistogram {
+    fn observe(&mut self, events: &[TraceEvent]) {
+        for event in events {
+            if event.hart >= self.counts.len() {
+                self.counts
+                    .resize(event.hart + 1, [0; OpcodeClass::ALL.len()]);
+            }
+            self.counts[event.hart][OpcodeClass::of(event.instruction) as usize] += 1;
+        }
+    }
+
+    fn finish(&mut self) {
+        for (hart, counts) in self.counts.iter().enumerate() {
+            let total: u64 = counts.iter().sum();
+            if self.show_hart {
+                if total == 0 {
+                    continue;
+                }
+                println!("hart {hart}:");
+            }
+            for class in OpcodeClass::ALL {
+                let count = counts[class as usize];
+                println!(
+                    "{:>8}: {count:>12} {:>6.2}%",
+                    class.name(),
+                    count as f64 * 100.0 / total.max(1) as f64
+                );
+            }
+        }
+    }
+}
+
+// TODO: Later reimplement this, copied from RISC-V emulator rust book
+/// Dumps the register file, the E base ABIs (ILP32E/LP64E) only have x0-x15
+/// which keep the names of the standard ABIs
+pub fn dump_registers(registers: &[u64]) {
+    let mut output = String::from("");
+    let abi = [
+        "zero", " ra ", " sp ", " gp ", " tp ", " t0 ", " t1 ", " t2 ", " s0 ", " s1 ", " a0 ",
+        " a1 ", " a2 ", " a3 ", " a4 ", " a5 ", " a6 ", " a7 ", " s2 ", " s3 ", " s4 ", " s5 ",
+        " s6 ", " s7 ", " s8 ", " s9 ", " s10", " s11", " t3 ", " t4 ", " t5 ", " t6 ",
+    ];
+    for i in (0..registers.len()).step_by(4) {
+        output = format!(
+            "{}\nx{:02}({})={:>#18x}\tx{:02}({})={:>#18x}\tx{:02}({})={:>#18x}\tx{:02}({})={:>#18x}",
+            output,
+            i,
+            abi[i],
+            registers[i],
+            i + 1,
+            abi[i + 1],
+            registers[i + 1],
+            i + 2,
+            abi[i + 2],
+            registers[i + 2],
+            i + 3,
+            abi[i + 3],
+            registers[i + 3],
+        );
+    }
+    println!("{}", output);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    //addi x5, x5, 1
+    const ADDI: u32 = 0x0012_8293;
+    //sw x7, 8(x10)
+    const SW: u32 = 0x0075_2423;
+
+    /// Observer keeping the events it receives
+    struct Collector(Arc<Mutex<Vec<TraceEvent>>>);
+
+    impl TraceObserver for Collector {
+        fn observe(&mut self, events: &[TraceEvent]) {
+            self.0.lock().unwrap().extend_from_slice(events);
+        }
+    }
+
+    /// Records the instructions through a tracer and returns the events
+    /// handed to its observer
+    fn trace(
+        filter: TraceFilter,
+        sample_interval: u64,
+        instructions: &[(u64, u32, PrivilegeMode)],
+    ) -> Vec<TraceEvent> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut tracer = Tracer::new(
+            filter,
+            sample_interval,
+            vec![Box::new(Collector(events.clone()))],
+        );
+        for (pc, instruction, privilege_mode) in instructions {
+            tracer.record(0, *pc, *instruction, *privilege_mode);
+        }
+        drop(tracer);
+        Arc::try_unwrap(events).unwrap().into_inner().unwrap()
+    }
+
+    fn sequences(events: &[TraceEvent]) -> Vec<u64> {
+        events.iter().map(|event| event.sequence).collect()
+    }
+
+    #[test]
+    fn opcode_classes() {
+        assert_eq!(OpcodeClass::of(ADDI), OpcodeClass::Alu);
+        assert_eq!(OpcodeClass::of(SW), OpcodeClass::Store);
+        //jal x0, 0
+        assert_eq!(OpcodeClass::of(0x0000_006f), OpcodeClass::Jump);
+        //fence.i
+        assert_eq!(OpcodeClass::of(0x0000_100f), OpcodeClass::Fence);
+        //ecall
+        assert_eq!(OpcodeClass::of(0x0000_0073), OpcodeClass::System);
+        for class in OpcodeClass::ALL {
+            assert_eq!(OpcodeClass::from_name(class.name()), Some(class));
+        }
+    }
+
+    #[test]
+    fn filters_keep_the_execution_sequence() {
+        let instructions = [
+            (0x1000, ADDI, PrivilegeMode::Machine),
+            (0x1004, SW, PrivilegeMode::Machine),
+            (0x2000, ADDI, PrivilegeMode::Supervisor),
+            (0x2004, SW, PrivilegeMode::User),
+        ];
+        let pc_range = TraceFilter {
+            pc_range: Some(0x1004..=0x2000),
+            ..TraceFilter::default()
+        };
+        assert_eq!(sequences(&trace(pc_range, 1, &instructions)), [1, 2]);
+        let privilege_mode = TraceFilter {
+            privilege_mode: Some(PrivilegeMode::Machine),
+            ..TraceFilter::default()
+        };
+        assert_eq!(sequences(&trace(privilege_mode, 1, &instructions)), [0, 1]);
+        let store = TraceFilter {
+            opcode_class: Some(OpcodeClass::Store),
+            ..TraceFilter::default()
+        };
+        assert_eq!(sequences(&trace(store.clone(), 1, &instructions)), [1, 3]);
+        let user_store = TraceFilter {
+            privilege_mode: Some(PrivilegeMode::User),
+            ..store
+        };
+        let events = trace(user_store, 1, &instructions);
+        assert_eq!(sequences(&events), [3]);
+        assert_eq!(events[0].pc, 0x2004);
+        assert_eq!(events[0].instruction, SW);
+    }
+
+    #[test]
+    fn sampling_counts_the_matching_instructions() {
+        let instructions: Vec<_> = (0..12)
+            .map(|index| {
+                let instruction = if index % 2 == 0 { ADDI } else { SW };
+                (0x1000 + 4 * index, instruction, PrivilegeMode::Machine)
+            })
+            .collect();
+        let all = trace(TraceFilter::default(), 3, &instructions);
+        assert_eq!(sequences(&all), [0, 3, 6, 9]);
+        let alu = TraceFilter {
+            opcode_class: Some(OpcodeClass::Alu),
+            ..TraceFilter::default()
+        };
+        assert_eq!(sequences(&trace(alu, 2, &instructions)), [0, 4, 8]);
+    }
+
+    #[test]
+    fn batches_reach_the_observers_in_order() {
+        let instructions: Vec<_> = (0..3 * BATCH_SIZE as u64 + 5)
+            .map(|index| (4 * index, ADDI, PrivilegeMode::Machine))
+            .collect();
+        let events = trace(TraceFilter::default(), 0, &instructions);
+        assert_eq!(events.len(), instructions.len());
+        assert!(events
+            .iter()
+            .enumerate()
+            .all(|(index, event)| event.sequence == index as u64));
+    }
+
+    #[test]
+    fn no_tracer_without_observers() {
+        assert!(TraceConfig::default().tracer().is_none());
+        let config = TraceConfig {
+            histogram: true,
+            ..TraceConfig::default()
+        };
+        assert!(config.tracer().is_some());
+    }
+
+    #[test]
+    fn printed_batches_end_on_a_line_boundary() {
+        let mut observer = PrintObserver::new(true);
+        let events: Vec<_> = [(0, ADDI), (1, SW)]
+            .into_iter()
+            .map(|(sequence, instruction)| TraceEvent {
+                sequence,
+                hart: 3,
+                pc: 0x8000_0000 + 4 * sequence,
+                instruction,
+                privilege_mode: PrivilegeMode::Supervisor,
+            })
+            .collect();
+        observer.format(&events);
+        let lines = String::from_utf8(observer.lines.clone()).unwrap();
+        assert!(lines.ends_with('\n'));
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.lines().all(|line| line.starts_with("h3   ")));
+        assert!(lines.contains(" S 80000004: 00752423 "));
+    }
+}
@@ -0,0 +1,94 @@
+//! Tests of the benchmark harness, the benchmark target itself has no test
+//! harness
+
+#[path = "../benches/suite/harness.rs"]
+mod harness;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use harness::{format_baseline, parse_baseline, run, Comparison, Measurement};
+use riscvemulator::cpu::config::CpuConfig;
+
+/// Powers the machine off with an exit status
+fn exit(status: u32) -> Vec<u8> {
+    let value = status << 16 | if status == 0 { 0x5555 } else { 0x3333 };
+    let (upper, lower) = ((value + 0x800) >> 12, value & 0xfff);
+    [
+        //lui t5, 0x100
+        0x0010_0f37,
+        //lui t6, upper
+        upper << 12 | 0xfb7,
+        //addi t6, t6, lower
+        lower << 20 | 0xf8f93,
+        //sw t6, 0(t5)
+        0x01ff_2023,
+        //jal x0, 0
+        0x0000_006f,
+    ]
+    .iter()
+    .flat_map(|raw: &u32| raw.to_le_bytes())
+    .collect()
+}
+
+#[test]
+fn runs_end_with_the_exit_status() {
+    let config = CpuConfig::default();
+    let measurement = run(&exit(0), &config).unwrap();
+    assert_eq!(measurement.instructions, 4);
+    assert_eq!(
+        run(&exit(3), &config),
+        Err("exited with status 3".to_string())
+    );
+    //lui t5, 0x100 then fetching zeroes
+    let invalid = run(&0x0010_0f37_u32.to_le_bytes(), &config).unwrap_err();
+    assert!(invalid.starts_with("0: "), "{invalid}");
+}
+
+#[test]
+fn baselines_round_trip() {
+    let measurements = BTreeMap::from([
+        (
+            "coremark".to_string(),
+            Measurement {
+                instructions: 123_456_789,
+                time: Duration::from_nanos(987_654_321),
+            },
+        ),
+        (
+            "dhrystone".to_string(),
+            Measurement {
+                instructions: 42,
+                time: Duration::from_nanos(1),
+            },
+        ),
+    ]);
+    let text = format_baseline(&measurements);
+    assert_eq!(text, "coremark 123456789 987654321\ndhrystone 42 1\n");
+    assert_eq!(parse_baseline(&text), measurements);
+    //Malformed lines are skipped
+    assert_eq!(
+        parse_baseline("broken\ncoremark 123456789 987654321\nnan x 1\n").len(),
+        1
+    );
+}
+
+#[test]
+fn slowdowns_over_the_threshold_are_regressions() {
+    let measurement = |instructions, millis| Measurement {
+        instructions,
+        time: Duration::from_millis(millis),
+    };
+    let saved = measurement(1000, 100);
+    assert_eq!(measurement(1000, 10).mips(), 0.1);
+    let faster = Comparison::new(&saved, &measurement(1000, 90), 5.0);
+    assert_eq!(faster.to_string(), "-10.0%");
+    assert!(!faster.failed());
+    let noise = Comparison::new(&saved, &measurement(1000, 104), 5.0);
+    assert!(!noise.failed());
+    let slower = Comparison::new(&saved, &measurement(1000, 110), 5.0);
+    assert_eq!(slower.to_string(), "+10.0% regression");
+    assert!(slower.failed());
+    let changed = Comparison::new(&saved, &measurement(999, 50), 5.0);
+    assert_eq!(changed, Comparison::InstructionsChanged);
+    assert!(changed.failed());
+}